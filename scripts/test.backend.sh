@@ -17,6 +17,15 @@ else
   cargo build --locked --target wasm32-unknown-unknown --release -p backend
 fi
 
+# The tests against the stand-ins need a backend built with the `bitcoin-api-stand-in`, `ckbtc-minter-stand-in` and `btc-fresh-addresses` features.
+if [ -f "./backend-stand-in.wasm.gz" ]; then
+  echo "Use existing backend-stand-in.wasm.gz canister."
+  export BACKEND_STAND_IN_WASM_PATH="../../backend-stand-in.wasm.gz"
+else
+  echo "Building backend canister with the stand-ins."
+  cargo build --locked --target wasm32-unknown-unknown --release -p backend --features bitcoin-api-stand-in,ckbtc-minter-stand-in,btc-fresh-addresses --target-dir target/stand-in
+fi
+
 if [ -f "./$BITCON_CANISTER_WASM" ]; then
   echo "Use existing $BITCON_CANISTER_WASM canister."
 else
@@ -1,8 +1,16 @@
 use crate::MAX_SYMBOL_LENGTH;
+use bitcoin::base58;
 use shared::types::token::UserToken;
 
+/// The length of a Solana address, an ed25519 public key.
+const SOLANA_ADDRESS_LENGTH: usize = 32;
+
 pub fn assert_token_symbol_length(token: &UserToken) -> Result<(), String> {
-    if let Some(symbol) = token.symbol.as_ref() {
+    assert_symbol_length(token.symbol.as_deref())
+}
+
+pub fn assert_symbol_length(symbol: Option<&str>) -> Result<(), String> {
+    if let Some(symbol) = symbol {
         if symbol.len() > MAX_SYMBOL_LENGTH {
             return Err(format!(
                 "Token symbol should not exceed {MAX_SYMBOL_LENGTH} bytes",
@@ -20,3 +28,11 @@ pub fn assert_token_enabled_is_some(UserToken { enabled, .. }: &UserToken) -> Re
 
     Ok(())
 }
+
+/// Checks that a Solana address is the base58 encoding of 32 bytes.
+pub fn assert_solana_address(address: &str) -> Result<(), String> {
+    match base58::decode(address) {
+        Ok(bytes) if bytes.len() == SOLANA_ADDRESS_LENGTH => Ok(()),
+        _ => Err(format!("Invalid Solana address {address}")),
+    }
+}
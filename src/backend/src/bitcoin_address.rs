@@ -0,0 +1,84 @@
+//! Parsing and validation of the bitcoin addresses given in requests.
+use crate::signer::transform_network;
+use bitcoin::{address::NetworkUnchecked, Address};
+use shared::types::bitcoin::{BtcAddressError, BtcNetwork};
+
+/// Parses a bitcoin address and checks that it is valid for the given network.
+pub fn parse_address(address: &str, network: BtcNetwork) -> Result<Address, BtcAddressError> {
+    parse_unchecked_address(address)?
+        .require_network(transform_network(network))
+        .map_err(|_| BtcAddressError::NetworkMismatch {
+            address: address.to_string(),
+            network,
+        })
+}
+
+/// Checks that a bitcoin address is valid for at least one network.
+///
+/// Only for requests that don't specify a network.
+pub fn check_address(address: &str) -> Result<(), BtcAddressError> {
+    parse_unchecked_address(address).map(|_| ())
+}
+
+fn parse_unchecked_address(address: &str) -> Result<Address<NetworkUnchecked>, BtcAddressError> {
+    address
+        .parse::<Address<NetworkUnchecked>>()
+        .map_err(|_| BtcAddressError::Malformed {
+            address: address.to_string(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REGTEST_ADDRESS: &str = "bcrt1qpg7udjvq7gx2fp480pgt4hnhj3qc4nhrkstc33";
+    const TESTNET_ADDRESS: &str = "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx";
+
+    #[test]
+    fn parse_address_accepts_address_of_network() {
+        assert_eq!(
+            parse_address(REGTEST_ADDRESS, BtcNetwork::Regtest)
+                .unwrap()
+                .to_string(),
+            REGTEST_ADDRESS
+        );
+        assert!(parse_address(TESTNET_ADDRESS, BtcNetwork::Testnet).is_ok());
+    }
+
+    #[test]
+    fn parse_address_rejects_other_network() {
+        assert_eq!(
+            parse_address(REGTEST_ADDRESS, BtcNetwork::Mainnet),
+            Err(BtcAddressError::NetworkMismatch {
+                address: REGTEST_ADDRESS.to_string(),
+                network: BtcNetwork::Mainnet,
+            })
+        );
+        assert_eq!(
+            parse_address(TESTNET_ADDRESS, BtcNetwork::Regtest),
+            Err(BtcAddressError::NetworkMismatch {
+                address: TESTNET_ADDRESS.to_string(),
+                network: BtcNetwork::Regtest,
+            })
+        );
+    }
+
+    #[test]
+    fn parse_address_rejects_malformed_address() {
+        for address in [
+            "not an address",
+            "",
+            "bcrt1qpg7udjvq7gx2fp480pgt4hnhj3qc4nhrkstc34",
+        ] {
+            assert_eq!(
+                parse_address(address, BtcNetwork::Regtest),
+                Err(BtcAddressError::Malformed {
+                    address: address.to_string(),
+                })
+            );
+            assert!(check_address(address).is_err());
+        }
+        assert_eq!(check_address(TESTNET_ADDRESS), Ok(()));
+    }
+}
@@ -1,83 +1,511 @@
+//! Access to the bitcoin network.
+//!
+//! The canister uses the bitcoin API of the management canister through the `BitcoinApi` trait.
+//! Builds with the `bitcoin-api-stand-in` feature use the programmable `StandInBitcoinApi`
+//! instead, so that tests can control the UTXOs and fees seen by the canister.
+use crate::{
+    bitcoin_error::{BtcError, BtcFeeError},
+    read_config,
+    utxos_cache::{mutate_utxos_cache, CachedUtxos, UtxosCacheKey},
+};
+use futures::future::join_all;
 use ic_cdk::api::management_canister::bitcoin::{
-    bitcoin_get_current_fee_percentiles, bitcoin_get_utxos, BitcoinNetwork,
-    GetCurrentFeePercentilesRequest, GetUtxosRequest, GetUtxosResponse, MillisatoshiPerByte, Utxo,
-    UtxoFilter,
+    bitcoin_get_current_fee_percentiles, bitcoin_get_utxos, bitcoin_send_transaction,
+    BitcoinNetwork, GetCurrentFeePercentilesRequest, GetUtxosRequest, GetUtxosResponse,
+    MillisatoshiPerByte, SendTransactionRequest, Utxo, UtxoFilter,
 };
+use shared::types::bitcoin::{BtcFeePercentiles, BtcFeePolicy, BtcNetwork, BtcNetworkPolicy};
+
+#[cfg(any(test, feature = "bitcoin-api-stand-in"))]
+pub mod stand_in;
+
+#[cfg(feature = "bitcoin-api-stand-in")]
+use stand_in::StandInBitcoinApi;
+
+/// The calls made to the bitcoin network.
+pub trait BitcoinApi {
+    /// Returns a page of the UTXOs of the given bitcoin address.
+    async fn get_utxos(
+        &self,
+        network: BtcNetwork,
+        address: String,
+        filter: Option<UtxoFilter>,
+    ) -> Result<GetUtxosResponse, BtcError>;
+
+    /// Returns the 100 fee percentiles measured in millisatoshi/byte.
+    async fn get_current_fee_percentiles(
+        &self,
+        network: BtcNetwork,
+    ) -> Result<Vec<MillisatoshiPerByte>, BtcError>;
+
+    /// Sends a signed transaction to the bitcoin network.
+    // The signed transactions are broadcast by the frontend for now.
+    #[allow(dead_code)]
+    async fn send_transaction(
+        &self,
+        network: BtcNetwork,
+        transaction: Vec<u8>,
+    ) -> Result<(), BtcError>;
+}
+
+/// The bitcoin API of the management canister.
+pub struct ManagementCanisterBitcoinApi;
+
+/// The network of the bitcoin API of the management canister.
+fn management_canister_network(network: BtcNetwork) -> BitcoinNetwork {
+    match network {
+        BtcNetwork::Mainnet => BitcoinNetwork::Mainnet,
+        BtcNetwork::Testnet => BitcoinNetwork::Testnet,
+        BtcNetwork::Regtest => BitcoinNetwork::Regtest,
+    }
+}
+
+impl BitcoinApi for ManagementCanisterBitcoinApi {
+    /// NOTE: Relies on the `bitcoin_get_utxos` endpoint.
+    /// See [IC Interface](https://internetcomputer.org/docs/current/references/ic-interface-spec/#ic-bitcoin_get_utxos)
+    async fn get_utxos(
+        &self,
+        network: BtcNetwork,
+        address: String,
+        filter: Option<UtxoFilter>,
+    ) -> Result<GetUtxosResponse, BtcError> {
+        let utxos_res = bitcoin_get_utxos(GetUtxosRequest {
+            address,
+            network: management_canister_network(network),
+            filter,
+        })
+        .await
+        .map_err(BtcError::bitcoin_api_rejected)?;
+
+        Ok(utxos_res.0)
+    }
+
+    /// Percentiles are computed from the last 10,000 transactions (if available).
+    ///
+    /// Relies on the `bitcoin_get_current_fee_percentiles` endpoint.
+    /// See [Bitcoin API](https://internetcomputer.org/docs/current/references/ic-interface-spec/#ic-bitcoin_get_current_fee_percentiles)
+    async fn get_current_fee_percentiles(
+        &self,
+        network: BtcNetwork,
+    ) -> Result<Vec<MillisatoshiPerByte>, BtcError> {
+        let res = bitcoin_get_current_fee_percentiles(GetCurrentFeePercentilesRequest {
+            network: management_canister_network(network),
+        })
+        .await
+        .map_err(BtcError::bitcoin_api_rejected)?;
+
+        Ok(res.0)
+    }
+
+    /// Relies on the `bitcoin_send_transaction` endpoint.
+    /// See [IC Interface](https://internetcomputer.org/docs/current/references/ic-interface-spec/#ic-bitcoin_send_transaction)
+    async fn send_transaction(
+        &self,
+        network: BtcNetwork,
+        transaction: Vec<u8>,
+    ) -> Result<(), BtcError> {
+        bitcoin_send_transaction(SendTransactionRequest {
+            transaction,
+            network: management_canister_network(network),
+        })
+        .await
+        .map_err(BtcError::bitcoin_api_rejected)
+    }
+}
+
+/// The bitcoin API used by the canister.
+#[cfg(not(feature = "bitcoin-api-stand-in"))]
+fn bitcoin_api() -> ManagementCanisterBitcoinApi {
+    ManagementCanisterBitcoinApi
+}
 
-/// Returns the UTXOs of the given bitcoin address.
+/// The bitcoin API used by the canister.
+#[cfg(feature = "bitcoin-api-stand-in")]
+fn bitcoin_api() -> StandInBitcoinApi {
+    stand_in::canister_stand_in()
+}
+
+/// Returns all the UTXOs of a specific address.
+/// API interface returns a paginated view of the utxos but we need to get them all.
 ///
-/// NOTE: Relies on the `bitcoin_get_utxos` endpoint.
-/// See [IC Interface](https://internetcomputer.org/docs/current/references/ic-interface-spec/#ic-bitcoin_get_utxos)
-async fn get_utxos(
-    network: BitcoinNetwork,
+/// The UTXOs are served from the cache for a while, then refreshed, see `utxos_cache`.
+pub async fn get_all_utxos(
+    network: BtcNetwork,
     address: String,
-    filter: Option<UtxoFilter>,
-) -> Result<GetUtxosResponse, String> {
-    let utxos_res = bitcoin_get_utxos(GetUtxosRequest {
-        address,
+    min_confirmations: Option<u32>,
+) -> Result<Vec<Utxo>, BtcError> {
+    let cap = read_config(|config| {
+        max_min_confirmations(config.btc_network_policies.as_deref(), network)
+    });
+    let min_confirmations = min_confirmations
+        .map(|min_confirmations| cap.map_or(min_confirmations, |cap| min_confirmations.min(cap)));
+    get_all_utxos_from(
+        &bitcoin_api(),
         network,
-        filter,
-    })
+        address,
+        min_confirmations,
+        ic_cdk::api::time(),
+    )
     .await
-    .map_err(|err| err.1)?;
+}
 
-    Ok(utxos_res.0)
+/// The cap of the confirmations required from the UTXOs of the network, if any.
+///
+/// Unless the policy of the network sets it, the confirmations are capped to 1 on regtest, as the
+/// tests with regtest fail if more confirmations are required, and not capped on the other
+/// networks.
+fn max_min_confirmations(
+    policies: Option<&[BtcNetworkPolicy]>,
+    network: BtcNetwork,
+) -> Option<u32> {
+    policies
+        .and_then(|policies| policies.iter().find(|policy| policy.network == network))
+        .and_then(|policy| policy.max_min_confirmations)
+        .or_else(|| (network == BtcNetwork::Regtest).then_some(1))
 }
-/// Returns all the UTXOs of a specific address.
-/// API interface returns a paginated view of the utxos but we need to get them all.
-pub async fn get_all_utxos(
-    network: BitcoinNetwork,
+
+/// Returns all the UTXOs of each of the given addresses.
+pub async fn get_all_utxos_of_addresses(
+    network: BtcNetwork,
+    addresses: &[String],
+    min_confirmations: Option<u32>,
+) -> Result<Vec<Vec<Utxo>>, BtcError> {
+    join_all(
+        addresses
+            .iter()
+            .map(|address| get_all_utxos(network, address.clone(), min_confirmations)),
+    )
+    .await
+    .into_iter()
+    .collect()
+}
+
+/// Returns all the UTXOs of a specific address, as seen by the given bitcoin API.
+///
+/// The cached UTXOs are served without any call until they are `MAX_UTXOS_AGE_NS` old. Then the
+/// first page tells the current tip and the cached UTXOs of that tip are served if any, otherwise
+/// all the pages are fetched and must report the same tip, so that the cached UTXOs are those of
+/// a single tip.
+async fn get_all_utxos_from(
+    bitcoin_api: &impl BitcoinApi,
+    network: BtcNetwork,
     address: String,
     min_confirmations: Option<u32>,
-) -> Result<Vec<Utxo>, String> {
-    let final_min_confirmations = if network == BitcoinNetwork::Regtest {
-        // Tests with Regtest fail if min_confirmations is higher than 1.
-        Some(1)
-    } else {
-        min_confirmations
+    now_ns: u64,
+) -> Result<Vec<Utxo>, BtcError> {
+    let cache_key = UtxosCacheKey {
+        network,
+        address: address.clone(),
+        min_confirmations,
     };
-    let filter = final_min_confirmations.map(UtxoFilter::MinConfirmations);
-    let mut utxos_response = get_utxos(network, address.clone(), filter).await?;
+    if let Some(utxos) = mutate_utxos_cache(|cache| cache.get(&cache_key, now_ns)) {
+        return Ok(utxos);
+    }
+
+    let filter = min_confirmations.map(UtxoFilter::MinConfirmations);
+    let mut utxos_response = bitcoin_api
+        .get_utxos(network, address.clone(), filter)
+        .await?;
+    let tip_height = utxos_response.tip_height;
+    let tip_block_hash = utxos_response.tip_block_hash;
+    if let Some(utxos) =
+        mutate_utxos_cache(|cache| cache.refresh(&cache_key, tip_height, &tip_block_hash, now_ns))
+    {
+        return Ok(utxos);
+    }
 
     let mut all_utxos: Vec<Utxo> = utxos_response.utxos;
     let mut next_page: Option<Vec<u8>> = utxos_response.next_page;
     while next_page.is_some() {
-        utxos_response =
-            get_utxos(network, address.clone(), next_page.map(UtxoFilter::Page)).await?;
+        utxos_response = bitcoin_api
+            .get_utxos(network, address.clone(), next_page.map(UtxoFilter::Page))
+            .await?;
+        if utxos_response.tip_block_hash != tip_block_hash {
+            return Err(BtcError::InternalError {
+                msg: "The tip changed while fetching the pages of UTXOs".to_string(),
+            });
+        }
         all_utxos.extend(utxos_response.utxos);
         next_page = utxos_response.next_page;
     }
 
+    mutate_utxos_cache(|cache| {
+        cache.insert(
+            cache_key,
+            CachedUtxos {
+                tip_height,
+                tip_block_hash,
+                utxos: all_utxos.clone(),
+            },
+            now_ns,
+        );
+    });
+
     Ok(all_utxos)
 }
 
-/// Returns the 100 fee percentiles measured in millisatoshi/byte.
-/// Percentiles are computed from the last 10,000 transactions (if available).
+/// Bounds of the explicit fee rates accepted by `BtcFeePolicy::Custom`, in satoshi/vbyte.
 ///
-/// Relies on the `bitcoin_get_current_fee_percentiles` endpoint.
-/// See [Bitcoin API](https://internetcomputer.org/docs/current/references/ic-interface-spec/#ic-bitcoin_get_current_fee_percentiles)
-async fn get_current_fee_percentiles(
-    network: BitcoinNetwork,
-) -> Result<Vec<MillisatoshiPerByte>, String> {
-    let res = bitcoin_get_current_fee_percentiles(GetCurrentFeePercentilesRequest { network })
-        .await
-        .map_err(|err| err.1)?;
+/// Below the minimum, transactions are not relayed by the nodes.
+/// Above the maximum, the fee is most likely a mistake.
+const MIN_FEE_SATOSHI_PER_VBYTE: u64 = 1;
+const MAX_FEE_SATOSHI_PER_VBYTE: u64 = 1_000;
+
+/// Returns the fee rate at the given percentile (0-100) of the fee percentiles.
+fn fee_at_percentile(fee_percentiles: &[MillisatoshiPerByte], percentile: u8) -> Option<u64> {
+    let index = (fee_percentiles.len() * usize::from(percentile) / 100)
+        .min(fee_percentiles.len().checked_sub(1)?);
+    fee_percentiles.get(index).copied()
+}
+
+/// Converts an explicit fee rate in satoshi/vbyte to millisatoshi/vbyte, checking its bounds.
+fn custom_fee_per_byte(satoshi_per_vbyte: u64) -> Result<u64, BtcFeeError> {
+    if (MIN_FEE_SATOSHI_PER_VBYTE..=MAX_FEE_SATOSHI_PER_VBYTE).contains(&satoshi_per_vbyte) {
+        Ok(satoshi_per_vbyte * 1000)
+    } else {
+        Err(BtcFeeError::FeeRateOutOfBounds {
+            min_satoshi_per_vbyte: MIN_FEE_SATOSHI_PER_VBYTE,
+            max_satoshi_per_vbyte: MAX_FEE_SATOSHI_PER_VBYTE,
+        })
+    }
+}
 
-    Ok(res.0)
+/// Returns the fee rate, in millisatoshi/byte, to use for the given fee policy.
+///
+/// The fee tiers map to the percentiles set in the config.
+pub async fn get_fee_per_byte_for_policy(
+    network: BtcNetwork,
+    fee_policy: BtcFeePolicy,
+) -> Result<u64, BtcFeeError> {
+    let percentiles = read_config(|config| config.btc_fee_percentiles.unwrap_or_default());
+    get_fee_per_byte_for_policy_from(&bitcoin_api(), network, fee_policy, percentiles).await
 }
 
-/// Returns the 50th percentile for sending fees.
-pub async fn get_fee_per_byte(network: BitcoinNetwork) -> Result<u64, String> {
+/// Returns the fee rate, in millisatoshi/byte, that the given bitcoin API implies for the fee
+/// policy.
+async fn get_fee_per_byte_for_policy_from(
+    bitcoin_api: &impl BitcoinApi,
+    network: BtcNetwork,
+    fee_policy: BtcFeePolicy,
+    percentiles: BtcFeePercentiles,
+) -> Result<u64, BtcFeeError> {
+    let percentile = match fee_policy {
+        BtcFeePolicy::Slow => percentiles.slow,
+        BtcFeePolicy::Standard => percentiles.standard,
+        BtcFeePolicy::Fast => percentiles.fast,
+        BtcFeePolicy::Custom { satoshi_per_vbyte } => {
+            return custom_fee_per_byte(satoshi_per_vbyte)
+        }
+    };
+
     // Get fee percentiles from previous transactions to estimate our own fee.
-    let fee_percentiles = get_current_fee_percentiles(network).await?;
+    let fee_percentiles = bitcoin_api.get_current_fee_percentiles(network).await?;
 
-    if fee_percentiles.is_empty() {
-        // There are no fee percentiles. This case can only happen on a regtest
-        // network where there are no non-coinbase transactions. In this case,
-        // we use a default of 2000 millisatoshis/byte (i.e. 2 satoshi/byte)
-        Ok(2000)
-    } else {
-        let middle = fee_percentiles.len() / 2;
-        Ok(fee_percentiles[middle])
+    // There are no fee percentiles. This case can only happen on a regtest
+    // network where there are no non-coinbase transactions. In this case,
+    // we use a default of 2000 millisatoshis/byte (i.e. 2 satoshi/byte)
+    Ok(fee_at_percentile(&fee_percentiles, percentile).unwrap_or(2000))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::stand_in::StandInBitcoinApi;
+    use super::*;
+    use crate::utxos_cache::MAX_UTXOS_AGE_NS;
+    use futures::executor::block_on;
+    use ic_cdk::api::management_canister::bitcoin::Outpoint;
+
+    const ADDRESS: &str = "bcrt1qpg7udjvq7gx2fp480pgt4hnhj3qc4nhrkstc33";
+
+    fn utxo(vout: u32, height: u32) -> Utxo {
+        Utxo {
+            outpoint: Outpoint {
+                txid: vec![1; 32],
+                vout,
+            },
+            value: 1_000,
+            height,
+        }
+    }
+
+    #[test]
+    fn fee_at_percentile_picks_the_matching_percentile() {
+        let fee_percentiles: Vec<u64> = (0..100).map(|i| i * 1000).collect();
+        assert_eq!(fee_at_percentile(&fee_percentiles, 25), Some(25_000));
+        assert_eq!(fee_at_percentile(&fee_percentiles, 50), Some(50_000));
+        assert_eq!(fee_at_percentile(&fee_percentiles, 100), Some(99_000));
+        assert_eq!(fee_at_percentile(&[], 50), None);
+    }
+
+    #[test]
+    fn custom_fee_per_byte_is_bounded() {
+        let out_of_bounds = Err(BtcFeeError::FeeRateOutOfBounds {
+            min_satoshi_per_vbyte: MIN_FEE_SATOSHI_PER_VBYTE,
+            max_satoshi_per_vbyte: MAX_FEE_SATOSHI_PER_VBYTE,
+        });
+        assert_eq!(custom_fee_per_byte(10), Ok(10_000));
+        assert_eq!(custom_fee_per_byte(0), out_of_bounds);
+        assert_eq!(
+            custom_fee_per_byte(MAX_FEE_SATOSHI_PER_VBYTE + 1),
+            out_of_bounds
+        );
+    }
+
+    #[test]
+    fn get_all_utxos_applies_min_confirmations() {
+        let bitcoin_api = StandInBitcoinApi::default();
+        bitcoin_api.set_tip_height(BtcNetwork::Mainnet, 100);
+        bitcoin_api.set_utxos(
+            BtcNetwork::Mainnet,
+            ADDRESS.to_string(),
+            vec![utxo(0, 90), utxo(1, 100)],
+        );
+
+        let get_all_utxos = |min_confirmations| {
+            block_on(get_all_utxos_from(
+                &bitcoin_api,
+                BtcNetwork::Mainnet,
+                ADDRESS.to_string(),
+                min_confirmations,
+                0,
+            ))
+        };
+
+        assert_eq!(get_all_utxos(None), Ok(vec![utxo(0, 90), utxo(1, 100)]));
+        assert_eq!(get_all_utxos(Some(1)), Ok(vec![utxo(0, 90), utxo(1, 100)]));
+        assert_eq!(get_all_utxos(Some(6)), Ok(vec![utxo(0, 90)]));
+    }
+
+    #[test]
+    fn min_confirmations_are_capped_by_the_network_policy() {
+        assert_eq!(max_min_confirmations(None, BtcNetwork::Regtest), Some(1));
+        assert_eq!(max_min_confirmations(None, BtcNetwork::Testnet), None);
+
+        let policies = [BtcNetworkPolicy {
+            network: BtcNetwork::Testnet,
+            max_min_confirmations: Some(2),
+            max_unconfirmed_chain_depth: None,
+        }];
+        assert_eq!(
+            max_min_confirmations(Some(&policies), BtcNetwork::Testnet),
+            Some(2)
+        );
+        // The policies of the other networks keep the default cap of regtest.
+        assert_eq!(
+            max_min_confirmations(Some(&policies), BtcNetwork::Regtest),
+            Some(1)
+        );
+        assert_eq!(
+            max_min_confirmations(Some(&policies), BtcNetwork::Mainnet),
+            None
+        );
+
+        let regtest_policies = [BtcNetworkPolicy {
+            network: BtcNetwork::Regtest,
+            max_min_confirmations: Some(3),
+            max_unconfirmed_chain_depth: None,
+        }];
+        assert_eq!(
+            max_min_confirmations(Some(&regtest_policies), BtcNetwork::Regtest),
+            Some(3)
+        );
+        let regtest_policies = [BtcNetworkPolicy {
+            max_min_confirmations: None,
+            max_unconfirmed_chain_depth: Some(1),
+            ..regtest_policies[0]
+        }];
+        assert_eq!(
+            max_min_confirmations(Some(&regtest_policies), BtcNetwork::Regtest),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn get_all_utxos_refetches_when_the_tip_changes() {
+        let bitcoin_api = StandInBitcoinApi::default();
+        bitcoin_api.set_utxos(BtcNetwork::Testnet, ADDRESS.to_string(), vec![utxo(0, 1)]);
+        let get_all_utxos = |now_ns| {
+            block_on(get_all_utxos_from(
+                &bitcoin_api,
+                BtcNetwork::Testnet,
+                ADDRESS.to_string(),
+                None,
+                now_ns,
+            ))
+        };
+        assert_eq!(get_all_utxos(0), Ok(vec![utxo(0, 1)]));
+
+        bitcoin_api.set_utxos(BtcNetwork::Testnet, ADDRESS.to_string(), vec![utxo(1, 2)]);
+        // Within the same tip, the cached UTXOs are served.
+        assert_eq!(get_all_utxos(MAX_UTXOS_AGE_NS), Ok(vec![utxo(0, 1)]));
+
+        // The new tip is only seen once the cached UTXOs are too old.
+        bitcoin_api.set_tip_height(BtcNetwork::Testnet, 2);
+        assert_eq!(
+            get_all_utxos(2 * MAX_UTXOS_AGE_NS - 1),
+            Ok(vec![utxo(0, 1)])
+        );
+        assert_eq!(get_all_utxos(2 * MAX_UTXOS_AGE_NS), Ok(vec![utxo(1, 2)]));
+    }
+
+    #[test]
+    fn get_all_utxos_follows_the_pages() {
+        let bitcoin_api = StandInBitcoinApi::default();
+        bitcoin_api.set_page_size(2);
+        bitcoin_api.set_tip_height(BtcNetwork::Regtest, 1);
+        let utxos: Vec<Utxo> = (0..5).map(|vout| utxo(vout, 1)).collect();
+        bitcoin_api.set_utxos(BtcNetwork::Regtest, ADDRESS.to_string(), utxos.clone());
+        let get_all_utxos = |min_confirmations| {
+            block_on(get_all_utxos_from(
+                &bitcoin_api,
+                BtcNetwork::Regtest,
+                ADDRESS.to_string(),
+                min_confirmations,
+                0,
+            ))
+        };
+
+        assert_eq!(get_all_utxos(None), Ok(utxos.clone()));
+        assert_eq!(get_all_utxos(Some(1)), Ok(utxos.clone()));
+        assert_eq!(get_all_utxos(Some(2)), Ok(vec![]));
+
+        // All the pages of the cached UTXOs are served while the tip does not change.
+        bitcoin_api.set_utxos(BtcNetwork::Regtest, ADDRESS.to_string(), vec![]);
+        assert_eq!(get_all_utxos(None), Ok(utxos.clone()));
+        assert_eq!(
+            block_on(get_all_utxos_from(
+                &bitcoin_api,
+                BtcNetwork::Regtest,
+                ADDRESS.to_string(),
+                None,
+                MAX_UTXOS_AGE_NS,
+            )),
+            Ok(utxos)
+        );
+    }
+
+    #[test]
+    fn fee_per_byte_follows_the_fee_percentiles() {
+        let bitcoin_api = StandInBitcoinApi::default();
+        let fee_per_byte = |fee_policy| {
+            block_on(get_fee_per_byte_for_policy_from(
+                &bitcoin_api,
+                BtcNetwork::Mainnet,
+                fee_policy,
+                BtcFeePercentiles::default(),
+            ))
+        };
+        // Without fee percentiles, the regtest default applies.
+        assert_eq!(fee_per_byte(BtcFeePolicy::Fast), Ok(2_000));
+
+        bitcoin_api.set_fee_percentiles(BtcNetwork::Mainnet, (0..100).map(|i| i * 1_000).collect());
+        assert_eq!(fee_per_byte(BtcFeePolicy::Slow), Ok(25_000));
+        assert_eq!(fee_per_byte(BtcFeePolicy::Fast), Ok(75_000));
+        assert_eq!(
+            fee_per_byte(BtcFeePolicy::Custom {
+                satoshi_per_vbyte: 3
+            }),
+            Ok(3_000)
+        );
     }
 }
@@ -0,0 +1,180 @@
+//! A programmable bitcoin API, for tests.
+//!
+//! Tests set the UTXOs, tip heights and fee percentiles that the stand-in serves. The canister
+//! built with the `bitcoin-api-stand-in` feature uses a stand-in that the controllers program
+//! through dedicated endpoints.
+use super::BitcoinApi;
+use crate::bitcoin_error::BtcError;
+use ic_cdk::api::management_canister::bitcoin::{
+    GetUtxosResponse, MillisatoshiPerByte, Outpoint, Utxo, UtxoFilter,
+};
+use shared::types::bitcoin::BtcNetwork;
+use std::{cell::RefCell, collections::BTreeMap, rc::Rc};
+
+#[cfg(feature = "bitcoin-api-stand-in")]
+thread_local! {
+    static CANISTER_STAND_IN: StandInBitcoinApi = StandInBitcoinApi::default();
+}
+
+/// The stand-in used by the canister built with the `bitcoin-api-stand-in` feature.
+#[cfg(feature = "bitcoin-api-stand-in")]
+pub fn canister_stand_in() -> StandInBitcoinApi {
+    CANISTER_STAND_IN.with(Clone::clone)
+}
+
+#[derive(Debug, Default)]
+struct StandInState {
+    tip_heights: BTreeMap<BtcNetwork, u32>,
+    utxos: BTreeMap<(BtcNetwork, String), Vec<Utxo>>,
+    /// The heights of the blocks spending UTXOs, by network, txid and vout.
+    spent_heights: BTreeMap<(BtcNetwork, Vec<u8>, u32), u32>,
+    fee_percentiles: BTreeMap<BtcNetwork, Vec<MillisatoshiPerByte>>,
+    page_size: Option<usize>,
+}
+
+/// A bitcoin API that serves programmed data.
+///
+/// Clones share the same data. The UTXOs of an address are served in a single page unless a page
+/// size is set, and any transaction is accepted.
+#[derive(Clone, Debug, Default)]
+pub struct StandInBitcoinApi {
+    state: Rc<RefCell<StandInState>>,
+}
+
+impl StandInBitcoinApi {
+    /// Sets the height of the tip of the network, 0 by default.
+    pub fn set_tip_height(&self, network: BtcNetwork, tip_height: u32) {
+        self.state
+            .borrow_mut()
+            .tip_heights
+            .insert(network, tip_height);
+    }
+
+    /// Sets the UTXOs of the address, none by default.
+    ///
+    /// The confirmations of a UTXO are counted from its height to the tip height.
+    pub fn set_utxos(&self, network: BtcNetwork, address: String, utxos: Vec<Utxo>) {
+        self.state
+            .borrow_mut()
+            .utxos
+            .insert((network, address), utxos);
+    }
+
+    /// Marks a UTXO as spent by a transaction of the block at the given height.
+    ///
+    /// Like the bitcoin canister, which serves the UTXO set of the block that has the required
+    /// confirmations, the UTXO is still served to requests of more confirmations than the
+    /// spending block has.
+    pub fn set_spent_height(&self, network: BtcNetwork, outpoint: Outpoint, height: u32) {
+        self.state
+            .borrow_mut()
+            .spent_heights
+            .insert((network, outpoint.txid, outpoint.vout), height);
+    }
+
+    /// Sets the number of UTXOs served per page, unlimited by default.
+    pub fn set_page_size(&self, page_size: usize) {
+        self.state.borrow_mut().page_size = Some(page_size);
+    }
+
+    /// Sets the fee percentiles of the network, none by default.
+    pub fn set_fee_percentiles(
+        &self,
+        network: BtcNetwork,
+        fee_percentiles: Vec<MillisatoshiPerByte>,
+    ) {
+        self.state
+            .borrow_mut()
+            .fee_percentiles
+            .insert(network, fee_percentiles);
+    }
+}
+
+/// The hash of the tip of a stand-in network, which only depends on its height.
+fn tip_block_hash(tip_height: u32) -> Vec<u8> {
+    let mut hash = vec![0; 32];
+    hash[28..].copy_from_slice(&tip_height.to_be_bytes());
+    hash
+}
+
+/// The page token of the stand-in: the confirmations required from the UTXOs, and the index of
+/// the first UTXO of the page.
+fn encode_page(min_confirmations: u32, offset: usize) -> Vec<u8> {
+    let offset = u32::try_from(offset).unwrap_or(u32::MAX);
+    [min_confirmations.to_be_bytes(), offset.to_be_bytes()].concat()
+}
+
+fn decode_page(page: &[u8]) -> Result<(u32, usize), BtcError> {
+    let malformed_page = || BtcError::InternalError {
+        msg: "Malformed page of the stand-in bitcoin API".to_string(),
+    };
+    let (min_confirmations, offset) = page.split_at_checked(4).ok_or_else(malformed_page)?;
+    let min_confirmations =
+        u32::from_be_bytes(min_confirmations.try_into().map_err(|_| malformed_page())?);
+    let offset = u32::from_be_bytes(offset.try_into().map_err(|_| malformed_page())?);
+    Ok((min_confirmations, offset as usize))
+}
+
+impl BitcoinApi for StandInBitcoinApi {
+    async fn get_utxos(
+        &self,
+        network: BtcNetwork,
+        address: String,
+        filter: Option<UtxoFilter>,
+    ) -> Result<GetUtxosResponse, BtcError> {
+        let state = self.state.borrow();
+        let tip_height = state.tip_heights.get(&network).copied().unwrap_or_default();
+        let (min_confirmations, offset) = match filter {
+            None => (0, 0),
+            Some(UtxoFilter::MinConfirmations(min_confirmations)) => (min_confirmations, 0),
+            Some(UtxoFilter::Page(page)) => decode_page(&page)?,
+        };
+        let utxos: Vec<Utxo> = state
+            .utxos
+            .get(&(network, address))
+            .into_iter()
+            .flatten()
+            .filter(|utxo| (tip_height + 1).saturating_sub(utxo.height) >= min_confirmations)
+            .filter(|utxo| {
+                state
+                    .spent_heights
+                    .get(&(network, utxo.outpoint.txid.clone(), utxo.outpoint.vout))
+                    .map_or(true, |&spent_height| {
+                        (tip_height + 1).saturating_sub(spent_height) < min_confirmations
+                    })
+            })
+            .skip(offset)
+            .cloned()
+            .collect();
+        let page_size = state.page_size.unwrap_or(usize::MAX);
+        let next_page =
+            (utxos.len() > page_size).then(|| encode_page(min_confirmations, offset + page_size));
+        Ok(GetUtxosResponse {
+            utxos: utxos.into_iter().take(page_size).collect(),
+            tip_block_hash: tip_block_hash(tip_height),
+            tip_height,
+            next_page,
+        })
+    }
+
+    async fn get_current_fee_percentiles(
+        &self,
+        network: BtcNetwork,
+    ) -> Result<Vec<MillisatoshiPerByte>, BtcError> {
+        Ok(self
+            .state
+            .borrow()
+            .fee_percentiles
+            .get(&network)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn send_transaction(
+        &self,
+        _network: BtcNetwork,
+        _transaction: Vec<u8>,
+    ) -> Result<(), BtcError> {
+        Ok(())
+    }
+}
@@ -0,0 +1,186 @@
+//! The failures of the calls made by the bitcoin endpoints.
+//!
+//! The chain fusion signer, the bitcoin API and the config fail with a `BtcError`, which every
+//! endpoint converts to a variant of its own error, so that the frontend can tell them apart.
+//! `InternalError` remains for the failures that the frontend can't act on.
+//!
+//! The endpoints that predate these errors keep their first error types, in which every new
+//! failure is an `InternalError`, so that their clients keep decoding them. Their `_v2` versions
+//! return the structured errors.
+use ic_cdk::api::call::RejectionCode;
+use shared::types::bitcoin::{
+    BtcAddPendingTransactionError, BtcAddPendingTransactionErrorV2,
+    BtcBuildUnsignedTransactionError, BtcBumpFeeError, BtcGetAddressError, BtcGetBalanceError,
+    BtcGetPendingTransactionsError, SelectedUtxosFeeError, SelectedUtxosFeeErrorV2,
+};
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum BtcError {
+    /// The chain fusion signer, or the threshold key it derives from, could not be reached.
+    SignerUnavailable {
+        msg: String,
+    },
+    /// The bitcoin API rejected the call.
+    BitcoinApiRejected {
+        code: u32,
+        msg: String,
+    },
+    /// The config of the canister does not allow the call.
+    Misconfigured {
+        msg: String,
+    },
+    InternalError {
+        msg: String,
+    },
+}
+
+impl BtcError {
+    /// The error of a call to the bitcoin API that was rejected.
+    #[must_use]
+    pub fn bitcoin_api_rejected((code, msg): (RejectionCode, String)) -> Self {
+        Self::BitcoinApiRejected {
+            code: code as u32,
+            msg,
+        }
+    }
+}
+
+/// The failures of `bitcoin_api::get_fee_per_byte_for_policy`.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum BtcFeeError {
+    /// The explicit fee rate of `BtcFeePolicy::Custom` is out of the accepted bounds.
+    FeeRateOutOfBounds {
+        min_satoshi_per_vbyte: u64,
+        max_satoshi_per_vbyte: u64,
+    },
+    Btc(BtcError),
+}
+
+impl From<BtcError> for BtcFeeError {
+    fn from(err: BtcError) -> Self {
+        Self::Btc(err)
+    }
+}
+
+/// Converts a `BtcError` to the variants with the same name of the error of an endpoint.
+macro_rules! impl_from_btc_error {
+    ($($error:ident),*) => {
+        $(
+            impl From<BtcError> for $error {
+                fn from(err: BtcError) -> Self {
+                    match err {
+                        BtcError::SignerUnavailable { msg } => Self::SignerUnavailable { msg },
+                        BtcError::BitcoinApiRejected { code, msg } => {
+                            Self::BitcoinApiRejected { code, msg }
+                        }
+                        BtcError::Misconfigured { msg } => Self::Misconfigured { msg },
+                        BtcError::InternalError { msg } => Self::InternalError { msg },
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_from_btc_error!(
+    BtcGetBalanceError,
+    SelectedUtxosFeeErrorV2,
+    BtcBuildUnsignedTransactionError,
+    BtcBumpFeeError,
+    BtcAddPendingTransactionErrorV2,
+    BtcGetPendingTransactionsError
+);
+
+/// Converts a `BtcFeeError` for the endpoints that choose a fee rate.
+macro_rules! impl_from_btc_fee_error {
+    ($($error:ident),*) => {
+        $(
+            impl From<BtcFeeError> for $error {
+                fn from(err: BtcFeeError) -> Self {
+                    match err {
+                        BtcFeeError::FeeRateOutOfBounds {
+                            min_satoshi_per_vbyte,
+                            max_satoshi_per_vbyte,
+                        } => Self::FeeRateOutOfBounds {
+                            min_satoshi_per_vbyte,
+                            max_satoshi_per_vbyte,
+                        },
+                        BtcFeeError::Btc(err) => err.into(),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_from_btc_fee_error!(SelectedUtxosFeeErrorV2, BtcBumpFeeError);
+
+/// Building a transaction fails like the selection of its UTXOs.
+impl From<SelectedUtxosFeeErrorV2> for BtcBuildUnsignedTransactionError {
+    fn from(err: SelectedUtxosFeeErrorV2) -> Self {
+        match err {
+            SelectedUtxosFeeErrorV2::InternalError { msg } => Self::InternalError { msg },
+            SelectedUtxosFeeErrorV2::PendingTransactions => Self::PendingTransactions,
+            SelectedUtxosFeeErrorV2::InsufficientFunds => Self::InsufficientFunds,
+            SelectedUtxosFeeErrorV2::InvalidAddress(err) => Self::InvalidAddress(err),
+            SelectedUtxosFeeErrorV2::InvalidInput(err) => Self::InvalidInput(err),
+            SelectedUtxosFeeErrorV2::InvalidOutput(err) => Self::InvalidOutput(err),
+            SelectedUtxosFeeErrorV2::FeeRateOutOfBounds {
+                min_satoshi_per_vbyte,
+                max_satoshi_per_vbyte,
+            } => Self::FeeRateOutOfBounds {
+                min_satoshi_per_vbyte,
+                max_satoshi_per_vbyte,
+            },
+            SelectedUtxosFeeErrorV2::SignerUnavailable { msg } => Self::SignerUnavailable { msg },
+            SelectedUtxosFeeErrorV2::BitcoinApiRejected { code, msg } => {
+                Self::BitcoinApiRejected { code, msg }
+            }
+            SelectedUtxosFeeErrorV2::Misconfigured { msg } => Self::Misconfigured { msg },
+            SelectedUtxosFeeErrorV2::UnsupportedAddressType => Self::UnsupportedAddressType,
+        }
+    }
+}
+
+/// The first version of `btc_select_user_utxos_fee` only tells pending transactions apart.
+impl From<SelectedUtxosFeeErrorV2> for SelectedUtxosFeeError {
+    fn from(err: SelectedUtxosFeeErrorV2) -> Self {
+        match err {
+            SelectedUtxosFeeErrorV2::PendingTransactions => Self::PendingTransactions,
+            err => Self::InternalError {
+                msg: format!("{err:?}"),
+            },
+        }
+    }
+}
+
+impl From<BtcAddPendingTransactionErrorV2> for BtcAddPendingTransactionError {
+    fn from(err: BtcAddPendingTransactionErrorV2) -> Self {
+        Self::InternalError {
+            msg: format!("{err:?}"),
+        }
+    }
+}
+
+/// The first version of `btc_get_pending_transactions` fails with the error of
+/// `btc_add_pending_transaction`.
+impl From<BtcGetPendingTransactionsError> for BtcAddPendingTransactionError {
+    fn from(err: BtcGetPendingTransactionsError) -> Self {
+        Self::InternalError {
+            msg: format!("{err:?}"),
+        }
+    }
+}
+
+/// Deriving an address only calls the chain fusion signer.
+impl From<BtcError> for BtcGetAddressError {
+    fn from(err: BtcError) -> Self {
+        match err {
+            BtcError::SignerUnavailable { msg } => Self::SignerUnavailable { msg },
+            BtcError::Misconfigured { msg } => Self::Misconfigured { msg },
+            BtcError::BitcoinApiRejected { msg, .. } | BtcError::InternalError { msg } => {
+                Self::InternalError { msg }
+            }
+        }
+    }
+}
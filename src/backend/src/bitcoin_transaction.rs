@@ -0,0 +1,294 @@
+//! Code for building unsigned bitcoin transactions.
+use bitcoin::{
+    absolute::LockTime, hashes::Hash, key::XOnlyPublicKey, psbt::Psbt, transaction::Version,
+    Address, Amount, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, Witness,
+};
+use ic_cdk::api::management_canister::bitcoin::Utxo;
+
+/// An address whose UTXOs are spent by a transaction.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SourceAddress {
+    pub address: Address,
+    /// The internal key of the address if it is a taproot address.
+    pub tap_internal_key: Option<XOnlyPublicKey>,
+}
+
+/// Builds a PSBT (BIP-174) for an unsigned transaction that spends the given UTXOs, each of its
+/// source address, pays the given amounts to the destination addresses and sends the change to
+/// `change_address`.
+///
+/// Every input signals replaceability (BIP-125) and carries the output it spends, which is
+/// what a signer needs to compute the segwit signature hash. Inputs of taproot addresses also
+/// carry the internal key of their address.
+pub fn build_unsigned_psbt(
+    inputs: &[(&Utxo, &SourceAddress)],
+    change_address: &Address,
+    outputs: &[(Address, u64)],
+    fee_satoshis: u64,
+) -> Result<Psbt, String> {
+    let total_satoshis: u64 = inputs.iter().map(|(utxo, _)| utxo.value).sum();
+    let amount_satoshis = outputs
+        .iter()
+        .try_fold(0_u64, |sum, (_, amount)| sum.checked_add(*amount))
+        .ok_or("The amount of the outputs overflows")?;
+    let change_satoshis = total_satoshis
+        .checked_sub(amount_satoshis)
+        .and_then(|remaining| remaining.checked_sub(fee_satoshis))
+        .ok_or_else(|| {
+            format!(
+                "Insufficient funds: {total_satoshis} satoshis available, {} required",
+                amount_satoshis.saturating_add(fee_satoshis)
+            )
+        })?;
+
+    let input = inputs
+        .iter()
+        .map(|(utxo, _)| {
+            let txid = Txid::from_slice(&utxo.outpoint.txid)
+                .map_err(|err| format!("Invalid UTXO transaction id: {err}"))?;
+            Ok(TxIn {
+                previous_output: OutPoint::new(txid, utxo.outpoint.vout),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Witness::new(),
+            })
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    let mut output: Vec<TxOut> = outputs
+        .iter()
+        .map(|(address, amount)| TxOut {
+            value: Amount::from_sat(*amount),
+            script_pubkey: address.script_pubkey(),
+        })
+        .collect();
+    if change_satoshis > 0 {
+        output.push(TxOut {
+            value: Amount::from_sat(change_satoshis),
+            script_pubkey: change_address.script_pubkey(),
+        });
+    }
+
+    let transaction = Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input,
+        output,
+    };
+
+    let mut psbt = Psbt::from_unsigned_tx(transaction)
+        .map_err(|err| format!("Failed to create PSBT: {err}"))?;
+    for (psbt_input, (utxo, source)) in psbt.inputs.iter_mut().zip(inputs) {
+        psbt_input.witness_utxo = Some(TxOut {
+            value: Amount::from_sat(utxo.value),
+            script_pubkey: source.address.script_pubkey(),
+        });
+        psbt_input.tap_internal_key = source.tap_internal_key;
+    }
+
+    Ok(psbt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin_address::parse_address;
+    use ic_cdk::api::management_canister::bitcoin::Outpoint;
+    use shared::types::bitcoin::BtcNetwork;
+    use std::str::FromStr;
+
+    const SOURCE_ADDRESS: &str = "bcrt1qpg7udjvq7gx2fp480pgt4hnhj3qc4nhrkstc33";
+    const DESTINATION_ADDRESS: &str = "bcrt1q0ht9tyks4vh7p5p904t340cr9nvahy7uevmqwj";
+
+    fn utxo(txid_byte: u8, vout: u32, value: u64) -> Utxo {
+        Utxo {
+            outpoint: Outpoint {
+                txid: vec![txid_byte; 32],
+                vout,
+            },
+            value,
+            height: 100,
+        }
+    }
+
+    fn addresses() -> (Address, Address) {
+        (
+            parse_address(SOURCE_ADDRESS, BtcNetwork::Regtest).unwrap(),
+            parse_address(DESTINATION_ADDRESS, BtcNetwork::Regtest).unwrap(),
+        )
+    }
+
+    fn segwit_source(address: &Address) -> SourceAddress {
+        SourceAddress {
+            address: address.clone(),
+            tap_internal_key: None,
+        }
+    }
+
+    fn inputs<'a>(
+        utxos: &'a [Utxo],
+        source: &'a SourceAddress,
+    ) -> Vec<(&'a Utxo, &'a SourceAddress)> {
+        utxos.iter().map(|utxo| (utxo, source)).collect()
+    }
+
+    #[test]
+    fn build_unsigned_psbt_sends_change_back_to_source() {
+        let (source, destination) = addresses();
+        let utxos = vec![utxo(1, 0, 60_000), utxo(2, 3, 50_000)];
+
+        let psbt = build_unsigned_psbt(
+            &inputs(&utxos, &segwit_source(&source)),
+            &source,
+            &[(destination.clone(), 100_000)],
+            1_000,
+        )
+        .unwrap();
+
+        let tx = &psbt.unsigned_tx;
+        assert_eq!(tx.input.len(), 2);
+        assert_eq!(tx.input[1].previous_output.vout, 3);
+        assert!(tx.input.iter().all(|input| input.sequence.is_rbf()));
+        assert_eq!(tx.output.len(), 2);
+        assert_eq!(tx.output[0].value.to_sat(), 100_000);
+        assert_eq!(tx.output[0].script_pubkey, destination.script_pubkey());
+        assert_eq!(tx.output[1].value.to_sat(), 9_000);
+        assert_eq!(tx.output[1].script_pubkey, source.script_pubkey());
+        assert_eq!(psbt.fee().unwrap().to_sat(), 1_000);
+    }
+
+    #[test]
+    fn build_unsigned_psbt_omits_empty_change() {
+        let (source, destination) = addresses();
+        let utxos = vec![utxo(1, 0, 101_000)];
+
+        let psbt = build_unsigned_psbt(
+            &inputs(&utxos, &segwit_source(&source)),
+            &source,
+            &[(destination, 100_000)],
+            1_000,
+        )
+        .unwrap();
+
+        assert_eq!(psbt.unsigned_tx.output.len(), 1);
+    }
+
+    #[test]
+    fn build_unsigned_psbt_pays_all_outputs() {
+        let (source, destination) = addresses();
+        let utxos = vec![utxo(1, 0, 200_000)];
+
+        let psbt = build_unsigned_psbt(
+            &inputs(&utxos, &segwit_source(&source)),
+            &source,
+            &[(destination.clone(), 100_000), (source.clone(), 50_000)],
+            1_000,
+        )
+        .unwrap();
+
+        let tx = &psbt.unsigned_tx;
+        assert_eq!(tx.output.len(), 3);
+        assert_eq!(tx.output[0].value.to_sat(), 100_000);
+        assert_eq!(tx.output[0].script_pubkey, destination.script_pubkey());
+        assert_eq!(tx.output[1].value.to_sat(), 50_000);
+        assert_eq!(tx.output[2].value.to_sat(), 49_000);
+        assert_eq!(psbt.fee().unwrap().to_sat(), 1_000);
+    }
+
+    #[test]
+    fn build_unsigned_psbt_fails_if_funds_are_insufficient() {
+        let (source, destination) = addresses();
+        let utxos = vec![utxo(1, 0, 100_500)];
+
+        assert!(build_unsigned_psbt(
+            &inputs(&utxos, &segwit_source(&source)),
+            &source,
+            &[(destination, 100_000)],
+            1_000
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn build_unsigned_psbt_serialization_roundtrips() {
+        let (source, destination) = addresses();
+        let utxos = vec![utxo(1, 0, 200_000)];
+
+        let psbt = build_unsigned_psbt(
+            &inputs(&utxos, &segwit_source(&source)),
+            &source,
+            &[(destination, 100_000)],
+            1_000,
+        )
+        .unwrap();
+        let bytes = psbt.serialize();
+
+        assert_eq!(Psbt::deserialize(&bytes).unwrap(), psbt);
+    }
+
+    #[test]
+    fn build_unsigned_psbt_sets_internal_key_of_taproot_inputs() {
+        let source = parse_address(
+            "bcrt1p5cyxnuxmeuwuvkwfem96lqzszd02n6xdcjrs20cac6yqjjwudpxqvg32hk",
+            BtcNetwork::Regtest,
+        )
+        .unwrap();
+        let (_, destination) = addresses();
+        let internal_key = XOnlyPublicKey::from_str(
+            "cc8a4bc64d897bddc5fbc2f670f7a8ba0b386779106cf1223c6fc5d7cd6fc115",
+        )
+        .unwrap();
+        let utxos = vec![utxo(1, 0, 60_000), utxo(2, 1, 50_000)];
+
+        let taproot_source = SourceAddress {
+            address: source.clone(),
+            tap_internal_key: Some(internal_key),
+        };
+
+        let psbt = build_unsigned_psbt(
+            &inputs(&utxos, &taproot_source),
+            &source,
+            &[(destination, 100_000)],
+            1_000,
+        )
+        .unwrap();
+
+        for input in &psbt.inputs {
+            assert_eq!(input.tap_internal_key, Some(internal_key));
+            assert_eq!(
+                input.witness_utxo.as_ref().unwrap().script_pubkey,
+                source.script_pubkey()
+            );
+        }
+    }
+
+    #[test]
+    fn build_unsigned_psbt_spends_utxos_of_several_addresses() {
+        let (source, destination) = addresses();
+        let other_source = segwit_source(&destination);
+        let source = segwit_source(&source);
+        let utxos = [utxo(1, 0, 60_000), utxo(2, 1, 50_000)];
+
+        let psbt = build_unsigned_psbt(
+            &[(&utxos[0], &source), (&utxos[1], &other_source)],
+            &source.address,
+            &[(destination.clone(), 100_000)],
+            1_000,
+        )
+        .unwrap();
+
+        let witness_scripts: Vec<_> = psbt
+            .inputs
+            .iter()
+            .map(|input| input.witness_utxo.as_ref().unwrap().script_pubkey.clone())
+            .collect();
+        assert_eq!(
+            witness_scripts,
+            vec![source.address.script_pubkey(), destination.script_pubkey()]
+        );
+        assert_eq!(
+            psbt.unsigned_tx.output[1].script_pubkey,
+            source.address.script_pubkey()
+        );
+    }
+}
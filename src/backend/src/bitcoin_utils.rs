@@ -1,4 +1,6 @@
-use ic_cdk::api::management_canister::bitcoin::Utxo;
+use bitcoin::{consensus::encode::VarInt, Address, AddressType, Script};
+use ic_cdk::api::management_canister::bitcoin::{Outpoint, Utxo};
+use shared::types::bitcoin::BtcGetBalanceResponse;
 
 /// Functions [inspired by ckBTC Minter](https://github.com/dfinity/ic/blob/285a5db07da50a4e350ec43bf3b488cc6fe36102/rs/bitcoin/ckbtc/minter/src/lib.rs#L1258)
 
@@ -86,35 +88,176 @@ pub fn utxos_selection(
     input_utxos
 }
 
-/// Estimates the size of transaction (in vbytes) with the given number of inputs and outputs.
 // See [MediaWiki](https://github.com/bitcoin/bips/blob/master/bip-0141.mediawiki)
 // for the transaction structure and
 // [Stack Exchange](https://bitcoin.stackexchange.com/questions/92587/calculate-transaction-fee-for-external-addresses-which-doesnt-belong-to-my-loca/92600#92600)
 // for transaction size estimate.
-const INPUT_SIZE_VBYTES: u64 = 68;
-const OUTPUT_SIZE_VBYTES: u64 = 31;
-const TX_OVERHEAD_VBYTES: u64 = 11;
-fn tx_vsize_estimate(input_count: u64, output_count: u64) -> u64 {
-    input_count * INPUT_SIZE_VBYTES + output_count * OUTPUT_SIZE_VBYTES + TX_OVERHEAD_VBYTES
+//
+// The weights of the inputs include their signatures, assumed to be at most 72 bytes long for
+// ECDSA (low S, with the sighash flag) and 64 bytes long for Schnorr (default sighash).
+const P2PKH_INPUT_WEIGHT: u64 = 592;
+/// P2SH inputs are assumed to wrap a P2WPKH script.
+const P2SH_P2WPKH_INPUT_WEIGHT: u64 = 364;
+const P2WPKH_INPUT_WEIGHT: u64 = 272;
+/// P2WSH inputs are assumed to spend a 2-of-3 multisig script.
+const P2WSH_INPUT_WEIGHT: u64 = 418;
+/// P2TR inputs are assumed to be spent with the key path.
+const P2TR_INPUT_WEIGHT: u64 = 230;
+pub const P2WPKH_OUTPUT_SIZE_VBYTES: u64 = 31;
+/// Version and lock time.
+const TX_OVERHEAD_WEIGHT: u64 = 32;
+/// Marker and flag of the transactions with witnesses.
+const SEGWIT_OVERHEAD_WEIGHT: u64 = 2;
+const WITNESS_SCALE_FACTOR: u64 = 4;
+
+/// Returns the weight of an input spending an output of the given address.
+fn input_weight(address: &Address) -> u64 {
+    match address.address_type() {
+        Some(AddressType::P2pkh) => P2PKH_INPUT_WEIGHT,
+        Some(AddressType::P2sh) => P2SH_P2WPKH_INPUT_WEIGHT,
+        Some(AddressType::P2wpkh) => P2WPKH_INPUT_WEIGHT,
+        Some(AddressType::P2tr) => P2TR_INPUT_WEIGHT,
+        // Other witness programs can't be sized without knowing how they are spent,
+        // so we assume the largest of the ones we know.
+        _ => P2WSH_INPUT_WEIGHT,
+    }
+}
+
+/// Returns the fee, in millisatoshi, of an input spending an output of the given address.
+pub fn input_fee_millisatoshi(address: &Address, fee_millisatoshi_per_vbyte: u64) -> u64 {
+    input_weight(address) * fee_millisatoshi_per_vbyte / WITNESS_SCALE_FACTOR
+}
+
+/// Returns the size, in vbytes, of an output locked by the given script.
+///
+/// An output is made of its value (8 bytes) and its length-prefixed script.
+pub fn output_vsize(script_pubkey: &Script) -> u64 {
+    let script_len = script_pubkey.len() as u64;
+    8 + VarInt(script_len).size() as u64 + script_len
+}
+
+/// Estimates the size of transaction (in vbytes) spending the given number of UTXOs of
+/// `source_address` to outputs of the given sizes.
+pub fn tx_vsize_estimate(source_address: &Address, input_count: u64, output_vsizes: &[u64]) -> u64 {
+    let segwit_overhead_weight = if address_type_is_segwit(source_address) {
+        SEGWIT_OVERHEAD_WEIGHT
+    } else {
+        0
+    };
+    let counts_weight = (VarInt(input_count).size() + VarInt(output_vsizes.len() as u64).size())
+        as u64
+        * WITNESS_SCALE_FACTOR;
+    let weight = TX_OVERHEAD_WEIGHT
+        + segwit_overhead_weight
+        + counts_weight
+        + input_count * input_weight(source_address)
+        + output_vsizes.iter().sum::<u64>() * WITNESS_SCALE_FACTOR;
+    weight.div_ceil(WITNESS_SCALE_FACTOR)
+}
+
+fn address_type_is_segwit(address: &Address) -> bool {
+    !matches!(address.address_type(), Some(AddressType::P2pkh))
 }
 
-/// Estimates the transaction fee, in satoshi, based on the number of utxos and outputs
+/// Estimates the transaction fee, in satoshi, based on the utxos and the outputs
 ///
 /// Arguments:
+///   * `source_address` - the address of the UTXOs used for the transaction.
 ///   * `selected_utxos_count` - the number of UTXOs used for the transaction.
 ///   * `median_fee_millisatoshi_per_vbyte` - the median network fee, in millisatoshi per vbyte.
-///   * `output_count` - the number of outputs of the bitcoin transaction.
+///   * `output_vsizes` - the sizes, in vbytes, of the outputs of the bitcoin transaction.
 pub fn estimate_fee(
+    source_address: &Address,
     selected_utxos_count: u64,
     median_fee_millisatoshi_per_vbyte: u64,
-    output_count: u64,
+    output_vsizes: &[u64],
 ) -> u64 {
-    tx_vsize_estimate(selected_utxos_count, output_count) * median_fee_millisatoshi_per_vbyte / 1000
+    tx_vsize_estimate(source_address, selected_utxos_count, output_vsizes)
+        * median_fee_millisatoshi_per_vbyte
+        / 1000
+}
+
+/// Selects UTXOs covering `amount` as well as the fee of the transaction spending them.
+///
+/// Returns the selected UTXOs and the fee in satoshi, or an empty vector and a fee of 0 if the
+/// available UTXOs cannot cover both.
+///
+/// POSTCONDITION: `!solution.is_empty() ⇒ sum(u.value for u in solution) ≥ amount + fee`
+pub fn utxos_selection_with_fee(
+    amount: u64,
+    available_utxos: &[Utxo],
+    source_address: &Address,
+    fee_millisatoshi_per_vbyte: u64,
+    output_vsizes: &[u64],
+) -> (Vec<Utxo>, u64) {
+    let mut fee_satoshis = 0;
+    loop {
+        let mut remaining_utxos = available_utxos.to_vec();
+        let selected_utxos = utxos_selection(
+            amount.saturating_add(fee_satoshis),
+            &mut remaining_utxos,
+            output_vsizes.len(),
+        );
+        if selected_utxos.is_empty() {
+            return (vec![], 0);
+        }
+
+        let selected_fee_satoshis = estimate_fee(
+            source_address,
+            selected_utxos.len() as u64,
+            fee_millisatoshi_per_vbyte,
+            output_vsizes,
+        );
+        let selected_satoshis: u64 = selected_utxos.iter().map(|u| u.value).sum();
+        if selected_satoshis >= amount.saturating_add(selected_fee_satoshis) {
+            return (selected_utxos, selected_fee_satoshis);
+        }
+        // The selection covers the previous fee target but not its own fee, which is therefore
+        // strictly higher: try again with the higher target.
+        fee_satoshis = selected_fee_satoshis;
+    }
+}
+
+fn total_value<'a>(utxos: impl Iterator<Item = &'a Utxo>) -> u64 {
+    utxos.map(|u| u.value).sum()
+}
+
+/// Computes the balance of an address from all its UTXOs, the UTXOs with enough confirmations,
+/// the UTXOs spent by its pending transactions and the outpoints of its frozen UTXOs.
+pub fn balance(
+    all_utxos: &[Utxo],
+    confirmed_utxos: &[Utxo],
+    pending_utxos: &[Utxo],
+    frozen_outpoints: &[Outpoint],
+) -> BtcGetBalanceResponse {
+    let is_locked = |u: &&Utxo| pending_utxos.contains(u) || frozen_outpoints.contains(&u.outpoint);
+    BtcGetBalanceResponse {
+        confirmed_satoshis: total_value(confirmed_utxos.iter()),
+        unconfirmed_satoshis: total_value(
+            all_utxos.iter().filter(|u| !confirmed_utxos.contains(u)),
+        ),
+        locked_satoshis: total_value(all_utxos.iter().filter(is_locked)),
+        spendable_satoshis: total_value(confirmed_utxos.iter().filter(|u| !is_locked(u))),
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use bitcoin::{
+        absolute::LockTime,
+        ecdsa,
+        hashes::Hash,
+        opcodes::all::OP_CHECKMULTISIG,
+        script::{Builder, PushBytes},
+        secp256k1::{Keypair, Message, Secp256k1, SecretKey},
+        taproot,
+        transaction::Version,
+        Amount, CompressedPublicKey, EcdsaSighashType, Network, OutPoint, PubkeyHash, PublicKey,
+        ScriptBuf, Sequence, TapSighashType, Transaction, TxIn, TxOut, WPubkeyHash, WScriptHash,
+        Witness,
+    };
     use ic_cdk::api::management_canister::bitcoin::Outpoint;
+    use proptest::prelude::*;
 
     // Import the outer scope
     use super::*;
@@ -241,18 +384,395 @@ mod tests {
 
     #[test]
     fn estimate_fee_returns_overhead_if_no_input_nor_output() {
-        assert_eq!(estimate_fee(0, 1000, 0), TX_OVERHEAD_VBYTES);
+        assert_eq!(estimate_fee(&p2wpkh_address(), 0, 1000, &[]), 11);
     }
 
     #[test]
     fn estimate_fee_incrases_per_input_count() {
-        assert_eq!(estimate_fee(2, 1000, 2), 209);
-        assert_eq!(estimate_fee(4, 1000, 2), 345);
+        let address = p2wpkh_address();
+        assert_eq!(
+            estimate_fee(&address, 2, 1000, &[P2WPKH_OUTPUT_SIZE_VBYTES; 2]),
+            209
+        );
+        assert_eq!(
+            estimate_fee(&address, 4, 1000, &[P2WPKH_OUTPUT_SIZE_VBYTES; 2]),
+            345
+        );
     }
 
     #[test]
     fn estimate_fee_incrases_per_output_count() {
-        assert_eq!(estimate_fee(2, 1000, 2), 209);
-        assert_eq!(estimate_fee(2, 1000, 4), 271);
+        let address = p2wpkh_address();
+        assert_eq!(
+            estimate_fee(&address, 2, 1000, &[P2WPKH_OUTPUT_SIZE_VBYTES; 2]),
+            209
+        );
+        assert_eq!(
+            estimate_fee(&address, 2, 1000, &[P2WPKH_OUTPUT_SIZE_VBYTES; 4]),
+            271
+        );
+    }
+
+    #[test]
+    fn estimate_fee_depends_on_output_type() {
+        let p2wpkh_script = ScriptBuf::new_p2wpkh(&WPubkeyHash::all_zeros());
+        let p2wsh_script = ScriptBuf::new_p2wsh(&WScriptHash::all_zeros());
+        let p2pkh_script = ScriptBuf::new_p2pkh(&PubkeyHash::all_zeros());
+        assert_eq!(output_vsize(&p2wpkh_script), P2WPKH_OUTPUT_SIZE_VBYTES);
+        assert_eq!(output_vsize(&p2wsh_script), 43);
+        assert_eq!(output_vsize(&p2pkh_script), 34);
+        assert_eq!(
+            estimate_fee(
+                &p2wpkh_address(),
+                2,
+                1000,
+                &[
+                    output_vsize(&p2wsh_script),
+                    output_vsize(&p2pkh_script),
+                    output_vsize(&p2wpkh_script)
+                ]
+            ),
+            255
+        );
+    }
+
+    const TWO_P2WPKH_OUTPUTS: [u64; 2] = [P2WPKH_OUTPUT_SIZE_VBYTES; 2];
+
+    fn utxo(vout: u32, value: u64) -> Utxo {
+        Utxo {
+            outpoint: Outpoint {
+                txid: Vec::new(),
+                vout,
+            },
+            value,
+            height: 32u32,
+        }
+    }
+
+    #[test]
+    fn utxos_selection_with_fee_covers_the_fee() {
+        // 100 satoshi fit in the first UTXO, but not with the fee of 141 satoshi.
+        let available_utxos = vec![utxo(0, 150), utxo(1, 300)];
+        let (selected_utxos, fee) = utxos_selection_with_fee(
+            100,
+            &available_utxos,
+            &p2wpkh_address(),
+            1000,
+            &TWO_P2WPKH_OUTPUTS,
+        );
+        assert_eq!(fee, 141);
+        assert_utxos_eq(selected_utxos, vec![utxo(1, 300)]);
+    }
+
+    #[test]
+    fn utxos_selection_with_fee_adds_inputs_for_the_fee() {
+        let available_utxos = vec![utxo(0, 150), utxo(1, 200)];
+        let (selected_utxos, fee) = utxos_selection_with_fee(
+            100,
+            &available_utxos,
+            &p2wpkh_address(),
+            1000,
+            &TWO_P2WPKH_OUTPUTS,
+        );
+        assert_eq!(fee, 209);
+        assert_utxos_eq(selected_utxos, vec![utxo(1, 200), utxo(0, 150)]);
+    }
+
+    #[test]
+    fn utxos_selection_with_fee_returns_empty_vector_if_fee_is_not_covered() {
+        let available_utxos = vec![utxo(0, 150), utxo(1, 150)];
+        let (selected_utxos, fee) = utxos_selection_with_fee(
+            100,
+            &available_utxos,
+            &p2wpkh_address(),
+            1000,
+            &TWO_P2WPKH_OUTPUTS,
+        );
+        assert_eq!(fee, 0);
+        assert!(selected_utxos.is_empty());
+    }
+
+    #[test]
+    fn balance_splits_confirmed_unconfirmed_and_locked_utxos() {
+        let confirmed_utxos = vec![utxo(0, 1_000), utxo(1, 2_000), utxo(2, 4_000)];
+        let mut all_utxos = confirmed_utxos.clone();
+        all_utxos.push(utxo(3, 8_000));
+        let pending_utxos = vec![utxo(1, 2_000), utxo(3, 8_000)];
+
+        assert_eq!(
+            balance(&all_utxos, &confirmed_utxos, &pending_utxos, &[]),
+            BtcGetBalanceResponse {
+                confirmed_satoshis: 7_000,
+                unconfirmed_satoshis: 8_000,
+                locked_satoshis: 10_000,
+                spendable_satoshis: 5_000,
+            }
+        );
+
+        // Frozen UTXOs are locked too, even when they are also spent by a pending transaction.
+        let frozen_outpoints = vec![utxo(1, 2_000).outpoint, utxo(2, 4_000).outpoint];
+        assert_eq!(
+            balance(
+                &all_utxos,
+                &confirmed_utxos,
+                &pending_utxos,
+                &frozen_outpoints
+            ),
+            BtcGetBalanceResponse {
+                confirmed_satoshis: 7_000,
+                unconfirmed_satoshis: 8_000,
+                locked_satoshis: 14_000,
+                spendable_satoshis: 1_000,
+            }
+        );
+    }
+
+    #[test]
+    fn balance_ignores_pending_utxos_already_spent() {
+        let all_utxos = vec![utxo(0, 1_000)];
+        let pending_utxos = vec![utxo(1, 2_000)];
+
+        assert_eq!(
+            balance(&all_utxos, &all_utxos, &pending_utxos, &[]),
+            BtcGetBalanceResponse {
+                confirmed_satoshis: 1_000,
+                unconfirmed_satoshis: 0,
+                locked_satoshis: 0,
+                spendable_satoshis: 1_000,
+            }
+        );
+    }
+
+    fn arb_utxos() -> impl Strategy<Value = Vec<Utxo>> {
+        prop::collection::vec(1u64..1_000_000, 0..50).prop_map(|values| {
+            values
+                .into_iter()
+                .enumerate()
+                .map(|(vout, value)| utxo(vout as u32, value))
+                .collect()
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn utxos_selection_satisfies_postconditions(
+            available_utxos in arb_utxos(),
+            target in 1u64..10_000_000,
+            output_count in 1usize..5,
+        ) {
+            let mut remaining_utxos = available_utxos.clone();
+            let solution = utxos_selection(target, &mut remaining_utxos, output_count);
+            let available_satoshis: u64 = available_utxos.iter().map(|u| u.value).sum();
+            let solution_satoshis: u64 = solution.iter().map(|u| u.value).sum();
+
+            prop_assert_eq!(available_satoshis >= target, !solution.is_empty());
+            if solution.is_empty() {
+                // The UTXOs are given back, possibly in a different order.
+                remaining_utxos.sort_by_key(|u| u.outpoint.vout);
+                prop_assert_eq!(remaining_utxos, available_utxos);
+            } else {
+                prop_assert!(solution_satoshis >= target);
+                prop_assert_eq!(remaining_utxos.len() + solution.len(), available_utxos.len());
+                prop_assert!(solution.iter().all(|u| !remaining_utxos.contains(u)));
+            }
+        }
+
+        #[test]
+        fn utxos_selection_with_fee_satisfies_postcondition(
+            available_utxos in arb_utxos(),
+            amount in 1u64..10_000_000,
+            fee_millisatoshi_per_vbyte in 0u64..100_000,
+        ) {
+            let address = p2wpkh_address();
+            let (solution, fee) = utxos_selection_with_fee(
+                amount,
+                &available_utxos,
+                &address,
+                fee_millisatoshi_per_vbyte,
+                &TWO_P2WPKH_OUTPUTS,
+            );
+            let solution_satoshis: u64 = solution.iter().map(|u| u.value).sum();
+
+            if solution.is_empty() {
+                prop_assert_eq!(fee, 0);
+            } else {
+                prop_assert!(solution_satoshis >= amount + fee);
+                prop_assert_eq!(
+                    fee,
+                    estimate_fee(
+                        &address,
+                        solution.len() as u64,
+                        fee_millisatoshi_per_vbyte,
+                        &TWO_P2WPKH_OUTPUTS
+                    )
+                );
+            }
+        }
+    }
+
+    fn secret_key(index: u8) -> SecretKey {
+        SecretKey::from_slice(&[index + 1; 32]).unwrap()
+    }
+
+    fn compressed_public_key(index: u8) -> CompressedPublicKey {
+        CompressedPublicKey(secret_key(index).public_key(&Secp256k1::new()))
+    }
+
+    fn p2wpkh_address() -> Address {
+        Address::p2wpkh(&compressed_public_key(0), Network::Bitcoin)
+    }
+
+    fn multisig_script() -> ScriptBuf {
+        Builder::new()
+            .push_int(2)
+            .push_key(&PublicKey::from(compressed_public_key(0)))
+            .push_key(&PublicKey::from(compressed_public_key(1)))
+            .push_key(&PublicKey::from(compressed_public_key(2)))
+            .push_int(3)
+            .push_opcode(OP_CHECKMULTISIG)
+            .into_script()
+    }
+
+    fn ecdsa_signature(key_index: u8, input_index: usize) -> ecdsa::Signature {
+        let message = Message::from_digest([u8::try_from(input_index).unwrap(); 32]);
+        ecdsa::Signature {
+            signature: Secp256k1::new().sign_ecdsa(&message, &secret_key(key_index)),
+            sighash_type: EcdsaSighashType::All,
+        }
+    }
+
+    /// Returns an address of the given type along with the signed script and witness spending one
+    /// of its outputs.
+    fn signed_input(
+        address_type: AddressType,
+        input_index: usize,
+    ) -> (Address, ScriptBuf, Witness) {
+        let secp = Secp256k1::new();
+        let public_key = compressed_public_key(0);
+        match address_type {
+            AddressType::P2pkh => (
+                Address::p2pkh(public_key, Network::Bitcoin),
+                Builder::new()
+                    .push_slice(ecdsa_signature(0, input_index).serialize())
+                    .push_key(&PublicKey::from(public_key))
+                    .into_script(),
+                Witness::new(),
+            ),
+            AddressType::P2sh => {
+                let redeem_script = ScriptBuf::new_p2wpkh(&public_key.wpubkey_hash());
+                let redeem_script_push: &PushBytes = redeem_script.as_bytes().try_into().unwrap();
+                (
+                    Address::p2shwpkh(&public_key, Network::Bitcoin),
+                    Builder::new().push_slice(redeem_script_push).into_script(),
+                    Witness::p2wpkh(&ecdsa_signature(0, input_index), &public_key.0),
+                )
+            }
+            AddressType::P2wpkh => (
+                Address::p2wpkh(&public_key, Network::Bitcoin),
+                ScriptBuf::new(),
+                Witness::p2wpkh(&ecdsa_signature(0, input_index), &public_key.0),
+            ),
+            AddressType::P2wsh => (
+                Address::p2wsh(&multisig_script(), Network::Bitcoin),
+                ScriptBuf::new(),
+                Witness::from_slice(&[
+                    vec![],
+                    ecdsa_signature(0, input_index).to_vec(),
+                    ecdsa_signature(1, input_index).to_vec(),
+                    multisig_script().to_bytes(),
+                ]),
+            ),
+            AddressType::P2tr => {
+                let keypair = Keypair::from_secret_key(&secp, &secret_key(0));
+                let message = Message::from_digest([u8::try_from(input_index).unwrap(); 32]);
+                (
+                    Address::p2tr(&secp, keypair.x_only_public_key().0, None, Network::Bitcoin),
+                    ScriptBuf::new(),
+                    Witness::p2tr_key_spend(&taproot::Signature {
+                        signature: secp.sign_schnorr_no_aux_rand(&message, &keypair),
+                        sighash_type: TapSighashType::Default,
+                    }),
+                )
+            }
+            _ => unreachable!("unsupported address type"),
+        }
+    }
+
+    /// Checks the estimated size of transactions against the size of real signed transactions.
+    fn assert_vsize_estimate_matches_signed_transaction(address_type: AddressType) {
+        let output_scripts = vec![
+            Address::p2pkh(compressed_public_key(1), Network::Bitcoin).script_pubkey(),
+            Address::p2shwpkh(&compressed_public_key(1), Network::Bitcoin).script_pubkey(),
+            Address::p2wpkh(&compressed_public_key(1), Network::Bitcoin).script_pubkey(),
+            Address::p2wsh(&multisig_script(), Network::Bitcoin).script_pubkey(),
+            signed_input(AddressType::P2tr, 0).0.script_pubkey(),
+            ScriptBuf::new_op_return(b"oisy"),
+        ];
+        let output_vsizes: Vec<u64> = output_scripts
+            .iter()
+            .map(|script_pubkey| output_vsize(script_pubkey.as_script()))
+            .collect();
+
+        for input_count in [1, 3] {
+            let mut source_address = None;
+            let input = (0..input_count)
+                .map(|input_index| {
+                    let (address, script_sig, witness) = signed_input(address_type, input_index);
+                    source_address = Some(address);
+                    TxIn {
+                        previous_output: OutPoint::null(),
+                        script_sig,
+                        sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                        witness,
+                    }
+                })
+                .collect();
+            let transaction = Transaction {
+                version: Version::TWO,
+                lock_time: LockTime::ZERO,
+                input,
+                output: output_scripts
+                    .iter()
+                    .map(|script_pubkey| TxOut {
+                        value: Amount::from_sat(10_000),
+                        script_pubkey: script_pubkey.clone(),
+                    })
+                    .collect(),
+            };
+
+            let estimate =
+                tx_vsize_estimate(&source_address.unwrap(), input_count as u64, &output_vsizes);
+            let vsize = transaction.vsize() as u64;
+            // Signatures might be shorter than the estimated upper bound.
+            assert!(
+                vsize <= estimate && estimate <= vsize + input_count as u64,
+                "{address_type} transaction with {input_count} inputs: estimated {estimate} vbytes, actual {vsize} vbytes"
+            );
+        }
+    }
+
+    #[test]
+    fn tx_vsize_estimate_matches_p2pkh_transaction() {
+        assert_vsize_estimate_matches_signed_transaction(AddressType::P2pkh);
+    }
+
+    #[test]
+    fn tx_vsize_estimate_matches_p2sh_p2wpkh_transaction() {
+        assert_vsize_estimate_matches_signed_transaction(AddressType::P2sh);
+    }
+
+    #[test]
+    fn tx_vsize_estimate_matches_p2wpkh_transaction() {
+        assert_vsize_estimate_matches_signed_transaction(AddressType::P2wpkh);
+    }
+
+    #[test]
+    fn tx_vsize_estimate_matches_p2wsh_transaction() {
+        assert_vsize_estimate_matches_signed_transaction(AddressType::P2wsh);
+    }
+
+    #[test]
+    fn tx_vsize_estimate_matches_p2tr_transaction() {
+        assert_vsize_estimate_matches_signed_transaction(AddressType::P2tr);
     }
 }
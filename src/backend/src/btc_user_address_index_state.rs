@@ -0,0 +1,138 @@
+use crate::types::{BtcUserNextAddressIndexMap, StoredPrincipal};
+
+/// The maximum number of addresses of each type of a user, including the default address.
+///
+/// The pending transactions of the addresses of both types of a user must fit in the
+/// `MAX_ADDRESS_COUNT_PER_USER` addresses of `BtcUserPendingTransactions`.
+const MAX_ADDRESSES_PER_USER: u32 = 10;
+
+/// Returns the index of the next fresh address of a user, which is also the number of addresses
+/// the user has. Every user has the default address, with index 0.
+pub fn next_address_index(
+    next_address_index_map: &BtcUserNextAddressIndexMap,
+    principal: StoredPrincipal,
+) -> u32 {
+    next_address_index_map.get(&principal).unwrap_or(1)
+}
+
+/// Issues the index of a fresh address of a user, following `last_address_index`, the last index
+/// the caller knows of. An issued index is never issued again.
+///
+/// If an index was issued since, e.g. by a concurrent call, that index is returned instead, so
+/// that concurrent calls issue a single index. Fails, without changing anything, if the user
+/// would have more than `MAX_ADDRESSES_PER_USER` addresses.
+pub fn issue_address_index(
+    next_address_index_map: &mut BtcUserNextAddressIndexMap,
+    principal: StoredPrincipal,
+    last_address_index: u32,
+) -> Result<u32, String> {
+    let address_index = next_address_index(next_address_index_map, principal);
+    if address_index > last_address_index + 1 {
+        return Ok(address_index - 1);
+    }
+    if address_index >= MAX_ADDRESSES_PER_USER {
+        return Err(format!(
+            "Addresses should not exceed {MAX_ADDRESSES_PER_USER}"
+        ));
+    }
+    next_address_index_map.insert(principal, address_index + 1);
+    Ok(address_index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candid::Principal;
+    use ic_stable_structures::{
+        memory_manager::{MemoryId, MemoryManager},
+        DefaultMemoryImpl,
+    };
+
+    const PRINCIPAL_TEXT_1: &str =
+        "7blps-itamd-lzszp-7lbda-4nngn-fev5u-2jvpn-6y3ap-eunp7-kz57e-fqe";
+    const PRINCIPAL_TEXT_2: &str =
+        "xzg7k-thc6c-idntg-knmtz-2fbhh-utt3e-snqw6-5xph3-54pbp-7axl5-tae";
+
+    fn prepare_btree() -> BtcUserNextAddressIndexMap {
+        const BTC_USER_NEXT_ADDRESS_INDEX_MEMORY_ID: MemoryId = MemoryId::new(9);
+        let memory_manager = MemoryManager::init(DefaultMemoryImpl::default());
+        BtcUserNextAddressIndexMap::new(memory_manager.get(BTC_USER_NEXT_ADDRESS_INDEX_MEMORY_ID))
+    }
+
+    fn principal(text: &str) -> StoredPrincipal {
+        StoredPrincipal(Principal::from_text(text).unwrap())
+    }
+
+    #[test]
+    fn test_issue_address_index_per_user() {
+        let mut next_address_index_map = prepare_btree();
+        assert_eq!(
+            next_address_index(&next_address_index_map, principal(PRINCIPAL_TEXT_1)),
+            1
+        );
+
+        assert_eq!(
+            issue_address_index(&mut next_address_index_map, principal(PRINCIPAL_TEXT_1), 0),
+            Ok(1)
+        );
+        assert_eq!(
+            issue_address_index(&mut next_address_index_map, principal(PRINCIPAL_TEXT_1), 1),
+            Ok(2)
+        );
+        assert_eq!(
+            issue_address_index(&mut next_address_index_map, principal(PRINCIPAL_TEXT_2), 0),
+            Ok(1)
+        );
+        assert_eq!(
+            next_address_index(&next_address_index_map, principal(PRINCIPAL_TEXT_1)),
+            3
+        );
+    }
+
+    #[test]
+    fn test_issue_address_index_max_limit() {
+        let mut next_address_index_map = prepare_btree();
+        for last_address_index in 0..MAX_ADDRESSES_PER_USER - 1 {
+            issue_address_index(
+                &mut next_address_index_map,
+                principal(PRINCIPAL_TEXT_1),
+                last_address_index,
+            )
+            .unwrap();
+        }
+
+        assert_eq!(
+            issue_address_index(
+                &mut next_address_index_map,
+                principal(PRINCIPAL_TEXT_1),
+                MAX_ADDRESSES_PER_USER - 1
+            ),
+            Err(format!(
+                "Addresses should not exceed {MAX_ADDRESSES_PER_USER}"
+            ))
+        );
+        assert_eq!(
+            next_address_index(&next_address_index_map, principal(PRINCIPAL_TEXT_1)),
+            MAX_ADDRESSES_PER_USER
+        );
+    }
+
+    #[test]
+    fn test_issue_address_index_once_for_concurrent_calls() {
+        let mut next_address_index_map = prepare_btree();
+
+        // Both calls saw the default address as the last one.
+        assert_eq!(
+            issue_address_index(&mut next_address_index_map, principal(PRINCIPAL_TEXT_1), 0),
+            Ok(1)
+        );
+        assert_eq!(
+            issue_address_index(&mut next_address_index_map, principal(PRINCIPAL_TEXT_1), 0),
+            Ok(1)
+        );
+        assert_eq!(
+            next_address_index(&next_address_index_map, principal(PRINCIPAL_TEXT_1)),
+            2
+        );
+    }
+}
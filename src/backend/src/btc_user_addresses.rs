@@ -0,0 +1,207 @@
+//! The bitcoin addresses of the users.
+//!
+//! Besides its default address, with index 0, a user can issue fresh receive addresses with
+//! `btc_get_fresh_address`, see `FRESH_ADDRESSES_ENABLED`. The UTXOs of all the addresses of a
+//! type are spent together, and the change of the transactions goes back to the default address.
+//! The transactions built by the backend are registered as pending transactions of the default
+//! address.
+use crate::{
+    bitcoin_api,
+    bitcoin_error::BtcError,
+    bitcoin_transaction::SourceAddress,
+    btc_user_address_index_state,
+    btc_user_pending_tx_state::{with_btc_pending_transactions, StoredPendingTransaction},
+    read_state,
+    signer::{btc_principal_to_public_keys, btc_public_key_to_address},
+    types::StoredPrincipal,
+};
+use bitcoin::{key::XOnlyPublicKey, Address, AddressType};
+use candid::Principal;
+use ic_cdk::api::management_canister::bitcoin::Utxo;
+use shared::types::bitcoin::{BtcAddressType, BtcNetwork};
+
+/// Whether fresh addresses are issued to the users, and their UTXOs spent.
+///
+/// The fresh addresses derive from the `[0, principal, index]` path, while the chain fusion signer
+/// only signs bitcoin transactions with the `[0, caller]` key, see `signer::btc_derivation_path`.
+/// Until it can sign for them, only the backends built with the `btc-fresh-addresses` feature
+/// issue them.
+pub const FRESH_ADDRESSES_ENABLED: bool = cfg!(feature = "btc-fresh-addresses");
+
+/// Whether an address of a user never received funds that are still there, nor spent any in a
+/// pending transaction, as far as the bitcoin API and the pending transactions tell.
+pub async fn is_unused(
+    network: BtcNetwork,
+    principal: &Principal,
+    address: &str,
+) -> Result<bool, BtcError> {
+    let has_pending_transactions = with_btc_pending_transactions(|pending_transactions| {
+        !pending_transactions
+            .get_pending_transactions(principal, address)
+            .is_empty()
+    });
+    if has_pending_transactions {
+        return Ok(false);
+    }
+    let utxos = bitcoin_api::get_all_utxos(network, address.to_string(), None).await?;
+    Ok(utxos.is_empty())
+}
+
+/// The addresses of one type of a user, ordered by index.
+pub struct BtcUserAddresses {
+    sources: Vec<SourceAddress>,
+}
+
+/// The UTXOs of the addresses of a user, per address.
+pub struct BtcUserUtxos {
+    utxos_per_address: Vec<Vec<Utxo>>,
+}
+
+impl BtcUserAddresses {
+    /// Derives the addresses of the given type of a user, only the default one unless
+    /// `FRESH_ADDRESSES_ENABLED`.
+    pub async fn of_type(
+        network: BtcNetwork,
+        principal: &Principal,
+        address_type: BtcAddressType,
+    ) -> Result<Self, BtcError> {
+        let address_count = if FRESH_ADDRESSES_ENABLED {
+            read_state(|s| {
+                btc_user_address_index_state::next_address_index(
+                    &s.btc_user_next_address_index,
+                    StoredPrincipal(*principal),
+                )
+            })
+        } else {
+            1
+        };
+        let public_keys = btc_principal_to_public_keys(principal, address_count).await?;
+        let sources = public_keys
+            .iter()
+            .map(|public_key| SourceAddress {
+                address: btc_public_key_to_address(public_key, network, address_type),
+                tap_internal_key: (address_type == BtcAddressType::P2tr)
+                    .then(|| XOnlyPublicKey::from(public_key.0)),
+            })
+            .collect();
+        Ok(Self { sources })
+    }
+
+    /// Derives the addresses of a user of the same type as `address`, if it is one of them.
+    ///
+    /// Taproot addresses are never issued to the users, see `BtcAddressType::P2tr`.
+    pub async fn containing(
+        network: BtcNetwork,
+        principal: &Principal,
+        address: &Address,
+    ) -> Result<Option<Self>, BtcError> {
+        let address_type = match address.address_type() {
+            Some(AddressType::P2wpkh) => BtcAddressType::P2wpkh,
+            _ => return Ok(None),
+        };
+        let user_addresses = Self::of_type(network, principal, address_type).await?;
+        Ok(user_addresses
+            .sources
+            .iter()
+            .any(|source| source.address == *address)
+            .then_some(user_addresses))
+    }
+
+    /// The default address, which receives the change of the transactions.
+    #[must_use]
+    pub fn default_source(&self) -> &SourceAddress {
+        &self.sources[0]
+    }
+
+    fn addresses(&self) -> Vec<String> {
+        self.sources
+            .iter()
+            .map(|source| source.address.to_string())
+            .collect()
+    }
+
+    /// Returns all the UTXOs of the addresses.
+    pub async fn get_all_utxos(
+        &self,
+        network: BtcNetwork,
+        min_confirmations: Option<u32>,
+    ) -> Result<BtcUserUtxos, BtcError> {
+        let utxos_per_address =
+            bitcoin_api::get_all_utxos_of_addresses(network, &self.addresses(), min_confirmations)
+                .await?;
+        Ok(BtcUserUtxos { utxos_per_address })
+    }
+
+    /// Pairs the given UTXOs with their address, for `bitcoin_transaction::build_unsigned_psbt`.
+    pub fn inputs<'a>(
+        &'a self,
+        utxos: &'a [Utxo],
+        user_utxos: &BtcUserUtxos,
+    ) -> Result<Vec<(&'a Utxo, &'a SourceAddress)>, String> {
+        utxos
+            .iter()
+            .map(|utxo| {
+                user_utxos
+                    .utxos_per_address
+                    .iter()
+                    .position(|address_utxos| address_utxos.contains(utxo))
+                    .map(|index| (utxo, &self.sources[index]))
+                    .ok_or_else(|| "The UTXO is not a UTXO of the addresses".to_string())
+            })
+            .collect()
+    }
+
+    /// Updates the pending transactions of all the addresses given their current UTXOs, see
+    /// `BtcUserPendingTransactions::prune_pending_transactions`.
+    pub fn prune_pending_transactions(
+        &self,
+        principal: Principal,
+        current_utxos: &[Utxo],
+        all_utxos: &[Utxo],
+        now_ns: u64,
+    ) {
+        with_btc_pending_transactions(|pending_transactions| {
+            for address in self.addresses() {
+                pending_transactions.prune_pending_transactions(
+                    principal,
+                    &address,
+                    current_utxos,
+                    all_utxos,
+                    now_ns,
+                );
+            }
+        });
+    }
+
+    /// Returns the pending transactions of all the addresses.
+    #[must_use]
+    pub fn get_pending_transactions(&self, principal: &Principal) -> Vec<StoredPendingTransaction> {
+        with_btc_pending_transactions(|pending_transactions| {
+            self.addresses()
+                .iter()
+                .flat_map(|address| {
+                    pending_transactions.get_pending_transactions(principal, address)
+                })
+                .collect()
+        })
+    }
+
+    /// Returns the UTXOs spent by the pending transactions of all the addresses.
+    #[must_use]
+    pub fn get_pending_utxos(&self, principal: &Principal) -> Vec<Utxo> {
+        with_btc_pending_transactions(|pending_transactions| {
+            self.addresses()
+                .iter()
+                .flat_map(|address| pending_transactions.get_pending_utxos(principal, address))
+                .collect()
+        })
+    }
+}
+
+impl BtcUserUtxos {
+    /// All the UTXOs, of all the addresses.
+    #[must_use]
+    pub fn all(&self) -> Vec<Utxo> {
+        self.utxos_per_address.concat()
+    }
+}
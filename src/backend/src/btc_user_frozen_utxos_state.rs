@@ -0,0 +1,160 @@
+use crate::types::{BtcUserFrozenUtxosMap, Candid, StoredPrincipal};
+use ic_cdk::api::management_canister::bitcoin::Outpoint;
+
+const MAX_FROZEN_UTXOS_PER_USER: usize = 1000;
+
+/// Returns the outpoints of the UTXOs that a user froze, i.e. that must never be spent.
+pub fn get_frozen_utxos(
+    frozen_utxos_map: &BtcUserFrozenUtxosMap,
+    principal: StoredPrincipal,
+) -> Vec<Outpoint> {
+    frozen_utxos_map.get(&principal).unwrap_or_default().0
+}
+
+/// Freezes the UTXOs of a user with the given outpoints.
+/// Freezing a UTXO that is already frozen does nothing.
+///
+/// Fails, without changing anything, if the user would have more than
+/// `MAX_FROZEN_UTXOS_PER_USER` frozen UTXOs.
+pub fn freeze_utxos(
+    frozen_utxos_map: &mut BtcUserFrozenUtxosMap,
+    principal: StoredPrincipal,
+    outpoints: &[Outpoint],
+) -> Result<(), String> {
+    let mut frozen_utxos = get_frozen_utxos(frozen_utxos_map, principal);
+    for outpoint in outpoints {
+        if !frozen_utxos.contains(outpoint) {
+            frozen_utxos.push(outpoint.clone());
+        }
+    }
+    if frozen_utxos.len() > MAX_FROZEN_UTXOS_PER_USER {
+        return Err(format!(
+            "Frozen UTXOs should not exceed {MAX_FROZEN_UTXOS_PER_USER}"
+        ));
+    }
+    frozen_utxos_map.insert(principal, Candid(frozen_utxos));
+    Ok(())
+}
+
+/// Unfreezes the UTXOs of a user with the given outpoints, so that they can be spent again.
+pub fn unfreeze_utxos(
+    frozen_utxos_map: &mut BtcUserFrozenUtxosMap,
+    principal: StoredPrincipal,
+    outpoints: &[Outpoint],
+) {
+    let mut frozen_utxos = get_frozen_utxos(frozen_utxos_map, principal);
+    frozen_utxos.retain(|outpoint| !outpoints.contains(outpoint));
+    if frozen_utxos.is_empty() {
+        frozen_utxos_map.remove(&principal);
+    } else {
+        frozen_utxos_map.insert(principal, Candid(frozen_utxos));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candid::Principal;
+    use ic_stable_structures::{
+        memory_manager::{MemoryId, MemoryManager},
+        DefaultMemoryImpl,
+    };
+
+    const PRINCIPAL_TEXT_1: &str =
+        "7blps-itamd-lzszp-7lbda-4nngn-fev5u-2jvpn-6y3ap-eunp7-kz57e-fqe";
+    const PRINCIPAL_TEXT_2: &str =
+        "xzg7k-thc6c-idntg-knmtz-2fbhh-utt3e-snqw6-5xph3-54pbp-7axl5-tae";
+
+    fn prepare_btree() -> BtcUserFrozenUtxosMap {
+        const BTC_USER_FROZEN_UTXOS_MEMORY_ID: MemoryId = MemoryId::new(7);
+        let memory_manager = MemoryManager::init(DefaultMemoryImpl::default());
+        BtcUserFrozenUtxosMap::new(memory_manager.get(BTC_USER_FROZEN_UTXOS_MEMORY_ID))
+    }
+
+    fn outpoint(vout: u32) -> Outpoint {
+        Outpoint {
+            txid: vec![1; 32],
+            vout,
+        }
+    }
+
+    fn principal(text: &str) -> StoredPrincipal {
+        StoredPrincipal(Principal::from_text(text).unwrap())
+    }
+
+    #[test]
+    fn test_freeze_utxos_per_principal() {
+        let mut frozen_utxos_map = prepare_btree();
+        let principal_1 = principal(PRINCIPAL_TEXT_1);
+        let principal_2 = principal(PRINCIPAL_TEXT_2);
+
+        freeze_utxos(
+            &mut frozen_utxos_map,
+            principal_1,
+            &[outpoint(0), outpoint(1)],
+        )
+        .unwrap();
+        // Freezing again doesn't duplicate the outpoint.
+        freeze_utxos(&mut frozen_utxos_map, principal_1, &[outpoint(1)]).unwrap();
+
+        assert_eq!(
+            get_frozen_utxos(&frozen_utxos_map, principal_1),
+            vec![outpoint(0), outpoint(1)]
+        );
+        assert!(get_frozen_utxos(&frozen_utxos_map, principal_2).is_empty());
+    }
+
+    #[test]
+    fn test_unfreeze_utxos() {
+        let mut frozen_utxos_map = prepare_btree();
+        let principal = principal(PRINCIPAL_TEXT_1);
+
+        freeze_utxos(
+            &mut frozen_utxos_map,
+            principal,
+            &[outpoint(0), outpoint(1)],
+        )
+        .unwrap();
+        unfreeze_utxos(
+            &mut frozen_utxos_map,
+            principal,
+            &[outpoint(0), outpoint(2)],
+        );
+
+        assert_eq!(
+            get_frozen_utxos(&frozen_utxos_map, principal),
+            vec![outpoint(1)]
+        );
+
+        unfreeze_utxos(&mut frozen_utxos_map, principal, &[outpoint(1)]);
+
+        assert!(frozen_utxos_map.is_empty());
+    }
+
+    #[test]
+    fn test_freeze_utxos_max_limit() {
+        let mut frozen_utxos_map = prepare_btree();
+        let principal = principal(PRINCIPAL_TEXT_1);
+        let outpoints: Vec<Outpoint> = (0..MAX_FROZEN_UTXOS_PER_USER as u32)
+            .map(outpoint)
+            .collect();
+
+        freeze_utxos(&mut frozen_utxos_map, principal, &outpoints).unwrap();
+        let result = freeze_utxos(
+            &mut frozen_utxos_map,
+            principal,
+            &[outpoint(MAX_FROZEN_UTXOS_PER_USER as u32)],
+        );
+
+        assert_eq!(
+            result,
+            Err(format!(
+                "Frozen UTXOs should not exceed {MAX_FROZEN_UTXOS_PER_USER}"
+            ))
+        );
+        assert_eq!(
+            get_frozen_utxos(&frozen_utxos_map, principal).len(),
+            MAX_FROZEN_UTXOS_PER_USER
+        );
+    }
+}
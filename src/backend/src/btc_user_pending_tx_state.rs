@@ -0,0 +1,1455 @@
+use crate::mutate_state;
+use crate::types::{
+    BitcoinAddress, BtcUserPendingTransactionsMap, Candid, StoredPendingTransactionKey,
+};
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::api::management_canister::bitcoin::Utxo;
+use shared::types::bitcoin::{
+    BtcNetwork, BtcNetworkPolicy, BtcPendingTransactionStatus, BtcTxOutput,
+};
+use std::collections::BTreeSet;
+use std::ops::Bound;
+
+const MAX_PENDING_TRANSACTIONS: usize = 1000;
+const MAX_ADDRESS_COUNT_PER_USER: usize = 20;
+const DAY_IN_NS: u64 = 24 * 60 * 60 * 1_000_000_000;
+/// The maximum number of unconfirmed pending transactions that a transaction spending unconfirmed
+/// change can descend from, when the network policy does not set it.
+const MAX_UNCONFIRMED_CHAIN_DEPTH: u32 = 2;
+
+#[derive(CandidType, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct StoredPendingTransaction {
+    pub txid: Vec<u8>,
+    pub utxos: Vec<Utxo>,
+    pub created_at_timestamp_ns: u64,
+    /// The outputs of the transaction, excluding the change.
+    /// Only known for the transactions built by the backend.
+    pub outputs: Option<Vec<BtcTxOutput>>,
+    /// Only known for the transactions built by the backend.
+    pub fee_satoshis: Option<u64>,
+    /// `None` for the transactions stored before their status was tracked, which are
+    /// `BtcPendingTransactionStatus::Broadcast`.
+    pub status: Option<BtcPendingTransactionStatus>,
+    /// When the status last changed. `None` if it never changed.
+    pub status_updated_at_timestamp_ns: Option<u64>,
+}
+
+impl StoredPendingTransaction {
+    pub fn status(&self) -> BtcPendingTransactionStatus {
+        self.status
+            .clone()
+            .unwrap_or(BtcPendingTransactionStatus::Broadcast)
+    }
+
+    /// Whether the transaction might still be confirmed, i.e. its UTXOs can't be spent again.
+    pub fn is_in_flight(&self) -> bool {
+        matches!(
+            self.status(),
+            BtcPendingTransactionStatus::Built
+                | BtcPendingTransactionStatus::Broadcast
+                | BtcPendingTransactionStatus::InBlock { .. }
+        )
+    }
+
+    /// The status of the transaction reported as broadcast by the user: `Broadcast`, unless the
+    /// network already shows it.
+    pub fn reported_status(&self) -> BtcPendingTransactionStatus {
+        match self.status() {
+            status @ (BtcPendingTransactionStatus::InBlock { .. }
+            | BtcPendingTransactionStatus::Confirmed { .. }) => status,
+            _ => BtcPendingTransactionStatus::Broadcast,
+        }
+    }
+
+    fn set_status(&mut self, status: BtcPendingTransactionStatus, now_ns: u64) {
+        self.status = Some(status);
+        self.status_updated_at_timestamp_ns = Some(now_ns);
+    }
+
+    fn status_updated_at_timestamp_ns(&self) -> u64 {
+        self.status_updated_at_timestamp_ns
+            .unwrap_or(self.created_at_timestamp_ns)
+    }
+}
+
+/// `BtcUserPendingTransactions` should be used to access and manage the pending bitcoin transactions in the stable memory.
+///
+/// Pending transactions are stored per (`user_principal`, `address`, `txid`).
+/// With this structure, if multiple users share the same address
+/// they wouldn't share the pending transactions.
+/// This is not possible with the current implementation of the addresses in CFS.
+/// But something to have in mind for the future.
+pub struct BtcUserPendingTransactions<'a> {
+    pending_transactions_map: &'a mut BtcUserPendingTransactionsMap,
+    /// Maximum number of transactions that will be stored per `(principal, address)` tuple.
+    max_pending_transactions: usize,
+    /// Maxumum number of addresses per user.
+    max_addresses_per_user: usize,
+}
+
+impl<'a> BtcUserPendingTransactions<'a> {
+    pub fn new(
+        pending_transactions_map: &'a mut BtcUserPendingTransactionsMap,
+        max_pending_txs: Option<usize>,
+        max_addresses_per_user: Option<usize>,
+    ) -> Self {
+        Self {
+            pending_transactions_map,
+            max_pending_transactions: max_pending_txs.unwrap_or(MAX_PENDING_TRANSACTIONS),
+            max_addresses_per_user: max_addresses_per_user.unwrap_or(MAX_ADDRESS_COUNT_PER_USER),
+        }
+    }
+
+    /// Iterates over all the pending transactions of a specific principal, ordered by address and txid.
+    fn iter_principal<'b>(
+        &'b self,
+        principal: &'b Principal,
+    ) -> impl Iterator<Item = (StoredPendingTransactionKey, StoredPendingTransaction)> + 'b {
+        let start = StoredPendingTransactionKey {
+            principal: *principal,
+            address: BitcoinAddress::new(),
+            txid: Vec::new(),
+        };
+        self.pending_transactions_map
+            .range((Bound::Included(start), Bound::Unbounded))
+            .take_while(move |(key, _)| key.principal == *principal)
+            .map(|(key, transaction)| (key, transaction.0))
+    }
+
+    /// Returns the pending transactions of a specific principal per address.
+    pub fn get_pending_transactions(
+        &self,
+        principal: &Principal,
+        address: &str,
+    ) -> Vec<StoredPendingTransaction> {
+        self.iter_principal(principal)
+            .filter(|(key, _)| key.address == address)
+            .map(|(_, transaction)| transaction)
+            .collect()
+    }
+
+    /// Returns the UTXOs spent by the in-flight pending transactions of a specific principal per
+    /// address. These UTXOs must not be spent again.
+    pub fn get_pending_utxos(&self, principal: &Principal, address: &str) -> Vec<Utxo> {
+        self.get_pending_transactions(principal, address)
+            .into_iter()
+            .filter(StoredPendingTransaction::is_in_flight)
+            .flat_map(|transaction| transaction.utxos)
+            .collect()
+    }
+
+    /// Returns the pending transaction of a specific principal and address with the given txid.
+    pub fn get_pending_transaction(
+        &self,
+        principal: &Principal,
+        address: &str,
+        txid: &[u8],
+    ) -> Option<StoredPendingTransaction> {
+        self.pending_transactions_map
+            .get(&StoredPendingTransactionKey {
+                principal: *principal,
+                address: address.to_string(),
+                txid: txid.to_vec(),
+            })
+            .map(|transaction| transaction.0)
+    }
+
+    /// Adds a pending transaction for a specific principal and address.
+    /// It has a limit of storable transactions set on init.
+    ///
+    /// Note: Adding a transaction with a txid that is already stored for the same principal and address
+    /// replaces the stored transaction.
+    pub fn add_pending_transaction(
+        &mut self,
+        principal: Principal,
+        address: BitcoinAddress,
+        new_transaction: StoredPendingTransaction,
+    ) -> Result<(), String> {
+        let key = StoredPendingTransactionKey {
+            principal,
+            address,
+            txid: new_transaction.txid.clone(),
+        };
+        if !self.pending_transactions_map.contains_key(&key) {
+            let mut addresses: BTreeSet<BitcoinAddress> = BTreeSet::new();
+            let mut address_transaction_count = 0;
+            for (stored_key, _) in self.iter_principal(&principal) {
+                if stored_key.address == key.address {
+                    address_transaction_count += 1;
+                }
+                addresses.insert(stored_key.address);
+            }
+            if address_transaction_count >= self.max_pending_transactions {
+                return Err("Maximum pending transactions reached".to_string());
+            }
+            if address_transaction_count == 0 && addresses.len() >= self.max_addresses_per_user {
+                return Err("Maximum address per user reached".to_string());
+            }
+        }
+        self.pending_transactions_map
+            .insert(key, Candid(new_transaction));
+        Ok(())
+    }
+
+    /// Replaces the pending transaction with txid `replaced_txid` of a specific principal and
+    /// address, e.g. by a transaction paying a higher fee (BIP-125).
+    /// The replaced transaction is kept with the status `BtcPendingTransactionStatus::Replaced`.
+    ///
+    /// Fails, without changing anything, if there is no pending transaction to replace.
+    pub fn replace_pending_transaction(
+        &mut self,
+        principal: Principal,
+        address: BitcoinAddress,
+        replaced_txid: Vec<u8>,
+        new_transaction: StoredPendingTransaction,
+    ) -> Result<(), String> {
+        let replaced_key = StoredPendingTransactionKey {
+            principal,
+            address: address.clone(),
+            txid: replaced_txid,
+        };
+        let Some(Candid(mut replaced_transaction)) =
+            self.pending_transactions_map.get(&replaced_key)
+        else {
+            return Err("Pending transaction not found".to_string());
+        };
+        replaced_transaction.set_status(
+            BtcPendingTransactionStatus::Replaced {
+                txid: new_transaction.txid.clone(),
+            },
+            new_transaction.created_at_timestamp_ns,
+        );
+        self.pending_transactions_map
+            .insert(replaced_key, Candid(replaced_transaction));
+        let key = StoredPendingTransactionKey {
+            principal,
+            address,
+            txid: new_transaction.txid.clone(),
+        };
+        self.pending_transactions_map
+            .insert(key, Candid(new_transaction));
+        Ok(())
+    }
+
+    /// Removes the pending transaction of a specific principal and address with the given txid,
+    /// e.g. because the user knows that it failed. Its UTXOs can be spent again.
+    pub fn remove_pending_transaction(
+        &mut self,
+        principal: Principal,
+        address: BitcoinAddress,
+        txid: Vec<u8>,
+    ) -> Result<StoredPendingTransaction, String> {
+        self.pending_transactions_map
+            .remove(&StoredPendingTransactionKey {
+                principal,
+                address,
+                txid,
+            })
+            .map(|transaction| transaction.0)
+            .ok_or_else(|| "Pending transaction not found".to_string())
+    }
+
+    /// Updates the status of the pending transactions of a specific principal per address, given
+    /// the current UTXOs of the address, and prunes the ones that are settled for a day.
+    ///
+    /// `all_utxos` are the UTXOs of the address without confirmation requirement, which no longer
+    /// include the UTXOs spent by the transactions in a block.
+    ///
+    /// An in-flight transaction becomes:
+    /// - `InBlock` when none of its utxos are present in `all_utxos`, but some are still in the
+    ///   current utxos list, i.e. the block of the transaction does not have enough confirmations
+    ///   yet.
+    /// - `Confirmed` when none of its utxos are present in the current utxos list.
+    ///   Normally, all utxos of a pending transaction should be present or not.
+    ///   Partial presence could happen if the utxos of a pending transaction were not really used in the transaction.
+    ///   We don't confirm in partial presence because, in the end, partial presence will be temporary for one day.
+    ///   A transaction spending the change of an in-flight transaction is not confirmed before it,
+    ///   as the change is not in the current utxos until it has enough confirmations.
+    /// - `Dropped` when it is older than 1 day.
+    ///   We consider that if a pending transaction is older than one day
+    ///   it means it failed and we can free to utxos to be used again.
+    ///
+    /// Confirmed, dropped and replaced transactions are kept for a day, so that the user can see
+    /// what happened to them.
+    pub fn prune_pending_transactions(
+        &mut self,
+        principal: Principal,
+        address: &str,
+        current_utxos: &[Utxo],
+        all_utxos: &[Utxo],
+        now_ns: u64,
+    ) {
+        let mut keys_to_remove: Vec<StoredPendingTransactionKey> = Vec::new();
+        let mut transactions_to_update: Vec<(
+            StoredPendingTransactionKey,
+            StoredPendingTransaction,
+        )> = Vec::new();
+        let in_flight_txids: BTreeSet<Vec<u8>> = self
+            .iter_principal(&principal)
+            .filter(|(_, transaction)| transaction.is_in_flight())
+            .map(|(_, transaction)| transaction.txid)
+            .collect();
+        for (key, mut transaction) in self
+            .iter_principal(&principal)
+            .filter(|(key, _)| key.address == address)
+        {
+            if !transaction.is_in_flight() {
+                if transaction.status_updated_at_timestamp_ns() + DAY_IN_NS < now_ns {
+                    keys_to_remove.push(key);
+                }
+                continue;
+            }
+            let all_utxos_spent = transaction
+                .utxos
+                .iter()
+                .all(|utxo| !current_utxos.contains(utxo));
+            let spends_in_flight_change = transaction.utxos.iter().any(|utxo| {
+                utxo.outpoint.txid != transaction.txid
+                    && in_flight_txids.contains(&utxo.outpoint.txid)
+            });
+            if all_utxos_spent && !spends_in_flight_change {
+                // The outputs paying back to the address, if any, tell the block of the transaction.
+                let height = current_utxos
+                    .iter()
+                    .filter(|utxo| utxo.outpoint.txid == transaction.txid)
+                    .map(|utxo| utxo.height)
+                    .max();
+                transaction.set_status(BtcPendingTransactionStatus::Confirmed { height }, now_ns);
+                transactions_to_update.push((key, transaction));
+            } else if transaction
+                .utxos
+                .iter()
+                .all(|utxo| !all_utxos.contains(utxo))
+            {
+                let status = BtcPendingTransactionStatus::InBlock {
+                    height: all_utxos
+                        .iter()
+                        .filter(|utxo| utxo.outpoint.txid == transaction.txid)
+                        .map(|utxo| utxo.height)
+                        .max(),
+                };
+                if transaction.status() != status {
+                    transaction.set_status(status, now_ns);
+                    transactions_to_update.push((key, transaction));
+                }
+            } else if transaction.created_at_timestamp_ns + DAY_IN_NS < now_ns {
+                transaction.set_status(BtcPendingTransactionStatus::Dropped, now_ns);
+                transactions_to_update.push((key, transaction));
+            }
+        }
+        for key in &keys_to_remove {
+            self.pending_transactions_map.remove(key);
+        }
+        for (key, transaction) in transactions_to_update {
+            self.pending_transactions_map
+                .insert(key, Candid(transaction));
+        }
+    }
+}
+
+/// The maximum number of unconfirmed pending transactions that a transaction spending unconfirmed
+/// change can descend from on the network.
+pub fn max_unconfirmed_chain_depth(
+    policies: Option<&[BtcNetworkPolicy]>,
+    network: BtcNetwork,
+) -> u32 {
+    policies
+        .and_then(|policies| policies.iter().find(|policy| policy.network == network))
+        .and_then(|policy| policy.max_unconfirmed_chain_depth)
+        .unwrap_or(MAX_UNCONFIRMED_CHAIN_DEPTH)
+}
+
+/// Returns the UTXOs among `unconfirmed_utxos` that are the change of the given in-flight pending
+/// transactions of a user, and that can be spent before they are confirmed.
+///
+/// A transaction spending such a change descends from the pending transaction, which can itself
+/// spend the change of another in-flight pending transaction. The change is only spendable if the
+/// new transaction descends from at most `max_chain_depth` in-flight pending transactions. The
+/// confirmed, dropped and replaced transactions are not counted: the change of the dropped and
+/// replaced ones will never be confirmed.
+pub fn spendable_unconfirmed_change(
+    pending_transactions: &[StoredPendingTransaction],
+    unconfirmed_utxos: &[Utxo],
+    max_chain_depth: u32,
+) -> Vec<Utxo> {
+    unconfirmed_utxos
+        .iter()
+        .filter(|utxo| {
+            let chain_depth = unconfirmed_chain_depth(
+                pending_transactions,
+                &utxo.outpoint.txid,
+                max_chain_depth + 1,
+            );
+            (1..=max_chain_depth).contains(&chain_depth)
+        })
+        .cloned()
+        .collect()
+}
+
+/// The number of in-flight pending transactions in the longest chain of in-flight pending
+/// transactions ending with the transaction `txid`, counted up to `limit`. 0 if `txid` is not an
+/// in-flight pending transaction.
+fn unconfirmed_chain_depth(
+    pending_transactions: &[StoredPendingTransaction],
+    txid: &[u8],
+    limit: u32,
+) -> u32 {
+    if limit == 0 {
+        return 0;
+    }
+    pending_transactions
+        .iter()
+        .find(|transaction| transaction.txid == txid && transaction.is_in_flight())
+        .map_or(0, |transaction| {
+            1 + transaction
+                .utxos
+                .iter()
+                .map(|utxo| {
+                    unconfirmed_chain_depth(pending_transactions, &utxo.outpoint.txid, limit - 1)
+                })
+                .max()
+                .unwrap_or(0)
+        })
+}
+
+/// Gives access to the pending bitcoin transactions of all users, with the default limits.
+pub fn with_btc_pending_transactions<R>(f: impl FnOnce(&mut BtcUserPendingTransactions) -> R) -> R {
+    mutate_state(|s| {
+        f(&mut BtcUserPendingTransactions::new(
+            &mut s.btc_user_pending_transactions,
+            None,
+            None,
+        ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_cdk::api::management_canister::bitcoin::Outpoint;
+    use ic_stable_structures::{
+        memory_manager::{MemoryId, MemoryManager},
+        DefaultMemoryImpl,
+    };
+    use std::cell::RefCell;
+
+    const UTXO_1: Utxo = Utxo {
+        outpoint: Outpoint {
+            txid: vec![],
+            vout: 0,
+        },
+        value: 1000,
+        height: 100,
+    };
+    const UTXO_2: Utxo = Utxo {
+        outpoint: Outpoint {
+            txid: vec![],
+            vout: 1,
+        },
+        value: 2000,
+        height: 120,
+    };
+    const UTXO_3: Utxo = Utxo {
+        outpoint: Outpoint {
+            txid: vec![],
+            vout: 2,
+        },
+        value: 3000,
+        height: 150,
+    };
+    const UTXO_4: Utxo = Utxo {
+        outpoint: Outpoint {
+            txid: vec![],
+            vout: 2,
+        },
+        value: 8000,
+        height: 160,
+    };
+
+    const PRINCIPAL_TEXT_1: &str =
+        "7blps-itamd-lzszp-7lbda-4nngn-fev5u-2jvpn-6y3ap-eunp7-kz57e-fqe";
+    const PRINCIPAL_TEXT_2: &str =
+        "xzg7k-thc6c-idntg-knmtz-2fbhh-utt3e-snqw6-5xph3-54pbp-7axl5-tae";
+    const ADDRESS_1: &str = "test-address-1";
+    const ADDRESS_2: &str = "test-address-2";
+    const ADDRESS_3: &str = "test-address-3";
+    const ADDRESS_4: &str = "test-address-4";
+
+    fn prepare_btree() -> BtcUserPendingTransactionsMap {
+        const BTC_USER_PENDING_TRANSACTIONS_MEMORY_ID: MemoryId = MemoryId::new(5);
+        let memory = RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+        BtcUserPendingTransactionsMap::new(
+            memory.borrow().get(BTC_USER_PENDING_TRANSACTIONS_MEMORY_ID),
+        )
+    }
+
+    #[test]
+    fn test_get_pending_transactions_empty() {
+        let mut pending_transactions_map = prepare_btree();
+        let btc_user_pending_transactions =
+            BtcUserPendingTransactions::new(&mut pending_transactions_map, None, None);
+        let principal = Principal::from_text(PRINCIPAL_TEXT_1).unwrap();
+
+        let pending_txs =
+            btc_user_pending_transactions.get_pending_transactions(&principal, ADDRESS_1);
+        assert!(pending_txs.is_empty());
+    }
+
+    #[test]
+    fn test_add_pending_transaction_per_address() {
+        let mut pending_transactions_map = prepare_btree();
+        let mut btc_user_pending_transactions =
+            BtcUserPendingTransactions::new(&mut pending_transactions_map, None, None);
+        let principal = Principal::from_text(PRINCIPAL_TEXT_1).unwrap();
+        let tx = StoredPendingTransaction {
+            txid: vec![],
+            utxos: vec![UTXO_1],
+            created_at_timestamp_ns: 1_000_000,
+            outputs: None,
+            fee_satoshis: None,
+            status: None,
+            status_updated_at_timestamp_ns: None,
+        };
+
+        // Add the pending transaction
+        let result = btc_user_pending_transactions.add_pending_transaction(
+            principal.clone(),
+            ADDRESS_1.to_string(),
+            tx.clone(),
+        );
+        assert!(result.is_ok());
+
+        // Check that the transaction was added
+        let pending_txs =
+            btc_user_pending_transactions.get_pending_transactions(&principal, ADDRESS_1);
+        assert_eq!(pending_txs.len(), 1);
+        assert_eq!(pending_txs[0], tx);
+
+        // Check that the transaction was added to the proper address
+        let pending_txs =
+            btc_user_pending_transactions.get_pending_transactions(&principal, ADDRESS_2);
+        assert!(pending_txs.is_empty());
+    }
+
+    #[test]
+    fn test_add_pending_transaction_does_not_add_other_principal() {
+        let mut pending_transactions_map = prepare_btree();
+        let mut btc_user_pending_transactions =
+            BtcUserPendingTransactions::new(&mut pending_transactions_map, None, None);
+        let principal1 = Principal::from_text(PRINCIPAL_TEXT_1).unwrap();
+        let principal2 = Principal::from_text(PRINCIPAL_TEXT_2).unwrap();
+        let tx = StoredPendingTransaction {
+            txid: vec![],
+            utxos: vec![UTXO_1],
+            created_at_timestamp_ns: 1_000_000,
+            outputs: None,
+            fee_satoshis: None,
+            status: None,
+            status_updated_at_timestamp_ns: None,
+        };
+
+        let result = btc_user_pending_transactions.add_pending_transaction(
+            principal1.clone(),
+            ADDRESS_1.to_string(),
+            tx.clone(),
+        );
+        assert!(result.is_ok());
+
+        let pending_txs =
+            btc_user_pending_transactions.get_pending_transactions(&principal2, ADDRESS_1);
+        assert!(pending_txs.is_empty());
+    }
+
+    // Test for add_pending_transaction when max_pending_transactions is reached
+    #[test]
+    fn test_add_pending_transaction_max_limit() {
+        let mut pending_transactions_map = prepare_btree();
+        let mut btc_user_pending_transactions =
+            BtcUserPendingTransactions::new(&mut pending_transactions_map, Some(3), None);
+        let principal = Principal::from_text(PRINCIPAL_TEXT_1).unwrap();
+
+        let tx1 = StoredPendingTransaction {
+            txid: vec![1, 2, 3],
+            utxos: vec![UTXO_1],
+            created_at_timestamp_ns: 1_000_000,
+            outputs: None,
+            fee_satoshis: None,
+            status: None,
+            status_updated_at_timestamp_ns: None,
+        };
+        let tx2 = StoredPendingTransaction {
+            txid: vec![4, 5, 6],
+            utxos: vec![UTXO_2],
+            created_at_timestamp_ns: 2_000_000,
+            outputs: None,
+            fee_satoshis: None,
+            status: None,
+            status_updated_at_timestamp_ns: None,
+        };
+        let tx3 = StoredPendingTransaction {
+            txid: vec![7, 8, 9],
+            utxos: vec![UTXO_3],
+            created_at_timestamp_ns: 3_000_000,
+            outputs: None,
+            fee_satoshis: None,
+            status: None,
+            status_updated_at_timestamp_ns: None,
+        };
+        let tx4 = StoredPendingTransaction {
+            txid: vec![10, 11, 12],
+            utxos: vec![UTXO_4],
+            created_at_timestamp_ns: 4_000_000,
+            outputs: None,
+            fee_satoshis: None,
+            status: None,
+            status_updated_at_timestamp_ns: None,
+        };
+
+        // Add 3 transactions (max_pending_transactions = 3)
+        btc_user_pending_transactions
+            .add_pending_transaction(principal.clone(), ADDRESS_1.to_string(), tx1)
+            .unwrap();
+        btc_user_pending_transactions
+            .add_pending_transaction(principal.clone(), ADDRESS_1.to_string(), tx2)
+            .unwrap();
+        btc_user_pending_transactions
+            .add_pending_transaction(principal.clone(), ADDRESS_1.to_string(), tx3)
+            .unwrap();
+
+        // Try adding a 4th transaction and expect an error
+        let result = btc_user_pending_transactions.add_pending_transaction(
+            principal.clone(),
+            ADDRESS_1.to_string(),
+            tx4,
+        );
+        assert!(result.is_err());
+        assert_eq!(result.unwrap_err(), "Maximum pending transactions reached");
+    }
+
+    // Test for add_pending_transaction when max_addresses_per_user is reached
+    #[test]
+    fn test_add_pending_transaction_max_address_limit() {
+        let mut pending_transactions_map = prepare_btree();
+        let mut btc_user_pending_transactions =
+            BtcUserPendingTransactions::new(&mut pending_transactions_map, None, Some(3));
+        let principal = Principal::from_text(PRINCIPAL_TEXT_1).unwrap();
+
+        let tx1 = StoredPendingTransaction {
+            txid: vec![1, 2, 3],
+            utxos: vec![UTXO_1],
+            created_at_timestamp_ns: 1_000_000,
+            outputs: None,
+            fee_satoshis: None,
+            status: None,
+            status_updated_at_timestamp_ns: None,
+        };
+        let tx2 = StoredPendingTransaction {
+            txid: vec![4, 5, 6],
+            utxos: vec![UTXO_2],
+            created_at_timestamp_ns: 2_000_000,
+            outputs: None,
+            fee_satoshis: None,
+            status: None,
+            status_updated_at_timestamp_ns: None,
+        };
+        let tx3 = StoredPendingTransaction {
+            txid: vec![7, 8, 9],
+            utxos: vec![UTXO_3],
+            created_at_timestamp_ns: 3_000_000,
+            outputs: None,
+            fee_satoshis: None,
+            status: None,
+            status_updated_at_timestamp_ns: None,
+        };
+        let tx4 = StoredPendingTransaction {
+            txid: vec![10, 11, 12],
+            utxos: vec![UTXO_4],
+            created_at_timestamp_ns: 4_000_000,
+            outputs: None,
+            fee_satoshis: None,
+            status: None,
+            status_updated_at_timestamp_ns: None,
+        };
+
+        // Add 3 transactions (max_addresses_per_user = 3)
+        btc_user_pending_transactions
+            .add_pending_transaction(principal.clone(), ADDRESS_1.to_string(), tx1)
+            .unwrap();
+        btc_user_pending_transactions
+            .add_pending_transaction(principal.clone(), ADDRESS_2.to_string(), tx2)
+            .unwrap();
+        btc_user_pending_transactions
+            .add_pending_transaction(principal.clone(), ADDRESS_3.to_string(), tx3)
+            .unwrap();
+
+        // Try adding a 4th address and expect an error
+        let result = btc_user_pending_transactions.add_pending_transaction(
+            principal.clone(),
+            ADDRESS_4.to_string(),
+            tx4,
+        );
+        assert!(result.is_err());
+        assert_eq!(result.unwrap_err(), "Maximum address per user reached");
+    }
+
+    #[test]
+    fn test_old_pending_transactions_are_dropped() {
+        let mut pending_transactions_map = prepare_btree();
+        let mut btc_user_pending_transactions =
+            BtcUserPendingTransactions::new(&mut pending_transactions_map, None, None);
+        let principal = Principal::from_text(PRINCIPAL_TEXT_1).unwrap();
+
+        let yesterday_ns = 1_000_000;
+        let now_ns = yesterday_ns + DAY_IN_NS;
+
+        let old_transaction = StoredPendingTransaction {
+            txid: vec![1, 2, 3],
+            utxos: vec![UTXO_1],
+            created_at_timestamp_ns: yesterday_ns,
+            outputs: None,
+            fee_satoshis: None,
+            status: None,
+            status_updated_at_timestamp_ns: None,
+        };
+        btc_user_pending_transactions
+            .add_pending_transaction(
+                principal.clone(),
+                ADDRESS_1.to_string(),
+                old_transaction.clone(),
+            )
+            .unwrap();
+
+        let valid_transaction = StoredPendingTransaction {
+            txid: vec![4, 5, 6],
+            utxos: vec![UTXO_2],
+            created_at_timestamp_ns: now_ns,
+            outputs: None,
+            fee_satoshis: None,
+            status: None,
+            status_updated_at_timestamp_ns: None,
+        };
+        btc_user_pending_transactions
+            .add_pending_transaction(
+                principal.clone(),
+                ADDRESS_1.to_string(),
+                valid_transaction.clone(),
+            )
+            .unwrap();
+
+        let pending_txs =
+            btc_user_pending_transactions.get_pending_transactions(&principal, ADDRESS_1);
+        assert_eq!(pending_txs.len(), 2);
+
+        let all_utxos = &[UTXO_1, UTXO_2];
+
+        btc_user_pending_transactions.prune_pending_transactions(
+            principal.clone(),
+            ADDRESS_1,
+            all_utxos,
+            all_utxos,
+            now_ns + 1,
+        );
+
+        let pending_txs =
+            btc_user_pending_transactions.get_pending_transactions(&principal, ADDRESS_1);
+        assert_eq!(pending_txs.len(), 2);
+        assert_eq!(
+            pending_txs[0].status(),
+            BtcPendingTransactionStatus::Dropped
+        );
+        assert_eq!(pending_txs[1], valid_transaction);
+        assert_eq!(
+            btc_user_pending_transactions.get_pending_utxos(&principal, ADDRESS_1),
+            vec![UTXO_2]
+        );
+
+        // The dropped transaction is pruned a day later.
+        btc_user_pending_transactions.prune_pending_transactions(
+            principal.clone(),
+            ADDRESS_1,
+            all_utxos,
+            all_utxos,
+            now_ns + DAY_IN_NS + 2,
+        );
+
+        let pending_txs =
+            btc_user_pending_transactions.get_pending_transactions(&principal, ADDRESS_1);
+        assert_eq!(pending_txs.len(), 1);
+        assert_eq!(pending_txs[0].txid, valid_transaction.txid);
+    }
+
+    #[test]
+    fn test_pending_transactions_with_spent_utxos_are_confirmed() {
+        let mut pending_transactions_map = prepare_btree();
+        let mut btc_user_pending_transactions =
+            BtcUserPendingTransactions::new(&mut pending_transactions_map, None, None);
+        let principal = Principal::from_text(PRINCIPAL_TEXT_1).unwrap();
+
+        let now_ns = 1_000_000_000_000;
+
+        let transaction_1 = StoredPendingTransaction {
+            txid: vec![1, 2, 3],
+            utxos: vec![UTXO_1],
+            created_at_timestamp_ns: now_ns,
+            outputs: None,
+            fee_satoshis: None,
+            status: None,
+            status_updated_at_timestamp_ns: None,
+        };
+        let transaction_2 = StoredPendingTransaction {
+            txid: vec![4, 5, 6],
+            utxos: vec![UTXO_2],
+            created_at_timestamp_ns: now_ns,
+            outputs: None,
+            fee_satoshis: None,
+            status: None,
+            status_updated_at_timestamp_ns: None,
+        };
+
+        btc_user_pending_transactions
+            .add_pending_transaction(
+                principal.clone(),
+                ADDRESS_1.to_string(),
+                transaction_1.clone(),
+            )
+            .unwrap();
+        btc_user_pending_transactions
+            .add_pending_transaction(
+                principal.clone(),
+                ADDRESS_1.to_string(),
+                transaction_2.clone(),
+            )
+            .unwrap();
+
+        let pending_txs =
+            btc_user_pending_transactions.get_pending_transactions(&principal, ADDRESS_1);
+        assert_eq!(pending_txs.len(), 2);
+
+        let change_utxo = Utxo {
+            outpoint: Outpoint {
+                txid: transaction_2.txid.clone(),
+                vout: 1,
+            },
+            value: 500,
+            height: 200,
+        };
+        let available_utxos = &[UTXO_1, change_utxo];
+        btc_user_pending_transactions.prune_pending_transactions(
+            principal.clone(),
+            ADDRESS_1,
+            available_utxos,
+            available_utxos,
+            now_ns,
+        );
+
+        let pending_txs =
+            btc_user_pending_transactions.get_pending_transactions(&principal, ADDRESS_1);
+        assert_eq!(pending_txs.len(), 2);
+        assert_eq!(pending_txs[0], transaction_1);
+        assert_eq!(
+            pending_txs[1].status(),
+            BtcPendingTransactionStatus::Confirmed { height: Some(200) }
+        );
+        assert_eq!(
+            btc_user_pending_transactions.get_pending_utxos(&principal, ADDRESS_1),
+            vec![UTXO_1]
+        );
+    }
+
+    #[test]
+    fn test_built_transaction_is_seen_then_confirmed() {
+        let mut pending_transactions_map = prepare_btree();
+        let mut btc_user_pending_transactions =
+            BtcUserPendingTransactions::new(&mut pending_transactions_map, None, None);
+        let principal = Principal::from_text(PRINCIPAL_TEXT_1).unwrap();
+
+        let now_ns = 1_000_000_000_000;
+        let mut transaction = StoredPendingTransaction {
+            txid: vec![1, 2, 3],
+            utxos: vec![UTXO_1],
+            created_at_timestamp_ns: now_ns,
+            outputs: None,
+            fee_satoshis: None,
+            status: Some(BtcPendingTransactionStatus::Built),
+            status_updated_at_timestamp_ns: None,
+        };
+        assert!(transaction.is_in_flight());
+        assert_eq!(
+            transaction.reported_status(),
+            BtcPendingTransactionStatus::Broadcast
+        );
+
+        transaction.status = Some(transaction.reported_status());
+        btc_user_pending_transactions
+            .add_pending_transaction(principal, ADDRESS_1.to_string(), transaction.clone())
+            .unwrap();
+        let status = |btc_user_pending_transactions: &BtcUserPendingTransactions| {
+            btc_user_pending_transactions
+                .get_pending_transaction(&principal, ADDRESS_1, &transaction.txid)
+                .unwrap()
+                .status()
+        };
+
+        // The network does not show the transaction yet.
+        btc_user_pending_transactions.prune_pending_transactions(
+            principal,
+            ADDRESS_1,
+            &[UTXO_1],
+            &[UTXO_1],
+            now_ns,
+        );
+        assert_eq!(
+            status(&btc_user_pending_transactions),
+            BtcPendingTransactionStatus::Broadcast
+        );
+
+        // The block spending the UTXO does not have enough confirmations yet.
+        btc_user_pending_transactions.prune_pending_transactions(
+            principal,
+            ADDRESS_1,
+            &[UTXO_1],
+            &[],
+            now_ns + 1,
+        );
+        assert_eq!(
+            status(&btc_user_pending_transactions),
+            BtcPendingTransactionStatus::InBlock { height: None }
+        );
+        let in_block_transaction = btc_user_pending_transactions
+            .get_pending_transaction(&principal, ADDRESS_1, &transaction.txid)
+            .unwrap();
+        assert_eq!(
+            in_block_transaction.status_updated_at_timestamp_ns,
+            Some(now_ns + 1)
+        );
+        // Reporting the transaction again does not forget that the network showed it.
+        assert_eq!(
+            in_block_transaction.reported_status(),
+            BtcPendingTransactionStatus::InBlock { height: None }
+        );
+
+        btc_user_pending_transactions.prune_pending_transactions(
+            principal,
+            ADDRESS_1,
+            &[],
+            &[],
+            now_ns + 2,
+        );
+        assert_eq!(
+            status(&btc_user_pending_transactions),
+            BtcPendingTransactionStatus::Confirmed { height: None }
+        );
+    }
+
+    #[test]
+    fn test_built_transaction_never_broadcast_is_dropped() {
+        let mut pending_transactions_map = prepare_btree();
+        let mut btc_user_pending_transactions =
+            BtcUserPendingTransactions::new(&mut pending_transactions_map, None, None);
+        let principal = Principal::from_text(PRINCIPAL_TEXT_1).unwrap();
+
+        let now_ns = 1_000_000_000_000;
+        let transaction = StoredPendingTransaction {
+            txid: vec![1, 2, 3],
+            utxos: vec![UTXO_1],
+            created_at_timestamp_ns: now_ns,
+            outputs: None,
+            fee_satoshis: None,
+            status: Some(BtcPendingTransactionStatus::Built),
+            status_updated_at_timestamp_ns: None,
+        };
+        btc_user_pending_transactions
+            .add_pending_transaction(principal, ADDRESS_1.to_string(), transaction.clone())
+            .unwrap();
+
+        btc_user_pending_transactions.prune_pending_transactions(
+            principal,
+            ADDRESS_1,
+            &[UTXO_1],
+            &[UTXO_1],
+            now_ns + DAY_IN_NS + 1,
+        );
+        assert_eq!(
+            btc_user_pending_transactions
+                .get_pending_transaction(&principal, ADDRESS_1, &transaction.txid)
+                .unwrap()
+                .status(),
+            BtcPendingTransactionStatus::Dropped
+        );
+        assert!(btc_user_pending_transactions
+            .get_pending_utxos(&principal, ADDRESS_1)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_confirmed_height_is_unknown_without_outputs_to_the_address() {
+        let mut pending_transactions_map = prepare_btree();
+        let mut btc_user_pending_transactions =
+            BtcUserPendingTransactions::new(&mut pending_transactions_map, None, None);
+        let principal = Principal::from_text(PRINCIPAL_TEXT_1).unwrap();
+
+        let now_ns = 1_000_000_000_000;
+        let transaction = StoredPendingTransaction {
+            txid: vec![1, 2, 3],
+            utxos: vec![UTXO_1],
+            created_at_timestamp_ns: now_ns,
+            outputs: None,
+            fee_satoshis: None,
+            status: None,
+            status_updated_at_timestamp_ns: None,
+        };
+        btc_user_pending_transactions
+            .add_pending_transaction(principal, ADDRESS_1.to_string(), transaction)
+            .unwrap();
+
+        btc_user_pending_transactions.prune_pending_transactions(
+            principal,
+            ADDRESS_1,
+            &[UTXO_2],
+            &[UTXO_2],
+            now_ns,
+        );
+
+        let pending_txs =
+            btc_user_pending_transactions.get_pending_transactions(&principal, ADDRESS_1);
+        assert_eq!(
+            pending_txs[0].status(),
+            BtcPendingTransactionStatus::Confirmed { height: None }
+        );
+        assert_eq!(pending_txs[0].status_updated_at_timestamp_ns, Some(now_ns));
+    }
+
+    #[test]
+    fn test_prune_ignores_other_addresses() {
+        let mut pending_transactions_map = prepare_btree();
+        let mut btc_user_pending_transactions =
+            BtcUserPendingTransactions::new(&mut pending_transactions_map, None, None);
+        let principal = Principal::from_text(PRINCIPAL_TEXT_1).unwrap();
+
+        let now_ns = 1_000_000_000_000;
+        let transaction = StoredPendingTransaction {
+            txid: vec![1, 2, 3],
+            utxos: vec![UTXO_1],
+            created_at_timestamp_ns: now_ns,
+            outputs: None,
+            fee_satoshis: None,
+            status: None,
+            status_updated_at_timestamp_ns: None,
+        };
+        btc_user_pending_transactions
+            .add_pending_transaction(principal, ADDRESS_2.to_string(), transaction.clone())
+            .unwrap();
+
+        btc_user_pending_transactions.prune_pending_transactions(
+            principal,
+            ADDRESS_1,
+            &[],
+            &[],
+            now_ns,
+        );
+
+        assert_eq!(
+            btc_user_pending_transactions.get_pending_transactions(&principal, ADDRESS_2),
+            vec![transaction]
+        );
+    }
+
+    #[test]
+    fn test_does_not_confirm_with_partial_available_utxos() {
+        let mut pending_transactions_map = prepare_btree();
+        let mut btc_user_pending_transactions =
+            BtcUserPendingTransactions::new(&mut pending_transactions_map, None, None);
+        let principal = Principal::from_text(PRINCIPAL_TEXT_1).unwrap();
+
+        let now_ns = 1_000_000_000_000;
+
+        let transaction_1 = StoredPendingTransaction {
+            txid: vec![1, 2, 3],
+            utxos: vec![UTXO_1],
+            created_at_timestamp_ns: now_ns,
+            outputs: None,
+            fee_satoshis: None,
+            status: None,
+            status_updated_at_timestamp_ns: None,
+        };
+        let transaction_2 = StoredPendingTransaction {
+            txid: vec![4, 5, 6],
+            utxos: vec![UTXO_2, UTXO_3],
+            created_at_timestamp_ns: now_ns,
+            outputs: None,
+            fee_satoshis: None,
+            status: None,
+            status_updated_at_timestamp_ns: None,
+        };
+
+        btc_user_pending_transactions
+            .add_pending_transaction(
+                principal.clone(),
+                ADDRESS_1.to_string(),
+                transaction_1.clone(),
+            )
+            .unwrap();
+        btc_user_pending_transactions
+            .add_pending_transaction(
+                principal.clone(),
+                ADDRESS_1.to_string(),
+                transaction_2.clone(),
+            )
+            .unwrap();
+
+        let pending_txs =
+            btc_user_pending_transactions.get_pending_transactions(&principal, ADDRESS_1);
+        assert_eq!(pending_txs.len(), 2);
+
+        let available_utxos = &[UTXO_1, UTXO_3];
+        btc_user_pending_transactions.prune_pending_transactions(
+            principal.clone(),
+            ADDRESS_1,
+            available_utxos,
+            available_utxos,
+            now_ns,
+        );
+
+        let pending_txs =
+            btc_user_pending_transactions.get_pending_transactions(&principal, ADDRESS_1);
+        assert_eq!(pending_txs, vec![transaction_1, transaction_2]);
+    }
+
+    #[test]
+    fn test_replace_pending_transaction() {
+        let mut pending_transactions_map = prepare_btree();
+        let mut btc_user_pending_transactions =
+            BtcUserPendingTransactions::new(&mut pending_transactions_map, None, None);
+        let principal = Principal::from_text(PRINCIPAL_TEXT_1).unwrap();
+
+        let transaction = StoredPendingTransaction {
+            txid: vec![1, 2, 3],
+            utxos: vec![UTXO_1],
+            created_at_timestamp_ns: 1_000_000,
+            outputs: None,
+            fee_satoshis: Some(100),
+            status: None,
+            status_updated_at_timestamp_ns: None,
+        };
+        let replacement = StoredPendingTransaction {
+            txid: vec![4, 5, 6],
+            utxos: vec![UTXO_1, UTXO_2],
+            created_at_timestamp_ns: 2_000_000,
+            outputs: None,
+            fee_satoshis: Some(300),
+            status: None,
+            status_updated_at_timestamp_ns: None,
+        };
+
+        btc_user_pending_transactions
+            .add_pending_transaction(principal, ADDRESS_1.to_string(), transaction.clone())
+            .unwrap();
+        btc_user_pending_transactions
+            .replace_pending_transaction(
+                principal,
+                ADDRESS_1.to_string(),
+                transaction.txid.clone(),
+                replacement.clone(),
+            )
+            .unwrap();
+
+        let pending_txs =
+            btc_user_pending_transactions.get_pending_transactions(&principal, ADDRESS_1);
+        assert_eq!(pending_txs.len(), 2);
+        assert_eq!(
+            pending_txs[0].status(),
+            BtcPendingTransactionStatus::Replaced {
+                txid: replacement.txid.clone()
+            }
+        );
+        assert_eq!(pending_txs[1], replacement);
+        assert_eq!(
+            btc_user_pending_transactions.get_pending_utxos(&principal, ADDRESS_1),
+            vec![UTXO_1, UTXO_2]
+        );
+        assert_eq!(
+            btc_user_pending_transactions.get_pending_transaction(
+                &principal,
+                ADDRESS_1,
+                &replacement.txid
+            ),
+            Some(replacement)
+        );
+    }
+
+    #[test]
+    fn test_replace_missing_pending_transaction_fails() {
+        let mut pending_transactions_map = prepare_btree();
+        let mut btc_user_pending_transactions =
+            BtcUserPendingTransactions::new(&mut pending_transactions_map, None, None);
+        let principal = Principal::from_text(PRINCIPAL_TEXT_1).unwrap();
+
+        let transaction = StoredPendingTransaction {
+            txid: vec![1, 2, 3],
+            utxos: vec![UTXO_1],
+            created_at_timestamp_ns: 1_000_000,
+            outputs: None,
+            fee_satoshis: None,
+            status: None,
+            status_updated_at_timestamp_ns: None,
+        };
+        btc_user_pending_transactions
+            .add_pending_transaction(principal, ADDRESS_1.to_string(), transaction.clone())
+            .unwrap();
+
+        let result = btc_user_pending_transactions.replace_pending_transaction(
+            principal,
+            ADDRESS_2.to_string(),
+            transaction.txid.clone(),
+            transaction.clone(),
+        );
+
+        assert_eq!(result, Err("Pending transaction not found".to_string()));
+        assert!(btc_user_pending_transactions
+            .get_pending_transactions(&principal, ADDRESS_2)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_remove_pending_transaction() {
+        let mut pending_transactions_map = prepare_btree();
+        let mut btc_user_pending_transactions =
+            BtcUserPendingTransactions::new(&mut pending_transactions_map, None, None);
+        let principal = Principal::from_text(PRINCIPAL_TEXT_1).unwrap();
+
+        let transaction = StoredPendingTransaction {
+            txid: vec![1, 2, 3],
+            utxos: vec![UTXO_1],
+            created_at_timestamp_ns: 1_000_000,
+            outputs: None,
+            fee_satoshis: None,
+            status: None,
+            status_updated_at_timestamp_ns: None,
+        };
+        btc_user_pending_transactions
+            .add_pending_transaction(principal, ADDRESS_1.to_string(), transaction.clone())
+            .unwrap();
+
+        assert_eq!(
+            btc_user_pending_transactions.remove_pending_transaction(
+                principal,
+                ADDRESS_2.to_string(),
+                transaction.txid.clone()
+            ),
+            Err("Pending transaction not found".to_string())
+        );
+        assert_eq!(
+            btc_user_pending_transactions.remove_pending_transaction(
+                principal,
+                ADDRESS_1.to_string(),
+                transaction.txid.clone()
+            ),
+            Ok(transaction)
+        );
+        assert!(btc_user_pending_transactions
+            .get_pending_transactions(&principal, ADDRESS_1)
+            .is_empty());
+        assert!(btc_user_pending_transactions
+            .get_pending_utxos(&principal, ADDRESS_1)
+            .is_empty());
+    }
+
+    fn change_of(transaction: &StoredPendingTransaction, value: u64) -> Utxo {
+        Utxo {
+            outpoint: Outpoint {
+                txid: transaction.txid.clone(),
+                vout: 1,
+            },
+            value,
+            height: 200,
+        }
+    }
+
+    #[test]
+    fn test_spending_in_flight_change_is_not_confirmed_before_it() {
+        let mut pending_transactions_map = prepare_btree();
+        let mut btc_user_pending_transactions =
+            BtcUserPendingTransactions::new(&mut pending_transactions_map, None, None);
+        let principal = Principal::from_text(PRINCIPAL_TEXT_1).unwrap();
+
+        let now_ns = 1_000_000_000_000;
+        let parent = StoredPendingTransaction {
+            txid: vec![1, 2, 3],
+            utxos: vec![UTXO_1],
+            created_at_timestamp_ns: now_ns,
+            outputs: None,
+            fee_satoshis: None,
+            status: None,
+            status_updated_at_timestamp_ns: None,
+        };
+        let child = StoredPendingTransaction {
+            txid: vec![4, 5, 6],
+            utxos: vec![change_of(&parent, 500)],
+            ..parent.clone()
+        };
+        for transaction in [&parent, &child] {
+            btc_user_pending_transactions
+                .add_pending_transaction(principal, ADDRESS_1.to_string(), transaction.clone())
+                .unwrap();
+        }
+
+        // The change of the parent is not confirmed enough to be in the current utxos.
+        btc_user_pending_transactions.prune_pending_transactions(
+            principal,
+            ADDRESS_1,
+            &[UTXO_1],
+            &[change_of(&parent, 500)],
+            now_ns,
+        );
+        let statuses: Vec<_> = btc_user_pending_transactions
+            .get_pending_transactions(&principal, ADDRESS_1)
+            .iter()
+            .map(StoredPendingTransaction::status)
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                BtcPendingTransactionStatus::InBlock { height: Some(200) },
+                BtcPendingTransactionStatus::Broadcast
+            ]
+        );
+
+        btc_user_pending_transactions.prune_pending_transactions(
+            principal,
+            ADDRESS_1,
+            &[],
+            &[],
+            now_ns,
+        );
+        btc_user_pending_transactions.prune_pending_transactions(
+            principal,
+            ADDRESS_1,
+            &[],
+            &[],
+            now_ns,
+        );
+        let statuses: Vec<_> = btc_user_pending_transactions
+            .get_pending_transactions(&principal, ADDRESS_1)
+            .iter()
+            .map(StoredPendingTransaction::status)
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                BtcPendingTransactionStatus::Confirmed { height: None },
+                BtcPendingTransactionStatus::Confirmed { height: None }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_spendable_unconfirmed_change_limits_the_chain_depth() {
+        let transaction_1 = StoredPendingTransaction {
+            txid: vec![1, 2, 3],
+            utxos: vec![UTXO_1],
+            created_at_timestamp_ns: 1_000_000,
+            outputs: None,
+            fee_satoshis: None,
+            status: None,
+            status_updated_at_timestamp_ns: None,
+        };
+        let transaction_2 = StoredPendingTransaction {
+            txid: vec![4, 5, 6],
+            utxos: vec![UTXO_2, change_of(&transaction_1, 500)],
+            ..transaction_1.clone()
+        };
+        let transaction_3 = StoredPendingTransaction {
+            txid: vec![7, 8, 9],
+            utxos: vec![change_of(&transaction_2, 400)],
+            ..transaction_1.clone()
+        };
+        let pending_transactions = [transaction_1, transaction_2, transaction_3];
+        let other_utxo = Utxo {
+            height: 200,
+            ..UTXO_3
+        };
+        let unconfirmed_utxos: Vec<Utxo> = pending_transactions
+            .iter()
+            .map(|transaction| change_of(transaction, 300))
+            .chain([other_utxo])
+            .collect();
+
+        assert_eq!(
+            spendable_unconfirmed_change(&pending_transactions, &unconfirmed_utxos, 2),
+            unconfirmed_utxos[..2].to_vec()
+        );
+        assert_eq!(
+            spendable_unconfirmed_change(&pending_transactions, &unconfirmed_utxos, 3),
+            unconfirmed_utxos[..3].to_vec()
+        );
+        assert!(
+            spendable_unconfirmed_change(&pending_transactions, &unconfirmed_utxos, 0).is_empty()
+        );
+    }
+
+    #[test]
+    fn test_spendable_unconfirmed_change_only_counts_in_flight_transactions() {
+        let confirmed = StoredPendingTransaction {
+            txid: vec![1, 2, 3],
+            utxos: vec![UTXO_1],
+            created_at_timestamp_ns: 1_000_000,
+            outputs: None,
+            fee_satoshis: None,
+            status: Some(BtcPendingTransactionStatus::Confirmed { height: Some(200) }),
+            status_updated_at_timestamp_ns: None,
+        };
+        let child = StoredPendingTransaction {
+            txid: vec![4, 5, 6],
+            utxos: vec![change_of(&confirmed, 500)],
+            status: Some(BtcPendingTransactionStatus::InBlock { height: Some(200) }),
+            ..confirmed.clone()
+        };
+        let dropped = StoredPendingTransaction {
+            txid: vec![7, 8, 9],
+            utxos: vec![UTXO_2],
+            status: Some(BtcPendingTransactionStatus::Dropped),
+            ..confirmed.clone()
+        };
+        let replaced = StoredPendingTransaction {
+            txid: vec![10, 11, 12],
+            utxos: vec![UTXO_3],
+            status: Some(BtcPendingTransactionStatus::Replaced {
+                txid: vec![13, 14, 15],
+            }),
+            ..confirmed.clone()
+        };
+        let pending_transactions = [confirmed, child, dropped, replaced];
+        let unconfirmed_utxos: Vec<Utxo> = pending_transactions
+            .iter()
+            .map(|transaction| change_of(transaction, 300))
+            .collect();
+
+        // The child descends from a single in-flight transaction, as its parent is confirmed.
+        assert_eq!(
+            spendable_unconfirmed_change(&pending_transactions, &unconfirmed_utxos, 1),
+            vec![unconfirmed_utxos[1].clone()]
+        );
+    }
+
+    #[test]
+    fn test_max_unconfirmed_chain_depth_follows_the_network_policy() {
+        assert_eq!(
+            max_unconfirmed_chain_depth(None, BtcNetwork::Mainnet),
+            MAX_UNCONFIRMED_CHAIN_DEPTH
+        );
+
+        let policies = [BtcNetworkPolicy {
+            network: BtcNetwork::Regtest,
+            max_min_confirmations: Some(1),
+            max_unconfirmed_chain_depth: Some(5),
+        }];
+        assert_eq!(
+            max_unconfirmed_chain_depth(Some(&policies), BtcNetwork::Regtest),
+            5
+        );
+        assert_eq!(
+            max_unconfirmed_chain_depth(Some(&policies), BtcNetwork::Mainnet),
+            MAX_UNCONFIRMED_CHAIN_DEPTH
+        );
+    }
+}
@@ -0,0 +1,303 @@
+//! Access to the ckBTC minter.
+//!
+//! The canister calls the minter configured in `ckbtc_minter_canister_id`, and its ledger
+//! configured in `ckbtc_ledger_canister_id`, through the `CkBtcMinter` trait. Builds with the
+//! `ckbtc-minter-stand-in` feature use the programmable `StandInCkBtcMinter` instead, so that tests
+//! do not need a minter.
+use candid::{Nat, Principal};
+use ic_ckbtc_minter_client::{
+    Account, EstimateWithdrawalFeeArg, GetBtcAddressArg, RetrieveBtcStatusV2,
+    RetrieveBtcStatusV2Arg, Service as CkBtcMinterService,
+};
+// Any ICRC-3 ledger, such as the ckBTC ledger, serves its blocks like the cycles ledger.
+use ic_cycles_ledger_client::{
+    GetBlocksArgsItem, GetBlocksResult, Service as LedgerService, Value,
+};
+use shared::types::ckbtc::{CkBtcEstimateWithdrawalFeeResponse, CkBtcWithdrawalStatus};
+
+#[cfg(any(test, feature = "ckbtc-minter-stand-in"))]
+pub mod stand_in;
+
+#[cfg(feature = "ckbtc-minter-stand-in")]
+use stand_in::StandInCkBtcMinter;
+
+/// The calls made to the ckBTC minter.
+pub trait CkBtcMinter {
+    /// Returns the bitcoin address where the owner deposits BTC to get ckBTC.
+    async fn get_btc_address(&self, owner: Principal) -> Result<String, String>;
+
+    /// Returns the fees of withdrawing the given amount, or a typical withdrawal if not set.
+    async fn estimate_withdrawal_fee(
+        &self,
+        amount_satoshis: Option<u64>,
+    ) -> Result<CkBtcEstimateWithdrawalFeeResponse, String>;
+
+    /// Returns the status of the withdrawal with the given burn block index.
+    async fn retrieve_btc_status(&self, block_index: u64) -> Result<CkBtcWithdrawalStatus, String>;
+
+    /// Returns the burn block indices of the withdrawals that the owner requested with
+    /// `retrieve_btc_with_approval`.
+    async fn withdrawal_block_indices(&self, owner: Principal) -> Result<Vec<u64>, String>;
+
+    /// Returns the burn of the withdrawal with the given burn block index, read from the ckBTC
+    /// ledger, or `None` if the block is not the burn of a withdrawal.
+    async fn withdrawal_burn(&self, block_index: u64) -> Result<Option<WithdrawalBurn>, String>;
+}
+
+/// The ckBTC burned by a withdrawal.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct WithdrawalBurn {
+    /// The destination of the BTC, from the memo of the burn.
+    pub address: String,
+    pub amount_satoshis: u64,
+}
+
+impl CkBtcMinter for CkBtcMinterService {
+    async fn get_btc_address(&self, owner: Principal) -> Result<String, String> {
+        let (address,) = CkBtcMinterService::get_btc_address(
+            self,
+            &GetBtcAddressArg {
+                owner: Some(owner),
+                subaccount: None,
+            },
+        )
+        .await
+        .map_err(|err| err.1)?;
+        Ok(address)
+    }
+
+    async fn estimate_withdrawal_fee(
+        &self,
+        amount_satoshis: Option<u64>,
+    ) -> Result<CkBtcEstimateWithdrawalFeeResponse, String> {
+        let (fee,) = CkBtcMinterService::estimate_withdrawal_fee(
+            self,
+            &EstimateWithdrawalFeeArg {
+                amount: amount_satoshis,
+            },
+        )
+        .await
+        .map_err(|err| err.1)?;
+        Ok(CkBtcEstimateWithdrawalFeeResponse {
+            bitcoin_fee_satoshis: fee.bitcoin_fee,
+            minter_fee_satoshis: fee.minter_fee,
+        })
+    }
+
+    async fn retrieve_btc_status(&self, block_index: u64) -> Result<CkBtcWithdrawalStatus, String> {
+        let (status,) = self
+            .retrieve_btc_status_v2(&RetrieveBtcStatusV2Arg { block_index })
+            .await
+            .map_err(|err| err.1)?;
+        Ok(withdrawal_status(status))
+    }
+
+    async fn withdrawal_block_indices(&self, owner: Principal) -> Result<Vec<u64>, String> {
+        let (withdrawals,) = self
+            .retrieve_btc_status_v2_by_account(&Some(Account {
+                owner,
+                subaccount: None,
+            }))
+            .await
+            .map_err(|err| err.1)?;
+        Ok(withdrawals
+            .into_iter()
+            .map(|withdrawal| withdrawal.block_index)
+            .collect())
+    }
+
+    async fn withdrawal_burn(&self, block_index: u64) -> Result<Option<WithdrawalBurn>, String> {
+        let ledger = crate::read_config(|config| config.ckbtc_ledger_canister_id)
+            .map(LedgerService)
+            .ok_or_else(|| "The ckBTC ledger is not configured".to_string())?;
+        let args = vec![GetBlocksArgsItem {
+            start: block_index.into(),
+            length: 1_u64.into(),
+        }];
+        let (mut blocks,) = ledger.icrc_3_get_blocks(&args).await.map_err(|err| err.1)?;
+        // The ledger may have moved the block to an archive already.
+        if let Some(archived) = blocks.archived_blocks.first() {
+            let callback = &archived.callback.0;
+            (blocks,) = ic_cdk::call::<_, (GetBlocksResult,)>(
+                callback.principal,
+                &callback.method,
+                (&archived.args,),
+            )
+            .await
+            .map_err(|err| err.1)?;
+        }
+        Ok(blocks
+            .blocks
+            .iter()
+            .find(|block| block.id == Nat::from(block_index))
+            .and_then(|block| withdrawal_burn(&block.block)))
+    }
+}
+
+/// The burn of a withdrawal in an ICRC-3 block, if the block is a burn with the memo of
+/// `retrieve_btc_with_approval`.
+fn withdrawal_burn(block: &Value) -> Option<WithdrawalBurn> {
+    let transaction = field(block, "tx")?;
+    if !matches!(field(transaction, "op")?, Value::Text(op) if op == "burn") {
+        return None;
+    }
+    let amount_satoshis = match field(transaction, "amt")? {
+        Value::Nat(amount) => u64::try_from(&amount.0).ok()?,
+        Value::Nat64(amount) => *amount,
+        _ => return None,
+    };
+    let Value::Blob(memo) = field(transaction, "memo")? else {
+        return None;
+    };
+    Some(WithdrawalBurn {
+        address: burn_memo_address(memo)?,
+        amount_satoshis,
+    })
+}
+
+/// The value of a field of an ICRC-3 map.
+fn field<'a>(value: &'a Value, name: &str) -> Option<&'a Value> {
+    let Value::Map(fields) = value else {
+        return None;
+    };
+    fields
+        .iter()
+        .find(|(field_name, _)| field_name == name)
+        .map(|(_, value)| value.as_ref())
+}
+
+/// The destination address in the memo of a withdrawal burn.
+///
+/// The minter encodes the memo `BurnMemo::Convert { address, kyt_fee, status }` in CBOR as
+/// `[0, [address, kyt_fee, status]]`, without the trailing fields that are not set.
+fn burn_memo_address(memo: &[u8]) -> Option<String> {
+    let [0x82, 0x00, 0x81..=0x97, address @ ..] = memo else {
+        return None;
+    };
+    let (length, address) = match address {
+        [header @ 0x60..=0x77, address @ ..] => (usize::from(header - 0x60), address),
+        [0x78, length, address @ ..] => (usize::from(*length), address),
+        _ => return None,
+    };
+    String::from_utf8(address.get(..length)?.to_vec()).ok()
+}
+
+fn withdrawal_status(status: RetrieveBtcStatusV2) -> CkBtcWithdrawalStatus {
+    match status {
+        RetrieveBtcStatusV2::Unknown => CkBtcWithdrawalStatus::Unknown,
+        RetrieveBtcStatusV2::Pending => CkBtcWithdrawalStatus::Pending,
+        RetrieveBtcStatusV2::Signing => CkBtcWithdrawalStatus::Signing,
+        RetrieveBtcStatusV2::Sending { txid } => CkBtcWithdrawalStatus::Sending {
+            txid: txid.into_vec(),
+        },
+        RetrieveBtcStatusV2::Submitted { txid } => CkBtcWithdrawalStatus::Submitted {
+            txid: txid.into_vec(),
+        },
+        RetrieveBtcStatusV2::Confirmed { txid } => CkBtcWithdrawalStatus::Confirmed {
+            txid: txid.into_vec(),
+        },
+        RetrieveBtcStatusV2::AmountTooLow => CkBtcWithdrawalStatus::AmountTooLow,
+        RetrieveBtcStatusV2::WillReimburse(_) => CkBtcWithdrawalStatus::WillReimburse,
+        RetrieveBtcStatusV2::Reimbursed(deposit) => CkBtcWithdrawalStatus::Reimbursed {
+            mint_block_index: deposit.mint_block_index,
+        },
+    }
+}
+
+/// The minter used by the canister.
+///
+/// # Errors
+/// - If no minter is configured.
+#[cfg(not(feature = "ckbtc-minter-stand-in"))]
+pub fn ckbtc_minter() -> Result<CkBtcMinterService, String> {
+    crate::read_config(|config| config.ckbtc_minter_canister_id)
+        .map(CkBtcMinterService)
+        .ok_or_else(|| "The ckBTC minter is not configured".to_string())
+}
+
+/// The minter used by the canister.
+///
+/// # Errors
+/// Never, the stand-in is always available.
+#[cfg(feature = "ckbtc-minter-stand-in")]
+#[allow(clippy::unnecessary_wraps)]
+pub fn ckbtc_minter() -> Result<StandInCkBtcMinter, String> {
+    Ok(stand_in::canister_stand_in())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_bytes::ByteBuf;
+
+    const ADDRESS: &str = "bc1qpg7udjvq7gx2fp480pgt4hnhj3qc4nhrkstc33";
+
+    /// The memo of a withdrawal to `ADDRESS`, with the given CBOR encoding of the fields after the
+    /// address.
+    fn memo(field_count: u8, other_fields: &[u8]) -> Vec<u8> {
+        let mut memo = vec![
+            0x82,
+            0x00,
+            0x80 + field_count,
+            0x78,
+            u8::try_from(ADDRESS.len()).unwrap(),
+        ];
+        memo.extend_from_slice(ADDRESS.as_bytes());
+        memo.extend_from_slice(other_fields);
+        memo
+    }
+
+    fn block(op: &str, amount: u64, memo: Vec<u8>) -> Value {
+        let transaction = Value::Map(vec![
+            ("op".to_string(), Box::new(Value::Text(op.to_string()))),
+            ("amt".to_string(), Box::new(Value::Nat(amount.into()))),
+            (
+                "memo".to_string(),
+                Box::new(Value::Blob(ByteBuf::from(memo))),
+            ),
+        ]);
+        Value::Map(vec![
+            ("ts".to_string(), Box::new(Value::Nat64(1))),
+            ("tx".to_string(), Box::new(transaction)),
+        ])
+    }
+
+    #[test]
+    fn burn_memo_address_is_decoded() {
+        assert_eq!(burn_memo_address(&memo(1, &[])), Some(ADDRESS.to_string()));
+        // A KYT fee of 2000 and no status.
+        assert_eq!(
+            burn_memo_address(&memo(3, &[0x19, 0x07, 0xd0, 0xf6])),
+            Some(ADDRESS.to_string())
+        );
+        let short_address = [0x82, 0x00, 0x81, 0x63, b'a', b'b', b'c'];
+        assert_eq!(burn_memo_address(&short_address), Some("abc".to_string()));
+    }
+
+    #[test]
+    fn other_memos_have_no_address() {
+        assert_eq!(burn_memo_address(&[]), None);
+        // Another variant.
+        assert_eq!(
+            burn_memo_address(&[0x82, 0x01, 0x81, 0x63, b'a', b'b', b'c']),
+            None
+        );
+        // No address.
+        assert_eq!(burn_memo_address(&[0x82, 0x00, 0x81, 0xf6]), None);
+        // A truncated address.
+        assert_eq!(burn_memo_address(&memo(1, &[])[..20]), None);
+    }
+
+    #[test]
+    fn withdrawal_burns_are_read_from_blocks() {
+        assert_eq!(
+            withdrawal_burn(&block("burn", 100_000, memo(1, &[]))),
+            Some(WithdrawalBurn {
+                address: ADDRESS.to_string(),
+                amount_satoshis: 100_000,
+            })
+        );
+        assert_eq!(withdrawal_burn(&block("xfer", 100_000, memo(1, &[]))), None);
+        assert_eq!(withdrawal_burn(&block("burn", 100_000, vec![])), None);
+    }
+}
@@ -0,0 +1,148 @@
+//! A programmable ckBTC minter, for tests.
+//!
+//! Tests set the owners, burns and statuses of the withdrawals that the stand-in reports. The
+//! canister built with the `ckbtc-minter-stand-in` feature uses a stand-in that the controllers
+//! program through dedicated endpoints.
+use super::{CkBtcMinter, WithdrawalBurn};
+use bitcoin::{Address, Network, ScriptBuf};
+use candid::Principal;
+use shared::types::ckbtc::{CkBtcEstimateWithdrawalFeeResponse, CkBtcWithdrawalStatus};
+use std::{cell::RefCell, collections::BTreeMap, rc::Rc};
+
+/// The bitcoin fee of any withdrawal, in satoshis.
+pub const STAND_IN_BITCOIN_FEE_SATOSHIS: u64 = 2_000;
+/// The minter fee of any withdrawal, in satoshis.
+pub const STAND_IN_MINTER_FEE_SATOSHIS: u64 = 300;
+
+#[cfg(feature = "ckbtc-minter-stand-in")]
+thread_local! {
+    static CANISTER_STAND_IN: StandInCkBtcMinter = StandInCkBtcMinter::default();
+}
+
+/// The stand-in used by the canister built with the `ckbtc-minter-stand-in` feature.
+#[cfg(feature = "ckbtc-minter-stand-in")]
+pub fn canister_stand_in() -> StandInCkBtcMinter {
+    CANISTER_STAND_IN.with(Clone::clone)
+}
+
+/// A ckBTC minter that reports programmed withdrawal owners, burns and statuses.
+///
+/// Clones share the same withdrawals. The deposit addresses are regtest addresses derived from
+/// the owner, and all withdrawals have the same fees.
+#[derive(Clone, Debug, Default)]
+pub struct StandInCkBtcMinter {
+    statuses: Rc<RefCell<BTreeMap<u64, CkBtcWithdrawalStatus>>>,
+    withdrawals: Rc<RefCell<BTreeMap<u64, (Principal, WithdrawalBurn)>>>,
+}
+
+impl StandInCkBtcMinter {
+    /// Sets the status of the withdrawal with the given burn block index, `Pending` by default.
+    pub fn set_withdrawal_status(&self, block_index: u64, status: CkBtcWithdrawalStatus) {
+        self.statuses.borrow_mut().insert(block_index, status);
+    }
+
+    /// Sets the owner and the burn of the withdrawal with the given burn block index, none by
+    /// default.
+    pub fn set_withdrawal(&self, block_index: u64, owner: Principal, burn: WithdrawalBurn) {
+        self.withdrawals
+            .borrow_mut()
+            .insert(block_index, (owner, burn));
+    }
+}
+
+impl CkBtcMinter for StandInCkBtcMinter {
+    async fn get_btc_address(&self, owner: Principal) -> Result<String, String> {
+        let script = ScriptBuf::from_bytes(owner.as_slice().to_vec());
+        Ok(Address::p2wsh(&script, Network::Regtest).to_string())
+    }
+
+    async fn estimate_withdrawal_fee(
+        &self,
+        _amount_satoshis: Option<u64>,
+    ) -> Result<CkBtcEstimateWithdrawalFeeResponse, String> {
+        Ok(CkBtcEstimateWithdrawalFeeResponse {
+            bitcoin_fee_satoshis: STAND_IN_BITCOIN_FEE_SATOSHIS,
+            minter_fee_satoshis: STAND_IN_MINTER_FEE_SATOSHIS,
+        })
+    }
+
+    async fn retrieve_btc_status(&self, block_index: u64) -> Result<CkBtcWithdrawalStatus, String> {
+        Ok(self
+            .statuses
+            .borrow()
+            .get(&block_index)
+            .cloned()
+            .unwrap_or(CkBtcWithdrawalStatus::Pending))
+    }
+
+    async fn withdrawal_block_indices(&self, owner: Principal) -> Result<Vec<u64>, String> {
+        Ok(self
+            .withdrawals
+            .borrow()
+            .iter()
+            .filter(|(_, (withdrawal_owner, _))| *withdrawal_owner == owner)
+            .map(|(block_index, _)| *block_index)
+            .collect())
+    }
+
+    async fn withdrawal_burn(&self, block_index: u64) -> Result<Option<WithdrawalBurn>, String> {
+        Ok(self
+            .withdrawals
+            .borrow()
+            .get(&block_index)
+            .map(|(_, burn)| burn.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+
+    #[test]
+    fn deposit_addresses_depend_on_the_owner() {
+        let minter = StandInCkBtcMinter::default();
+        let address = |owner| block_on(minter.get_btc_address(owner)).unwrap();
+
+        let anonymous = address(Principal::anonymous());
+        assert!(anonymous.starts_with("bcrt1"));
+        assert_eq!(anonymous, address(Principal::anonymous()));
+        assert_ne!(anonymous, address(Principal::management_canister()));
+    }
+
+    #[test]
+    fn reports_programmed_statuses() {
+        let minter = StandInCkBtcMinter::default();
+        let confirmed = CkBtcWithdrawalStatus::Confirmed { txid: vec![1; 32] };
+        minter.clone().set_withdrawal_status(1, confirmed.clone());
+
+        assert_eq!(block_on(minter.retrieve_btc_status(1)), Ok(confirmed));
+        assert_eq!(
+            block_on(minter.retrieve_btc_status(2)),
+            Ok(CkBtcWithdrawalStatus::Pending)
+        );
+    }
+
+    #[test]
+    fn reports_withdrawals_of_programmed_owners() {
+        let minter = StandInCkBtcMinter::default();
+        let burn = WithdrawalBurn {
+            address: "bcrt1qpg7udjvq7gx2fp480pgt4hnhj3qc4nhrkstc33".to_string(),
+            amount_satoshis: 100_000,
+        };
+        minter.set_withdrawal(1, Principal::anonymous(), burn.clone());
+        minter.set_withdrawal(2, Principal::management_canister(), burn.clone());
+        minter.set_withdrawal(3, Principal::anonymous(), burn.clone());
+
+        assert_eq!(
+            block_on(minter.withdrawal_block_indices(Principal::anonymous())),
+            Ok(vec![1, 3])
+        );
+        assert_eq!(
+            block_on(minter.withdrawal_block_indices(Principal::management_canister())),
+            Ok(vec![2])
+        );
+        assert_eq!(block_on(minter.withdrawal_burn(2)), Ok(Some(burn)));
+        assert_eq!(block_on(minter.withdrawal_burn(4)), Ok(None));
+    }
+}
@@ -0,0 +1,237 @@
+use crate::types::{Candid, CkBtcUserWithdrawalsMap, StoredPrincipal};
+use candid::{CandidType, Deserialize};
+use shared::types::ckbtc::{CkBtcWithdrawal, CkBtcWithdrawalStatus};
+
+const MAX_WITHDRAWALS_PER_USER: usize = 100;
+/// How long a withdrawal is kept after the minter reported its final status, in nanoseconds.
+const FINAL_WITHDRAWAL_RETENTION_NS: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+/// A `retrieve_btc_with_approval` request of a user, tracked until the minter settles it.
+#[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+pub struct StoredCkBtcWithdrawal {
+    pub block_index: u64,
+    pub address: String,
+    pub amount_satoshis: u64,
+    pub created_at_timestamp_ns: u64,
+    /// The last status reported by the minter. `None` until the minter is first asked.
+    pub status: Option<CkBtcWithdrawalStatus>,
+    /// When the minter first reported a final status.
+    pub finalized_at_timestamp_ns: Option<u64>,
+}
+
+impl StoredCkBtcWithdrawal {
+    /// Whether the minter will not change the status of the withdrawal anymore.
+    ///
+    /// The minter reports `Unknown` for block indices that are not withdrawals, which will never
+    /// change either.
+    pub fn is_final(&self) -> bool {
+        matches!(
+            self.status,
+            Some(
+                CkBtcWithdrawalStatus::Unknown
+                    | CkBtcWithdrawalStatus::Confirmed { .. }
+                    | CkBtcWithdrawalStatus::AmountTooLow
+                    | CkBtcWithdrawalStatus::Reimbursed { .. }
+            )
+        )
+    }
+}
+
+impl From<StoredCkBtcWithdrawal> for CkBtcWithdrawal {
+    fn from(withdrawal: StoredCkBtcWithdrawal) -> Self {
+        CkBtcWithdrawal {
+            block_index: withdrawal.block_index,
+            address: withdrawal.address,
+            amount_satoshis: withdrawal.amount_satoshis,
+            created_at_timestamp_ns: withdrawal.created_at_timestamp_ns,
+            status: withdrawal.status.unwrap_or(CkBtcWithdrawalStatus::Pending),
+        }
+    }
+}
+
+/// Returns the withdrawals of a user, oldest first.
+pub fn get_withdrawals(
+    withdrawals_map: &CkBtcUserWithdrawalsMap,
+    principal: StoredPrincipal,
+) -> Vec<StoredCkBtcWithdrawal> {
+    withdrawals_map.get(&principal).unwrap_or_default().0
+}
+
+/// Tracks a withdrawal of a user. A withdrawal with the same block index is replaced.
+///
+/// Fails, without changing anything, if the user would have more than
+/// `MAX_WITHDRAWALS_PER_USER` withdrawals.
+pub fn add_withdrawal(
+    withdrawals_map: &mut CkBtcUserWithdrawalsMap,
+    principal: StoredPrincipal,
+    withdrawal: StoredCkBtcWithdrawal,
+) -> Result<(), String> {
+    let mut withdrawals = get_withdrawals(withdrawals_map, principal);
+    withdrawals.retain(|stored| stored.block_index != withdrawal.block_index);
+    if withdrawals.len() >= MAX_WITHDRAWALS_PER_USER {
+        return Err(format!(
+            "Withdrawals should not exceed {MAX_WITHDRAWALS_PER_USER}"
+        ));
+    }
+    withdrawals.push(withdrawal);
+    withdrawals_map.insert(principal, Candid(withdrawals));
+    Ok(())
+}
+
+/// Records the statuses reported by the minter, given by block index, and removes the
+/// withdrawals whose status has been final for longer than `FINAL_WITHDRAWAL_RETENTION_NS`.
+///
+/// Returns the remaining withdrawals of the user.
+pub fn update_withdrawal_statuses(
+    withdrawals_map: &mut CkBtcUserWithdrawalsMap,
+    principal: StoredPrincipal,
+    statuses: &[(u64, CkBtcWithdrawalStatus)],
+    now_ns: u64,
+) -> Vec<StoredCkBtcWithdrawal> {
+    let mut withdrawals = get_withdrawals(withdrawals_map, principal);
+    for withdrawal in &mut withdrawals {
+        if let Some((_, status)) = statuses
+            .iter()
+            .find(|(block_index, _)| *block_index == withdrawal.block_index)
+        {
+            withdrawal.status = Some(status.clone());
+            if withdrawal.is_final() && withdrawal.finalized_at_timestamp_ns.is_none() {
+                withdrawal.finalized_at_timestamp_ns = Some(now_ns);
+            }
+        }
+    }
+    withdrawals.retain(|withdrawal| {
+        withdrawal
+            .finalized_at_timestamp_ns
+            .map_or(true, |finalized_at| {
+                now_ns.saturating_sub(finalized_at) < FINAL_WITHDRAWAL_RETENTION_NS
+            })
+    });
+    if withdrawals.is_empty() {
+        withdrawals_map.remove(&principal);
+    } else {
+        withdrawals_map.insert(principal, Candid(withdrawals.clone()));
+    }
+    withdrawals
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candid::Principal;
+    use ic_stable_structures::{
+        memory_manager::{MemoryId, MemoryManager},
+        DefaultMemoryImpl,
+    };
+
+    const PRINCIPAL_TEXT: &str = "7blps-itamd-lzszp-7lbda-4nngn-fev5u-2jvpn-6y3ap-eunp7-kz57e-fqe";
+
+    fn prepare_btree() -> CkBtcUserWithdrawalsMap {
+        const CKBTC_USER_WITHDRAWALS_MEMORY_ID: MemoryId = MemoryId::new(8);
+        let memory_manager = MemoryManager::init(DefaultMemoryImpl::default());
+        CkBtcUserWithdrawalsMap::new(memory_manager.get(CKBTC_USER_WITHDRAWALS_MEMORY_ID))
+    }
+
+    fn principal() -> StoredPrincipal {
+        StoredPrincipal(Principal::from_text(PRINCIPAL_TEXT).unwrap())
+    }
+
+    fn withdrawal(block_index: u64) -> StoredCkBtcWithdrawal {
+        StoredCkBtcWithdrawal {
+            block_index,
+            address: "bcrt1qpg7udjvq7gx2fp480pgt4hnhj3qc4nhrkstc33".to_string(),
+            amount_satoshis: 100_000,
+            created_at_timestamp_ns: 0,
+            status: None,
+            finalized_at_timestamp_ns: None,
+        }
+    }
+
+    #[test]
+    fn test_add_withdrawal_replaces_same_block_index() {
+        let mut withdrawals_map = prepare_btree();
+
+        add_withdrawal(&mut withdrawals_map, principal(), withdrawal(1)).unwrap();
+        add_withdrawal(&mut withdrawals_map, principal(), withdrawal(2)).unwrap();
+        let mut replacement = withdrawal(1);
+        replacement.amount_satoshis = 200_000;
+        add_withdrawal(&mut withdrawals_map, principal(), replacement.clone()).unwrap();
+
+        assert_eq!(
+            get_withdrawals(&withdrawals_map, principal()),
+            vec![withdrawal(2), replacement]
+        );
+    }
+
+    #[test]
+    fn test_add_withdrawal_max_limit() {
+        let mut withdrawals_map = prepare_btree();
+        for block_index in 0..MAX_WITHDRAWALS_PER_USER as u64 {
+            add_withdrawal(&mut withdrawals_map, principal(), withdrawal(block_index)).unwrap();
+        }
+
+        assert_eq!(
+            add_withdrawal(
+                &mut withdrawals_map,
+                principal(),
+                withdrawal(MAX_WITHDRAWALS_PER_USER as u64)
+            ),
+            Err(format!(
+                "Withdrawals should not exceed {MAX_WITHDRAWALS_PER_USER}"
+            ))
+        );
+        // Replacing a withdrawal is still possible.
+        assert_eq!(
+            add_withdrawal(&mut withdrawals_map, principal(), withdrawal(0)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_final_withdrawals_are_kept_for_a_day() {
+        let mut withdrawals_map = prepare_btree();
+        add_withdrawal(&mut withdrawals_map, principal(), withdrawal(1)).unwrap();
+        add_withdrawal(&mut withdrawals_map, principal(), withdrawal(2)).unwrap();
+        let confirmed = CkBtcWithdrawalStatus::Confirmed { txid: vec![1; 32] };
+
+        let withdrawals = update_withdrawal_statuses(
+            &mut withdrawals_map,
+            principal(),
+            &[(1, CkBtcWithdrawalStatus::Signing), (2, confirmed.clone())],
+            10,
+        );
+        assert_eq!(
+            withdrawals
+                .iter()
+                .map(|withdrawal| (withdrawal.status.clone(), withdrawal.is_final()))
+                .collect::<Vec<_>>(),
+            vec![
+                (Some(CkBtcWithdrawalStatus::Signing), false),
+                (Some(confirmed.clone()), true)
+            ]
+        );
+
+        // The final status is not polled again, so it keeps its finalization time.
+        let withdrawals = update_withdrawal_statuses(
+            &mut withdrawals_map,
+            principal(),
+            &[(1, CkBtcWithdrawalStatus::Signing)],
+            10 + FINAL_WITHDRAWAL_RETENTION_NS - 1,
+        );
+        assert_eq!(withdrawals.len(), 2);
+
+        let withdrawals = update_withdrawal_statuses(
+            &mut withdrawals_map,
+            principal(),
+            &[(1, CkBtcWithdrawalStatus::Signing)],
+            10 + FINAL_WITHDRAWAL_RETENTION_NS,
+        );
+        assert_eq!(
+            withdrawals
+                .iter()
+                .map(|withdrawal| withdrawal.block_index)
+                .collect::<Vec<_>>(),
+            vec![1]
+        );
+    }
+}
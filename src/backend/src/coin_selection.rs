@@ -0,0 +1,817 @@
+//! Selection of the UTXOs spent by a bitcoin transaction.
+use crate::bitcoin_utils::{
+    estimate_fee, input_fee_millisatoshi, output_vsize, tx_vsize_estimate, utxos_selection,
+    utxos_selection_with_fee,
+};
+use bitcoin::Address;
+use ic_cdk::api::management_canister::bitcoin::Utxo;
+use shared::types::bitcoin::BtcCoinSelection;
+
+/// The maximum number of steps of the branch and bound search, as in Bitcoin Core.
+const BRANCH_AND_BOUND_MAX_TRIES: usize = 100_000;
+/// The fee rate that a replacement transaction has to pay on top of the transaction it replaces,
+/// as in Bitcoin Core.
+const INCREMENTAL_RELAY_FEE_MILLISATOSHI_PER_VBYTE: u64 = 1_000;
+
+/// The transaction for which UTXOs are selected.
+pub struct CoinSelectionParams<'a> {
+    /// The address spending its UTXOs, which also receives the change.
+    pub source_address: &'a Address,
+    /// The sum of the outputs, excluding the change, in satoshi.
+    pub amount_satoshis: u64,
+    /// The sizes, in vbytes, of the outputs, excluding the change.
+    pub output_vsizes: &'a [u64],
+    pub fee_millisatoshi_per_vbyte: u64,
+}
+
+/// The selected UTXOs and how their value is split between the outputs, the fee and the change.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CoinSelection {
+    pub utxos: Vec<Utxo>,
+    pub fee_satoshis: u64,
+    /// The value of the change output, or 0 if the transaction has none.
+    pub change_satoshis: u64,
+}
+
+impl CoinSelection {
+    /// Drops the change output if its value is below the dust limit of the change address, in
+    /// which case nodes would not relay the transaction. Its value goes to the fee instead.
+    fn without_dust_change(mut self, params: &CoinSelectionParams) -> Self {
+        let dust_limit_satoshis = params
+            .source_address
+            .script_pubkey()
+            .minimal_non_dust()
+            .to_sat();
+        if self.change_satoshis < dust_limit_satoshis {
+            self.fee_satoshis += self.change_satoshis;
+            self.change_satoshis = 0;
+        }
+        self
+    }
+}
+
+/// Selects the UTXOs paying the outputs and the fee of a transaction with the given strategy.
+///
+/// Returns `None` if the available UTXOs cannot cover both.
+///
+/// POSTCONDITION: `selection.utxos` is a non-empty subset of `available_utxos`.
+/// POSTCONDITION: `sum(u.value for u in selection.utxos) = amount + selection.fee_satoshis + selection.change_satoshis`
+/// POSTCONDITION: `selection.fee_satoshis ≥ estimate_fee(selection)`, the change output included if there is change.
+/// POSTCONDITION: `selection.change_satoshis` is 0 or above the dust limit of the source address.
+pub fn select_utxos(
+    strategy: BtcCoinSelection,
+    available_utxos: &[Utxo],
+    params: &CoinSelectionParams,
+) -> Option<CoinSelection> {
+    match strategy {
+        BtcCoinSelection::Greedy => greedy_selection(available_utxos, params),
+        BtcCoinSelection::BranchAndBound => branch_and_bound_selection(available_utxos, params)
+            .or_else(|| greedy_selection(available_utxos, params)),
+    }
+}
+
+/// Selects all the available UTXOs for a transaction sending their value, minus the fee, to the
+/// outputs of `params`, without change. The amount of `params` is ignored.
+///
+/// Returns the selection and the amount sent, or `None` if the UTXOs don't cover the fee.
+pub fn select_all_utxos(
+    available_utxos: &[Utxo],
+    params: &CoinSelectionParams,
+) -> Option<(CoinSelection, u64)> {
+    let fee_satoshis = estimate_fee(
+        params.source_address,
+        available_utxos.len() as u64,
+        params.fee_millisatoshi_per_vbyte,
+        params.output_vsizes,
+    );
+    let available_satoshis: u64 = available_utxos.iter().map(|utxo| utxo.value).sum();
+    let amount_satoshis = available_satoshis
+        .checked_sub(fee_satoshis)
+        .filter(|amount| *amount > 0)?;
+    Some((
+        CoinSelection {
+            utxos: available_utxos.to_vec(),
+            fee_satoshis,
+            change_satoshis: 0,
+        },
+        amount_satoshis,
+    ))
+}
+
+/// Selects exactly the given UTXOs, chosen by the user, for a transaction paying the outputs of
+/// `params`.
+///
+/// The transaction has a change output if the UTXOs can pay for it and the change is not dust.
+/// Otherwise, the excess value goes to the fee.
+///
+/// Returns `None` if the UTXOs cannot cover both the outputs and the fee.
+pub fn select_given_utxos(utxos: &[Utxo], params: &CoinSelectionParams) -> Option<CoinSelection> {
+    if utxos.is_empty() {
+        return None;
+    }
+    let input_count = utxos.len() as u64;
+    let selected_satoshis: u64 = utxos.iter().map(|utxo| utxo.value).sum();
+    let fee_with_change_satoshis = estimate_fee(
+        params.source_address,
+        input_count,
+        params.fee_millisatoshi_per_vbyte,
+        &output_vsizes_with_change(params),
+    );
+    if let Some(change_satoshis) = selected_satoshis
+        .checked_sub(params.amount_satoshis)
+        .and_then(|remaining| remaining.checked_sub(fee_with_change_satoshis))
+    {
+        return Some(
+            CoinSelection {
+                utxos: utxos.to_vec(),
+                fee_satoshis: fee_with_change_satoshis,
+                change_satoshis,
+            }
+            .without_dust_change(params),
+        );
+    }
+
+    let fee_satoshis = selected_satoshis.checked_sub(params.amount_satoshis)?;
+    let min_fee_satoshis = estimate_fee(
+        params.source_address,
+        input_count,
+        params.fee_millisatoshi_per_vbyte,
+        params.output_vsizes,
+    );
+    (fee_satoshis >= min_fee_satoshis).then(|| CoinSelection {
+        utxos: utxos.to_vec(),
+        fee_satoshis,
+        change_satoshis: 0,
+    })
+}
+
+/// Selects the UTXOs of a transaction replacing a pending one (BIP-125) with a higher fee.
+///
+/// The replacement spends all the UTXOs of the original transaction, pays the same outputs and,
+/// if the UTXOs of the original transaction don't cover the higher fee, spends more of
+/// `available_utxos`. Its fee rate is the highest of the fee rate of `params` and the fee rate of
+/// the original transaction plus the incremental relay fee rate, and its fee is at least the fee
+/// of the original transaction plus the incremental relay fee. Change below the dust limit goes to
+/// the fee.
+///
+/// Returns `None` if the available UTXOs cannot cover the fee.
+///
+/// POSTCONDITION: `original_utxos ⊆ selection.utxos ⊆ original_utxos ∪ available_utxos`
+/// POSTCONDITION: `sum(u.value for u in selection.utxos) = amount + selection.fee_satoshis + selection.change_satoshis`
+/// POSTCONDITION: `selection.fee_satoshis > original_fee_satoshis`
+pub fn select_replacement_utxos(
+    original_utxos: &[Utxo],
+    original_fee_satoshis: u64,
+    available_utxos: &[Utxo],
+    params: &CoinSelectionParams,
+) -> Option<CoinSelection> {
+    let output_vsizes = output_vsizes_with_change(params);
+    let original_satoshis: u64 = original_utxos.iter().map(|utxo| utxo.value).sum();
+    let original_has_change =
+        original_satoshis > params.amount_satoshis.saturating_add(original_fee_satoshis);
+    let original_vsize = tx_vsize_estimate(
+        params.source_address,
+        original_utxos.len() as u64,
+        if original_has_change {
+            &output_vsizes
+        } else {
+            params.output_vsizes
+        },
+    );
+    let original_fee_millisatoshi_per_vbyte =
+        (original_fee_satoshis * 1000).div_ceil(original_vsize.max(1));
+    let fee_millisatoshi_per_vbyte = params
+        .fee_millisatoshi_per_vbyte
+        .max(original_fee_millisatoshi_per_vbyte + INCREMENTAL_RELAY_FEE_MILLISATOSHI_PER_VBYTE);
+
+    let mut utxos = original_utxos.to_vec();
+    let mut remaining_utxos: Vec<Utxo> = available_utxos
+        .iter()
+        .filter(|utxo| !original_utxos.contains(utxo))
+        .cloned()
+        .collect();
+    loop {
+        let input_count = utxos.len() as u64;
+        let fee_satoshis = estimate_fee(
+            params.source_address,
+            input_count,
+            fee_millisatoshi_per_vbyte,
+            &output_vsizes,
+        )
+        .max(
+            original_fee_satoshis
+                + estimate_fee(
+                    params.source_address,
+                    input_count,
+                    INCREMENTAL_RELAY_FEE_MILLISATOSHI_PER_VBYTE,
+                    &output_vsizes,
+                ),
+        );
+        let selected_satoshis: u64 = utxos.iter().map(|utxo| utxo.value).sum();
+        let required_satoshis = params.amount_satoshis.saturating_add(fee_satoshis);
+        if selected_satoshis >= required_satoshis {
+            return Some(
+                CoinSelection {
+                    utxos,
+                    fee_satoshis,
+                    change_satoshis: selected_satoshis - required_satoshis,
+                }
+                .without_dust_change(params),
+            );
+        }
+
+        // The additional UTXOs increase the fee, which is why the selection is checked again.
+        let additional_utxos = utxos_selection(
+            required_satoshis - selected_satoshis,
+            &mut remaining_utxos,
+            output_vsizes.len(),
+        );
+        if additional_utxos.is_empty() {
+            return None;
+        }
+        utxos.extend(additional_utxos);
+    }
+}
+
+/// Returns the sizes of the outputs of the transaction, including the change output.
+fn output_vsizes_with_change(params: &CoinSelectionParams) -> Vec<u64> {
+    let mut output_vsizes = params.output_vsizes.to_vec();
+    output_vsizes.push(output_vsize(&params.source_address.script_pubkey()));
+    output_vsizes
+}
+
+/// Selects UTXOs with the algorithm of the ckBTC minter, paying for a change output unless the
+/// change is dust.
+fn greedy_selection(
+    available_utxos: &[Utxo],
+    params: &CoinSelectionParams,
+) -> Option<CoinSelection> {
+    let (utxos, fee_satoshis) = utxos_selection_with_fee(
+        params.amount_satoshis,
+        available_utxos,
+        params.source_address,
+        params.fee_millisatoshi_per_vbyte,
+        &output_vsizes_with_change(params),
+    );
+    if utxos.is_empty() {
+        return None;
+    }
+
+    let selected_satoshis: u64 = utxos.iter().map(|utxo| utxo.value).sum();
+    Some(
+        CoinSelection {
+            utxos,
+            fee_satoshis,
+            change_satoshis: selected_satoshis - params.amount_satoshis - fee_satoshis,
+        }
+        .without_dust_change(params),
+    )
+}
+
+/// Selects UTXOs for a transaction without change output, whose excess value goes to the fee.
+///
+/// The values are the effective values of the UTXOs, i.e. their value minus the fee of spending
+/// them. A selection is accepted if its excess costs less than a change output would, either
+/// because of the fee of creating and later spending the change output, or because the change
+/// would be dust. Among the accepted selections, the one with the smallest excess is returned.
+fn branch_and_bound_selection(
+    available_utxos: &[Utxo],
+    params: &CoinSelectionParams,
+) -> Option<CoinSelection> {
+    let fee_millisatoshi_per_vbyte = params.fee_millisatoshi_per_vbyte;
+    let input_fee_millisatoshi =
+        input_fee_millisatoshi(params.source_address, fee_millisatoshi_per_vbyte);
+
+    // UTXOs costing more to spend than they are worth are never selected.
+    let mut candidates: Vec<(&Utxo, u64)> = available_utxos
+        .iter()
+        .filter_map(|utxo| {
+            let effective_value = utxo
+                .value
+                .checked_mul(1000)?
+                .checked_sub(input_fee_millisatoshi)?;
+            (effective_value > 0).then_some((utxo, effective_value))
+        })
+        .collect();
+    candidates.sort_by(|(_, a), (_, b)| b.cmp(a));
+
+    let change_script_pubkey = params.source_address.script_pubkey();
+    let change_output_fee_millisatoshi =
+        output_vsize(&change_script_pubkey) * fee_millisatoshi_per_vbyte;
+    let cost_of_change = (change_output_fee_millisatoshi + input_fee_millisatoshi).max(
+        change_output_fee_millisatoshi + change_script_pubkey.minimal_non_dust().to_sat() * 1000,
+    );
+    let target = params.amount_satoshis.checked_mul(1000)?.checked_add(
+        tx_vsize_estimate(params.source_address, 0, params.output_vsizes)
+            * fee_millisatoshi_per_vbyte,
+    )?;
+
+    // The effective values approximate the fee, which is checked for the actual selection.
+    let covers_fee = |selection: &[usize]| {
+        let selected_satoshis: u64 = selection.iter().map(|i| candidates[*i].0.value).sum();
+        let fee_satoshis = estimate_fee(
+            params.source_address,
+            selection.len() as u64,
+            fee_millisatoshi_per_vbyte,
+            params.output_vsizes,
+        );
+        selected_satoshis >= params.amount_satoshis.saturating_add(fee_satoshis)
+    };
+    let selection = branch_and_bound(
+        &candidates
+            .iter()
+            .map(|(_, effective_value)| *effective_value)
+            .collect::<Vec<_>>(),
+        target,
+        target.saturating_add(cost_of_change),
+        covers_fee,
+    )?;
+
+    let utxos: Vec<Utxo> = selection.iter().map(|i| candidates[*i].0.clone()).collect();
+    let selected_satoshis: u64 = utxos.iter().map(|utxo| utxo.value).sum();
+    Some(CoinSelection {
+        utxos,
+        fee_satoshis: selected_satoshis - params.amount_satoshis,
+        change_satoshis: 0,
+    })
+}
+
+/// Searches the subset of `values` whose sum is in `target..=upper_bound` and is the closest to
+/// `target`, among the subsets accepted by `is_valid`.
+///
+/// `values` must be sorted in decreasing order. The search is a depth-first traversal of the
+/// binary tree of the decisions to include or omit each value, which explores inclusion first
+/// and cuts the branches that cannot reach the target or already exceed the upper bound. It stops
+/// after `BRANCH_AND_BOUND_MAX_TRIES` steps.
+///
+/// Returns the indices of the values in the subset.
+fn branch_and_bound(
+    values: &[u64],
+    target: u64,
+    upper_bound: u64,
+    is_valid: impl Fn(&[usize]) -> bool,
+) -> Option<Vec<usize>> {
+    let mut selection: Vec<usize> = vec![];
+    let mut selected_value: u64 = 0;
+    // The sum of the values not decided yet.
+    let mut available_value: u64 = values.iter().sum();
+    let mut best: Option<(Vec<usize>, u64)> = None;
+    let mut index = 0;
+
+    for _ in 0..BRANCH_AND_BOUND_MAX_TRIES {
+        let backtrack = if selected_value + available_value < target || selected_value > upper_bound
+        {
+            true
+        } else if selected_value >= target {
+            let excess = selected_value - target;
+            if best
+                .as_ref()
+                .is_none_or(|(_, best_excess)| excess < *best_excess)
+                && is_valid(&selection)
+            {
+                if excess == 0 {
+                    return Some(selection);
+                }
+                best = Some((selection.clone(), excess));
+            }
+            true
+        } else {
+            false
+        };
+
+        if backtrack {
+            let Some(&last) = selection.last() else {
+                // Every branch was explored.
+                break;
+            };
+            // Give back the values omitted after the last selected one, then omit it.
+            index -= 1;
+            while index > last {
+                available_value += values[index];
+                index -= 1;
+            }
+            selected_value -= values[last];
+            selection.pop();
+        } else {
+            available_value -= values[index];
+            // Including a value after omitting an equal one leads to the selections explored when
+            // the equal one was included.
+            let omitted_equal_value = index > 0
+                && selection.last() != Some(&(index - 1))
+                && values[index - 1] == values[index];
+            if !omitted_equal_value {
+                selection.push(index);
+                selected_value += values[index];
+            }
+        }
+        index += 1;
+    }
+
+    best.map(|(selection, _)| selection)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin_address::parse_address;
+    use ic_cdk::api::management_canister::bitcoin::Outpoint;
+    use proptest::prelude::*;
+    use shared::types::bitcoin::BtcNetwork;
+
+    const SOURCE_ADDRESS: &str = "bcrt1qpg7udjvq7gx2fp480pgt4hnhj3qc4nhrkstc33";
+    const P2WPKH_OUTPUT_VSIZE: u64 = 31;
+
+    fn utxo(vout: u32, value: u64) -> Utxo {
+        Utxo {
+            outpoint: Outpoint {
+                txid: vec![1; 32],
+                vout,
+            },
+            value,
+            height: 100,
+        }
+    }
+
+    fn utxos(values: &[u64]) -> Vec<Utxo> {
+        values
+            .iter()
+            .enumerate()
+            .map(|(vout, value)| utxo(vout as u32, *value))
+            .collect()
+    }
+
+    fn source_address() -> Address {
+        parse_address(SOURCE_ADDRESS, BtcNetwork::Regtest).unwrap()
+    }
+
+    fn params(source_address: &Address, amount_satoshis: u64) -> CoinSelectionParams<'_> {
+        CoinSelectionParams {
+            source_address,
+            amount_satoshis,
+            output_vsizes: &[P2WPKH_OUTPUT_VSIZE],
+            fee_millisatoshi_per_vbyte: 1000,
+        }
+    }
+
+    #[test]
+    fn branch_and_bound_finds_exact_match() {
+        assert_eq!(
+            branch_and_bound(&[50, 40, 30, 20, 10], 60, 60, |_| true),
+            Some(vec![0, 4])
+        );
+    }
+
+    #[test]
+    fn branch_and_bound_returns_smallest_excess_in_window() {
+        assert_eq!(
+            branch_and_bound(&[50, 40, 25], 64, 70, |_| true),
+            Some(vec![1, 2])
+        );
+        assert_eq!(branch_and_bound(&[50, 40, 25], 91, 100, |_| true), None);
+    }
+
+    #[test]
+    fn branch_and_bound_skips_invalid_selections() {
+        assert_eq!(
+            branch_and_bound(&[50, 40, 30, 20, 10], 60, 60, |selection| selection.len()
+                > 2),
+            Some(vec![2, 3, 4])
+        );
+    }
+
+    #[test]
+    fn branch_and_bound_selection_avoids_change() {
+        let source_address = source_address();
+        // 1 input and 2 outputs (with change) cost 141 satoshi, without change 110 satoshi.
+        let available_utxos = utxos(&[100_000, 50_110, 30_000]);
+
+        let selection = select_utxos(
+            BtcCoinSelection::BranchAndBound,
+            &available_utxos,
+            &params(&source_address, 50_000),
+        )
+        .unwrap();
+
+        assert_eq!(selection.utxos, vec![utxo(1, 50_110)]);
+        assert_eq!(selection.fee_satoshis, 110);
+        assert_eq!(selection.change_satoshis, 0);
+
+        let greedy_selection = select_utxos(
+            BtcCoinSelection::Greedy,
+            &available_utxos,
+            &params(&source_address, 50_000),
+        )
+        .unwrap();
+
+        // The UTXO of 50_110 satoshi doesn't cover the fee with a change output.
+        assert_eq!(greedy_selection.utxos, vec![utxo(0, 100_000)]);
+        assert_eq!(greedy_selection.fee_satoshis, 141);
+        assert_eq!(greedy_selection.change_satoshis, 49_859);
+    }
+
+    #[test]
+    fn branch_and_bound_selection_falls_back_to_greedy() {
+        let source_address = source_address();
+        let available_utxos = utxos(&[100_000, 80_000]);
+
+        let selection = select_utxos(
+            BtcCoinSelection::BranchAndBound,
+            &available_utxos,
+            &params(&source_address, 50_000),
+        )
+        .unwrap();
+
+        assert_eq!(selection.utxos, vec![utxo(1, 80_000)]);
+        assert_eq!(selection.fee_satoshis, 141);
+        assert_eq!(selection.change_satoshis, 29_859);
+    }
+
+    #[test]
+    fn dust_change_goes_to_the_fee() {
+        let source_address = source_address();
+        // 1 input and 2 outputs (with change) cost 141 satoshi, leaving 59 satoshi of change,
+        // below the dust limit of 294 satoshi of P2WPKH outputs.
+        let available_utxos = utxos(&[50_200]);
+
+        let selection = select_utxos(
+            BtcCoinSelection::Greedy,
+            &available_utxos,
+            &params(&source_address, 50_000),
+        )
+        .unwrap();
+        assert_eq!(selection.fee_satoshis, 200);
+        assert_eq!(selection.change_satoshis, 0);
+
+        let selection =
+            select_given_utxos(&available_utxos, &params(&source_address, 50_000)).unwrap();
+        assert_eq!(selection.fee_satoshis, 200);
+        assert_eq!(selection.change_satoshis, 0);
+
+        // 294 satoshi of change is not dust.
+        let selection =
+            select_given_utxos(&utxos(&[50_435]), &params(&source_address, 50_000)).unwrap();
+        assert_eq!(selection.fee_satoshis, 141);
+        assert_eq!(selection.change_satoshis, 294);
+    }
+
+    #[test]
+    fn selection_fails_if_funds_are_insufficient() {
+        let source_address = source_address();
+        for strategy in [BtcCoinSelection::Greedy, BtcCoinSelection::BranchAndBound] {
+            assert_eq!(
+                select_utxos(
+                    strategy,
+                    &utxos(&[30_000, 20_000]),
+                    &params(&source_address, 50_000)
+                ),
+                None
+            );
+        }
+    }
+
+    #[test]
+    fn select_all_utxos_sends_all_but_the_fee() {
+        let source_address = source_address();
+        let available_utxos = utxos(&[100_000, 50_000]);
+
+        let (selection, amount_satoshis) =
+            select_all_utxos(&available_utxos, &params(&source_address, 0)).unwrap();
+
+        // 2 inputs and 1 output without change.
+        assert_eq!(selection.utxos, available_utxos);
+        assert_eq!(selection.fee_satoshis, 178);
+        assert_eq!(selection.change_satoshis, 0);
+        assert_eq!(amount_satoshis, 149_822);
+    }
+
+    #[test]
+    fn select_all_utxos_fails_if_fee_is_not_covered() {
+        let source_address = source_address();
+        assert_eq!(
+            select_all_utxos(&utxos(&[100]), &params(&source_address, 0)),
+            None
+        );
+        assert_eq!(select_all_utxos(&[], &params(&source_address, 0)), None);
+    }
+
+    #[test]
+    fn select_given_utxos_spends_all_of_them() {
+        let source_address = source_address();
+        let given_utxos = utxos(&[10_000, 60_000]);
+
+        let selection = select_given_utxos(&given_utxos, &params(&source_address, 50_000)).unwrap();
+
+        // 2 inputs and 2 outputs with change.
+        assert_eq!(selection.utxos, given_utxos);
+        assert_eq!(selection.fee_satoshis, 209);
+        assert_eq!(selection.change_satoshis, 19_791);
+    }
+
+    #[test]
+    fn select_given_utxos_without_room_for_change_pays_the_excess_as_fee() {
+        let source_address = source_address();
+        // 1 input and 2 outputs (with change) cost 141 satoshi, without change 110 satoshi.
+        let given_utxos = utxos(&[50_120]);
+
+        let selection = select_given_utxos(&given_utxos, &params(&source_address, 50_000)).unwrap();
+
+        assert_eq!(selection.fee_satoshis, 120);
+        assert_eq!(selection.change_satoshis, 0);
+
+        assert_eq!(
+            select_given_utxos(&utxos(&[50_100]), &params(&source_address, 50_000)),
+            None
+        );
+        assert_eq!(select_given_utxos(&[], &params(&source_address, 0)), None);
+    }
+
+    #[test]
+    fn replacement_pays_more_than_the_original_from_its_change() {
+        let source_address = source_address();
+        let original_utxos = utxos(&[100_000]);
+        let available_utxos = utxos(&[100_000, 80_000]);
+        // The original transaction paid 141 satoshi for 141 vbytes.
+        let params = params(&source_address, 50_000);
+
+        let selection =
+            select_replacement_utxos(&original_utxos, 141, &available_utxos, &params).unwrap();
+
+        assert_eq!(selection.utxos, original_utxos);
+        assert_eq!(selection.fee_satoshis, 282);
+        assert_eq!(selection.change_satoshis, 49_718);
+    }
+
+    #[test]
+    fn replacement_uses_the_requested_fee_rate_if_higher() {
+        let source_address = source_address();
+        let original_utxos = utxos(&[100_000]);
+        let params = CoinSelectionParams {
+            fee_millisatoshi_per_vbyte: 10_000,
+            ..params(&source_address, 50_000)
+        };
+
+        let selection = select_replacement_utxos(&original_utxos, 141, &[], &params).unwrap();
+
+        assert_eq!(selection.fee_satoshis, 1_410);
+        assert_eq!(selection.change_satoshis, 48_590);
+    }
+
+    #[test]
+    fn replacement_adds_utxos_to_cover_the_fee() {
+        let source_address = source_address();
+        // The original transaction had no change output.
+        let original_utxos = utxos(&[50_110]);
+        let available_utxos = vec![utxo(1, 30_000), utxo(2, 500)];
+        let params = params(&source_address, 50_000);
+
+        let selection =
+            select_replacement_utxos(&original_utxos, 110, &available_utxos, &params).unwrap();
+
+        assert_eq!(selection.utxos, vec![utxo(0, 50_110), utxo(2, 500)]);
+        assert!(selection.fee_satoshis > 110);
+        assert_eq!(
+            50_610,
+            50_000 + selection.fee_satoshis + selection.change_satoshis
+        );
+
+        assert_eq!(
+            select_replacement_utxos(&original_utxos, 110, &[], &params),
+            None
+        );
+    }
+
+    fn strategy() -> impl Strategy<Value = BtcCoinSelection> {
+        prop_oneof![
+            Just(BtcCoinSelection::Greedy),
+            Just(BtcCoinSelection::BranchAndBound)
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn selection_satisfies_postconditions(
+            strategy in strategy(),
+            values in prop::collection::vec(1u64..10_000_000, 0..20),
+            amount_satoshis in 1u64..50_000_000,
+            fee_millisatoshi_per_vbyte in 0u64..100_000,
+        ) {
+            let source_address = source_address();
+            let available_utxos = utxos(&values);
+            let params = CoinSelectionParams {
+                fee_millisatoshi_per_vbyte,
+                ..params(&source_address, amount_satoshis)
+            };
+
+            if let Some(selection) = select_utxos(strategy, &available_utxos, &params) {
+                prop_assert!(!selection.utxos.is_empty());
+                prop_assert!(selection.utxos.iter().all(|utxo| available_utxos.contains(utxo)));
+                let selected_satoshis: u64 = selection.utxos.iter().map(|utxo| utxo.value).sum();
+                prop_assert_eq!(
+                    selected_satoshis,
+                    amount_satoshis + selection.fee_satoshis + selection.change_satoshis
+                );
+                prop_assert!(
+                    selection.change_satoshis == 0
+                        || selection.change_satoshis
+                            >= source_address.script_pubkey().minimal_non_dust().to_sat()
+                );
+                let output_vsizes = if selection.change_satoshis > 0 {
+                    output_vsizes_with_change(&params)
+                } else {
+                    params.output_vsizes.to_vec()
+                };
+                prop_assert!(
+                    selection.fee_satoshis
+                        >= estimate_fee(
+                            &source_address,
+                            selection.utxos.len() as u64,
+                            fee_millisatoshi_per_vbyte,
+                            &output_vsizes
+                        )
+                );
+            }
+        }
+
+        #[test]
+        fn select_given_utxos_satisfies_postconditions(
+            values in prop::collection::vec(1u64..10_000_000, 0..20),
+            amount_satoshis in 1u64..50_000_000,
+            fee_millisatoshi_per_vbyte in 0u64..100_000,
+        ) {
+            let source_address = source_address();
+            let given_utxos = utxos(&values);
+            let params = CoinSelectionParams {
+                fee_millisatoshi_per_vbyte,
+                ..params(&source_address, amount_satoshis)
+            };
+
+            if let Some(selection) = select_given_utxos(&given_utxos, &params) {
+                prop_assert_eq!(&selection.utxos, &given_utxos);
+                let selected_satoshis: u64 = selection.utxos.iter().map(|utxo| utxo.value).sum();
+                prop_assert_eq!(
+                    selected_satoshis,
+                    amount_satoshis + selection.fee_satoshis + selection.change_satoshis
+                );
+                prop_assert!(
+                    selection.fee_satoshis
+                        >= estimate_fee(
+                            &source_address,
+                            selection.utxos.len() as u64,
+                            fee_millisatoshi_per_vbyte,
+                            params.output_vsizes
+                        )
+                );
+            }
+        }
+
+        #[test]
+        fn replacement_satisfies_postconditions(
+            values in prop::collection::vec(1u64..10_000_000, 1..20),
+            original_count in 1usize..5,
+            amount_satoshis in 1u64..50_000_000,
+            original_fee_satoshis in 0u64..100_000,
+            fee_millisatoshi_per_vbyte in 0u64..100_000,
+        ) {
+            let source_address = source_address();
+            let available_utxos = utxos(&values);
+            let original_utxos = &available_utxos[..original_count.min(available_utxos.len())];
+            let params = CoinSelectionParams {
+                fee_millisatoshi_per_vbyte,
+                ..params(&source_address, amount_satoshis)
+            };
+
+            if let Some(selection) = select_replacement_utxos(
+                original_utxos,
+                original_fee_satoshis,
+                &available_utxos,
+                &params,
+            ) {
+                prop_assert!(original_utxos.iter().all(|utxo| selection.utxos.contains(utxo)));
+                prop_assert!(selection.utxos.iter().all(|utxo| available_utxos.contains(utxo)));
+                let selected_satoshis: u64 = selection.utxos.iter().map(|utxo| utxo.value).sum();
+                prop_assert_eq!(
+                    selected_satoshis,
+                    amount_satoshis + selection.fee_satoshis + selection.change_satoshis
+                );
+                prop_assert!(selection.fee_satoshis > original_fee_satoshis);
+            }
+        }
+
+        #[test]
+        fn branch_and_bound_selection_succeeds_whenever_greedy_selection_does(
+            values in prop::collection::vec(1u64..10_000_000, 0..20),
+            amount_satoshis in 1u64..50_000_000,
+        ) {
+            let source_address = source_address();
+            let available_utxos = utxos(&values);
+            let params = params(&source_address, amount_satoshis);
+
+            if select_utxos(BtcCoinSelection::Greedy, &available_utxos, &params).is_some() {
+                prop_assert!(
+                    select_utxos(BtcCoinSelection::BranchAndBound, &available_utxos, &params)
+                        .is_some()
+                );
+            }
+        }
+    }
+}
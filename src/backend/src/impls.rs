@@ -1,5 +1,5 @@
 use crate::{
-    types::{Candid, StoredPrincipal},
+    types::{Candid, StoredPendingTransactionKey, StoredPrincipal},
     State,
 };
 use candid::{CandidType, Deserialize, Principal};
@@ -41,6 +41,10 @@ impl From<&State> for Stats {
             user_timestamps_count: state.user_profile_updated.len(),
             user_token_count: state.user_token.len(),
             custom_token_count: state.custom_token.len(),
+            btc_pending_transactions_count: state.btc_user_pending_transactions.len(),
+            btc_frozen_utxos_count: state.btc_user_frozen_utxos.len(),
+            ckbtc_withdrawals_count: state.ckbtc_user_withdrawals.len(),
+            btc_next_address_index_count: state.btc_user_next_address_index.len(),
         }
     }
 }
@@ -63,3 +67,15 @@ impl Storable for StoredPrincipal {
         ))
     }
 }
+
+impl Storable for StoredPendingTransactionKey {
+    const BOUND: Bound = Bound::Unbounded;
+
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).expect("encoding should always succeed"))
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        candid::decode_one(bytes.as_ref()).expect("decoding should succeed")
+    }
+}
@@ -0,0 +1,153 @@
+//! Offline derivation of the public keys of the threshold ECDSA signer.
+//!
+//! The management canister derives keys with a generalization of BIP-32 public child key
+//! derivation in which the path elements are arbitrary byte strings instead of 4-byte indices.
+//! See [ckBTC minter](https://github.com/dfinity/ic/blob/35153c7cb7b9d1da60472ca7e94c693e418f87bd/rs/bitcoin/ckbtc/minter/src/address.rs#L101-L101)
+use bitcoin::hashes::{hmac, sha512, Hash, HashEngine};
+use bitcoin::secp256k1::{PublicKey, Scalar, Secp256k1};
+
+/// A public key along with the chain code used to derive its children.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ExtendedPublicKey {
+    pub public_key: PublicKey,
+    pub chain_code: [u8; 32],
+}
+
+impl ExtendedPublicKey {
+    /// Parses a SEC1 encoded public key and its chain code, as returned by `ecdsa_public_key`.
+    pub fn from_slices(public_key: &[u8], chain_code: &[u8]) -> Result<Self, String> {
+        Ok(Self {
+            public_key: PublicKey::from_slice(public_key)
+                .map_err(|err| format!("Invalid public key: {err}"))?,
+            chain_code: chain_code
+                .try_into()
+                .map_err(|_| format!("Invalid chain code length: {}", chain_code.len()))?,
+        })
+    }
+
+    /// Derives the child key for the given path element.
+    fn derive_child(&self, index: &[u8]) -> Self {
+        let secp = Secp256k1::verification_only();
+        let mut ckd_input = self.public_key.serialize().to_vec();
+        loop {
+            let mut engine = hmac::HmacEngine::<sha512::Hash>::new(&self.chain_code);
+            engine.input(&ckd_input);
+            engine.input(index);
+            let hmac_output = hmac::Hmac::<sha512::Hash>::from_engine(engine).to_byte_array();
+            let mut key_offset = [0; 32];
+            let mut chain_code = [0; 32];
+            key_offset.copy_from_slice(&hmac_output[..32]);
+            chain_code.copy_from_slice(&hmac_output[32..]);
+
+            if let Some(public_key) = Scalar::from_be_bytes(key_offset)
+                .ok()
+                .and_then(|offset| self.public_key.add_exp_tweak(&secp, &offset).ok())
+            {
+                return Self {
+                    public_key,
+                    chain_code,
+                };
+            }
+
+            // The offset is larger than the group order or the derived key is the point at
+            // infinity: try again with the input `0x01 || chain_code`.
+            ckd_input = [&[1], chain_code.as_slice()].concat();
+        }
+    }
+
+    /// Derives the key for the given derivation path.
+    pub fn derive(&self, derivation_path: &[Vec<u8>]) -> Self {
+        derivation_path
+            .iter()
+            .fold(*self, |key, index| key.derive_child(index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::{
+        bip32::{ChildNumber, Xpriv, Xpub},
+        NetworkKind,
+    };
+
+    fn master_key() -> Xpub {
+        let secp = Secp256k1::new();
+        let master_private_key = Xpriv::new_master(NetworkKind::Main, &[7; 32]).unwrap();
+        Xpub::from_priv(&secp, &master_private_key)
+    }
+
+    fn extended_public_key(xpub: &Xpub) -> ExtendedPublicKey {
+        ExtendedPublicKey {
+            public_key: xpub.public_key,
+            chain_code: xpub.chain_code.to_bytes(),
+        }
+    }
+
+    #[test]
+    fn derivation_with_4_byte_indices_matches_bip_32() {
+        let secp = Secp256k1::new();
+        let master_key = master_key();
+        let indices = [0_u32, 1, 42, 0x7fff_ffff];
+
+        let expected = master_key
+            .derive_pub(
+                &secp,
+                &indices
+                    .iter()
+                    .map(|index| ChildNumber::from_normal_idx(*index).unwrap())
+                    .collect::<Vec<_>>(),
+            )
+            .unwrap();
+        let derived = extended_public_key(&master_key).derive(
+            &indices
+                .iter()
+                .map(|index| index.to_be_bytes().to_vec())
+                .collect::<Vec<_>>(),
+        );
+
+        assert_eq!(derived, extended_public_key(&expected));
+    }
+
+    #[test]
+    fn derivation_of_empty_path_returns_the_key() {
+        let master_key = extended_public_key(&master_key());
+        assert_eq!(master_key.derive(&[]), master_key);
+    }
+
+    #[test]
+    fn derivation_is_incremental() {
+        let master_key = extended_public_key(&master_key());
+        let principal = vec![1; 29];
+
+        assert_eq!(
+            master_key.derive(&[vec![0], principal.clone()]),
+            master_key.derive(&[vec![0]]).derive(&[principal])
+        );
+    }
+
+    #[test]
+    fn derivation_depends_on_the_whole_path_element() {
+        let master_key = extended_public_key(&master_key());
+
+        assert_ne!(
+            master_key.derive(&[vec![0], vec![1; 29]]).public_key,
+            master_key.derive(&[vec![0], vec![1; 28]]).public_key
+        );
+        assert_ne!(
+            master_key.derive(&[vec![0], vec![1; 29]]).public_key,
+            master_key.derive(&[vec![1], vec![1; 29]]).public_key
+        );
+    }
+
+    #[test]
+    fn from_slices_rejects_invalid_chain_code() {
+        let master_key = master_key();
+        assert!(
+            ExtendedPublicKey::from_slices(&master_key.public_key.serialize(), &[0; 31]).is_err()
+        );
+        assert!(
+            ExtendedPublicKey::from_slices(&master_key.public_key.serialize(), &[0; 32]).is_ok()
+        );
+    }
+}
@@ -1,16 +1,27 @@
-use crate::assertions::{assert_token_enabled_is_some, assert_token_symbol_length};
+use crate::assertions::{
+    assert_solana_address, assert_symbol_length, assert_token_enabled_is_some,
+    assert_token_symbol_length,
+};
 use crate::guards::{caller_is_allowed, may_read_user_data, may_write_user_data};
-use crate::token::{add_to_user_token, remove_from_user_token};
-use bitcoin_utils::estimate_fee;
+use crate::token::{
+    add_to_user_token, migrate_user_tokens_of, migrate_user_tokens_to_custom_tokens,
+    remove_from_user_token, user_custom_tokens, USER_TOKEN_MIGRATION_CHUNK_SIZE,
+};
+use bitcoin::hashes::Hash;
+use btc_user_addresses::{BtcUserAddresses, BtcUserUtxos};
+use btc_user_pending_tx_state::{with_btc_pending_transactions, StoredPendingTransaction};
 use candid::Principal;
+use ckbtc_minter::{ckbtc_minter, CkBtcMinter};
+use ckbtc_user_withdrawals_state::StoredCkBtcWithdrawal;
+use coin_selection::CoinSelectionParams;
 use config::find_credential_config;
 use ethers_core::abi::ethereum_types::H160;
-use heap_state::btc_user_pending_tx_state::StoredPendingTransaction;
-use heap_state::state::with_btc_pending_transactions;
+use futures::future::join_all;
+use ic_cdk::api::management_canister::bitcoin::{Outpoint, Utxo};
 use ic_cdk::api::time;
 use ic_cdk::eprintln;
 use ic_cdk_macros::{export_candid, init, post_upgrade, query, update};
-use ic_cdk_timers::{clear_timer, set_timer_interval};
+use ic_cdk_timers::{clear_timer, set_timer, set_timer_interval};
 use ic_stable_structures::{
     memory_manager::{MemoryId, MemoryManager},
     DefaultMemoryImpl,
@@ -19,15 +30,32 @@ use ic_verifiable_credentials::validate_ii_presentation_and_claims;
 use oisy_user::oisy_users;
 use serde_bytes::ByteBuf;
 use shared::http::{HttpRequest, HttpResponse};
-use shared::metrics::get_metrics;
+use shared::metrics::{get_metrics, CanisterMetric};
 use shared::std_canister_status;
+#[cfg(feature = "bitcoin-api-stand-in")]
+use shared::types::bitcoin::BtcNetwork;
 use shared::types::bitcoin::{
-    BtcAddPendingTransactionError, BtcAddPendingTransactionRequest, BtcGetPendingTransactionsError,
-    BtcGetPendingTransactionsReponse, BtcGetPendingTransactionsRequest, PendingTransaction,
-    SelectedUtxosFeeError, SelectedUtxosFeeRequest, SelectedUtxosFeeResponse,
+    BtcAddPendingTransactionError, BtcAddPendingTransactionErrorV2,
+    BtcAddPendingTransactionRequest, BtcAddressError, BtcAddressType,
+    BtcBuildUnsignedTransactionError, BtcBuildUnsignedTransactionRequest,
+    BtcBuildUnsignedTransactionResponse, BtcBumpFeeError, BtcBumpFeeRequest, BtcFreezeUtxosError,
+    BtcFreezeUtxosRequest, BtcGetAddressError, BtcGetAddressRequest, BtcGetAddressResponse,
+    BtcGetBalanceError, BtcGetBalanceRequest, BtcGetBalanceResponse,
+    BtcGetPendingTransactionsError, BtcGetPendingTransactionsReponse,
+    BtcGetPendingTransactionsRequest, BtcInputError, BtcOutputError, BtcPendingTransactionStatus,
+    BtcRemovePendingTransactionError, BtcRemovePendingTransactionRequest, BtcTxOutput,
+    PendingTransaction, SelectedUtxosFeeError, SelectedUtxosFeeErrorV2, SelectedUtxosFeeRequest,
+    SelectedUtxosFeeResponse,
+};
+#[cfg(feature = "ckbtc-minter-stand-in")]
+use shared::types::ckbtc::CkBtcWithdrawalStatus;
+use shared::types::ckbtc::{
+    CkBtcAddWithdrawalError, CkBtcAddWithdrawalRequest, CkBtcEstimateWithdrawalFeeError,
+    CkBtcEstimateWithdrawalFeeRequest, CkBtcEstimateWithdrawalFeeResponse, CkBtcGetBtcAddressError,
+    CkBtcGetBtcAddressResponse, CkBtcGetWithdrawalsError, CkBtcGetWithdrawalsResponse,
 };
-use shared::types::custom_token::{CustomToken, CustomTokenId};
-use shared::types::token::{UserToken, UserTokenId};
+use shared::types::custom_token::{CustomToken, CustomTokenId, ErcToken, SplToken, Token};
+use shared::types::token::{ChainId, UserToken, UserTokenId};
 use shared::types::user_profile::{
     AddUserCredentialError, AddUserCredentialRequest, GetUserProfileError, ListUsersRequest,
     ListUsersResponse, OisyUser, UserProfile,
@@ -35,23 +63,37 @@ use shared::types::user_profile::{
 use shared::types::{
     Arg, Config, Guards, InitArg, Migration, MigrationProgress, MigrationReport, Stats,
 };
-use signer::{btc_principal_to_p2wpkh_address, AllowSigningError};
+use signer::{btc_principal_to_address, AllowSigningError};
 use std::cell::RefCell;
 use std::time::Duration;
 use types::{
-    Candid, ConfigCell, CustomTokenMap, StoredPrincipal, UserProfileMap, UserProfileUpdatedMap,
-    UserTokenMap,
+    BtcUserFrozenUtxosMap, BtcUserNextAddressIndexMap, BtcUserPendingTransactionsMap, Candid,
+    CfsMasterPublicKeyCell, CkBtcUserWithdrawalsMap, ConfigCell, CustomTokenMap, StoredPrincipal,
+    UserProfileMap, UserProfileUpdatedMap, UserTokenMap,
 };
 use user_profile::{add_credential, create_profile, find_profile};
 use user_profile_model::UserProfileModel;
+use utxos_cache::read_utxos_cache;
+#[cfg(feature = "bitcoin-api-stand-in")]
+use utxos_cache::{mutate_utxos_cache, UtxosCache};
 
 mod assertions;
+mod bitcoin_address;
 mod bitcoin_api;
+mod bitcoin_error;
+mod bitcoin_transaction;
 mod bitcoin_utils;
+mod btc_user_address_index_state;
+mod btc_user_addresses;
+mod btc_user_frozen_utxos_state;
+mod btc_user_pending_tx_state;
+mod ckbtc_minter;
+mod ckbtc_user_withdrawals_state;
+mod coin_selection;
 mod config;
 mod guards;
-mod heap_state;
 mod impls;
+mod key_derivation;
 mod migrate;
 mod oisy_user;
 mod signer;
@@ -60,12 +102,18 @@ mod token;
 mod types;
 mod user_profile;
 mod user_profile_model;
+mod utxos_cache;
 
 const CONFIG_MEMORY_ID: MemoryId = MemoryId::new(0);
 const USER_TOKEN_MEMORY_ID: MemoryId = MemoryId::new(1);
 const USER_CUSTOM_TOKEN_MEMORY_ID: MemoryId = MemoryId::new(2);
 const USER_PROFILE_MEMORY_ID: MemoryId = MemoryId::new(3);
 const USER_PROFILE_UPDATED_MEMORY_ID: MemoryId = MemoryId::new(4);
+const BTC_USER_PENDING_TRANSACTIONS_MEMORY_ID: MemoryId = MemoryId::new(5);
+const CFS_MASTER_PUBLIC_KEY_MEMORY_ID: MemoryId = MemoryId::new(6);
+const BTC_USER_FROZEN_UTXOS_MEMORY_ID: MemoryId = MemoryId::new(7);
+const CKBTC_USER_WITHDRAWALS_MEMORY_ID: MemoryId = MemoryId::new(8);
+const BTC_USER_NEXT_ADDRESS_INDEX_MEMORY_ID: MemoryId = MemoryId::new(9);
 
 const MAX_SYMBOL_LENGTH: usize = 20;
 
@@ -82,6 +130,12 @@ thread_local! {
             // Use `UserProfileModel` to access and manage access to these states
             user_profile: UserProfileMap::init(mm.borrow().get(USER_PROFILE_MEMORY_ID)),
             user_profile_updated: UserProfileUpdatedMap::init(mm.borrow().get(USER_PROFILE_UPDATED_MEMORY_ID)),
+            // Use `BtcUserPendingTransactions` to access and manage access to this state
+            btc_user_pending_transactions: BtcUserPendingTransactionsMap::init(mm.borrow().get(BTC_USER_PENDING_TRANSACTIONS_MEMORY_ID)),
+            btc_user_frozen_utxos: BtcUserFrozenUtxosMap::init(mm.borrow().get(BTC_USER_FROZEN_UTXOS_MEMORY_ID)),
+            ckbtc_user_withdrawals: CkBtcUserWithdrawalsMap::init(mm.borrow().get(CKBTC_USER_WITHDRAWALS_MEMORY_ID)),
+            btc_user_next_address_index: BtcUserNextAddressIndexMap::init(mm.borrow().get(BTC_USER_NEXT_ADDRESS_INDEX_MEMORY_ID)),
+            cfs_master_public_key: CfsMasterPublicKeyCell::init(mm.borrow().get(CFS_MASTER_PUBLIC_KEY_MEMORY_ID), None).expect("cfs master public key cell initialization should succeed"),
             migration: None,
         })
     );
@@ -126,13 +180,27 @@ fn modify_state_config(state: &mut State, f: impl FnOnce(&mut Config)) {
 
 pub struct State {
     config: ConfigCell,
-    /// Initially intended for ERC20 tokens only, this field stores the list of tokens set by the users.
+    /// Formerly the list of ERC20 tokens set by the users, moved to `custom_token` in chunks
+    /// after the upgrade, see `step_user_token_migration`.  Until a user's tokens are moved, they
+    /// are read as custom tokens and moved before any write of the user's tokens.
     user_token: UserTokenMap,
-    /// Introduced to support a broader range of user-defined custom tokens, beyond just ERC20.
-    /// Future updates may include migrating existing ERC20 tokens to this more flexible structure.
+    /// The tokens set by the users: ICRC tokens and, as `Token::Erc20`, ERC20 tokens.
     custom_token: CustomTokenMap,
     user_profile: UserProfileMap,
     user_profile_updated: UserProfileUpdatedMap,
+    /// Pending bitcoin transactions of the users, used to avoid spending the same UTXOs twice.
+    /// Kept in stable memory so that they survive canister upgrades.
+    btc_user_pending_transactions: BtcUserPendingTransactionsMap,
+    /// The UTXOs that the users never want to spend, excluded from the coin selection.
+    btc_user_frozen_utxos: BtcUserFrozenUtxosMap,
+    /// The ckBTC withdrawals requested by the users, polled from the minter until they settle.
+    ckbtc_user_withdrawals: CkBtcUserWithdrawalsMap,
+    /// The index of the next fresh bitcoin address of the users, whose addresses are derived up to
+    /// that index.
+    btc_user_next_address_index: BtcUserNextAddressIndexMap,
+    /// Cache of the chain fusion signer master public key, used to derive the bitcoin keys of the
+    /// users without calling the management canister.
+    cfs_master_public_key: CfsMasterPublicKeyCell,
     migration: Option<Migration>,
 }
 
@@ -166,6 +234,23 @@ fn post_upgrade(arg: Option<Arg>) {
             });
         }
     }
+    // One-off: the ERC20 tokens are stored as custom tokens. A no-op once `user_token` is empty.
+    set_timer(Duration::from_secs(0), step_user_token_migration);
+}
+
+/// Moves the ERC20 tokens of a chunk of users to the custom tokens, then schedules the next chunk
+/// until `user_token` is empty.
+fn step_user_token_migration() {
+    let complete = mutate_state(|s| {
+        migrate_user_tokens_to_custom_tokens(
+            &mut s.user_token,
+            &mut s.custom_token,
+            USER_TOKEN_MIGRATION_CHUNK_SIZE,
+        )
+    });
+    if !complete {
+        set_timer(Duration::from_secs(0), step_user_token_migration);
+    }
 }
 
 /// Show the canister configuration.
@@ -186,7 +271,7 @@ pub fn http_request(request: HttpRequest) -> HttpResponse {
         .next()
         .unwrap_or_else(|| unreachable!("Even splitting an empty string yields one entry"));
     match path {
-        "/metrics" => get_metrics(),
+        "/metrics" => get_metrics(&canister_metrics()),
         _ => HttpResponse {
             status_code: 404,
             headers: vec![],
@@ -195,6 +280,45 @@ pub fn http_request(request: HttpRequest) -> HttpResponse {
     }
 }
 
+/// Metrics of the canister state, reported on `/metrics`.
+fn canister_metrics() -> Vec<CanisterMetric> {
+    let (utxos_cache_metrics, cached_utxo_sets_count, cached_utxos_count) =
+        read_utxos_cache(|cache| {
+            (
+                cache.metrics(),
+                cache.cached_utxo_sets_count() as u64,
+                cache.cached_utxos_count() as u64,
+            )
+        });
+    vec![
+        CanisterMetric::Counter {
+            name: "ic_eth_wallet_btc_utxos_cache_hits",
+            value: utxos_cache_metrics.hits,
+            help: "Number of UTXO queries served from the cache without any call",
+        },
+        CanisterMetric::Counter {
+            name: "ic_eth_wallet_btc_utxos_cache_refreshes",
+            value: utxos_cache_metrics.refreshes,
+            help: "Number of UTXO queries served from the cache after checking the tip",
+        },
+        CanisterMetric::Counter {
+            name: "ic_eth_wallet_btc_utxos_cache_misses",
+            value: utxos_cache_metrics.misses,
+            help: "Number of UTXO queries that fetched all the UTXOs",
+        },
+        CanisterMetric::Gauge {
+            name: "ic_eth_wallet_btc_utxos_cache_entries",
+            value: cached_utxo_sets_count,
+            help: "Number of UTXO sets in the cache",
+        },
+        CanisterMetric::Gauge {
+            name: "ic_eth_wallet_btc_utxos_cache_utxos",
+            value: cached_utxos_count,
+            help: "Number of UTXOs in the cache, over all the UTXO sets",
+        },
+    ]
+}
+
 fn parse_eth_address(address: &str) -> [u8; 20] {
     match address.parse() {
         Ok(H160(addr)) => addr,
@@ -204,6 +328,40 @@ fn parse_eth_address(address: &str) -> [u8; 20] {
     }
 }
 
+/// Whether a custom token is the ERC20 token with the given chain and contract address.
+fn is_erc20_token(token: &CustomToken, chain_id: ChainId, addr: [u8; 20]) -> bool {
+    matches!(&token.token, Token::Erc20(token)
+        if token.chain_id == chain_id && parse_eth_address(&token.contract_address) == addr)
+}
+
+/// Checks a custom token before it is stored, trapping if it is not valid.
+fn assert_custom_token_is_valid(token: &CustomToken) {
+    match &token.token {
+        Token::Icrc(_) => (),
+        Token::Erc20(ErcToken {
+            contract_address,
+            symbol,
+            ..
+        }) => {
+            assert_symbol_length(symbol.as_deref()).unwrap_or_else(|e| ic_cdk::trap(&e));
+            parse_eth_address(contract_address);
+        }
+        Token::Spl(SplToken {
+            mint_address,
+            token_program,
+            ..
+        }) => {
+            assert_solana_address(mint_address).unwrap_or_else(|e| ic_cdk::trap(&e));
+            if let Some(token_program) = token_program {
+                assert_solana_address(token_program).unwrap_or_else(|e| ic_cdk::trap(&e));
+            }
+        }
+    }
+}
+
+/// Add or update an ERC20 token of the user.
+///
+/// Kept for backwards compatibility: ERC20 tokens are stored as custom tokens.
 #[update(guard = "may_write_user_data")]
 #[allow(clippy::needless_pass_by_value)]
 fn set_user_token(token: UserToken) {
@@ -214,62 +372,94 @@ fn set_user_token(token: UserToken) {
 
     let stored_principal = StoredPrincipal(ic_cdk::caller());
 
-    let find = |t: &UserToken| {
-        t.chain_id == token.chain_id && parse_eth_address(&t.contract_address) == addr
-    };
+    let chain_id = token.chain_id;
+    let find = |t: &CustomToken| is_erc20_token(t, chain_id, addr);
 
-    mutate_state(|s| add_to_user_token(stored_principal, &mut s.user_token, &token, &find));
+    mutate_state(|s| {
+        migrate_user_tokens_of(stored_principal, &mut s.user_token, &mut s.custom_token);
+        add_to_user_token(
+            stored_principal,
+            &mut s.custom_token,
+            &CustomToken::from(token),
+            &find,
+        );
+    });
 }
 
+/// Add or update ERC20 tokens of the user.
+///
+/// Kept for backwards compatibility: ERC20 tokens are stored as custom tokens.
 #[update(guard = "may_write_user_data")]
 fn set_many_user_tokens(tokens: Vec<UserToken>) {
     let stored_principal = StoredPrincipal(ic_cdk::caller());
 
     mutate_state(|s| {
+        migrate_user_tokens_of(stored_principal, &mut s.user_token, &mut s.custom_token);
         for token in tokens {
             assert_token_symbol_length(&token).unwrap_or_else(|e| ic_cdk::trap(&e));
             assert_token_enabled_is_some(&token).unwrap_or_else(|e| ic_cdk::trap(&e));
-            parse_eth_address(&token.contract_address);
+            let addr = parse_eth_address(&token.contract_address);
 
-            let find = |t: &UserToken| {
-                t.chain_id == token.chain_id && (t.contract_address == token.contract_address)
-            };
+            let chain_id = token.chain_id;
+            let find = |t: &CustomToken| is_erc20_token(t, chain_id, addr);
 
-            add_to_user_token(stored_principal, &mut s.user_token, &token, &find);
+            add_to_user_token(
+                stored_principal,
+                &mut s.custom_token,
+                &CustomToken::from(token),
+                &find,
+            );
         }
     });
 }
 
+/// Remove an ERC20 token of the user.
+///
+/// Kept for backwards compatibility: ERC20 tokens are stored as custom tokens.
 #[update(guard = "may_write_user_data")]
 #[allow(clippy::needless_pass_by_value)]
 fn remove_user_token(token_id: UserTokenId) {
     let addr = parse_eth_address(&token_id.contract_address);
     let stored_principal = StoredPrincipal(ic_cdk::caller());
 
-    let find = |t: &UserToken| {
-        t.chain_id == token_id.chain_id && parse_eth_address(&t.contract_address) == addr
-    };
+    let find = |t: &CustomToken| is_erc20_token(t, token_id.chain_id, addr);
 
-    mutate_state(|s| remove_from_user_token(stored_principal, &mut s.user_token, &find));
+    mutate_state(|s| {
+        migrate_user_tokens_of(stored_principal, &mut s.user_token, &mut s.custom_token);
+        remove_from_user_token(stored_principal, &mut s.custom_token, &find);
+    });
 }
 
+/// The ERC20 tokens of the user.
+///
+/// Kept for backwards compatibility: ERC20 tokens are stored as custom tokens.
 #[query(guard = "may_read_user_data")]
 fn list_user_tokens() -> Vec<UserToken> {
     let stored_principal = StoredPrincipal(ic_cdk::caller());
-    read_state(|s| s.user_token.get(&stored_principal).unwrap_or_default().0)
+    read_state(|s| {
+        user_custom_tokens(&stored_principal, &s.user_token, &s.custom_token)
+            .iter()
+            .filter_map(CustomToken::to_user_token)
+            .collect()
+    })
 }
 
 /// Add, remove or update custom token for the user.
 #[update(guard = "may_write_user_data")]
 #[allow(clippy::needless_pass_by_value)]
 fn set_custom_token(token: CustomToken) {
+    assert_custom_token_is_valid(&token);
+
     let stored_principal = StoredPrincipal(ic_cdk::caller());
 
     let find = |t: &CustomToken| -> bool {
         CustomTokenId::from(&t.token) == CustomTokenId::from(&token.token)
     };
 
-    mutate_state(|s| add_to_user_token(stored_principal, &mut s.custom_token, &token, &find));
+    mutate_state(|s| {
+        migrate_user_tokens_of(stored_principal, &mut s.user_token, &mut s.custom_token);
+        add_to_user_token(stored_principal, &mut s.custom_token, &token, &find);
+    });
 }
 
 #[update(guard = "may_write_user_data")]
@@ -277,7 +467,10 @@ fn set_many_custom_tokens(tokens: Vec<CustomToken>) {
     let stored_principal = StoredPrincipal(ic_cdk::caller());
 
     mutate_state(|s| {
+        migrate_user_tokens_of(stored_principal, &mut s.user_token, &mut s.custom_token);
         for token in tokens {
+            assert_custom_token_is_valid(&token);
+
             let find = |t: &CustomToken| -> bool {
                 CustomTokenId::from(&t.token) == CustomTokenId::from(&token.token)
             };
@@ -290,128 +483,779 @@ fn set_many_custom_tokens(tokens: Vec<CustomToken>) {
 #[query(guard = "may_read_user_data")]
 fn list_custom_tokens() -> Vec<CustomToken> {
     let stored_principal = StoredPrincipal(ic_cdk::caller());
-    read_state(|s| s.custom_token.get(&stored_principal).unwrap_or_default().0)
+    read_state(|s| user_custom_tokens(&stored_principal, &s.user_token, &s.custom_token))
 }
 
 const MIN_CONFIRMATIONS_ACCEPTED_BTC_TX: u32 = 6;
 
+/// The address type of a request, if the backend can sign for it. Defaults to P2WPKH.
+///
+/// Taproot addresses are spent with Schnorr signatures, which the threshold ECDSA key the
+/// addresses derive from can't produce, so they are rejected until threshold Schnorr is used.
+fn btc_supported_address_type(address_type: Option<BtcAddressType>) -> Option<BtcAddressType> {
+    match address_type.unwrap_or_default() {
+        BtcAddressType::P2wpkh => Some(BtcAddressType::P2wpkh),
+        BtcAddressType::P2tr => None,
+    }
+}
+
+/// Returns the caller's default bitcoin address of the given type.
+#[update(guard = "may_read_user_data")]
+async fn btc_get_address(
+    params: BtcGetAddressRequest,
+) -> Result<BtcGetAddressResponse, BtcGetAddressError> {
+    let address_type = btc_supported_address_type(params.address_type)
+        .ok_or(BtcGetAddressError::UnsupportedAddressType)?;
+    let address =
+        btc_principal_to_address(params.network, &ic_cdk::caller(), address_type, 0).await?;
+
+    Ok(BtcGetAddressResponse { address })
+}
+
+/// Issues a fresh bitcoin address of the given type to the caller, so that payments can be
+/// received on an address that was never used before.
+///
+/// The last fresh address is returned again while it is unused, so that the caller only runs out
+/// of fresh addresses by using them. The UTXOs of the fresh addresses are spent, and counted in
+/// the balance, along with the ones of the default address.
+///
+/// Rejected until the chain fusion signer can sign for the fresh addresses, see
+/// `btc_user_addresses::FRESH_ADDRESSES_ENABLED`.
+#[update(guard = "may_write_user_data")]
+async fn btc_get_fresh_address(
+    params: BtcGetAddressRequest,
+) -> Result<BtcGetAddressResponse, BtcGetAddressError> {
+    let principal = ic_cdk::caller();
+    let address_type = btc_supported_address_type(params.address_type)
+        .ok_or(BtcGetAddressError::UnsupportedAddressType)?;
+    if !btc_user_addresses::FRESH_ADDRESSES_ENABLED {
+        return Err(BtcGetAddressError::FreshAddressesUnsupported);
+    }
+    let last_address_index = read_state(|s| {
+        btc_user_address_index_state::next_address_index(
+            &s.btc_user_next_address_index,
+            StoredPrincipal(principal),
+        )
+    }) - 1;
+    if last_address_index > 0 {
+        let last_address =
+            btc_principal_to_address(params.network, &principal, address_type, last_address_index)
+                .await?;
+        if btc_user_addresses::is_unused(params.network, &principal, &last_address).await? {
+            return Ok(BtcGetAddressResponse {
+                address: last_address,
+            });
+        }
+    }
+    // Another call may have issued an index while the last address was checked.
+    let address_index = mutate_state(|s| {
+        btc_user_address_index_state::issue_address_index(
+            &mut s.btc_user_next_address_index,
+            StoredPrincipal(principal),
+            last_address_index,
+        )
+    })
+    .map_err(|msg| BtcGetAddressError::QuotaExceeded { msg })?;
+    let address =
+        btc_principal_to_address(params.network, &principal, address_type, address_index).await?;
+
+    Ok(BtcGetAddressResponse { address })
+}
+
+/// Returns the balance of the caller's addresses of the given type, in which the UTXOs spent by
+/// pending transactions and the frozen UTXOs are locked and not spendable.
+#[update(guard = "may_read_user_data")]
+async fn btc_get_balance(
+    params: BtcGetBalanceRequest,
+) -> Result<BtcGetBalanceResponse, BtcGetBalanceError> {
+    let principal = ic_cdk::caller();
+    let address_type = btc_supported_address_type(params.address_type)
+        .ok_or(BtcGetBalanceError::UnsupportedAddressType)?;
+    let user_addresses =
+        BtcUserAddresses::of_type(params.network, &principal, address_type).await?;
+    let all_utxos = user_addresses
+        .get_all_utxos(params.network, None)
+        .await?
+        .all();
+    let confirmed_utxos = user_addresses
+        .get_all_utxos(
+            params.network,
+            Some(
+                params
+                    .min_confirmations
+                    .unwrap_or(MIN_CONFIRMATIONS_ACCEPTED_BTC_TX),
+            ),
+        )
+        .await?
+        .all();
+    let now_ns = time();
+
+    user_addresses.prune_pending_transactions(principal, &confirmed_utxos, &all_utxos, now_ns);
+    let pending_utxos = user_addresses.get_pending_utxos(&principal);
+    let frozen_outpoints = read_state(|s| {
+        btc_user_frozen_utxos_state::get_frozen_utxos(
+            &s.btc_user_frozen_utxos,
+            StoredPrincipal(principal),
+        )
+    });
+
+    Ok(bitcoin_utils::balance(
+        &all_utxos,
+        &confirmed_utxos,
+        &pending_utxos,
+        &frozen_outpoints,
+    ))
+}
+
+/// Returns the total amount sent by the transaction along with the sizes, in vbytes, of its
+/// outputs, excluding the change output.
+fn btc_outputs_amount_and_vsizes(
+    params: &SelectedUtxosFeeRequest,
+) -> Result<(u64, Vec<u64>), SelectedUtxosFeeErrorV2> {
+    match &params.outputs {
+        None => Ok((
+            params.amount_satoshis,
+            vec![bitcoin_utils::P2WPKH_OUTPUT_SIZE_VBYTES],
+        )),
+        Some(outputs) if outputs.is_empty() => Err(SelectedUtxosFeeErrorV2::InvalidOutput(
+            BtcOutputError::NoOutputs,
+        )),
+        Some(outputs) => {
+            let mut amount_satoshis: u64 = 0;
+            let mut output_vsizes = Vec::with_capacity(outputs.len() + 1);
+            for output in outputs {
+                let address =
+                    bitcoin_address::parse_address(&output.destination_address, params.network)
+                        .map_err(SelectedUtxosFeeErrorV2::InvalidAddress)?;
+                output_vsizes.push(bitcoin_utils::output_vsize(&address.script_pubkey()));
+                amount_satoshis = amount_satoshis.checked_add(output.sent_satoshis).ok_or(
+                    SelectedUtxosFeeErrorV2::InvalidOutput(BtcOutputError::AmountOverflow),
+                )?;
+            }
+            Ok((amount_satoshis, output_vsizes))
+        }
+    }
+}
+
+/// Returns the UTXOs spent by the inputs chosen by the user, which must be UTXOs of the addresses
+/// that are neither spent by pending transactions nor frozen.
+fn btc_user_inputs(
+    inputs: &[Outpoint],
+    all_utxos: &[Utxo],
+    pending_utxos: &[Utxo],
+    frozen_outpoints: &[Outpoint],
+) -> Result<Vec<Utxo>, SelectedUtxosFeeErrorV2> {
+    if inputs.is_empty() {
+        return Err(SelectedUtxosFeeErrorV2::InvalidInput(
+            BtcInputError::NoInputs,
+        ));
+    }
+    let mut utxos: Vec<Utxo> = Vec::with_capacity(inputs.len());
+    for outpoint in inputs {
+        let utxo = all_utxos
+            .iter()
+            .find(|utxo| utxo.outpoint == *outpoint)
+            .ok_or_else(|| {
+                SelectedUtxosFeeErrorV2::InvalidInput(BtcInputError::NotFound {
+                    outpoint: outpoint.clone(),
+                })
+            })?;
+        if frozen_outpoints.contains(outpoint) {
+            return Err(SelectedUtxosFeeErrorV2::InvalidInput(
+                BtcInputError::Frozen {
+                    outpoint: outpoint.clone(),
+                },
+            ));
+        }
+        if pending_utxos.contains(utxo) {
+            return Err(SelectedUtxosFeeErrorV2::PendingTransactions);
+        }
+        if utxos.contains(utxo) {
+            return Err(SelectedUtxosFeeErrorV2::InvalidInput(
+                BtcInputError::Duplicated {
+                    outpoint: outpoint.clone(),
+                },
+            ));
+        }
+        utxos.push(utxo.clone());
+    }
+    Ok(utxos)
+}
+
+/// Selects the UTXOs of the caller's `user_addresses` needed to pay the outputs of the request,
+/// along with the fee of the transaction, and returns the UTXOs of the addresses they were
+/// selected from. The change goes to the default address.
+///
+/// Fails if the addresses have pending transactions, as their UTXOs might be selected again,
+/// unless sweeping, which selects all the UTXOs except the ones of the pending transactions, or
+/// spending the inputs chosen by the user. The UTXOs frozen by the user are never selected.
+/// Fails with `InsufficientFunds` if the available UTXOs don't cover the outputs and the fee.
+///
+/// When spending unconfirmed change, the unconfirmed change of the pending transactions can be
+/// selected too, and the UTXOs of the pending transactions are left out instead of failing.
+async fn select_user_utxos_fee(
+    principal: Principal,
+    user_addresses: &BtcUserAddresses,
+    params: &SelectedUtxosFeeRequest,
+) -> Result<(SelectedUtxosFeeResponse, BtcUserUtxos), SelectedUtxosFeeErrorV2> {
+    let change_address = &user_addresses.default_source().address;
+    let (amount_satoshis, mut output_vsizes) = btc_outputs_amount_and_vsizes(params)?;
+    let sweep = params.sweep.unwrap_or(false);
+    if sweep && output_vsizes.len() != 1 {
+        return Err(SelectedUtxosFeeErrorV2::InvalidOutput(
+            BtcOutputError::SweepWithManyOutputs,
+        ));
+    }
+    let spend_unconfirmed_change = params.spend_unconfirmed_change.unwrap_or(false);
+    let mut user_utxos = user_addresses
+        .get_all_utxos(
+            params.network,
+            Some(
+                params
+                    .min_confirmations
+                    .unwrap_or(MIN_CONFIRMATIONS_ACCEPTED_BTC_TX),
+            ),
+        )
+        .await?;
+    let mut all_utxos = user_utxos.all();
+    // The UTXOs without confirmation requirement include the unconfirmed change.
+    let unconfirmed_user_utxos = user_addresses.get_all_utxos(params.network, None).await?;
+    let now_ns = time();
+
+    user_addresses.prune_pending_transactions(
+        principal,
+        &all_utxos,
+        &unconfirmed_user_utxos.all(),
+        now_ns,
+    );
+    let pending_utxos = user_addresses.get_pending_utxos(&principal);
+    let frozen_outpoints = read_state(|s| {
+        btc_user_frozen_utxos_state::get_frozen_utxos(
+            &s.btc_user_frozen_utxos,
+            StoredPrincipal(principal),
+        )
+    });
+
+    let mut unconfirmed_change = Vec::new();
+    if spend_unconfirmed_change {
+        user_utxos = unconfirmed_user_utxos;
+        let unconfirmed_utxos: Vec<Utxo> = user_utxos
+            .all()
+            .into_iter()
+            .filter(|utxo| !all_utxos.contains(utxo))
+            .collect();
+        let max_chain_depth = read_config(|config| {
+            btc_user_pending_tx_state::max_unconfirmed_chain_depth(
+                config.btc_network_policies.as_deref(),
+                params.network,
+            )
+        });
+        unconfirmed_change = btc_user_pending_tx_state::spendable_unconfirmed_change(
+            &user_addresses.get_pending_transactions(&principal),
+            &unconfirmed_utxos,
+            max_chain_depth,
+        );
+        all_utxos.extend_from_slice(&unconfirmed_change);
+    }
+
+    let available_utxos = match &params.inputs {
+        Some(inputs) => btc_user_inputs(inputs, &all_utxos, &pending_utxos, &frozen_outpoints)?,
+        None if !sweep && !spend_unconfirmed_change && !pending_utxos.is_empty() => {
+            return Err(SelectedUtxosFeeErrorV2::PendingTransactions)
+        }
+        None => all_utxos
+            .into_iter()
+            .filter(|utxo| {
+                !pending_utxos.contains(utxo) && !frozen_outpoints.contains(&utxo.outpoint)
+            })
+            .collect(),
+    };
+
+    let fee_millisatoshi_per_vbyte = bitcoin_api::get_fee_per_byte_for_policy(
+        params.network,
+        params.fee_policy.unwrap_or_default(),
+    )
+    .await?;
+    let mut outputs = params.outputs.clone().unwrap_or_default();
+    let coin_selection_params = CoinSelectionParams {
+        source_address: change_address,
+        amount_satoshis,
+        output_vsizes: &output_vsizes,
+        fee_millisatoshi_per_vbyte,
+    };
+    let selection = if sweep {
+        coin_selection::select_all_utxos(&available_utxos, &coin_selection_params)
+    } else if params.inputs.is_some() {
+        coin_selection::select_given_utxos(&available_utxos, &coin_selection_params)
+            .map(|selection| (selection, amount_satoshis))
+    } else {
+        coin_selection::select_utxos(
+            params.coin_selection.unwrap_or_default(),
+            &available_utxos,
+            &coin_selection_params,
+        )
+        .map(|selection| (selection, amount_satoshis))
+    };
+    let (selection, amount_satoshis) =
+        selection.ok_or(SelectedUtxosFeeErrorV2::InsufficientFunds)?;
+
+    if selection.change_satoshis > 0 {
+        output_vsizes.push(bitcoin_utils::output_vsize(&change_address.script_pubkey()));
+    }
+    let vsize = bitcoin_utils::tx_vsize_estimate(
+        change_address,
+        selection.utxos.len() as u64,
+        &output_vsizes,
+    );
+
+    if sweep {
+        // The single output receives everything.
+        for output in &mut outputs {
+            output.sent_satoshis = amount_satoshis;
+        }
+    }
+    let spends_unconfirmed_change = selection
+        .utxos
+        .iter()
+        .any(|utxo| unconfirmed_change.contains(utxo));
+
+    Ok((
+        SelectedUtxosFeeResponse {
+            utxos: selection.utxos,
+            fee_satoshis: selection.fee_satoshis,
+            amount_satoshis,
+            outputs,
+            change_satoshis: (selection.change_satoshis > 0).then_some(selection.change_satoshis),
+            fee_millisatoshi_per_vbyte,
+            vsize,
+            spends_unconfirmed_change,
+        },
+        user_utxos,
+    ))
+}
+
+/// Selects the caller's UTXOs to send the amount and estimates the fee.
+///
+/// When the UTXOs are not enough, the selection is empty. The failures other than pending
+/// transactions are internal errors: `btc_select_user_utxos_fee_v2` tells them apart.
 #[update(guard = "may_read_user_data")]
 async fn btc_select_user_utxos_fee(
     params: SelectedUtxosFeeRequest,
 ) -> Result<SelectedUtxosFeeResponse, SelectedUtxosFeeError> {
+    match btc_select_user_utxos_fee_v2(params).await {
+        Err(SelectedUtxosFeeErrorV2::InsufficientFunds) => Ok(SelectedUtxosFeeResponse {
+            utxos: vec![],
+            fee_satoshis: 0,
+            amount_satoshis: 0,
+            outputs: vec![],
+            change_satoshis: None,
+            fee_millisatoshi_per_vbyte: 0,
+            vsize: 0,
+            spends_unconfirmed_change: false,
+        }),
+        result => result.map_err(SelectedUtxosFeeError::from),
+    }
+}
+
+/// Selects the caller's UTXOs to send the amount and estimates the fee.
+#[update(guard = "may_read_user_data")]
+async fn btc_select_user_utxos_fee_v2(
+    params: SelectedUtxosFeeRequest,
+) -> Result<SelectedUtxosFeeResponse, SelectedUtxosFeeErrorV2> {
     let principal = ic_cdk::caller();
-    let source_address = btc_principal_to_p2wpkh_address(params.network, &principal)
+    let address_type = btc_supported_address_type(params.address_type)
+        .ok_or(SelectedUtxosFeeErrorV2::UnsupportedAddressType)?;
+    let user_addresses =
+        BtcUserAddresses::of_type(params.network, &principal, address_type).await?;
+
+    select_user_utxos_fee(principal, &user_addresses, &params)
         .await
-        .map_err(|msg| SelectedUtxosFeeError::InternalError { msg })?;
-    let all_utxos = bitcoin_api::get_all_utxos(
-        params.network,
-        source_address.clone(),
-        Some(
-            params
-                .min_confirmations
-                .unwrap_or(MIN_CONFIRMATIONS_ACCEPTED_BTC_TX),
-        ),
+        .map(|(selection, _)| selection)
+}
+
+/// Builds an unsigned transaction from the caller's addresses, returned as a PSBT.
+///
+/// The UTXOs spent by the transaction are registered as pending, with the status `Built`, so that
+/// they are not selected again before the transaction is confirmed.
+#[update(guard = "may_write_user_data")]
+async fn btc_build_unsigned_transaction(
+    params: BtcBuildUnsignedTransactionRequest,
+) -> Result<BtcBuildUnsignedTransactionResponse, BtcBuildUnsignedTransactionError> {
+    let principal = ic_cdk::caller();
+    let destination_address =
+        bitcoin_address::parse_address(&params.destination_address, params.network)
+            .map_err(BtcBuildUnsignedTransactionError::InvalidAddress)?;
+    let address_type = btc_supported_address_type(params.address_type)
+        .ok_or(BtcBuildUnsignedTransactionError::UnsupportedAddressType)?;
+    let user_addresses =
+        BtcUserAddresses::of_type(params.network, &principal, address_type).await?;
+    let (selection, user_utxos) = select_user_utxos_fee(
+        principal,
+        &user_addresses,
+        &SelectedUtxosFeeRequest {
+            amount_satoshis: params.amount_satoshis,
+            network: params.network,
+            min_confirmations: params.min_confirmations,
+            outputs: Some(vec![BtcTxOutput {
+                destination_address: params.destination_address,
+                sent_satoshis: params.amount_satoshis,
+            }]),
+            fee_policy: params.fee_policy,
+            address_type: Some(address_type),
+            coin_selection: params.coin_selection,
+            sweep: None,
+            inputs: params.inputs,
+            spend_unconfirmed_change: params.spend_unconfirmed_change,
+        },
     )
-    .await
-    .map_err(|msg| SelectedUtxosFeeError::InternalError { msg })?;
-    let now_ns = time();
+    .await?;
+
+    let source_address = user_addresses.default_source();
+    let inputs = user_addresses
+        .inputs(&selection.utxos, &user_utxos)
+        .map_err(|msg| BtcBuildUnsignedTransactionError::InternalError { msg })?;
+    let psbt = bitcoin_transaction::build_unsigned_psbt(
+        &inputs,
+        &source_address.address,
+        &[(destination_address, params.amount_satoshis)],
+        selection.fee_satoshis,
+    )
+    .map_err(|msg| BtcBuildUnsignedTransactionError::InternalError { msg })?;
+    let txid = psbt.unsigned_tx.compute_txid().to_byte_array().to_vec();
 
-    let has_pending_transactions = with_btc_pending_transactions(|pending_transactions| {
-        pending_transactions.prune_pending_transactions(principal, &all_utxos, now_ns);
-        !pending_transactions
-            .get_pending_transactions(&principal, &source_address)
-            .is_empty()
-    });
+    with_btc_pending_transactions(|pending_transactions| {
+        pending_transactions.add_pending_transaction(
+            principal,
+            source_address.address.to_string(),
+            StoredPendingTransaction {
+                txid: txid.clone(),
+                utxos: selection.utxos.clone(),
+                created_at_timestamp_ns: time(),
+                outputs: Some(selection.outputs),
+                fee_satoshis: Some(selection.fee_satoshis),
+                status: Some(BtcPendingTransactionStatus::Built),
+                status_updated_at_timestamp_ns: None,
+            },
+        )
+    })
+    .map_err(|msg| BtcBuildUnsignedTransactionError::QuotaExceeded { msg })?;
+
+    Ok(BtcBuildUnsignedTransactionResponse {
+        psbt: psbt.serialize(),
+        txid,
+        utxos: selection.utxos,
+        fee_satoshis: selection.fee_satoshis,
+        spends_unconfirmed_change: selection.spends_unconfirmed_change,
+    })
+}
 
-    if has_pending_transactions {
-        return Err(SelectedUtxosFeeError::PendingTransactions);
-    }
+/// Builds an unsigned transaction replacing a pending transaction of the caller with a higher
+/// fee (BIP-125), returned as a PSBT.
+///
+/// The replacement spends the UTXOs of the pending transaction, and more if needed, to pay the
+/// same outputs. It replaces the pending transaction in the pending transactions of the caller.
+/// Only the transactions built with `btc_build_unsigned_transaction` can be replaced, as the
+/// outputs of the other pending transactions are not known. If the pending transaction spends the
+/// unconfirmed change of other pending transactions, so does the replacement.
+#[update(guard = "may_write_user_data")]
+async fn btc_bump_fee(
+    params: BtcBumpFeeRequest,
+) -> Result<BtcBuildUnsignedTransactionResponse, BtcBumpFeeError> {
+    let principal = ic_cdk::caller();
+    let address_type = btc_supported_address_type(params.address_type)
+        .ok_or(BtcBumpFeeError::UnsupportedAddressType)?;
+    let user_addresses =
+        BtcUserAddresses::of_type(params.network, &principal, address_type).await?;
+    // The transactions built by the backend are pending transactions of the default address.
+    let source_address = user_addresses.default_source();
+    let user_utxos = user_addresses
+        .get_all_utxos(
+            params.network,
+            Some(
+                params
+                    .min_confirmations
+                    .unwrap_or(MIN_CONFIRMATIONS_ACCEPTED_BTC_TX),
+            ),
+        )
+        .await?;
+    let all_utxos = user_utxos.all();
+    let unconfirmed_user_utxos = user_addresses.get_all_utxos(params.network, None).await?;
+    let unconfirmed_utxos = unconfirmed_user_utxos.all();
+    let now_ns = time();
 
-    let median_fee_millisatoshi_per_vbyte = bitcoin_api::get_fee_per_byte(params.network)
-        .await
-        .map_err(|msg| SelectedUtxosFeeError::InternalError { msg })?;
-    // We support sending to one destination only.
-    // Therefore, the outputs are the destination and the source address for the change.
-    let output_count = 2;
-    let mut available_utxos = all_utxos.clone();
-    let selected_utxos =
-        bitcoin_utils::utxos_selection(params.amount_satoshis, &mut available_utxos, output_count);
-
-    // Fee calculation might still take into account default tx size and expected output.
-    // But if there are no selcted utxos, no tx is possible. Therefore, no fee should be present.
-    if selected_utxos.is_empty() {
-        return Ok(SelectedUtxosFeeResponse {
-            utxos: selected_utxos,
-            fee_satoshis: 0,
+    user_addresses.prune_pending_transactions(principal, &all_utxos, &unconfirmed_utxos, now_ns);
+    let pending_utxos = user_addresses.get_pending_utxos(&principal);
+    let original_transaction = with_btc_pending_transactions(|pending_transactions| {
+        pending_transactions.get_pending_transaction(
+            &principal,
+            &source_address.address.to_string(),
+            &params.txid,
+        )
+    });
+    // Only the transactions that might still be confirmed can be replaced.
+    let original_transaction = original_transaction
+        .filter(StoredPendingTransaction::is_in_flight)
+        .ok_or(BtcBumpFeeError::TransactionNotFound)?;
+    let (Some(outputs), Some(original_fee_satoshis)) = (
+        original_transaction.outputs,
+        original_transaction.fee_satoshis,
+    ) else {
+        return Err(BtcBumpFeeError::InternalError {
+            msg: "The outputs of the pending transaction are unknown".to_string(),
         });
+    };
+    // The pending transaction may spend the unconfirmed change of other pending transactions, if
+    // it was built with `spend_unconfirmed_change`.
+    let max_chain_depth = read_config(|config| {
+        btc_user_pending_tx_state::max_unconfirmed_chain_depth(
+            config.btc_network_policies.as_deref(),
+            params.network,
+        )
+    });
+    let unconfirmed_change = btc_user_pending_tx_state::spendable_unconfirmed_change(
+        &user_addresses.get_pending_transactions(&principal),
+        &unconfirmed_utxos
+            .into_iter()
+            .filter(|utxo| !all_utxos.contains(utxo))
+            .collect::<Vec<_>>(),
+        max_chain_depth,
+    );
+    if !original_transaction
+        .utxos
+        .iter()
+        .all(|utxo| all_utxos.contains(utxo) || unconfirmed_change.contains(utxo))
+    {
+        return Err(BtcBumpFeeError::UtxosAlreadySpent);
     }
+    let spends_unconfirmed_change = original_transaction
+        .utxos
+        .iter()
+        .any(|utxo| unconfirmed_change.contains(utxo));
+    // The unconfirmed change is only in the UTXOs without confirmation requirement.
+    let user_utxos = if spends_unconfirmed_change {
+        unconfirmed_user_utxos
+    } else {
+        user_utxos
+    };
+    // The UTXOs of the other pending transactions, and the frozen UTXOs, can't be spent by the
+    // replacement.
+    let frozen_outpoints = read_state(|s| {
+        btc_user_frozen_utxos_state::get_frozen_utxos(
+            &s.btc_user_frozen_utxos,
+            StoredPrincipal(principal),
+        )
+    });
+    let available_utxos: Vec<_> = all_utxos
+        .into_iter()
+        .filter(|utxo| !pending_utxos.contains(utxo) && !frozen_outpoints.contains(&utxo.outpoint))
+        .collect();
 
-    let fee_satoshis = estimate_fee(
-        selected_utxos.len() as u64,
-        median_fee_millisatoshi_per_vbyte,
-        output_count as u64,
-    );
+    let destinations = outputs
+        .iter()
+        .map(|output| {
+            bitcoin_address::parse_address(&output.destination_address, params.network)
+                .map(|address| (address, output.sent_satoshis))
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(BtcBumpFeeError::InvalidAddress)?;
+    let output_vsizes: Vec<u64> = destinations
+        .iter()
+        .map(|(address, _)| bitcoin_utils::output_vsize(&address.script_pubkey()))
+        .collect();
+    let fee_millisatoshi_per_vbyte = bitcoin_api::get_fee_per_byte_for_policy(
+        params.network,
+        params.fee_policy.unwrap_or_default(),
+    )
+    .await?;
+    let selection = coin_selection::select_replacement_utxos(
+        &original_transaction.utxos,
+        original_fee_satoshis,
+        &available_utxos,
+        &CoinSelectionParams {
+            source_address: &source_address.address,
+            amount_satoshis: outputs.iter().map(|output| output.sent_satoshis).sum(),
+            output_vsizes: &output_vsizes,
+            fee_millisatoshi_per_vbyte,
+        },
+    )
+    .ok_or(BtcBumpFeeError::InsufficientFunds)?;
+
+    let inputs = user_addresses
+        .inputs(&selection.utxos, &user_utxos)
+        .map_err(|msg| BtcBumpFeeError::InternalError { msg })?;
+    let psbt = bitcoin_transaction::build_unsigned_psbt(
+        &inputs,
+        &source_address.address,
+        &destinations,
+        selection.fee_satoshis,
+    )
+    .map_err(|msg| BtcBumpFeeError::InternalError { msg })?;
+    let txid = psbt.unsigned_tx.compute_txid().to_byte_array().to_vec();
 
-    Ok(SelectedUtxosFeeResponse {
-        utxos: selected_utxos,
-        fee_satoshis,
+    with_btc_pending_transactions(|pending_transactions| {
+        pending_transactions.replace_pending_transaction(
+            principal,
+            source_address.address.to_string(),
+            params.txid,
+            StoredPendingTransaction {
+                txid: txid.clone(),
+                utxos: selection.utxos.clone(),
+                created_at_timestamp_ns: time(),
+                outputs: Some(outputs),
+                fee_satoshis: Some(selection.fee_satoshis),
+                status: Some(BtcPendingTransactionStatus::Built),
+                status_updated_at_timestamp_ns: None,
+            },
+        )
+    })
+    .map_err(|msg| BtcBumpFeeError::InternalError { msg })?;
+
+    Ok(BtcBuildUnsignedTransactionResponse {
+        psbt: psbt.serialize(),
+        txid,
+        utxos: selection.utxos,
+        fee_satoshis: selection.fee_satoshis,
+        spends_unconfirmed_change,
     })
 }
 
+/// Registers a transaction that the caller broadcast, as `btc_add_pending_transaction_v2` does.
+///
+/// All the failures are internal errors.
 #[update(guard = "may_write_user_data")]
 async fn btc_add_pending_transaction(
     params: BtcAddPendingTransactionRequest,
 ) -> Result<(), BtcAddPendingTransactionError> {
+    btc_add_pending_transaction_v2(params)
+        .await
+        .map_err(BtcAddPendingTransactionError::from)
+}
+
+/// Registers a transaction that the caller broadcast, so that its UTXOs are not selected again.
+///
+/// The address must be an address of the caller and the transaction can only spend the current
+/// UTXOs of the caller's addresses of its type. If the transaction was built with
+/// `btc_build_unsigned_transaction`, its outputs and fee are kept. The transaction is `Broadcast`
+/// until the bitcoin API shows its UTXOs as spent.
+#[update(guard = "may_write_user_data")]
+async fn btc_add_pending_transaction_v2(
+    params: BtcAddPendingTransactionRequest,
+) -> Result<(), BtcAddPendingTransactionErrorV2> {
     let principal = ic_cdk::caller();
-    let current_utxos = bitcoin_api::get_all_utxos(
-        params.network,
-        params.address.clone(),
-        Some(MIN_CONFIRMATIONS_ACCEPTED_BTC_TX),
-    )
-    .await
-    .map_err(|msg| BtcAddPendingTransactionError::InternalError { msg })?;
+    let address = bitcoin_address::parse_address(&params.address, params.network)
+        .map_err(BtcAddPendingTransactionErrorV2::InvalidAddress)?;
+    let Some(user_addresses) =
+        BtcUserAddresses::containing(params.network, &principal, &address).await?
+    else {
+        return Err(BtcAddPendingTransactionErrorV2::InvalidAddress(
+            BtcAddressError::NotOwned {
+                address: params.address,
+            },
+        ));
+    };
+    let address = address.to_string();
+
+    // The transaction can only spend UTXOs of the addresses.
+    let all_utxos = user_addresses
+        .get_all_utxos(params.network, None)
+        .await?
+        .all();
+    if let Some(utxo) = params.utxos.iter().find(|utxo| !all_utxos.contains(utxo)) {
+        return Err(BtcAddPendingTransactionErrorV2::UtxoNotFound {
+            outpoint: utxo.outpoint.clone(),
+        });
+    }
+    let current_utxos = user_addresses
+        .get_all_utxos(params.network, Some(MIN_CONFIRMATIONS_ACCEPTED_BTC_TX))
+        .await?
+        .all();
     let now_ns = time();
 
+    user_addresses.prune_pending_transactions(principal, &current_utxos, &all_utxos, now_ns);
     with_btc_pending_transactions(|pending_transactions| {
-        pending_transactions.prune_pending_transactions(principal, &current_utxos, now_ns);
+        let built_transaction =
+            pending_transactions.get_pending_transaction(&principal, &address, &params.txid);
+        // The transaction is only seen once the bitcoin API shows its UTXOs as spent.
+        let status = built_transaction.as_ref().map_or(
+            BtcPendingTransactionStatus::Broadcast,
+            StoredPendingTransaction::reported_status,
+        );
         let current_pending_transaction = StoredPendingTransaction {
             txid: params.txid,
             utxos: params.utxos,
             created_at_timestamp_ns: now_ns,
+            outputs: built_transaction
+                .as_ref()
+                .and_then(|transaction| transaction.outputs.clone()),
+            fee_satoshis: built_transaction.and_then(|transaction| transaction.fee_satoshis),
+            status: Some(status),
+            status_updated_at_timestamp_ns: None,
         };
         pending_transactions
-            .add_pending_transaction(principal, params.address, current_pending_transaction)
-            .map_err(|msg| BtcAddPendingTransactionError::InternalError { msg })
+            .add_pending_transaction(principal, address, current_pending_transaction)
+            .map_err(|msg| BtcAddPendingTransactionErrorV2::QuotaExceeded { msg })
     })
 }
 
+/// Returns the pending transactions of the caller's address, as `btc_get_pending_transactions_v2`
+/// does.
+///
+/// All the failures are internal errors.
 #[update(guard = "may_read_user_data")]
 async fn btc_get_pending_transactions(
     params: BtcGetPendingTransactionsRequest,
+) -> Result<BtcGetPendingTransactionsReponse, BtcAddPendingTransactionError> {
+    btc_get_pending_transactions_v2(params)
+        .await
+        .map_err(BtcAddPendingTransactionError::from)
+}
+
+/// Returns the pending transactions of the caller's address, along with their status.
+///
+/// The address must be an address of the caller. The transactions that are confirmed, dropped or
+/// replaced are kept for a day.
+#[update(guard = "may_read_user_data")]
+async fn btc_get_pending_transactions_v2(
+    params: BtcGetPendingTransactionsRequest,
 ) -> Result<BtcGetPendingTransactionsReponse, BtcGetPendingTransactionsError> {
     let principal = ic_cdk::caller();
+    let address = bitcoin_address::parse_address(&params.address, params.network)
+        .map_err(BtcGetPendingTransactionsError::InvalidAddress)?;
+    let Some(user_addresses) =
+        BtcUserAddresses::containing(params.network, &principal, &address).await?
+    else {
+        return Err(BtcGetPendingTransactionsError::InvalidAddress(
+            BtcAddressError::NotOwned {
+                address: params.address,
+            },
+        ));
+    };
+    let address = address.to_string();
+
+    let current_utxos = user_addresses
+        .get_all_utxos(params.network, Some(MIN_CONFIRMATIONS_ACCEPTED_BTC_TX))
+        .await?
+        .all();
+    let all_utxos = user_addresses
+        .get_all_utxos(params.network, None)
+        .await?
+        .all();
     let now_ns = time();
 
-    let current_utxos = bitcoin_api::get_all_utxos(
-        params.network,
-        params.address.clone(),
-        Some(MIN_CONFIRMATIONS_ACCEPTED_BTC_TX),
-    )
-    .await
-    .map_err(|msg| BtcGetPendingTransactionsError::InternalError { msg })?;
-
+    user_addresses.prune_pending_transactions(principal, &current_utxos, &all_utxos, now_ns);
     let stored_transactions = with_btc_pending_transactions(|pending_transactions| {
-        pending_transactions.prune_pending_transactions(principal, &current_utxos, now_ns);
-        pending_transactions
-            .get_pending_transactions(&principal, &params.address)
-            .clone()
+        pending_transactions.get_pending_transactions(&principal, &address)
     });
 
     let pending_transactions = stored_transactions
-        .iter()
+        .into_iter()
         .map(|tx| PendingTransaction {
-            txid: tx.txid.clone(),
-            utxos: tx.utxos.clone(),
+            status: tx.status(),
+            txid: tx.txid,
+            utxos: tx.utxos,
+            outputs: tx.outputs,
+            fee_satoshis: tx.fee_satoshis,
+            created_at_timestamp_ns: tx.created_at_timestamp_ns,
         })
         .collect();
 
@@ -420,6 +1264,178 @@ async fn btc_get_pending_transactions(
     })
 }
 
+/// Removes a pending transaction of the caller that is known to have failed, so that its UTXOs
+/// can be spent again without waiting for the transaction to be dropped.
+#[update(guard = "may_write_user_data")]
+#[allow(clippy::needless_pass_by_value)]
+fn btc_remove_pending_transaction(
+    params: BtcRemovePendingTransactionRequest,
+) -> Result<(), BtcRemovePendingTransactionError> {
+    let principal = ic_cdk::caller();
+    bitcoin_address::check_address(&params.address)
+        .map_err(BtcRemovePendingTransactionError::InvalidAddress)?;
+    with_btc_pending_transactions(|pending_transactions| {
+        pending_transactions
+            .remove_pending_transaction(principal, params.address, params.txid)
+            .map(|_| ())
+            .map_err(|_| BtcRemovePendingTransactionError::TransactionNotFound)
+    })
+}
+
+/// Freezes UTXOs of the caller, so that they are never spent by the transactions built by the
+/// backend.
+#[update(guard = "may_write_user_data")]
+#[allow(clippy::needless_pass_by_value)]
+fn btc_freeze_utxos(params: BtcFreezeUtxosRequest) -> Result<(), BtcFreezeUtxosError> {
+    let stored_principal = StoredPrincipal(ic_cdk::caller());
+    mutate_state(|s| {
+        btc_user_frozen_utxos_state::freeze_utxos(
+            &mut s.btc_user_frozen_utxos,
+            stored_principal,
+            &params.outpoints,
+        )
+    })
+    .map_err(|msg| BtcFreezeUtxosError::QuotaExceeded { msg })
+}
+
+/// Unfreezes UTXOs of the caller, so that they can be spent again.
+#[update(guard = "may_write_user_data")]
+#[allow(clippy::needless_pass_by_value)]
+fn btc_unfreeze_utxos(params: BtcFreezeUtxosRequest) {
+    let stored_principal = StoredPrincipal(ic_cdk::caller());
+    mutate_state(|s| {
+        btc_user_frozen_utxos_state::unfreeze_utxos(
+            &mut s.btc_user_frozen_utxos,
+            stored_principal,
+            &params.outpoints,
+        );
+    });
+}
+
+#[query(guard = "may_read_user_data")]
+fn btc_list_frozen_utxos() -> Vec<Outpoint> {
+    let stored_principal = StoredPrincipal(ic_cdk::caller());
+    read_state(|s| {
+        btc_user_frozen_utxos_state::get_frozen_utxos(&s.btc_user_frozen_utxos, stored_principal)
+    })
+}
+
+/// Returns the bitcoin address where the caller deposits BTC to get ckBTC.
+#[update(guard = "may_read_user_data")]
+async fn ckbtc_get_btc_address() -> Result<CkBtcGetBtcAddressResponse, CkBtcGetBtcAddressError> {
+    let principal = ic_cdk::caller();
+    let minter = ckbtc_minter().map_err(|msg| CkBtcGetBtcAddressError::InternalError { msg })?;
+    let address = minter
+        .get_btc_address(principal)
+        .await
+        .map_err(|msg| CkBtcGetBtcAddressError::InternalError { msg })?;
+    Ok(CkBtcGetBtcAddressResponse { address })
+}
+
+/// Estimates the fees deducted by the minter from a withdrawal of ckBTC to BTC.
+#[update(guard = "may_read_user_data")]
+async fn ckbtc_estimate_withdrawal_fee(
+    params: CkBtcEstimateWithdrawalFeeRequest,
+) -> Result<CkBtcEstimateWithdrawalFeeResponse, CkBtcEstimateWithdrawalFeeError> {
+    let minter =
+        ckbtc_minter().map_err(|msg| CkBtcEstimateWithdrawalFeeError::InternalError { msg })?;
+    minter
+        .estimate_withdrawal_fee(params.amount_satoshis)
+        .await
+        .map_err(|msg| CkBtcEstimateWithdrawalFeeError::InternalError { msg })
+}
+
+/// Tracks a withdrawal that the caller requested from the minter with
+/// `retrieve_btc_with_approval`, so that its status can be polled with `ckbtc_get_withdrawals`.
+///
+/// The minter must report the withdrawal as one of the caller's, so that a caller can't track the
+/// withdrawals of others, and its burn on the ckBTC ledger must have the given destination address
+/// and amount.
+#[update(guard = "may_write_user_data")]
+async fn ckbtc_add_withdrawal(
+    params: CkBtcAddWithdrawalRequest,
+) -> Result<(), CkBtcAddWithdrawalError> {
+    let principal = ic_cdk::caller();
+    let minter = ckbtc_minter().map_err(|msg| CkBtcAddWithdrawalError::InternalError { msg })?;
+    let block_indices = minter
+        .withdrawal_block_indices(principal)
+        .await
+        .map_err(|msg| CkBtcAddWithdrawalError::InternalError { msg })?;
+    if !block_indices.contains(&params.block_index) {
+        return Err(CkBtcAddWithdrawalError::WithdrawalNotFound);
+    }
+    let burn = minter
+        .withdrawal_burn(params.block_index)
+        .await
+        .map_err(|msg| CkBtcAddWithdrawalError::InternalError { msg })?;
+    if burn.map_or(true, |burn| {
+        burn.address != params.address || burn.amount_satoshis != params.amount_satoshis
+    }) {
+        return Err(CkBtcAddWithdrawalError::WithdrawalNotFound);
+    }
+
+    let stored_principal = StoredPrincipal(principal);
+    let withdrawal = StoredCkBtcWithdrawal {
+        block_index: params.block_index,
+        address: params.address,
+        amount_satoshis: params.amount_satoshis,
+        created_at_timestamp_ns: time(),
+        status: None,
+        finalized_at_timestamp_ns: None,
+    };
+    mutate_state(|s| {
+        ckbtc_user_withdrawals_state::add_withdrawal(
+            &mut s.ckbtc_user_withdrawals,
+            stored_principal,
+            withdrawal,
+        )
+    })
+    .map_err(|msg| CkBtcAddWithdrawalError::InternalError { msg })
+}
+
+/// Returns the withdrawals of the caller, with their statuses polled from the minter.
+///
+/// Settled withdrawals are returned for a day, then forgotten. A withdrawal whose status cannot
+/// be polled keeps its last known status.
+#[update(guard = "may_read_user_data")]
+async fn ckbtc_get_withdrawals() -> Result<CkBtcGetWithdrawalsResponse, CkBtcGetWithdrawalsError> {
+    let stored_principal = StoredPrincipal(ic_cdk::caller());
+    let unsettled_block_indices: Vec<u64> = read_state(|s| {
+        ckbtc_user_withdrawals_state::get_withdrawals(&s.ckbtc_user_withdrawals, stored_principal)
+    })
+    .into_iter()
+    .filter(|withdrawal| !withdrawal.is_final())
+    .map(|withdrawal| withdrawal.block_index)
+    .collect();
+    let statuses = if unsettled_block_indices.is_empty() {
+        Vec::new()
+    } else {
+        let minter =
+            ckbtc_minter().map_err(|msg| CkBtcGetWithdrawalsError::InternalError { msg })?;
+        join_all(
+            unsettled_block_indices
+                .iter()
+                .map(|block_index| minter.retrieve_btc_status(*block_index)),
+        )
+        .await
+        .into_iter()
+        .zip(unsettled_block_indices)
+        .filter_map(|(status, block_index)| Some((block_index, status.ok()?)))
+        .collect()
+    };
+    let withdrawals = mutate_state(|s| {
+        ckbtc_user_withdrawals_state::update_withdrawal_statuses(
+            &mut s.ckbtc_user_withdrawals,
+            stored_principal,
+            &statuses,
+            time(),
+        )
+    });
+    Ok(CkBtcGetWithdrawalsResponse {
+        withdrawals: withdrawals.into_iter().map(Into::into).collect(),
+    })
+}
+
 #[update(guard = "may_write_user_data")]
 #[allow(clippy::needless_pass_by_value)]
 fn add_user_credential(request: AddUserCredentialRequest) -> Result<(), AddUserCredentialError> {
@@ -543,6 +1559,64 @@ fn bulk_up(data: Vec<u8>) {
     migrate::bulk_up(&data);
 }
 
+/// Sets the tip height served by the stand-in bitcoin API.
+#[cfg(feature = "bitcoin-api-stand-in")]
+#[update(guard = "caller_is_allowed")]
+fn set_btc_stand_in_tip_height(network: BtcNetwork, tip_height: u32) {
+    bitcoin_api::stand_in::canister_stand_in().set_tip_height(network, tip_height);
+    mutate_utxos_cache(UtxosCache::clear);
+}
+
+/// Sets the UTXOs of an address served by the stand-in bitcoin API.
+#[cfg(feature = "bitcoin-api-stand-in")]
+#[update(guard = "caller_is_allowed")]
+fn set_btc_stand_in_utxos(network: BtcNetwork, address: String, utxos: Vec<Utxo>) {
+    bitcoin_api::stand_in::canister_stand_in().set_utxos(network, address, utxos);
+    mutate_utxos_cache(UtxosCache::clear);
+}
+
+/// Marks a UTXO as spent in the block at the given height of the stand-in bitcoin API.
+#[cfg(feature = "bitcoin-api-stand-in")]
+#[update(guard = "caller_is_allowed")]
+fn set_btc_stand_in_spent_height(network: BtcNetwork, outpoint: Outpoint, height: u32) {
+    bitcoin_api::stand_in::canister_stand_in().set_spent_height(network, outpoint, height);
+    mutate_utxos_cache(UtxosCache::clear);
+}
+
+/// Sets the fee percentiles served by the stand-in bitcoin API.
+#[cfg(feature = "bitcoin-api-stand-in")]
+#[update(guard = "caller_is_allowed")]
+fn set_btc_stand_in_fee_percentiles(network: BtcNetwork, fee_percentiles: Vec<u64>) {
+    bitcoin_api::stand_in::canister_stand_in().set_fee_percentiles(network, fee_percentiles);
+}
+
+/// Sets the status of a withdrawal reported by the stand-in ckBTC minter.
+#[cfg(feature = "ckbtc-minter-stand-in")]
+#[update(guard = "caller_is_allowed")]
+fn set_ckbtc_stand_in_withdrawal_status(block_index: u64, status: CkBtcWithdrawalStatus) {
+    ckbtc_minter::stand_in::canister_stand_in().set_withdrawal_status(block_index, status);
+}
+
+/// Sets the owner, destination address and amount of a withdrawal reported by the stand-in ckBTC
+/// minter.
+#[cfg(feature = "ckbtc-minter-stand-in")]
+#[update(guard = "caller_is_allowed")]
+fn set_ckbtc_stand_in_withdrawal(
+    block_index: u64,
+    owner: Principal,
+    address: String,
+    amount_satoshis: u64,
+) {
+    ckbtc_minter::stand_in::canister_stand_in().set_withdrawal(
+        block_index,
+        owner,
+        ckbtc_minter::WithdrawalBurn {
+            address,
+            amount_satoshis,
+        },
+    );
+}
+
 /// Starts user data migration to a given canister.
 ///
 /// # Errors
@@ -1,8 +1,11 @@
 use crate::{
+    btc_user_pending_tx_state::StoredPendingTransaction,
+    ckbtc_user_withdrawals_state::StoredCkBtcWithdrawal,
     mutate_state, read_state,
-    types::{Candid, StoredPrincipal},
+    types::{BitcoinAddress, Candid, StoredPendingTransactionKey, StoredPrincipal},
 };
 use candid::{decode_one, encode_one, CandidType, Principal};
+use ic_cdk::api::management_canister::bitcoin::Outpoint;
 use ic_cdk::eprintln;
 use ic_cdk_timers::clear_timer;
 use serde::Deserialize;
@@ -30,6 +33,10 @@ pub enum MigrationChunk {
     CustomToken(Vec<(Principal, Vec<CustomToken>)>),
     UserProfile(Vec<((Timestamp, Principal), StoredUserProfile)>),
     UserProfileUpdated(Vec<(Principal, Timestamp)>),
+    BtcPendingTransactions(Vec<(Principal, Vec<(BitcoinAddress, StoredPendingTransaction)>)>),
+    BtcFrozenUtxos(Vec<(Principal, Vec<Outpoint>)>),
+    CkBtcWithdrawals(Vec<(Principal, Vec<StoredCkBtcWithdrawal>)>),
+    BtcNextAddressIndices(Vec<(Principal, u32)>),
 }
 
 /// Bulk uploads data to this canister.
@@ -74,6 +81,49 @@ pub fn bulk_up(data: &[u8]) {
                 }
             });
         }
+        MigrationChunk::BtcPendingTransactions(pending_transactions) => {
+            mutate_state(|state| {
+                for (principal, transactions) in pending_transactions {
+                    for (address, transaction) in transactions {
+                        let key = StoredPendingTransactionKey {
+                            principal,
+                            address,
+                            txid: transaction.txid.clone(),
+                        };
+                        state
+                            .btc_user_pending_transactions
+                            .insert(key, Candid(transaction));
+                    }
+                }
+            });
+        }
+        MigrationChunk::BtcFrozenUtxos(frozen_utxos) => {
+            mutate_state(|state| {
+                for (principal, outpoints) in frozen_utxos {
+                    state
+                        .btc_user_frozen_utxos
+                        .insert(StoredPrincipal(principal), Candid(outpoints));
+                }
+            });
+        }
+        MigrationChunk::CkBtcWithdrawals(withdrawals) => {
+            mutate_state(|state| {
+                for (principal, withdrawals) in withdrawals {
+                    state
+                        .ckbtc_user_withdrawals
+                        .insert(StoredPrincipal(principal), Candid(withdrawals));
+                }
+            });
+        }
+        MigrationChunk::BtcNextAddressIndices(next_address_indices) => {
+            mutate_state(|state| {
+                for (principal, next_address_index) in next_address_indices {
+                    state
+                        .btc_user_next_address_index
+                        .insert(StoredPrincipal(principal), next_address_index);
+                }
+            });
+        }
     }
 }
 
@@ -153,6 +203,102 @@ fn next_user_timestamp_chunk(user_maybe: Option<Principal>) -> Vec<(Principal, T
     })
 }
 
+/// The next chunk of pending bitcoin transactions to be migrated, grouped by user.
+fn next_btc_pending_transactions_chunk(
+    last_principal: Option<Principal>,
+) -> Vec<(Principal, Vec<(BitcoinAddress, StoredPendingTransaction)>)> {
+    let chunk_size = 5;
+    let range = last_principal.map_or((Bound::Unbounded, Bound::Unbounded), |principal| {
+        (
+            Bound::Included(StoredPendingTransactionKey {
+                principal,
+                address: BitcoinAddress::new(),
+                txid: Vec::new(),
+            }),
+            Bound::Unbounded,
+        )
+    });
+    read_state(|state| {
+        let mut chunk: Vec<(Principal, Vec<(BitcoinAddress, StoredPendingTransaction)>)> =
+            Vec::new();
+        for (key, transaction) in state
+            .btc_user_pending_transactions
+            .range(range)
+            .skip_while(|(key, _)| Some(key.principal) == last_principal)
+        {
+            match chunk.last_mut() {
+                Some((principal, transactions)) if *principal == key.principal => {
+                    transactions.push((key.address, transaction.0));
+                }
+                _ if chunk.len() == chunk_size => break,
+                _ => chunk.push((key.principal, vec![(key.address, transaction.0)])),
+            }
+        }
+        chunk
+    })
+}
+
+/// The next chunk of frozen bitcoin UTXOs to be migrated.
+fn next_btc_frozen_utxos_chunk(
+    last_principal: Option<Principal>,
+) -> Vec<(Principal, Vec<Outpoint>)> {
+    let chunk_size = 5;
+    let range = last_principal.map_or((Bound::Unbounded, Bound::Unbounded), |principal| {
+        (
+            Bound::Excluded(StoredPrincipal(principal)),
+            Bound::Unbounded,
+        )
+    });
+    read_state(|state| {
+        state
+            .btc_user_frozen_utxos
+            .range(range)
+            .take(chunk_size)
+            .map(|(stored_principal, outpoints)| (stored_principal.0, outpoints.0))
+            .collect::<Vec<_>>()
+    })
+}
+
+/// The next chunk of ckBTC withdrawals to be migrated.
+fn next_ckbtc_withdrawals_chunk(
+    last_principal: Option<Principal>,
+) -> Vec<(Principal, Vec<StoredCkBtcWithdrawal>)> {
+    let chunk_size = 5;
+    let range = last_principal.map_or((Bound::Unbounded, Bound::Unbounded), |principal| {
+        (
+            Bound::Excluded(StoredPrincipal(principal)),
+            Bound::Unbounded,
+        )
+    });
+    read_state(|state| {
+        state
+            .ckbtc_user_withdrawals
+            .range(range)
+            .take(chunk_size)
+            .map(|(stored_principal, withdrawals)| (stored_principal.0, withdrawals.0))
+            .collect::<Vec<_>>()
+    })
+}
+
+/// The next chunk of next bitcoin address indices to be migrated.
+fn next_btc_next_address_indices_chunk(last_principal: Option<Principal>) -> Vec<(Principal, u32)> {
+    let chunk_size = 5;
+    let range = last_principal.map_or((Bound::Unbounded, Bound::Unbounded), |principal| {
+        (
+            Bound::Excluded(StoredPrincipal(principal)),
+            Bound::Unbounded,
+        )
+    });
+    read_state(|state| {
+        state
+            .btc_user_next_address_index
+            .range(range)
+            .take(chunk_size)
+            .map(|(stored_principal, next_address_index)| (stored_principal.0, next_address_index))
+            .collect::<Vec<_>>()
+    })
+}
+
 /// Migrates a chunk of data.
 ///
 /// # Returns
@@ -224,6 +370,37 @@ pub async fn step_migration() -> Result<MigrationProgress, MigrationError> {
                 let chunk = next_user_profile_chunk(last_user_profile);
                 migrate!(migration, chunk, MigratedUserProfilesUpTo, UserProfile)
             }
+            MigrationProgress::MigratedBtcPendingTransactionsUpTo(last_principal) => {
+                let chunk = next_btc_pending_transactions_chunk(last_principal);
+                migrate!(
+                    migration,
+                    chunk,
+                    MigratedBtcPendingTransactionsUpTo,
+                    BtcPendingTransactions
+                )
+            }
+            MigrationProgress::MigratedBtcFrozenUtxosUpTo(last_principal) => {
+                let chunk = next_btc_frozen_utxos_chunk(last_principal);
+                migrate!(migration, chunk, MigratedBtcFrozenUtxosUpTo, BtcFrozenUtxos)
+            }
+            MigrationProgress::MigratedCkBtcWithdrawalsUpTo(last_principal) => {
+                let chunk = next_ckbtc_withdrawals_chunk(last_principal);
+                migrate!(
+                    migration,
+                    chunk,
+                    MigratedCkBtcWithdrawalsUpTo,
+                    CkBtcWithdrawals
+                )
+            }
+            MigrationProgress::MigratedBtcNextAddressIndicesUpTo(last_principal) => {
+                let chunk = next_btc_next_address_indices_chunk(last_principal);
+                migrate!(
+                    migration,
+                    chunk,
+                    MigratedBtcNextAddressIndicesUpTo,
+                    BtcNextAddressIndices
+                )
+            }
             MigrationProgress::CheckingDataMigration => {
                 assert_target_has_all_data(&migration).await?;
                 migration.progress.next()
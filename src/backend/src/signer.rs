@@ -1,17 +1,23 @@
 //! Code for inetracting with the chain fusion signer.
 use crate::{
-    read_config,
+    bitcoin_error::BtcError,
+    key_derivation::ExtendedPublicKey,
+    mutate_state, read_config, read_state,
     state::{CYCLES_LEDGER, SIGNER},
+    types::{Candid, StoredCfsMasterPublicKey},
+};
+use bitcoin::{
+    key::{Secp256k1, XOnlyPublicKey},
+    Address, CompressedPublicKey, Network,
 };
-use bitcoin::{Address, CompressedPublicKey, Network};
 use candid::{CandidType, Deserialize, Nat, Principal};
-use ic_cdk::api::management_canister::{
-    bitcoin::BitcoinNetwork,
-    ecdsa::{ecdsa_public_key, EcdsaCurve, EcdsaKeyId, EcdsaPublicKeyArgument},
+use ic_cdk::api::management_canister::ecdsa::{
+    ecdsa_public_key, EcdsaCurve, EcdsaKeyId, EcdsaPublicKeyArgument,
 };
 use ic_cycles_ledger_client::{Account, ApproveArgs, ApproveError, Service as CyclesLedgerService};
 use ic_ledger_types::Subaccount;
 use serde_bytes::ByteBuf;
+use shared::types::bitcoin::{BtcAddressType, BtcNetwork};
 
 #[derive(CandidType, Deserialize, Debug, Clone, Eq, PartialEq)]
 pub enum AllowSigningError {
@@ -91,51 +97,228 @@ pub fn principal2account(principal: &Principal) -> ByteBuf {
         .into()
 }
 
-/// Computes the public key of the specified principal.
-// TODO: Cache CFS pubkey and derive it offline as in [ckBTC minter](https://github.com/dfinity/ic/blob/35153c7cb7b9d1da60472ca7e94c693e418f87bd/rs/bitcoin/ckbtc/minter/src/address.rs#L101-L101)
-async fn cfs_ecdsa_pubkey_of(principal: &Principal) -> Result<Vec<u8>, String> {
-    let (ecdsa_key_name, maybe_cfs_canister_id) =
-        read_config(|s| (s.ecdsa_key_name.clone(), s.cfs_canister_id));
+/// Derivation path of the bitcoin key with the given index of the specified principal in the
+/// chain fusion signer.
+///
+/// The key with index 0 is the default key of the principal. The other keys, from which the
+/// fresh addresses of the principal are derived, have the big-endian index as extra path
+/// component.
+fn btc_derivation_path(principal: &Principal, address_index: u32) -> Vec<Vec<u8>> {
     // As set in [CFS](https://github.com/dfinity/chain-fusion-signer/blob/26b683c6de9971fdbf7bd4cebc04d427d1753289/src/signer/canister/src/derivation_path.rs#L6)
     // 0 is for BTC
     // 1 is for Eth
     // 0xff is generic
     let btc_schema = vec![0_u8];
-    let derivation_path = vec![btc_schema, principal.as_slice().to_vec()];
-    let cfs_canister_id = maybe_cfs_canister_id.ok_or("Missing CFS canister id")?;
-    if let Ok((key,)) = ecdsa_public_key(EcdsaPublicKeyArgument {
+    let mut derivation_path = vec![btc_schema, principal.as_slice().to_vec()];
+    if address_index > 0 {
+        derivation_path.push(address_index.to_be_bytes().to_vec());
+    }
+    derivation_path
+}
+
+/// Gets a public key of the chain fusion signer from the management canister.
+async fn cfs_ecdsa_public_key(
+    ecdsa_key_name: &str,
+    cfs_canister_id: Principal,
+    derivation_path: Vec<Vec<u8>>,
+) -> Result<ExtendedPublicKey, BtcError> {
+    let (key,) = ecdsa_public_key(EcdsaPublicKeyArgument {
         canister_id: Some(cfs_canister_id),
         derivation_path,
         key_id: EcdsaKeyId {
             curve: EcdsaCurve::Secp256k1,
-            name: ecdsa_key_name,
+            name: ecdsa_key_name.to_string(),
         },
     })
     .await
-    {
-        Ok(key.public_key)
-    } else {
-        Err("Failed to get ecdsa public key".to_string())
+    .map_err(|(_, msg)| BtcError::SignerUnavailable {
+        msg: format!("Failed to get ecdsa public key: {msg}"),
+    })?;
+    ExtendedPublicKey::from_slices(&key.public_key, &key.chain_code)
+        .map_err(|msg| BtcError::Misconfigured { msg })
+}
+
+/// Gets the master public key of the chain fusion signer.
+///
+/// The key is cached in stable memory and fetched again only if the ECDSA key name or the chain
+/// fusion signer canister id of the config changed.  Before being cached, the offline derivation
+/// from the fetched key is checked against the key the management canister derives for this
+/// canister.
+async fn cfs_master_public_key() -> Result<ExtendedPublicKey, BtcError> {
+    let (ecdsa_key_name, maybe_cfs_canister_id) =
+        read_config(|s| (s.ecdsa_key_name.clone(), s.cfs_canister_id));
+    let cfs_canister_id = maybe_cfs_canister_id.ok_or_else(|| BtcError::Misconfigured {
+        msg: "Missing CFS canister id".to_string(),
+    })?;
+
+    let cached = read_state(|s| {
+        s.cfs_master_public_key
+            .get()
+            .as_ref()
+            .map(|key| key.0.clone())
+    });
+    if let Some(cached) = cached {
+        if cached.ecdsa_key_name == ecdsa_key_name && cached.cfs_canister_id == cfs_canister_id {
+            return ExtendedPublicKey::from_slices(&cached.public_key, &cached.chain_code)
+                .map_err(|msg| BtcError::InternalError { msg });
+        }
+    }
+
+    let master_public_key = cfs_ecdsa_public_key(&ecdsa_key_name, cfs_canister_id, vec![]).await?;
+    let reference_path = btc_derivation_path(&ic_cdk::id(), 0);
+    let reference_public_key =
+        cfs_ecdsa_public_key(&ecdsa_key_name, cfs_canister_id, reference_path.clone()).await?;
+    if master_public_key.derive(&reference_path) != reference_public_key {
+        return Err(BtcError::Misconfigured {
+            msg: "Offline derivation does not match the chain fusion signer".to_string(),
+        });
     }
+
+    mutate_state(|s| {
+        s.cfs_master_public_key
+            .set(Some(Candid(StoredCfsMasterPublicKey {
+                ecdsa_key_name,
+                cfs_canister_id,
+                public_key: master_public_key.public_key.serialize().to_vec(),
+                chain_code: master_public_key.chain_code.to_vec(),
+            })))
+            .expect("setting the cfs master public key should succeed");
+    });
+    Ok(master_public_key)
 }
 
-fn transform_network(network: BitcoinNetwork) -> Network {
+/// The network of the `bitcoin` crate, which sets the HRP of the addresses: `bc` on mainnet,
+/// `tb` on the test networks and `bcrt` on regtest.
+pub fn transform_network(network: BtcNetwork) -> Network {
     match network {
-        BitcoinNetwork::Mainnet => Network::Bitcoin,
-        BitcoinNetwork::Testnet => Network::Testnet,
-        BitcoinNetwork::Regtest => Network::Regtest,
+        BtcNetwork::Mainnet => Network::Bitcoin,
+        BtcNetwork::Testnet => Network::Testnet4,
+        BtcNetwork::Regtest => Network::Regtest,
     }
 }
 
-/// Converts a public key to a P2PKH address.
-pub async fn btc_principal_to_p2wpkh_address(
-    network: BitcoinNetwork,
+/// Computes the bitcoin public keys with indices `0..address_count` of the specified principal.
+pub async fn btc_principal_to_public_keys(
     principal: &Principal,
-) -> Result<String, String> {
-    let ecdsa_pubkey = cfs_ecdsa_pubkey_of(principal).await?;
-    if let Ok(compressed_public_key) = CompressedPublicKey::from_slice(&ecdsa_pubkey) {
-        Ok(Address::p2wpkh(&compressed_public_key, transform_network(network)).to_string())
-    } else {
-        Err("Error getting P2WPKH from public key".to_string())
+    address_count: u32,
+) -> Result<Vec<CompressedPublicKey>, BtcError> {
+    let master_public_key = cfs_master_public_key().await?;
+    Ok((0..address_count)
+        .map(|address_index| {
+            CompressedPublicKey(
+                master_public_key
+                    .derive(&btc_derivation_path(principal, address_index))
+                    .public_key,
+            )
+        })
+        .collect())
+}
+
+/// Converts a public key to an address of the given type.
+///
+/// Taproot addresses follow [BIP-86](https://github.com/bitcoin/bips/blob/master/bip-0086.mediawiki):
+/// the public key is used as internal key, tweaked without any script tree.
+pub fn btc_public_key_to_address(
+    public_key: &CompressedPublicKey,
+    network: BtcNetwork,
+    address_type: BtcAddressType,
+) -> Address {
+    match address_type {
+        BtcAddressType::P2wpkh => Address::p2wpkh(public_key, transform_network(network)),
+        BtcAddressType::P2tr => Address::p2tr(
+            &Secp256k1::verification_only(),
+            XOnlyPublicKey::from(public_key.0),
+            None,
+            transform_network(network),
+        ),
+    }
+}
+
+/// Computes the address of the given type and index of the specified principal.
+pub async fn btc_principal_to_address(
+    network: BtcNetwork,
+    principal: &Principal,
+    address_type: BtcAddressType,
+    address_index: u32,
+) -> Result<String, BtcError> {
+    let master_public_key = cfs_master_public_key().await?;
+    let public_key = CompressedPublicKey(
+        master_public_key
+            .derive(&btc_derivation_path(principal, address_index))
+            .public_key,
+    );
+    Ok(btc_public_key_to_address(&public_key, network, address_type).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn public_key(hex_key: &str) -> CompressedPublicKey {
+        CompressedPublicKey::from_slice(&hex::decode(hex_key).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn default_derivation_path_has_no_index() {
+        let principal = Principal::from_slice(&[1, 2, 3]);
+
+        assert_eq!(
+            btc_derivation_path(&principal, 0),
+            vec![vec![0], vec![1, 2, 3]]
+        );
+        assert_eq!(
+            btc_derivation_path(&principal, 258),
+            vec![vec![0], vec![1, 2, 3], vec![0, 0, 1, 2]]
+        );
+    }
+
+    #[test]
+    fn p2wpkh_address_matches_bip_84_test_vector() {
+        let public_key =
+            public_key("0330d54fd0dd420a6e5f8d3624f5f3482cae350f79d5f0753bf5beef9c2d91af3c");
+        assert_eq!(
+            btc_public_key_to_address(&public_key, BtcNetwork::Mainnet, BtcAddressType::P2wpkh)
+                .to_string(),
+            "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu"
+        );
+    }
+
+    #[test]
+    fn p2tr_address_matches_bip_86_test_vector() {
+        let public_key =
+            public_key("03cc8a4bc64d897bddc5fbc2f670f7a8ba0b386779106cf1223c6fc5d7cd6fc115");
+        assert_eq!(
+            btc_public_key_to_address(&public_key, BtcNetwork::Mainnet, BtcAddressType::P2tr)
+                .to_string(),
+            "bc1p5cyxnuxmeuwuvkwfem96lqzszd02n6xdcjrs20cac6yqjjwudpxqkedrcr"
+        );
+    }
+
+    #[test]
+    fn p2wpkh_address_uses_network_prefix() {
+        let public_key =
+            public_key("0330d54fd0dd420a6e5f8d3624f5f3482cae350f79d5f0753bf5beef9c2d91af3c");
+        for (network, prefix) in [
+            (BtcNetwork::Mainnet, "bc1q"),
+            (BtcNetwork::Testnet, "tb1q"),
+            (BtcNetwork::Regtest, "bcrt1q"),
+        ] {
+            assert!(
+                btc_public_key_to_address(&public_key, network, BtcAddressType::P2wpkh)
+                    .to_string()
+                    .starts_with(prefix)
+            );
+        }
+    }
+
+    #[test]
+    fn p2tr_address_uses_network_prefix() {
+        let public_key =
+            public_key("03cc8a4bc64d897bddc5fbc2f670f7a8ba0b386779106cf1223c6fc5d7cd6fc115");
+        assert!(
+            btc_public_key_to_address(&public_key, BtcNetwork::Regtest, BtcAddressType::P2tr)
+                .to_string()
+                .starts_with("bcrt1p")
+        );
     }
 }
@@ -1,7 +1,7 @@
-use crate::types::{Candid, StoredPrincipal, VMem};
+use crate::types::{Candid, CustomTokenMap, StoredPrincipal, UserTokenMap, VMem};
 use candid::{CandidType, Deserialize};
 use ic_stable_structures::StableBTreeMap;
-use shared::types::TokenVersion;
+use shared::types::{custom_token::CustomToken, TokenVersion};
 
 const MAX_TOKEN_LIST_LENGTH: usize = 100;
 
@@ -22,7 +22,7 @@ pub fn add_to_user_token<T>(
             ic_cdk::trap("Version mismatch, token update not allowed");
         }
     } else {
-        if tokens.len() == MAX_TOKEN_LIST_LENGTH {
+        if tokens.len() >= MAX_TOKEN_LIST_LENGTH {
             ic_cdk::trap(&format!(
                 "Token list length should not exceed {MAX_TOKEN_LIST_LENGTH}"
             ));
@@ -51,3 +51,176 @@ pub fn remove_from_user_token<T>(
         }
     }
 }
+
+/// The number of users whose ERC20 tokens are moved to the custom tokens in one step of the
+/// migration, so that every step stays well within the instruction limit.
+pub const USER_TOKEN_MIGRATION_CHUNK_SIZE: usize = 100;
+
+/// Moves the ERC20 tokens of a user from `user_token` to the end of the user's custom tokens.
+///
+/// The tokens keep their version, so clients can keep updating them with the version they hold.
+/// A user may end up with more than `MAX_TOKEN_LIST_LENGTH` custom tokens, which are kept but
+/// can't be added to.
+pub fn migrate_user_tokens_of(
+    stored_principal: StoredPrincipal,
+    user_token: &mut UserTokenMap,
+    custom_token: &mut CustomTokenMap,
+) {
+    if let Some(Candid(user_tokens)) = user_token.remove(&stored_principal) {
+        let Candid(mut tokens) = custom_token.get(&stored_principal).unwrap_or_default();
+        tokens.extend(user_tokens.into_iter().map(CustomToken::from));
+        custom_token.insert(stored_principal, Candid(tokens));
+    }
+}
+
+/// Moves the ERC20 tokens of up to `max_users` users from `user_token` to the custom tokens.
+///
+/// Returns whether `user_token` is empty, i.e. whether the migration is complete.
+pub fn migrate_user_tokens_to_custom_tokens(
+    user_token: &mut UserTokenMap,
+    custom_token: &mut CustomTokenMap,
+    max_users: usize,
+) -> bool {
+    for _ in 0..max_users {
+        let Some((stored_principal, _)) = user_token.first_key_value() else {
+            break;
+        };
+        migrate_user_tokens_of(stored_principal, user_token, custom_token);
+    }
+    user_token.is_empty()
+}
+
+/// The custom tokens of a user, followed by the ERC20 tokens of the user that are not migrated
+/// yet, as they will be once migrated.
+pub fn user_custom_tokens(
+    stored_principal: &StoredPrincipal,
+    user_token: &UserTokenMap,
+    custom_token: &CustomTokenMap,
+) -> Vec<CustomToken> {
+    let Candid(mut tokens) = custom_token.get(stored_principal).unwrap_or_default();
+    if let Some(Candid(user_tokens)) = user_token.get(stored_principal) {
+        tokens.extend(user_tokens.into_iter().map(CustomToken::from));
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candid::Principal;
+    use ic_stable_structures::{
+        memory_manager::{MemoryId, MemoryManager},
+        DefaultMemoryImpl,
+    };
+    use shared::types::{
+        custom_token::{ErcToken, IcrcToken, Token},
+        token::UserToken,
+    };
+
+    const PRINCIPAL_TEXT: &str = "7blps-itamd-lzszp-7lbda-4nngn-fev5u-2jvpn-6y3ap-eunp7-kz57e-fqe";
+
+    fn icrc_token() -> CustomToken {
+        CustomToken {
+            token: Token::Icrc(IcrcToken {
+                ledger_id: Principal::from_text("ddsp7-7iaaa-aaaaq-aacqq-cai").unwrap(),
+                index_id: None,
+            }),
+            enabled: true,
+            version: Some(2),
+        }
+    }
+
+    fn legacy_token(enabled: Option<bool>) -> UserToken {
+        UserToken {
+            contract_address: "0x7439E9Bb6D8a84dd3A23fe621A30F95403F87fB9".to_string(),
+            chain_id: 11_155_111,
+            symbol: Some("WEENUS".to_string()),
+            decimals: Some(18),
+            version: Some(3),
+            enabled,
+        }
+    }
+
+    #[test]
+    fn test_migrate_user_tokens_to_custom_tokens() {
+        let memory_manager = MemoryManager::init(DefaultMemoryImpl::default());
+        let mut user_token = UserTokenMap::init(memory_manager.get(MemoryId::new(1)));
+        let mut custom_token = CustomTokenMap::init(memory_manager.get(MemoryId::new(2)));
+        let stored_principal = StoredPrincipal(Principal::from_text(PRINCIPAL_TEXT).unwrap());
+        user_token.insert(
+            stored_principal,
+            Candid(vec![legacy_token(Some(false)), legacy_token(None)]),
+        );
+        custom_token.insert(stored_principal, Candid(vec![icrc_token()]));
+        let tokens_before_migration =
+            user_custom_tokens(&stored_principal, &user_token, &custom_token);
+
+        assert!(migrate_user_tokens_to_custom_tokens(
+            &mut user_token,
+            &mut custom_token,
+            USER_TOKEN_MIGRATION_CHUNK_SIZE
+        ));
+
+        let erc20_token = |enabled| CustomToken {
+            token: Token::Erc20(ErcToken {
+                chain_id: 11_155_111,
+                contract_address: "0x7439E9Bb6D8a84dd3A23fe621A30F95403F87fB9".to_string(),
+                symbol: Some("WEENUS".to_string()),
+                decimals: Some(18),
+            }),
+            enabled,
+            version: Some(3),
+        };
+        assert!(user_token.is_empty());
+        assert_eq!(
+            custom_token
+                .get(&stored_principal)
+                .map(|Candid(tokens)| tokens),
+            Some(vec![icrc_token(), erc20_token(false), erc20_token(true)])
+        );
+        // The tokens are read the same before and after the migration.
+        assert_eq!(
+            user_custom_tokens(&stored_principal, &user_token, &custom_token),
+            tokens_before_migration
+        );
+        // The user tokens stored without `enabled` are listed as enabled.
+        let Candid(tokens) = custom_token.get(&stored_principal).unwrap();
+        assert_eq!(
+            tokens[1..]
+                .iter()
+                .map(CustomToken::to_user_token)
+                .collect::<Vec<_>>(),
+            vec![
+                Some(legacy_token(Some(false))),
+                Some(legacy_token(Some(true)))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_migrate_user_tokens_to_custom_tokens_in_chunks() {
+        let memory_manager = MemoryManager::init(DefaultMemoryImpl::default());
+        let mut user_token = UserTokenMap::init(memory_manager.get(MemoryId::new(1)));
+        let mut custom_token = CustomTokenMap::init(memory_manager.get(MemoryId::new(2)));
+        for i in 0..3 {
+            user_token.insert(
+                StoredPrincipal(Principal::from_slice(&[i])),
+                Candid(vec![legacy_token(Some(true))]),
+            );
+        }
+
+        assert!(!migrate_user_tokens_to_custom_tokens(
+            &mut user_token,
+            &mut custom_token,
+            2
+        ));
+        assert_eq!((user_token.len(), custom_token.len()), (1, 2));
+
+        assert!(migrate_user_tokens_to_custom_tokens(
+            &mut user_token,
+            &mut custom_token,
+            2
+        ));
+        assert_eq!((user_token.len(), custom_token.len()), (0, 3));
+    }
+}
@@ -1,4 +1,7 @@
+use crate::btc_user_pending_tx_state::StoredPendingTransaction;
+use crate::ckbtc_user_withdrawals_state::StoredCkBtcWithdrawal;
 use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::api::management_canister::bitcoin::Outpoint;
 use ic_stable_structures::{
     memory_manager::VirtualMemory, DefaultMemoryImpl, StableBTreeMap, StableCell,
 };
@@ -16,6 +19,21 @@ pub type UserProfileMap =
     StableBTreeMap<(Timestamp, StoredPrincipal), Candid<StoredUserProfile>, VMem>;
 /// Map of `user_principal` to `updated_timestamp` (in `UserProfile`)
 pub type UserProfileUpdatedMap = StableBTreeMap<StoredPrincipal, Timestamp, VMem>;
+/// Map of (`user_principal`, `bitcoin_address`, `txid`) to `StoredPendingTransaction`
+pub type BtcUserPendingTransactionsMap =
+    StableBTreeMap<StoredPendingTransactionKey, Candid<StoredPendingTransaction>, VMem>;
+/// Map of `user_principal` to the outpoints of the UTXOs that the user never wants to spend.
+pub type BtcUserFrozenUtxosMap = StableBTreeMap<StoredPrincipal, Candid<Vec<Outpoint>>, VMem>;
+/// Map of `user_principal` to the index of the next fresh bitcoin address of the user.
+pub type BtcUserNextAddressIndexMap = StableBTreeMap<StoredPrincipal, u32, VMem>;
+/// Map of `user_principal` to the ckBTC withdrawals that the user requested.
+pub type CkBtcUserWithdrawalsMap =
+    StableBTreeMap<StoredPrincipal, Candid<Vec<StoredCkBtcWithdrawal>>, VMem>;
+
+/// The chain fusion signer master public key, from which the bitcoin keys of the users are derived.
+pub type CfsMasterPublicKeyCell = StableCell<Option<Candid<StoredCfsMasterPublicKey>>, VMem>;
+
+pub type BitcoinAddress = String;
 
 #[derive(Default)]
 pub struct Candid<T>(pub T)
@@ -24,3 +42,26 @@ where
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct StoredPrincipal(pub Principal);
+
+/// Key of a pending bitcoin transaction of a user.
+///
+/// Note: The order of the fields matters, as it defines the order of the keys in the `StableBTreeMap`.
+/// All the pending transactions of a user, and of a user's address, are stored next to each other.
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct StoredPendingTransactionKey {
+    pub principal: Principal,
+    pub address: BitcoinAddress,
+    pub txid: Vec<u8>,
+}
+
+/// The public key and chain code of the chain fusion signer, as returned by `ecdsa_public_key`
+/// with an empty derivation path.
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct StoredCfsMasterPublicKey {
+    /// The key name and chain fusion signer the key belongs to.
+    /// The key must be fetched again if they change in the config.
+    pub ecdsa_key_name: String,
+    pub cfs_canister_id: Principal,
+    pub public_key: Vec<u8>,
+    pub chain_code: Vec<u8>,
+}
@@ -0,0 +1,308 @@
+//! In-memory cache of the UTXOs returned by `bitcoin_get_utxos`.
+//!
+//! The UTXO set of an address only changes when a new block is added, about every 10 minutes, so
+//! the cached UTXOs are served without any call for `MAX_UTXOS_AGE_NS` after they were fetched.
+//! Later queries refresh them incrementally: the first page is fetched and, if it reports the tip
+//! of the cached UTXOs, they are served again for `MAX_UTXOS_AGE_NS` without fetching the
+//! remaining pages. After a new block, all the pages are fetched again, as the bitcoin API does
+//! not tell which cached UTXOs the new blocks spent.
+//!
+//! The cache lives on the heap only, it is emptied on upgrade.
+use ic_cdk::api::management_canister::bitcoin::Utxo;
+use shared::types::bitcoin::BtcNetwork;
+use std::{cell::RefCell, collections::BTreeMap};
+
+/// The maximum number of cached UTXOs, over all the UTXO sets. The least recently used sets are
+/// evicted first, and the sets with more UTXOs are not cached.
+const MAX_CACHED_UTXOS: usize = 100_000;
+
+/// How long the cached UTXOs are served without checking the tip.
+pub const MAX_UTXOS_AGE_NS: u64 = 30 * 1_000_000_000;
+
+thread_local! {
+    static UTXOS_CACHE: RefCell<UtxosCache> = RefCell::new(UtxosCache::default());
+}
+
+pub fn read_utxos_cache<R>(f: impl FnOnce(&UtxosCache) -> R) -> R {
+    UTXOS_CACHE.with(|cell| f(&cell.borrow()))
+}
+
+pub fn mutate_utxos_cache<R>(f: impl FnOnce(&mut UtxosCache) -> R) -> R {
+    UTXOS_CACHE.with(|cell| f(&mut cell.borrow_mut()))
+}
+
+/// Identifies a `bitcoin_get_utxos` query.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct UtxosCacheKey {
+    pub network: BtcNetwork,
+    pub address: String,
+    pub min_confirmations: Option<u32>,
+}
+
+/// All the UTXOs of an address at a given tip.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CachedUtxos {
+    pub tip_height: u32,
+    pub tip_block_hash: Vec<u8>,
+    pub utxos: Vec<Utxo>,
+}
+
+/// Counts how the UTXO queries were served, to monitor the hit rate of the cache.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct UtxosCacheMetrics {
+    /// Served from the cache without any call.
+    pub hits: u64,
+    /// Served from the cache after fetching the first page only, which reported the same tip.
+    pub refreshes: u64,
+    /// Fetched completely.
+    pub misses: u64,
+}
+
+#[derive(Debug)]
+struct UtxosCacheEntry {
+    cached_utxos: CachedUtxos,
+    /// When the tip of the cached UTXOs was last reported by the bitcoin API.
+    checked_at_ns: u64,
+    /// The sequence number of the last use, to evict the least recently used sets first.
+    last_used: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct UtxosCache {
+    entries: BTreeMap<UtxosCacheKey, UtxosCacheEntry>,
+    cached_utxos_count: usize,
+    uses: u64,
+    metrics: UtxosCacheMetrics,
+}
+
+impl UtxosCache {
+    /// Returns the cached UTXOs if their tip was checked less than `MAX_UTXOS_AGE_NS` ago.
+    pub fn get(&mut self, key: &UtxosCacheKey, now_ns: u64) -> Option<Vec<Utxo>> {
+        let entry = self.entries.get_mut(key)?;
+        if now_ns.saturating_sub(entry.checked_at_ns) >= MAX_UTXOS_AGE_NS {
+            return None;
+        }
+        self.uses += 1;
+        entry.last_used = self.uses;
+        self.metrics.hits += 1;
+        Some(entry.cached_utxos.utxos.clone())
+    }
+
+    /// Returns the cached UTXOs if they were fetched at the given tip, which is then checked.
+    pub fn refresh(
+        &mut self,
+        key: &UtxosCacheKey,
+        tip_height: u32,
+        tip_block_hash: &[u8],
+        now_ns: u64,
+    ) -> Option<Vec<Utxo>> {
+        let entry = self.entries.get_mut(key)?;
+        if entry.cached_utxos.tip_height != tip_height
+            || entry.cached_utxos.tip_block_hash != tip_block_hash
+        {
+            return None;
+        }
+        self.uses += 1;
+        entry.last_used = self.uses;
+        entry.checked_at_ns = now_ns;
+        self.metrics.refreshes += 1;
+        Some(entry.cached_utxos.utxos.clone())
+    }
+
+    /// Stores the UTXOs fetched after a cache miss, evicting the least recently used sets until
+    /// the cached UTXOs fit in `MAX_CACHED_UTXOS`.
+    pub fn insert(&mut self, key: UtxosCacheKey, cached_utxos: CachedUtxos, now_ns: u64) {
+        self.metrics.misses += 1;
+        self.remove(&key);
+        if cached_utxos.utxos.len() > MAX_CACHED_UTXOS {
+            return;
+        }
+        while self.cached_utxos_count + cached_utxos.utxos.len() > MAX_CACHED_UTXOS {
+            let least_recently_used_key = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone());
+            match least_recently_used_key {
+                Some(least_recently_used_key) => self.remove(&least_recently_used_key),
+                None => break,
+            }
+        }
+        self.uses += 1;
+        self.cached_utxos_count += cached_utxos.utxos.len();
+        self.entries.insert(
+            key,
+            UtxosCacheEntry {
+                cached_utxos,
+                checked_at_ns: now_ns,
+                last_used: self.uses,
+            },
+        );
+    }
+
+    /// Empties the cache, when the UTXOs served by the stand-in bitcoin API are changed.
+    #[cfg(feature = "bitcoin-api-stand-in")]
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.cached_utxos_count = 0;
+    }
+
+    fn remove(&mut self, key: &UtxosCacheKey) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.cached_utxos_count -= entry.cached_utxos.utxos.len();
+        }
+    }
+
+    pub fn cached_utxo_sets_count(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn cached_utxos_count(&self) -> usize {
+        self.cached_utxos_count
+    }
+
+    pub fn metrics(&self) -> UtxosCacheMetrics {
+        self.metrics
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_cdk::api::management_canister::bitcoin::Outpoint;
+
+    fn key(address: &str) -> UtxosCacheKey {
+        UtxosCacheKey {
+            network: BtcNetwork::Regtest,
+            address: address.to_string(),
+            min_confirmations: Some(1),
+        }
+    }
+
+    fn cached_utxos(tip_block_hash: u8, utxos_count: usize) -> CachedUtxos {
+        CachedUtxos {
+            tip_height: 100,
+            tip_block_hash: vec![tip_block_hash; 32],
+            utxos: (0..)
+                .take(utxos_count)
+                .map(|vout| Utxo {
+                    outpoint: Outpoint {
+                        txid: vec![1; 32],
+                        vout,
+                    },
+                    value: 1_000,
+                    height: 100,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn serves_utxos_until_max_age() {
+        let mut cache = UtxosCache::default();
+        assert_eq!(cache.get(&key("a"), 0), None);
+
+        cache.insert(key("a"), cached_utxos(1, 1), 0);
+
+        assert_eq!(cache.get(&key("b"), 0), None);
+        assert_eq!(
+            cache.get(&key("a"), MAX_UTXOS_AGE_NS - 1),
+            Some(cached_utxos(1, 1).utxos)
+        );
+        assert_eq!(cache.get(&key("a"), MAX_UTXOS_AGE_NS), None);
+        assert_eq!(
+            cache.metrics(),
+            UtxosCacheMetrics {
+                hits: 1,
+                refreshes: 0,
+                misses: 1
+            }
+        );
+    }
+
+    #[test]
+    fn refreshes_utxos_of_same_tip() {
+        let mut cache = UtxosCache::default();
+        assert_eq!(cache.refresh(&key("a"), 100, &[1; 32], 0), None);
+
+        cache.insert(key("a"), cached_utxos(1, 1), 0);
+
+        assert_eq!(
+            cache.refresh(&key("a"), 100, &[2; 32], MAX_UTXOS_AGE_NS),
+            None
+        );
+        assert_eq!(
+            cache.refresh(&key("a"), 101, &[1; 32], MAX_UTXOS_AGE_NS),
+            None
+        );
+        assert_eq!(
+            cache.refresh(&key("b"), 100, &[1; 32], MAX_UTXOS_AGE_NS),
+            None
+        );
+        assert_eq!(
+            cache.refresh(&key("a"), 100, &[1; 32], MAX_UTXOS_AGE_NS),
+            Some(cached_utxos(1, 1).utxos)
+        );
+        // The refreshed UTXOs are served again until max age.
+        assert_eq!(
+            cache.get(&key("a"), 2 * MAX_UTXOS_AGE_NS - 1),
+            Some(cached_utxos(1, 1).utxos)
+        );
+        assert_eq!(
+            cache.metrics(),
+            UtxosCacheMetrics {
+                hits: 1,
+                refreshes: 1,
+                misses: 1
+            }
+        );
+    }
+
+    #[test]
+    fn replaces_utxos_of_previous_tip() {
+        let mut cache = UtxosCache::default();
+        cache.insert(key("a"), cached_utxos(1, 3), 0);
+        cache.insert(key("a"), cached_utxos(2, 2), 0);
+
+        assert_eq!(cache.cached_utxo_sets_count(), 1);
+        assert_eq!(cache.cached_utxos_count(), 2);
+        assert_eq!(cache.refresh(&key("a"), 100, &[1; 32], 0), None);
+        assert_eq!(
+            cache.refresh(&key("a"), 100, &[2; 32], 0),
+            Some(cached_utxos(2, 2).utxos)
+        );
+    }
+
+    #[test]
+    fn evicts_least_recently_used_utxos_beyond_max_utxos() {
+        let mut cache = UtxosCache::default();
+        let utxos_count = MAX_CACHED_UTXOS / 4;
+        for index in 0..4 {
+            cache.insert(key(&index.to_string()), cached_utxos(1, utxos_count), 0);
+        }
+        assert_eq!(cache.cached_utxos_count(), MAX_CACHED_UTXOS);
+        // The first set is used again, so the second one is the least recently used.
+        assert!(cache.get(&key("0"), 0).is_some());
+
+        cache.insert(key("new"), cached_utxos(1, utxos_count + 1), 0);
+
+        assert_eq!(cache.cached_utxo_sets_count(), 3);
+        assert_eq!(cache.cached_utxos_count(), 3 * utxos_count + 1);
+        assert!(cache.get(&key("0"), 0).is_some());
+        assert!(cache.get(&key("1"), 0).is_none());
+        assert!(cache.get(&key("2"), 0).is_none());
+        assert!(cache.get(&key("new"), 0).is_some());
+    }
+
+    #[test]
+    fn does_not_cache_utxo_sets_beyond_max_utxos() {
+        let mut cache = UtxosCache::default();
+        cache.insert(key("a"), cached_utxos(1, 1), 0);
+        cache.insert(key("b"), cached_utxos(1, MAX_CACHED_UTXOS + 1), 0);
+
+        assert_eq!(cache.cached_utxo_sets_count(), 1);
+        assert_eq!(cache.cached_utxos_count(), 1);
+        assert!(cache.get(&key("a"), 0).is_some());
+        assert!(cache.get(&key("b"), 0).is_none());
+    }
+}
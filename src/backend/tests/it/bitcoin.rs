@@ -1,17 +1,32 @@
 use candid::Principal;
-use ic_cdk::api::management_canister::bitcoin::{BitcoinNetwork, Outpoint, Utxo};
+use ic_cdk::api::management_canister::bitcoin::{Outpoint, Utxo};
+use serde_bytes::ByteBuf;
+use shared::http::{HttpRequest, HttpResponse};
 use shared::types::bitcoin::{
-    BtcAddPendingTransactionError, BtcAddPendingTransactionRequest, BtcGetPendingTransactionsError,
-    BtcGetPendingTransactionsReponse, BtcGetPendingTransactionsRequest, SelectedUtxosFeeError,
-    SelectedUtxosFeeRequest, SelectedUtxosFeeResponse,
+    BtcAddPendingTransactionError, BtcAddPendingTransactionErrorV2,
+    BtcAddPendingTransactionRequest, BtcAddressError, BtcAddressType,
+    BtcBuildUnsignedTransactionError, BtcBuildUnsignedTransactionRequest,
+    BtcBuildUnsignedTransactionResponse, BtcBumpFeeError, BtcBumpFeeRequest, BtcFeePolicy,
+    BtcFreezeUtxosError, BtcFreezeUtxosRequest, BtcGetAddressError, BtcGetAddressRequest,
+    BtcGetAddressResponse, BtcGetBalanceError, BtcGetBalanceRequest, BtcGetBalanceResponse,
+    BtcGetPendingTransactionsError, BtcGetPendingTransactionsReponse,
+    BtcGetPendingTransactionsRequest, BtcInputError, BtcNetwork, BtcOutputError,
+    BtcPendingTransactionStatus, BtcRemovePendingTransactionError,
+    BtcRemovePendingTransactionRequest, BtcTxOutput, PendingTransaction, SelectedUtxosFeeError,
+    SelectedUtxosFeeErrorV2, SelectedUtxosFeeRequest, SelectedUtxosFeeResponse,
 };
+use shared::types::Stats;
+use std::time::Duration;
 
 use crate::utils::{
-    mock::CALLER,
-    pocketic::{setup, PicCanisterTrait},
+    mock::{CALLER, USER_1},
+    pocketic::{
+        controller, setup, setup_with_stand_ins, BackendBuilder, PicBackend, PicCanisterTrait,
+    },
 };
 
 const MOCK_ADDRESS: &str = "bcrt1qpg7udjvq7gx2fp480pgt4hnhj3qc4nhrkstc33";
+const MOCK_DESTINATION_ADDRESS: &str = "bcrt1q0ht9tyks4vh7p5p904t340cr9nvahy7uevmqwj";
 
 #[test]
 fn test_select_user_utxos_fee_returns_zero_when_user_has_insufficient_funds() {
@@ -21,8 +36,15 @@ fn test_select_user_utxos_fee_returns_zero_when_user_has_insufficient_funds() {
 
     let request = SelectedUtxosFeeRequest {
         amount_satoshis: 100_000_000u64,
-        network: BitcoinNetwork::Regtest,
+        network: BtcNetwork::Regtest,
         min_confirmations: None,
+        outputs: None,
+        fee_policy: None,
+        address_type: None,
+        coin_selection: None,
+        sweep: None,
+        inputs: None,
+        spend_unconfirmed_change: None,
     };
     let response = pic_setup.update::<Result<SelectedUtxosFeeResponse, SelectedUtxosFeeError>>(
         caller,
@@ -40,6 +62,705 @@ fn test_select_user_utxos_fee_returns_zero_when_user_has_insufficient_funds() {
     assert_eq!(response.fee_satoshis, 0);
 }
 
+#[test]
+fn test_select_user_utxos_fee_v2_fails_when_user_has_insufficient_funds() {
+    let pic_setup = setup();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+
+    let request = SelectedUtxosFeeRequest {
+        amount_satoshis: 100_000_000u64,
+        network: BtcNetwork::Regtest,
+        min_confirmations: None,
+        outputs: None,
+        fee_policy: None,
+        address_type: None,
+        coin_selection: None,
+        sweep: None,
+        inputs: None,
+        spend_unconfirmed_change: None,
+    };
+    let response = pic_setup.update::<Result<SelectedUtxosFeeResponse, SelectedUtxosFeeErrorV2>>(
+        caller,
+        "btc_select_user_utxos_fee_v2",
+        request,
+    );
+
+    assert_eq!(
+        response,
+        Ok(Err(SelectedUtxosFeeErrorV2::InsufficientFunds))
+    );
+}
+
+#[test]
+fn test_get_address_of_each_type() {
+    let pic_setup = setup();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+
+    for address_type in [None, Some(BtcAddressType::P2wpkh)] {
+        let response = pic_setup
+            .update::<Result<BtcGetAddressResponse, BtcGetAddressError>>(
+                caller,
+                "btc_get_address",
+                BtcGetAddressRequest {
+                    network: BtcNetwork::Regtest,
+                    address_type,
+                },
+            )
+            .expect("Call failed")
+            .expect("Request was not successful");
+
+        assert!(response.address.starts_with("bcrt1q"));
+    }
+}
+
+#[test]
+fn test_taproot_addresses_are_not_supported() {
+    let pic_setup = setup();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+
+    for method in ["btc_get_address", "btc_get_fresh_address"] {
+        let response = pic_setup.update::<Result<BtcGetAddressResponse, BtcGetAddressError>>(
+            caller,
+            method,
+            BtcGetAddressRequest {
+                network: BtcNetwork::Regtest,
+                address_type: Some(BtcAddressType::P2tr),
+            },
+        );
+
+        assert_eq!(
+            response,
+            Ok(Err(BtcGetAddressError::UnsupportedAddressType))
+        );
+    }
+}
+
+#[test]
+fn test_get_address_is_stable_across_calls_and_upgrades() {
+    let pic_setup = setup();
+
+    let get_address = |user: &str| {
+        pic_setup
+            .update::<Result<BtcGetAddressResponse, BtcGetAddressError>>(
+                Principal::from_text(user).unwrap(),
+                "btc_get_address",
+                BtcGetAddressRequest {
+                    network: BtcNetwork::Regtest,
+                    address_type: None,
+                },
+            )
+            .expect("Call failed")
+            .expect("Request was not successful")
+            .address
+    };
+
+    // The first call fetches and caches the signer master public key, the following ones derive
+    // the address from the cached key.
+    let caller_address = get_address(CALLER);
+    assert_eq!(get_address(CALLER), caller_address);
+    assert_ne!(get_address(USER_1), caller_address);
+
+    pic_setup
+        .upgrade_latest_wasm(None)
+        .unwrap_or_else(|e| panic!("Upgrade canister failed with error: {}", e));
+
+    assert_eq!(get_address(CALLER), caller_address);
+}
+
+#[test]
+fn test_get_balance_of_address_without_utxos() {
+    let pic_setup = setup();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+
+    let response = pic_setup.update::<Result<BtcGetBalanceResponse, BtcGetBalanceError>>(
+        caller,
+        "btc_get_balance",
+        BtcGetBalanceRequest {
+            network: BtcNetwork::Regtest,
+            address_type: None,
+            min_confirmations: None,
+        },
+    );
+
+    assert_eq!(
+        response,
+        Ok(Ok(BtcGetBalanceResponse {
+            confirmed_satoshis: 0,
+            unconfirmed_satoshis: 0,
+            locked_satoshis: 0,
+            spendable_satoshis: 0,
+        }))
+    );
+}
+
+#[test]
+fn test_utxos_are_served_from_cache_within_same_tip() {
+    let pic_setup = setup();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+
+    let get_balance = || {
+        let response = pic_setup.update::<Result<BtcGetBalanceResponse, BtcGetBalanceError>>(
+            caller,
+            "btc_get_balance",
+            BtcGetBalanceRequest {
+                network: BtcNetwork::Regtest,
+                address_type: None,
+                min_confirmations: None,
+            },
+        );
+        assert!(matches!(response, Ok(Ok(_))));
+    };
+    let get_metrics = || {
+        let response = pic_setup
+            .query::<HttpResponse>(
+                caller,
+                "http_request",
+                HttpRequest {
+                    method: "GET".to_string(),
+                    url: "/metrics".to_string(),
+                    headers: vec![],
+                    body: ByteBuf::new(),
+                },
+            )
+            .expect("Failed to get the metrics");
+        String::from_utf8(response.body.into_vec()).unwrap()
+    };
+
+    get_balance();
+    get_balance();
+
+    // Only the first query fetched the UTXOs, the following ones were served from the cache
+    // without any call.
+    let metrics = get_metrics();
+    assert!(metrics.contains("ic_eth_wallet_btc_utxos_cache_misses 1 "));
+    assert!(metrics.contains("ic_eth_wallet_btc_utxos_cache_hits 3 "));
+    assert!(metrics.contains("ic_eth_wallet_btc_utxos_cache_refreshes 0 "));
+    assert!(metrics.contains("ic_eth_wallet_btc_utxos_cache_entries 1 "));
+
+    // Once the cached UTXOs are too old, the first page tells that the tip did not change.
+    pic_setup.pic.advance_time(Duration::from_secs(60));
+    get_balance();
+
+    let metrics = get_metrics();
+    assert!(metrics.contains("ic_eth_wallet_btc_utxos_cache_misses 1 "));
+    assert!(metrics.contains("ic_eth_wallet_btc_utxos_cache_hits 4 "));
+    assert!(metrics.contains("ic_eth_wallet_btc_utxos_cache_refreshes 1 "));
+}
+
+#[test]
+fn test_select_user_utxos_fee_from_taproot_address_is_not_supported() {
+    let pic_setup = setup();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+
+    let request = SelectedUtxosFeeRequest {
+        amount_satoshis: 100_000u64,
+        network: BtcNetwork::Regtest,
+        min_confirmations: None,
+        outputs: None,
+        fee_policy: None,
+        address_type: Some(BtcAddressType::P2tr),
+        coin_selection: None,
+        sweep: None,
+        inputs: None,
+        spend_unconfirmed_change: None,
+    };
+    let response = pic_setup.update::<Result<SelectedUtxosFeeResponse, SelectedUtxosFeeErrorV2>>(
+        caller,
+        "btc_select_user_utxos_fee_v2",
+        request,
+    );
+
+    assert_eq!(
+        response,
+        Ok(Err(SelectedUtxosFeeErrorV2::UnsupportedAddressType))
+    );
+}
+
+#[test]
+fn test_select_user_utxos_fee_rejects_out_of_bounds_fee_rate() {
+    let pic_setup = setup();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+
+    let request = SelectedUtxosFeeRequest {
+        amount_satoshis: 100_000u64,
+        network: BtcNetwork::Regtest,
+        min_confirmations: None,
+        outputs: None,
+        fee_policy: Some(BtcFeePolicy::Custom {
+            satoshi_per_vbyte: 0,
+        }),
+        address_type: None,
+        coin_selection: None,
+        sweep: None,
+        inputs: None,
+        spend_unconfirmed_change: None,
+    };
+    let response = pic_setup.update::<Result<SelectedUtxosFeeResponse, SelectedUtxosFeeErrorV2>>(
+        caller,
+        "btc_select_user_utxos_fee_v2",
+        request,
+    );
+
+    assert_eq!(
+        response,
+        Ok(Err(SelectedUtxosFeeErrorV2::FeeRateOutOfBounds {
+            min_satoshi_per_vbyte: 1,
+            max_satoshi_per_vbyte: 1_000,
+        }))
+    );
+}
+
+#[test]
+fn test_select_user_utxos_fee_rejects_empty_outputs() {
+    let pic_setup = setup();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+
+    let request = SelectedUtxosFeeRequest {
+        amount_satoshis: 0,
+        network: BtcNetwork::Regtest,
+        min_confirmations: None,
+        outputs: Some(vec![]),
+        fee_policy: None,
+        address_type: None,
+        coin_selection: None,
+        sweep: None,
+        inputs: None,
+        spend_unconfirmed_change: None,
+    };
+    let response = pic_setup.update::<Result<SelectedUtxosFeeResponse, SelectedUtxosFeeErrorV2>>(
+        caller,
+        "btc_select_user_utxos_fee_v2",
+        request,
+    );
+
+    assert_eq!(
+        response,
+        Ok(Err(SelectedUtxosFeeErrorV2::InvalidOutput(
+            BtcOutputError::NoOutputs
+        )))
+    );
+}
+
+#[test]
+fn test_sweep_without_utxos_fails_with_insufficient_funds() {
+    let pic_setup = setup();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+
+    let outputs = vec![BtcTxOutput {
+        destination_address: MOCK_DESTINATION_ADDRESS.to_string(),
+        sent_satoshis: 0,
+    }];
+    let request = SelectedUtxosFeeRequest {
+        amount_satoshis: 0,
+        network: BtcNetwork::Regtest,
+        min_confirmations: None,
+        outputs: Some(outputs),
+        fee_policy: None,
+        address_type: None,
+        coin_selection: None,
+        sweep: Some(true),
+        inputs: None,
+        spend_unconfirmed_change: None,
+    };
+    let response = pic_setup.update::<Result<SelectedUtxosFeeResponse, SelectedUtxosFeeErrorV2>>(
+        caller,
+        "btc_select_user_utxos_fee_v2",
+        request,
+    );
+
+    assert_eq!(
+        response,
+        Ok(Err(SelectedUtxosFeeErrorV2::InsufficientFunds))
+    );
+}
+
+#[test]
+fn test_sweep_rejects_many_outputs() {
+    let pic_setup = setup();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+
+    let request = SelectedUtxosFeeRequest {
+        amount_satoshis: 0,
+        network: BtcNetwork::Regtest,
+        min_confirmations: None,
+        outputs: Some(vec![
+            BtcTxOutput {
+                destination_address: MOCK_ADDRESS.to_string(),
+                sent_satoshis: 0,
+            },
+            BtcTxOutput {
+                destination_address: MOCK_DESTINATION_ADDRESS.to_string(),
+                sent_satoshis: 0,
+            },
+        ]),
+        fee_policy: None,
+        address_type: None,
+        coin_selection: None,
+        sweep: Some(true),
+        inputs: None,
+        spend_unconfirmed_change: None,
+    };
+    let response = pic_setup.update::<Result<SelectedUtxosFeeResponse, SelectedUtxosFeeErrorV2>>(
+        caller,
+        "btc_select_user_utxos_fee_v2",
+        request,
+    );
+
+    assert_eq!(
+        response,
+        Ok(Err(SelectedUtxosFeeErrorV2::InvalidOutput(
+            BtcOutputError::SweepWithManyOutputs
+        )))
+    );
+}
+
+#[test]
+fn test_select_user_utxos_fee_rejects_unknown_inputs() {
+    let pic_setup = setup();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+
+    let request = SelectedUtxosFeeRequest {
+        amount_satoshis: 100_000u64,
+        network: BtcNetwork::Regtest,
+        min_confirmations: None,
+        outputs: None,
+        fee_policy: None,
+        address_type: None,
+        coin_selection: None,
+        sweep: None,
+        inputs: Some(vec![UTXO_1.outpoint]),
+        spend_unconfirmed_change: None,
+    };
+    let response = pic_setup.update::<Result<SelectedUtxosFeeResponse, SelectedUtxosFeeErrorV2>>(
+        caller,
+        "btc_select_user_utxos_fee_v2",
+        request,
+    );
+
+    assert_eq!(
+        response,
+        Ok(Err(SelectedUtxosFeeErrorV2::InvalidInput(
+            BtcInputError::NotFound {
+                outpoint: UTXO_1.outpoint,
+            }
+        )))
+    );
+}
+
+#[test]
+fn test_freeze_and_unfreeze_utxos() {
+    let pic_setup = setup();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+    let outpoints = vec![UTXO_1.outpoint, UTXO_2.outpoint];
+
+    let freeze_response = pic_setup.update::<Result<(), BtcFreezeUtxosError>>(
+        caller,
+        "btc_freeze_utxos",
+        BtcFreezeUtxosRequest {
+            outpoints: outpoints.clone(),
+        },
+    );
+    assert_eq!(freeze_response, Ok(Ok(())));
+
+    let frozen_utxos = pic_setup
+        .query::<Vec<Outpoint>>(caller, "btc_list_frozen_utxos", ())
+        .expect("Call failed");
+    assert_eq!(frozen_utxos, outpoints);
+
+    // The frozen UTXOs are per user.
+    let other_frozen_utxos = pic_setup
+        .query::<Vec<Outpoint>>(
+            Principal::from_text(USER_1).unwrap(),
+            "btc_list_frozen_utxos",
+            (),
+        )
+        .expect("Call failed");
+    assert!(other_frozen_utxos.is_empty());
+
+    pic_setup
+        .update::<()>(
+            caller,
+            "btc_unfreeze_utxos",
+            BtcFreezeUtxosRequest {
+                outpoints: vec![UTXO_1.outpoint],
+            },
+        )
+        .expect("Call failed");
+
+    let frozen_utxos = pic_setup
+        .query::<Vec<Outpoint>>(caller, "btc_list_frozen_utxos", ())
+        .expect("Call failed");
+    assert_eq!(frozen_utxos, vec![UTXO_2.outpoint]);
+}
+
+#[test]
+fn test_build_unsigned_transaction_returns_insufficient_funds() {
+    let pic_setup = setup();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+
+    let request = BtcBuildUnsignedTransactionRequest {
+        destination_address: MOCK_DESTINATION_ADDRESS.to_string(),
+        amount_satoshis: 100_000u64,
+        network: BtcNetwork::Regtest,
+        fee_policy: Some(BtcFeePolicy::Custom {
+            satoshi_per_vbyte: 10,
+        }),
+        min_confirmations: None,
+        address_type: None,
+        coin_selection: None,
+        inputs: None,
+        spend_unconfirmed_change: None,
+    };
+    let response = pic_setup.update::<Result<
+        BtcBuildUnsignedTransactionResponse,
+        BtcBuildUnsignedTransactionError,
+    >>(caller, "btc_build_unsigned_transaction", request);
+
+    assert_eq!(
+        response.expect("Call failed"),
+        Err(BtcBuildUnsignedTransactionError::InsufficientFunds)
+    );
+}
+
+#[test]
+fn test_build_unsigned_transaction_rejects_address_of_other_network() {
+    let pic_setup = setup();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+
+    let request = BtcBuildUnsignedTransactionRequest {
+        destination_address: "bc1q0ht9tyks4vh7p5p904t340cr9nvahy7u3re7zg".to_string(),
+        amount_satoshis: 100_000u64,
+        network: BtcNetwork::Regtest,
+        fee_policy: None,
+        min_confirmations: None,
+        address_type: None,
+        coin_selection: None,
+        inputs: None,
+        spend_unconfirmed_change: None,
+    };
+    let response = pic_setup.update::<Result<
+        BtcBuildUnsignedTransactionResponse,
+        BtcBuildUnsignedTransactionError,
+    >>(caller, "btc_build_unsigned_transaction", request);
+
+    assert_eq!(
+        response.expect("Call failed"),
+        Err(BtcBuildUnsignedTransactionError::InvalidAddress(
+            BtcAddressError::NetworkMismatch {
+                address: "bc1q0ht9tyks4vh7p5p904t340cr9nvahy7u3re7zg".to_string(),
+                network: BtcNetwork::Regtest,
+            }
+        ))
+    );
+}
+
+#[test]
+fn test_fresh_addresses_are_not_issued() {
+    let pic_setup = setup();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+
+    // The signer can't sign for the fresh addresses yet, see `btc-fresh-addresses`.
+    let response = pic_setup.update::<Result<BtcGetAddressResponse, BtcGetAddressError>>(
+        caller,
+        "btc_get_fresh_address",
+        BtcGetAddressRequest {
+            network: BtcNetwork::Regtest,
+            address_type: None,
+        },
+    );
+
+    assert_eq!(
+        response,
+        Ok(Err(BtcGetAddressError::FreshAddressesUnsupported))
+    );
+}
+
+#[test]
+fn test_frozen_utxos_are_limited() {
+    let pic_setup = setup();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+
+    let freeze_response = pic_setup.update::<Result<(), BtcFreezeUtxosError>>(
+        caller,
+        "btc_freeze_utxos",
+        BtcFreezeUtxosRequest {
+            outpoints: (0..=1000)
+                .map(|vout| Outpoint {
+                    txid: vec![1; 32],
+                    vout,
+                })
+                .collect(),
+        },
+    );
+    assert_eq!(
+        freeze_response,
+        Ok(Err(BtcFreezeUtxosError::QuotaExceeded {
+            msg: "Frozen UTXOs should not exceed 1000".to_string()
+        }))
+    );
+}
+
+#[test]
+fn test_add_pending_transaction_rejects_invalid_address() {
+    let pic_setup = setup();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+
+    let add_pending_transaction = |address: &str, network: BtcNetwork| {
+        pic_setup.update::<Result<(), BtcAddPendingTransactionErrorV2>>(
+            caller,
+            "btc_add_pending_transaction_v2",
+            BtcAddPendingTransactionRequest {
+                txid: vec![1; 32],
+                utxos: vec![UTXO_1],
+                address: address.to_string(),
+                network,
+            },
+        )
+    };
+
+    assert_eq!(
+        add_pending_transaction(MOCK_ADDRESS, BtcNetwork::Mainnet),
+        Ok(Err(BtcAddPendingTransactionErrorV2::InvalidAddress(
+            BtcAddressError::NetworkMismatch {
+                address: MOCK_ADDRESS.to_string(),
+                network: BtcNetwork::Mainnet,
+            }
+        )))
+    );
+    assert_eq!(
+        add_pending_transaction("not an address", BtcNetwork::Regtest),
+        Ok(Err(BtcAddPendingTransactionErrorV2::InvalidAddress(
+            BtcAddressError::Malformed {
+                address: "not an address".to_string(),
+            }
+        )))
+    );
+}
+
+#[test]
+fn test_pending_transactions_require_own_address_and_current_utxos() {
+    let pic_setup = setup();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+    let address = caller_address(&pic_setup, caller);
+
+    let add_pending_transaction = |address: &str| {
+        pic_setup.update::<Result<(), BtcAddPendingTransactionErrorV2>>(
+            caller,
+            "btc_add_pending_transaction_v2",
+            BtcAddPendingTransactionRequest {
+                txid: vec![1; 32],
+                utxos: vec![UTXO_1],
+                address: address.to_string(),
+                network: BtcNetwork::Regtest,
+            },
+        )
+    };
+
+    assert_eq!(
+        add_pending_transaction(MOCK_ADDRESS),
+        Ok(Err(BtcAddPendingTransactionErrorV2::InvalidAddress(
+            BtcAddressError::NotOwned {
+                address: MOCK_ADDRESS.to_string(),
+            }
+        )))
+    );
+    // The regtest address of the caller has no UTXOs.
+    assert_eq!(
+        add_pending_transaction(&address),
+        Ok(Err(BtcAddPendingTransactionErrorV2::UtxoNotFound {
+            outpoint: UTXO_1.outpoint,
+        }))
+    );
+
+    let get_pending_transactions =
+        pic_setup
+            .update::<Result<BtcGetPendingTransactionsReponse, BtcGetPendingTransactionsError>>(
+                caller,
+                "btc_get_pending_transactions_v2",
+                BtcGetPendingTransactionsRequest {
+                    address: MOCK_ADDRESS.to_string(),
+                    network: BtcNetwork::Regtest,
+                },
+            );
+    assert_eq!(
+        get_pending_transactions,
+        Ok(Err(BtcGetPendingTransactionsError::InvalidAddress(
+            BtcAddressError::NotOwned {
+                address: MOCK_ADDRESS.to_string(),
+            }
+        )))
+    );
+
+    // The first versions of the endpoints only return internal errors.
+    let add_pending_transaction = pic_setup.update::<Result<(), BtcAddPendingTransactionError>>(
+        caller,
+        "btc_add_pending_transaction",
+        BtcAddPendingTransactionRequest {
+            txid: vec![1; 32],
+            utxos: vec![UTXO_1],
+            address: MOCK_ADDRESS.to_string(),
+            network: BtcNetwork::Regtest,
+        },
+    );
+    assert!(matches!(
+        add_pending_transaction,
+        Ok(Err(BtcAddPendingTransactionError::InternalError { .. }))
+    ));
+    let get_pending_transactions =
+        pic_setup
+            .update::<Result<BtcGetPendingTransactionsReponse, BtcAddPendingTransactionError>>(
+                caller,
+                "btc_get_pending_transactions",
+                BtcGetPendingTransactionsRequest {
+                    address: MOCK_ADDRESS.to_string(),
+                    network: BtcNetwork::Regtest,
+                },
+            );
+    assert!(matches!(
+        get_pending_transactions,
+        Ok(Err(BtcAddPendingTransactionError::InternalError { .. }))
+    ));
+
+    let stats = pic_setup
+        .query::<Stats>(controller(), "stats", ())
+        .expect("Failed to get stats");
+    assert_eq!(stats.btc_pending_transactions_count, 0);
+}
+
+/// The default regtest address of the caller.
+fn caller_address(pic_setup: &PicBackend, caller: Principal) -> String {
+    pic_setup
+        .update::<Result<BtcGetAddressResponse, BtcGetAddressError>>(
+            caller,
+            "btc_get_address",
+            BtcGetAddressRequest {
+                network: BtcNetwork::Regtest,
+                address_type: None,
+            },
+        )
+        .expect("Call failed")
+        .expect("Request was not successful")
+        .address
+}
+
 const UTXO_1: Utxo = Utxo {
     outpoint: Outpoint {
         txid: vec![],
@@ -48,94 +769,96 @@ const UTXO_1: Utxo = Utxo {
     value: 1000,
     height: 100,
 };
+const UTXO_2: Utxo = Utxo {
+    outpoint: Outpoint {
+        txid: vec![],
+        vout: 1,
+    },
+    value: 2000,
+    height: 100,
+};
 
 #[test]
-fn test_select_user_utxos_fee_pending_transaction_error() {
+fn test_bump_fee_of_unknown_transaction_fails() {
     let pic_setup = setup();
 
     let caller = Principal::from_text(CALLER).unwrap();
 
-    let txid = vec![];
-    let utxos = vec![UTXO_1];
-    let address = MOCK_ADDRESS.to_string();
-
-    let add_request = BtcAddPendingTransactionRequest {
-        txid: txid.clone(),
-        utxos: utxos.clone(),
-        address: address.clone(),
-        network: BitcoinNetwork::Regtest,
+    let request = BtcBumpFeeRequest {
+        txid: vec![1; 32],
+        network: BtcNetwork::Regtest,
+        fee_policy: Some(BtcFeePolicy::Fast),
+        min_confirmations: None,
+        address_type: None,
     };
 
-    let add_response = pic_setup.update::<Result<(), BtcAddPendingTransactionError>>(
-        caller,
-        "btc_add_pending_transaction",
-        add_request,
-    );
+    let response = pic_setup
+        .update::<Result<BtcBuildUnsignedTransactionResponse, BtcBumpFeeError>>(
+            caller,
+            "btc_bump_fee",
+            request,
+        );
+
+    assert_eq!(response, Ok(Err(BtcBumpFeeError::TransactionNotFound)));
+}
+
+#[test]
+fn test_select_user_utxos_fee_pending_transaction_error() {
+    let pic_setup = setup_with_stand_ins();
+
+    let caller = Principal::from_text(CALLER).unwrap();
 
-    assert!(add_response.is_ok());
+    // The UTXO of the pending transaction has 101 confirmations.
+    pic_setup.set_btc_stand_in_tip_height(BtcNetwork::Regtest, 200);
+    pic_setup.add_btc_pending_transaction(caller, vec![1; 32], vec![UTXO_1]);
 
     let request = SelectedUtxosFeeRequest {
         amount_satoshis: 100_000_000u64,
-        network: BitcoinNetwork::Regtest,
+        network: BtcNetwork::Regtest,
         min_confirmations: None,
+        outputs: None,
+        fee_policy: None,
+        address_type: None,
+        coin_selection: None,
+        sweep: None,
+        inputs: None,
+        spend_unconfirmed_change: None,
     };
     let select_response = pic_setup
-        .update::<Result<SelectedUtxosFeeResponse, SelectedUtxosFeeError>>(
+        .update::<Result<SelectedUtxosFeeResponse, SelectedUtxosFeeErrorV2>>(
             caller,
-            "btc_select_user_utxos_fee",
+            "btc_select_user_utxos_fee_v2",
             request,
         );
 
-    // The following passes if we don't prune the pending transactions before checking them
-    // because the utxos added in the pending transactions are not present from the bitcoin api.
-    // match response.expect("Call failed") {
-    //     Ok(_) => panic!("Selecting utxos should fail with pending transctions"),
-    //     Err(err) => assert_eq!(err, SelectedUtxosFeeError::PendingTransactions),
-    // }
-
-    // Because utxos from bitcoin API is an empty string.
-    // The selected utxos are empty and fee is 0.
-    let select_data = select_response
-        .expect("Call failed")
-        .expect("Request was not successful");
-
-    assert_eq!(select_data.utxos.len(), 0);
-    assert_eq!(select_data.fee_satoshis, 0);
+    // The only UTXO is still spent by the pending transaction.
+    assert_eq!(
+        select_response,
+        Ok(Err(SelectedUtxosFeeErrorV2::PendingTransactions))
+    );
 }
 
 #[test]
 fn test_add_and_read_pending_transactions() {
-    let pic_setup = setup();
+    let pic_setup = setup_with_stand_ins();
 
     let caller = Principal::from_text(CALLER).unwrap();
 
-    let txid = vec![];
+    let txid = vec![1; 32];
     let utxos = vec![UTXO_1];
-    let address = MOCK_ADDRESS.to_string();
-
-    let add_request = BtcAddPendingTransactionRequest {
-        txid: txid.clone(),
-        utxos: utxos.clone(),
-        address: address.clone(),
-        network: BitcoinNetwork::Regtest,
-    };
-
-    let add_response = pic_setup.update::<Result<(), BtcAddPendingTransactionError>>(
-        caller,
-        "btc_add_pending_transaction",
-        add_request,
-    );
 
-    assert!(add_response.is_ok());
+    let address = pic_setup.add_btc_pending_transaction(caller, txid.clone(), utxos.clone());
+    // The UTXO is spent.
+    pic_setup.set_btc_stand_in_utxos(BtcNetwork::Regtest, &address, vec![]);
 
     let read_request = BtcGetPendingTransactionsRequest {
         address: address.clone(),
-        network: BitcoinNetwork::Regtest,
+        network: BtcNetwork::Regtest,
     };
     let read_response = pic_setup.update::<Result<
         BtcGetPendingTransactionsReponse,
         BtcGetPendingTransactionsError,
-    >>(caller, "btc_get_pending_transactions", read_request);
+    >>(caller, "btc_get_pending_transactions_v2", read_request);
 
     assert!(read_response.is_ok());
 
@@ -143,13 +866,78 @@ fn test_add_and_read_pending_transactions() {
         .expect("Call failed")
         .expect("Request was not successful");
 
-    // Call to `btc_get_pending_transactions` cleans up the pending transactoins
-    // because the get_utxos call returns an empty list.
-    // Therefore, the pending transaction utxos are not available,
-    // which means it's not a pending transaction anymore and it gets pruned.
-    assert_eq!(data.transactions.len(), 0);
+    // The pending transaction utxos are not available anymore,
+    // which means the transaction is confirmed. It is kept for a day.
+    assert_eq!(
+        data.transactions,
+        vec![PendingTransaction {
+            txid,
+            utxos,
+            outputs: None,
+            fee_satoshis: None,
+            status: BtcPendingTransactionStatus::Confirmed { height: None },
+            created_at_timestamp_ns: data.transactions[0].created_at_timestamp_ns,
+        }]
+    );
+}
+
+#[test]
+fn test_remove_pending_transaction() {
+    let pic_setup = setup_with_stand_ins();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+
+    let address = pic_setup.add_btc_pending_transaction(caller, vec![1; 32], vec![UTXO_1]);
+
+    let remove_request = BtcRemovePendingTransactionRequest {
+        txid: vec![1; 32],
+        address,
+    };
+    let remove_response = pic_setup.update::<Result<(), BtcRemovePendingTransactionError>>(
+        caller,
+        "btc_remove_pending_transaction",
+        remove_request.clone(),
+    );
+    assert_eq!(remove_response, Ok(Ok(())));
+
+    let stats = pic_setup
+        .query::<Stats>(controller(), "stats", ())
+        .expect("Failed to get stats");
+    assert_eq!(stats.btc_pending_transactions_count, 0);
+
+    let remove_response = pic_setup.update::<Result<(), BtcRemovePendingTransactionError>>(
+        caller,
+        "btc_remove_pending_transaction",
+        remove_request,
+    );
+    assert_eq!(
+        remove_response,
+        Ok(Err(BtcRemovePendingTransactionError::TransactionNotFound))
+    );
+}
+
+#[test]
+fn test_pending_transactions_survive_upgrade() {
+    let pic_setup = setup_with_stand_ins();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+
+    pic_setup.add_btc_pending_transaction(caller, vec![1; 32], vec![UTXO_1]);
+
+    // The pending transactions are stored until a day after they are settled.
+    let stats_before_upgrade = pic_setup
+        .query::<Stats>(controller(), "stats", ())
+        .expect("Failed to get stats");
+
+    assert_eq!(stats_before_upgrade.btc_pending_transactions_count, 1);
+
+    pic_setup
+        .upgrade_with_wasm(&BackendBuilder::stand_in_wasm_path(), None)
+        .unwrap_or_else(|e| panic!("Upgrade canister failed with error: {}", e));
+
+    let stats_after_upgrade = pic_setup
+        .query::<Stats>(controller(), "stats", ())
+        .expect("Failed to get stats");
 
-    // I tried removing the call to prune and the test returns the pending transaction as expected.
-    // Ideally, we mock the call to get_utxos to return the pending utxo
-    // and the pending transaction won't be pruned.
+    assert_eq!(stats_after_upgrade, stats_before_upgrade);
 }
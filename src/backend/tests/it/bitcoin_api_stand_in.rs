@@ -0,0 +1,789 @@
+//! Tests of the bitcoin endpoints against the stand-in bitcoin API, which serves UTXOs and fee
+//! percentiles set by the tests.
+use candid::Principal;
+use ic_cdk::api::management_canister::bitcoin::{Outpoint, Utxo};
+use shared::types::bitcoin::{
+    BtcAddPendingTransactionErrorV2, BtcAddPendingTransactionRequest,
+    BtcBuildUnsignedTransactionError, BtcBuildUnsignedTransactionRequest,
+    BtcBuildUnsignedTransactionResponse, BtcBumpFeeError, BtcBumpFeeRequest, BtcFeePolicy,
+    BtcFreezeUtxosError, BtcFreezeUtxosRequest, BtcGetAddressError, BtcGetAddressRequest,
+    BtcGetAddressResponse, BtcGetBalanceError, BtcGetBalanceRequest, BtcGetBalanceResponse,
+    BtcGetPendingTransactionsError, BtcGetPendingTransactionsReponse,
+    BtcGetPendingTransactionsRequest, BtcInputError, BtcNetwork, BtcPendingTransactionStatus,
+    BtcTxOutput, SelectedUtxosFeeErrorV2, SelectedUtxosFeeRequest, SelectedUtxosFeeResponse,
+};
+
+use crate::utils::{
+    mock::CALLER,
+    pocketic::{setup_with_stand_ins, PicBackend, PicCanisterTrait},
+};
+
+const TIP_HEIGHT: u32 = 100;
+const DESTINATION_ADDRESS: &str = "bcrt1q0ht9tyks4vh7p5p904t340cr9nvahy7uevmqwj";
+const UTXO: Utxo = Utxo {
+    outpoint: Outpoint {
+        txid: vec![],
+        vout: 0,
+    },
+    value: 100_000_000,
+    height: TIP_HEIGHT,
+};
+
+/// Deploys the backend and gives a UTXO to the default regtest address of the caller.
+fn setup_caller_with_utxo() -> (PicBackend, Principal, String) {
+    let pic_setup = setup_with_stand_ins();
+    let caller = Principal::from_text(CALLER).unwrap();
+    let address = pic_setup
+        .update::<Result<BtcGetAddressResponse, BtcGetAddressError>>(
+            caller,
+            "btc_get_address",
+            BtcGetAddressRequest {
+                network: BtcNetwork::Regtest,
+                address_type: None,
+            },
+        )
+        .expect("Call failed")
+        .expect("Request was not successful")
+        .address;
+    pic_setup.set_btc_stand_in_tip_height(BtcNetwork::Regtest, TIP_HEIGHT);
+    pic_setup.set_btc_stand_in_utxos(BtcNetwork::Regtest, &address, vec![UTXO]);
+    (pic_setup, caller, address)
+}
+
+fn select_user_utxos_fee(
+    pic_setup: &PicBackend,
+    caller: Principal,
+) -> Result<SelectedUtxosFeeResponse, SelectedUtxosFeeErrorV2> {
+    pic_setup
+        .update::<Result<SelectedUtxosFeeResponse, SelectedUtxosFeeErrorV2>>(
+            caller,
+            "btc_select_user_utxos_fee_v2",
+            SelectedUtxosFeeRequest {
+                amount_satoshis: 50_000_000,
+                network: BtcNetwork::Regtest,
+                min_confirmations: None,
+                outputs: None,
+                fee_policy: None,
+                address_type: None,
+                coin_selection: None,
+                sweep: None,
+                inputs: None,
+                spend_unconfirmed_change: None,
+            },
+        )
+        .expect("Call failed")
+}
+
+#[test]
+fn test_select_user_utxos_fee_uses_utxos_and_fee_percentiles() {
+    let (pic_setup, caller, _) = setup_caller_with_utxo();
+
+    let default_fee = select_user_utxos_fee(&pic_setup, caller)
+        .expect("Request was not successful")
+        .fee_satoshis;
+
+    pic_setup.set_btc_stand_in_fee_percentiles(
+        BtcNetwork::Regtest,
+        (0..100).map(|i| i * 1_000).collect(),
+    );
+    let selected = select_user_utxos_fee(&pic_setup, caller).expect("Request was not successful");
+
+    assert_eq!(selected.utxos, vec![UTXO]);
+    // The default fee rate is 2 satoshi/vbyte, the standard tier is 50 satoshi/vbyte.
+    assert_eq!(selected.fee_satoshis, default_fee * 25);
+}
+
+#[test]
+fn test_select_user_utxos_fee_with_many_outputs() {
+    let (pic_setup, caller, address) = setup_caller_with_utxo();
+
+    let outputs = vec![
+        BtcTxOutput {
+            destination_address: address,
+            sent_satoshis: 10_000,
+        },
+        BtcTxOutput {
+            destination_address: DESTINATION_ADDRESS.to_string(),
+            sent_satoshis: 20_000,
+        },
+    ];
+    let selected = pic_setup
+        .update::<Result<SelectedUtxosFeeResponse, SelectedUtxosFeeErrorV2>>(
+            caller,
+            "btc_select_user_utxos_fee_v2",
+            SelectedUtxosFeeRequest {
+                amount_satoshis: 0,
+                network: BtcNetwork::Regtest,
+                min_confirmations: None,
+                outputs: Some(outputs.clone()),
+                fee_policy: None,
+                address_type: None,
+                coin_selection: None,
+                sweep: None,
+                inputs: None,
+                spend_unconfirmed_change: None,
+            },
+        )
+        .expect("Call failed")
+        .expect("Request was not successful");
+
+    assert_eq!(selected.utxos, vec![UTXO]);
+    assert_eq!(selected.amount_satoshis, 30_000);
+    assert_eq!(selected.outputs, outputs);
+    assert_eq!(
+        selected.change_satoshis,
+        Some(UTXO.value - 30_000 - selected.fee_satoshis)
+    );
+}
+
+#[test]
+fn test_select_user_utxos_fee_with_custom_fee_rate() {
+    let (pic_setup, caller, _) = setup_caller_with_utxo();
+
+    let selected = pic_setup
+        .update::<Result<SelectedUtxosFeeResponse, SelectedUtxosFeeErrorV2>>(
+            caller,
+            "btc_select_user_utxos_fee_v2",
+            SelectedUtxosFeeRequest {
+                amount_satoshis: 100_000,
+                network: BtcNetwork::Regtest,
+                min_confirmations: None,
+                outputs: None,
+                fee_policy: Some(BtcFeePolicy::Custom {
+                    satoshi_per_vbyte: 10,
+                }),
+                address_type: None,
+                coin_selection: None,
+                sweep: None,
+                inputs: None,
+                spend_unconfirmed_change: None,
+            },
+        )
+        .expect("Call failed")
+        .expect("Request was not successful");
+
+    assert_eq!(selected.fee_millisatoshi_per_vbyte, 10_000);
+    assert_eq!(selected.fee_satoshis, selected.vsize * 10);
+}
+
+#[test]
+fn test_select_user_utxos_fee_rejects_frozen_and_duplicated_inputs() {
+    let (pic_setup, caller, _) = setup_caller_with_utxo();
+    let select_inputs = |inputs: Vec<Outpoint>| {
+        pic_setup
+            .update::<Result<SelectedUtxosFeeResponse, SelectedUtxosFeeErrorV2>>(
+                caller,
+                "btc_select_user_utxos_fee_v2",
+                SelectedUtxosFeeRequest {
+                    amount_satoshis: 100_000,
+                    network: BtcNetwork::Regtest,
+                    min_confirmations: None,
+                    outputs: None,
+                    fee_policy: None,
+                    address_type: None,
+                    coin_selection: None,
+                    sweep: None,
+                    inputs: Some(inputs),
+                    spend_unconfirmed_change: None,
+                },
+            )
+            .expect("Call failed")
+    };
+
+    assert_eq!(
+        select_inputs(vec![]),
+        Err(SelectedUtxosFeeErrorV2::InvalidInput(
+            BtcInputError::NoInputs
+        ))
+    );
+    assert_eq!(
+        select_inputs(vec![UTXO.outpoint, UTXO.outpoint]),
+        Err(SelectedUtxosFeeErrorV2::InvalidInput(
+            BtcInputError::Duplicated {
+                outpoint: UTXO.outpoint
+            }
+        ))
+    );
+
+    let freeze_response = pic_setup.update::<Result<(), BtcFreezeUtxosError>>(
+        caller,
+        "btc_freeze_utxos",
+        BtcFreezeUtxosRequest {
+            outpoints: vec![UTXO.outpoint],
+        },
+    );
+    assert_eq!(freeze_response, Ok(Ok(())));
+    assert_eq!(
+        select_inputs(vec![UTXO.outpoint]),
+        Err(SelectedUtxosFeeErrorV2::InvalidInput(
+            BtcInputError::Frozen {
+                outpoint: UTXO.outpoint
+            }
+        ))
+    );
+}
+
+#[test]
+fn test_pending_transaction_locks_its_utxos() {
+    let (pic_setup, caller, address) = setup_caller_with_utxo();
+
+    let add_response = pic_setup.update::<Result<(), BtcAddPendingTransactionErrorV2>>(
+        caller,
+        "btc_add_pending_transaction_v2",
+        BtcAddPendingTransactionRequest {
+            txid: vec![1; 32],
+            utxos: vec![UTXO],
+            address: address.clone(),
+            network: BtcNetwork::Regtest,
+        },
+    );
+    assert_eq!(add_response, Ok(Ok(())));
+
+    assert_eq!(
+        select_user_utxos_fee(&pic_setup, caller),
+        Err(SelectedUtxosFeeErrorV2::PendingTransactions)
+    );
+    let balance = pic_setup.update::<Result<BtcGetBalanceResponse, BtcGetBalanceError>>(
+        caller,
+        "btc_get_balance",
+        BtcGetBalanceRequest {
+            network: BtcNetwork::Regtest,
+            address_type: None,
+            min_confirmations: None,
+        },
+    );
+    assert_eq!(
+        balance,
+        Ok(Ok(BtcGetBalanceResponse {
+            confirmed_satoshis: UTXO.value,
+            unconfirmed_satoshis: 0,
+            locked_satoshis: UTXO.value,
+            spendable_satoshis: 0,
+        }))
+    );
+    let pending_transactions = pic_setup
+        .update::<Result<BtcGetPendingTransactionsReponse, BtcGetPendingTransactionsError>>(
+            caller,
+            "btc_get_pending_transactions_v2",
+            BtcGetPendingTransactionsRequest {
+                address,
+                network: BtcNetwork::Regtest,
+            },
+        )
+        .expect("Call failed")
+        .expect("Request was not successful")
+        .transactions;
+    assert_eq!(pending_transactions.len(), 1);
+    assert_eq!(
+        pending_transactions[0].status,
+        BtcPendingTransactionStatus::Broadcast
+    );
+}
+
+#[test]
+fn test_pending_transaction_in_a_block_keeps_its_utxos_locked() {
+    let pic_setup = setup_with_stand_ins();
+    let caller = Principal::from_text(CALLER).unwrap();
+    // Unlike regtest, testnet requires 6 confirmations before the UTXOs can be spent.
+    let network = BtcNetwork::Testnet;
+    let address = pic_setup
+        .update::<Result<BtcGetAddressResponse, BtcGetAddressError>>(
+            caller,
+            "btc_get_address",
+            BtcGetAddressRequest {
+                network,
+                address_type: None,
+            },
+        )
+        .expect("Call failed")
+        .expect("Request was not successful")
+        .address;
+    let utxo = Utxo {
+        height: TIP_HEIGHT - 10,
+        ..UTXO
+    };
+    pic_setup.set_btc_stand_in_tip_height(network, TIP_HEIGHT);
+    pic_setup.set_btc_stand_in_utxos(network, &address, vec![utxo.clone()]);
+    let select_user_utxos_fee = || {
+        pic_setup
+            .update::<Result<SelectedUtxosFeeResponse, SelectedUtxosFeeErrorV2>>(
+                caller,
+                "btc_select_user_utxos_fee_v2",
+                SelectedUtxosFeeRequest {
+                    amount_satoshis: 50_000_000,
+                    network,
+                    min_confirmations: None,
+                    outputs: None,
+                    fee_policy: None,
+                    address_type: None,
+                    coin_selection: None,
+                    sweep: None,
+                    inputs: None,
+                    spend_unconfirmed_change: None,
+                },
+            )
+            .expect("Call failed")
+    };
+
+    let add_response = pic_setup.update::<Result<(), BtcAddPendingTransactionErrorV2>>(
+        caller,
+        "btc_add_pending_transaction_v2",
+        BtcAddPendingTransactionRequest {
+            txid: vec![1; 32],
+            utxos: vec![utxo.clone()],
+            address,
+            network,
+        },
+    );
+    assert_eq!(add_response, Ok(Ok(())));
+
+    // The transaction is in the next block, which is not deep enough for its UTXOs to be gone
+    // from the UTXOs with 6 confirmations.
+    pic_setup.set_btc_stand_in_tip_height(network, TIP_HEIGHT + 1);
+    pic_setup.set_btc_stand_in_spent_height(network, utxo.outpoint, TIP_HEIGHT + 1);
+    let balance = pic_setup
+        .update::<Result<BtcGetBalanceResponse, BtcGetBalanceError>>(
+            caller,
+            "btc_get_balance",
+            BtcGetBalanceRequest {
+                network,
+                address_type: None,
+                min_confirmations: None,
+            },
+        )
+        .expect("Call failed");
+    assert!(balance.is_ok());
+
+    assert_eq!(
+        select_user_utxos_fee(),
+        Err(SelectedUtxosFeeErrorV2::PendingTransactions)
+    );
+}
+
+#[test]
+fn test_built_transaction_is_broadcast_then_confirmed() {
+    let (pic_setup, caller, address) = setup_caller_with_utxo();
+    let statuses = || -> Vec<BtcPendingTransactionStatus> {
+        pic_setup
+            .update::<Result<BtcGetPendingTransactionsReponse, BtcGetPendingTransactionsError>>(
+                caller,
+                "btc_get_pending_transactions_v2",
+                BtcGetPendingTransactionsRequest {
+                    address: address.clone(),
+                    network: BtcNetwork::Regtest,
+                },
+            )
+            .expect("Call failed")
+            .expect("Request was not successful")
+            .transactions
+            .into_iter()
+            .map(|transaction| transaction.status)
+            .collect()
+    };
+
+    let built = pic_setup
+        .update::<Result<BtcBuildUnsignedTransactionResponse, BtcBuildUnsignedTransactionError>>(
+            caller,
+            "btc_build_unsigned_transaction",
+            BtcBuildUnsignedTransactionRequest {
+                destination_address: DESTINATION_ADDRESS.to_string(),
+                amount_satoshis: 10_000,
+                network: BtcNetwork::Regtest,
+                fee_policy: None,
+                min_confirmations: None,
+                address_type: None,
+                coin_selection: None,
+                inputs: None,
+                spend_unconfirmed_change: None,
+            },
+        )
+        .expect("Call failed")
+        .expect("Request was not successful");
+    assert_eq!(statuses(), vec![BtcPendingTransactionStatus::Built]);
+
+    let add_response = pic_setup.update::<Result<(), BtcAddPendingTransactionErrorV2>>(
+        caller,
+        "btc_add_pending_transaction_v2",
+        BtcAddPendingTransactionRequest {
+            txid: built.txid,
+            utxos: built.utxos,
+            address: address.clone(),
+            network: BtcNetwork::Regtest,
+        },
+    );
+    assert_eq!(add_response, Ok(Ok(())));
+    // The network does not show the transaction until its UTXOs are spent.
+    assert_eq!(statuses(), vec![BtcPendingTransactionStatus::Broadcast]);
+
+    pic_setup.set_btc_stand_in_tip_height(BtcNetwork::Regtest, TIP_HEIGHT + 1);
+    pic_setup.set_btc_stand_in_utxos(BtcNetwork::Regtest, &address, vec![]);
+    assert_eq!(
+        statuses(),
+        vec![BtcPendingTransactionStatus::Confirmed { height: None }]
+    );
+}
+
+#[test]
+fn test_unused_fresh_address_is_returned_again() {
+    let pic_setup = setup_with_stand_ins();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+
+    let get_fresh_address = || {
+        pic_setup
+            .update::<Result<BtcGetAddressResponse, BtcGetAddressError>>(
+                caller,
+                "btc_get_fresh_address",
+                BtcGetAddressRequest {
+                    network: BtcNetwork::Regtest,
+                    address_type: None,
+                },
+            )
+            .expect("Call failed")
+            .expect("Request was not successful")
+            .address
+    };
+    // More calls than the 10 addresses of a user never exhaust them, as nothing is paid to the
+    // fresh address.
+    let fresh_address = get_fresh_address();
+    for _ in 0..12 {
+        assert_eq!(get_fresh_address(), fresh_address);
+    }
+}
+
+#[test]
+fn test_fresh_addresses_are_limited_once_used() {
+    let (pic_setup, caller, address) = setup_caller_with_utxo();
+
+    let get_fresh_address = || {
+        pic_setup
+            .update::<Result<BtcGetAddressResponse, BtcGetAddressError>>(
+                caller,
+                "btc_get_fresh_address",
+                BtcGetAddressRequest {
+                    network: BtcNetwork::Regtest,
+                    address_type: None,
+                },
+            )
+            .expect("Call failed")
+    };
+    // Every user has a default address and up to 9 fresh ones, which are only issued once the
+    // previous one is used.
+    let mut fresh_addresses = vec![address];
+    for vout in 1..10 {
+        let fresh_address = get_fresh_address()
+            .expect("Request was not successful")
+            .address;
+        assert!(!fresh_addresses.contains(&fresh_address));
+        pic_setup.set_btc_stand_in_utxos(
+            BtcNetwork::Regtest,
+            &fresh_address,
+            vec![Utxo {
+                outpoint: Outpoint {
+                    txid: vec![3; 32],
+                    vout,
+                },
+                ..UTXO
+            }],
+        );
+        fresh_addresses.push(fresh_address);
+    }
+    assert_eq!(
+        get_fresh_address(),
+        Err(BtcGetAddressError::QuotaExceeded {
+            msg: "Addresses should not exceed 10".to_string()
+        })
+    );
+
+    // Once the UTXO of the last fresh address is spent in a new block, it is returned again.
+    pic_setup.set_btc_stand_in_tip_height(BtcNetwork::Regtest, TIP_HEIGHT + 1);
+    pic_setup.set_btc_stand_in_utxos(BtcNetwork::Regtest, &fresh_addresses[9], vec![]);
+    assert_eq!(
+        get_fresh_address().map(|response| response.address),
+        Ok(fresh_addresses[9].clone())
+    );
+}
+
+#[test]
+fn test_utxos_of_fresh_addresses_are_spent_with_the_default_address() {
+    let (pic_setup, caller, address) = setup_caller_with_utxo();
+
+    let get_fresh_address = || {
+        pic_setup
+            .update::<Result<BtcGetAddressResponse, BtcGetAddressError>>(
+                caller,
+                "btc_get_fresh_address",
+                BtcGetAddressRequest {
+                    network: BtcNetwork::Regtest,
+                    address_type: None,
+                },
+            )
+            .expect("Call failed")
+            .expect("Request was not successful")
+            .address
+    };
+    let fresh_address = get_fresh_address();
+    assert_ne!(fresh_address, address);
+    // The fresh address is returned again while it is unused.
+    assert_eq!(get_fresh_address(), fresh_address);
+
+    let fresh_utxo = Utxo {
+        outpoint: Outpoint {
+            txid: vec![2; 32],
+            vout: 0,
+        },
+        ..UTXO
+    };
+    pic_setup.set_btc_stand_in_utxos(
+        BtcNetwork::Regtest,
+        &fresh_address,
+        vec![fresh_utxo.clone()],
+    );
+
+    let balance = pic_setup.update::<Result<BtcGetBalanceResponse, BtcGetBalanceError>>(
+        caller,
+        "btc_get_balance",
+        BtcGetBalanceRequest {
+            network: BtcNetwork::Regtest,
+            address_type: None,
+            min_confirmations: None,
+        },
+    );
+    assert_eq!(
+        balance,
+        Ok(Ok(BtcGetBalanceResponse {
+            confirmed_satoshis: 2 * UTXO.value,
+            unconfirmed_satoshis: 0,
+            locked_satoshis: 0,
+            spendable_satoshis: 2 * UTXO.value,
+        }))
+    );
+    // A pending transaction of the fresh address locks its UTXOs, even for the default address.
+    let add_response = pic_setup.update::<Result<(), BtcAddPendingTransactionErrorV2>>(
+        caller,
+        "btc_add_pending_transaction_v2",
+        BtcAddPendingTransactionRequest {
+            txid: vec![1; 32],
+            utxos: vec![fresh_utxo],
+            address: fresh_address,
+            network: BtcNetwork::Regtest,
+        },
+    );
+    assert_eq!(add_response, Ok(Ok(())));
+    assert_eq!(
+        select_user_utxos_fee(&pic_setup, caller),
+        Err(SelectedUtxosFeeErrorV2::PendingTransactions)
+    );
+}
+
+#[test]
+fn test_testnet_has_its_own_addresses_and_utxos() {
+    let (pic_setup, caller, regtest_address) = setup_caller_with_utxo();
+    let testnet_address = pic_setup
+        .update::<Result<BtcGetAddressResponse, BtcGetAddressError>>(
+            caller,
+            "btc_get_address",
+            BtcGetAddressRequest {
+                network: BtcNetwork::Testnet,
+                address_type: None,
+            },
+        )
+        .expect("Call failed")
+        .expect("Request was not successful")
+        .address;
+    assert!(testnet_address.starts_with("tb1q"));
+    assert_ne!(testnet_address, regtest_address);
+
+    // Without the regtest cap, the UTXOs need 6 confirmations to be confirmed.
+    let testnet_utxo = Utxo {
+        height: TIP_HEIGHT - 4,
+        ..UTXO
+    };
+    pic_setup.set_btc_stand_in_tip_height(BtcNetwork::Testnet, TIP_HEIGHT);
+    pic_setup.set_btc_stand_in_utxos(BtcNetwork::Testnet, &testnet_address, vec![testnet_utxo]);
+    let balance = pic_setup.update::<Result<BtcGetBalanceResponse, BtcGetBalanceError>>(
+        caller,
+        "btc_get_balance",
+        BtcGetBalanceRequest {
+            network: BtcNetwork::Testnet,
+            address_type: None,
+            min_confirmations: None,
+        },
+    );
+    assert_eq!(
+        balance,
+        Ok(Ok(BtcGetBalanceResponse {
+            confirmed_satoshis: 0,
+            unconfirmed_satoshis: UTXO.value,
+            locked_satoshis: 0,
+            spendable_satoshis: 0,
+        }))
+    );
+}
+
+#[test]
+fn test_unconfirmed_change_of_pending_transactions_can_be_spent() {
+    let (pic_setup, caller, address) = setup_caller_with_utxo();
+    let change = |txid: &[u8]| Utxo {
+        outpoint: Outpoint {
+            txid: txid.to_vec(),
+            vout: 1,
+        },
+        value: UTXO.value / 2,
+        // Not mined yet.
+        height: TIP_HEIGHT + 1,
+    };
+    let add_pending_transaction = |txid: &[u8], utxo: Utxo| {
+        let add_response = pic_setup.update::<Result<(), BtcAddPendingTransactionErrorV2>>(
+            caller,
+            "btc_add_pending_transaction_v2",
+            BtcAddPendingTransactionRequest {
+                txid: txid.to_vec(),
+                utxos: vec![utxo],
+                address: address.clone(),
+                network: BtcNetwork::Regtest,
+            },
+        );
+        assert_eq!(add_response, Ok(Ok(())));
+    };
+    let select_spending_unconfirmed_change = || {
+        pic_setup
+            .update::<Result<SelectedUtxosFeeResponse, SelectedUtxosFeeErrorV2>>(
+                caller,
+                "btc_select_user_utxos_fee_v2",
+                SelectedUtxosFeeRequest {
+                    amount_satoshis: 10_000_000,
+                    network: BtcNetwork::Regtest,
+                    min_confirmations: None,
+                    outputs: None,
+                    fee_policy: None,
+                    address_type: None,
+                    coin_selection: None,
+                    sweep: None,
+                    inputs: None,
+                    spend_unconfirmed_change: Some(true),
+                },
+            )
+            .expect("Call failed")
+    };
+
+    let change_1 = change(&[1; 32]);
+    add_pending_transaction(&[1; 32], UTXO);
+    pic_setup.set_btc_stand_in_utxos(BtcNetwork::Regtest, &address, vec![UTXO, change_1.clone()]);
+    assert_eq!(
+        select_user_utxos_fee(&pic_setup, caller),
+        Err(SelectedUtxosFeeErrorV2::PendingTransactions)
+    );
+    let selected = select_spending_unconfirmed_change().expect("Request was not successful");
+    assert_eq!(selected.utxos, vec![change_1.clone()]);
+    assert!(selected.spends_unconfirmed_change);
+
+    // The second transaction of the chain descends from one pending transaction.
+    let change_2 = change(&[2; 32]);
+    add_pending_transaction(&[2; 32], change_1.clone());
+    pic_setup.set_btc_stand_in_utxos(
+        BtcNetwork::Regtest,
+        &address,
+        vec![UTXO, change_1.clone(), change_2.clone()],
+    );
+    let selected = select_spending_unconfirmed_change().expect("Request was not successful");
+    assert_eq!(selected.utxos, vec![change_2.clone()]);
+    assert!(selected.spends_unconfirmed_change);
+
+    // A third one would descend from more than 2 pending transactions.
+    add_pending_transaction(&[3; 32], change_2.clone());
+    pic_setup.set_btc_stand_in_utxos(
+        BtcNetwork::Regtest,
+        &address,
+        vec![UTXO, change_1, change_2, change(&[3; 32])],
+    );
+    assert_eq!(
+        select_spending_unconfirmed_change(),
+        Err(SelectedUtxosFeeErrorV2::InsufficientFunds)
+    );
+
+    // The transactions of the chain are neither seen nor confirmed while their UTXOs are unspent.
+    let statuses: Vec<_> = pic_setup
+        .update::<Result<BtcGetPendingTransactionsReponse, BtcGetPendingTransactionsError>>(
+            caller,
+            "btc_get_pending_transactions_v2",
+            BtcGetPendingTransactionsRequest {
+                address,
+                network: BtcNetwork::Regtest,
+            },
+        )
+        .expect("Call failed")
+        .expect("Request was not successful")
+        .transactions
+        .into_iter()
+        .map(|transaction| transaction.status)
+        .collect();
+    assert_eq!(statuses, vec![BtcPendingTransactionStatus::Broadcast; 3]);
+}
+
+#[test]
+fn test_transaction_spending_unconfirmed_change_can_be_replaced() {
+    let (pic_setup, caller, address) = setup_caller_with_utxo();
+    let change = Utxo {
+        outpoint: Outpoint {
+            txid: vec![1; 32],
+            vout: 1,
+        },
+        value: UTXO.value / 2,
+        // Not mined yet.
+        height: TIP_HEIGHT + 1,
+    };
+    let add_response = pic_setup.update::<Result<(), BtcAddPendingTransactionErrorV2>>(
+        caller,
+        "btc_add_pending_transaction_v2",
+        BtcAddPendingTransactionRequest {
+            txid: vec![1; 32],
+            utxos: vec![UTXO],
+            address: address.clone(),
+            network: BtcNetwork::Regtest,
+        },
+    );
+    assert_eq!(add_response, Ok(Ok(())));
+    pic_setup.set_btc_stand_in_utxos(BtcNetwork::Regtest, &address, vec![UTXO, change.clone()]);
+
+    let built = pic_setup
+        .update::<Result<BtcBuildUnsignedTransactionResponse, BtcBuildUnsignedTransactionError>>(
+            caller,
+            "btc_build_unsigned_transaction",
+            BtcBuildUnsignedTransactionRequest {
+                destination_address: DESTINATION_ADDRESS.to_string(),
+                amount_satoshis: 10_000,
+                network: BtcNetwork::Regtest,
+                fee_policy: None,
+                min_confirmations: None,
+                address_type: None,
+                coin_selection: None,
+                inputs: None,
+                spend_unconfirmed_change: Some(true),
+            },
+        )
+        .expect("Call failed")
+        .expect("Request was not successful");
+    assert_eq!(built.utxos, vec![change.clone()]);
+    assert!(built.spends_unconfirmed_change);
+
+    // The replacement spends the same unconfirmed change.
+    let replacement = pic_setup
+        .update::<Result<BtcBuildUnsignedTransactionResponse, BtcBumpFeeError>>(
+            caller,
+            "btc_bump_fee",
+            BtcBumpFeeRequest {
+                txid: built.txid.clone(),
+                network: BtcNetwork::Regtest,
+                fee_policy: Some(BtcFeePolicy::Fast),
+                min_confirmations: None,
+                address_type: None,
+            },
+        )
+        .expect("Call failed")
+        .expect("Request was not successful");
+    assert_ne!(replacement.txid, built.txid);
+    assert_eq!(replacement.utxos, vec![change]);
+    assert!(replacement.fee_satoshis > built.fee_satoshis);
+    assert!(replacement.spends_unconfirmed_change);
+}
@@ -0,0 +1,239 @@
+//! Tests of the ckBTC endpoints, against the stand-in ckBTC minter where a minter is needed.
+use candid::Principal;
+use shared::types::ckbtc::{
+    CkBtcAddWithdrawalError, CkBtcAddWithdrawalRequest, CkBtcEstimateWithdrawalFeeError,
+    CkBtcEstimateWithdrawalFeeRequest, CkBtcEstimateWithdrawalFeeResponse, CkBtcGetBtcAddressError,
+    CkBtcGetBtcAddressResponse, CkBtcGetWithdrawalsError, CkBtcGetWithdrawalsResponse,
+    CkBtcWithdrawal, CkBtcWithdrawalStatus,
+};
+
+use crate::utils::{
+    mock::{CALLER, USER_1},
+    pocketic::{setup, setup_with_stand_ins, PicBackend, PicCanisterTrait},
+};
+
+const WITHDRAWAL_ADDRESS: &str = "bcrt1qpg7udjvq7gx2fp480pgt4hnhj3qc4nhrkstc33";
+const WITHDRAWAL_AMOUNT_SATOSHIS: u64 = 100_000;
+
+fn get_btc_address(
+    pic_setup: &PicBackend,
+    caller: Principal,
+) -> Result<CkBtcGetBtcAddressResponse, CkBtcGetBtcAddressError> {
+    pic_setup
+        .update::<Result<CkBtcGetBtcAddressResponse, CkBtcGetBtcAddressError>>(
+            caller,
+            "ckbtc_get_btc_address",
+            (),
+        )
+        .expect("Call failed")
+}
+
+fn add_withdrawal(
+    pic_setup: &PicBackend,
+    caller: Principal,
+    block_index: u64,
+) -> Result<(), CkBtcAddWithdrawalError> {
+    pic_setup
+        .update::<Result<(), CkBtcAddWithdrawalError>>(
+            caller,
+            "ckbtc_add_withdrawal",
+            CkBtcAddWithdrawalRequest {
+                block_index,
+                address: WITHDRAWAL_ADDRESS.to_string(),
+                amount_satoshis: WITHDRAWAL_AMOUNT_SATOSHIS,
+            },
+        )
+        .expect("Call failed")
+}
+
+/// Adds a withdrawal that the stand-in minter reports as one of the caller's.
+fn add_own_withdrawal(pic_setup: &PicBackend, caller: Principal, block_index: u64) {
+    pic_setup.set_ckbtc_stand_in_withdrawal(
+        block_index,
+        caller,
+        WITHDRAWAL_ADDRESS,
+        WITHDRAWAL_AMOUNT_SATOSHIS,
+    );
+    add_withdrawal(pic_setup, caller, block_index).expect("Request was not successful");
+}
+
+fn get_withdrawals(
+    pic_setup: &PicBackend,
+    caller: Principal,
+) -> Result<Vec<CkBtcWithdrawal>, CkBtcGetWithdrawalsError> {
+    pic_setup
+        .update::<Result<CkBtcGetWithdrawalsResponse, CkBtcGetWithdrawalsError>>(
+            caller,
+            "ckbtc_get_withdrawals",
+            (),
+        )
+        .expect("Call failed")
+        .map(|response| response.withdrawals)
+}
+
+#[test]
+fn test_ckbtc_endpoints_need_a_minter() {
+    let pic_setup = setup();
+    let caller = Principal::from_text(CALLER).unwrap();
+
+    assert_eq!(
+        get_btc_address(&pic_setup, caller),
+        Err(CkBtcGetBtcAddressError::InternalError {
+            msg: "The ckBTC minter is not configured".to_string()
+        })
+    );
+    assert_eq!(
+        add_withdrawal(&pic_setup, caller, 1),
+        Err(CkBtcAddWithdrawalError::InternalError {
+            msg: "The ckBTC minter is not configured".to_string()
+        })
+    );
+    assert_eq!(
+        get_withdrawals(&pic_setup, caller),
+        Err(CkBtcGetWithdrawalsError::InternalError {
+            msg: "The ckBTC minter is not configured".to_string()
+        })
+    );
+}
+
+#[test]
+fn test_ckbtc_get_btc_address_depends_on_caller() {
+    let pic_setup = setup_with_stand_ins();
+    let caller = Principal::from_text(CALLER).unwrap();
+    let other_caller = Principal::from_text(USER_1).unwrap();
+
+    let address = get_btc_address(&pic_setup, caller)
+        .expect("Request was not successful")
+        .address;
+
+    assert_eq!(
+        get_btc_address(&pic_setup, caller).map(|response| response.address),
+        Ok(address.clone())
+    );
+    assert_ne!(
+        get_btc_address(&pic_setup, other_caller).map(|response| response.address),
+        Ok(address)
+    );
+}
+
+#[test]
+fn test_ckbtc_estimate_withdrawal_fee() {
+    let pic_setup = setup_with_stand_ins();
+    let caller = Principal::from_text(CALLER).unwrap();
+
+    let fee = pic_setup
+        .update::<Result<CkBtcEstimateWithdrawalFeeResponse, CkBtcEstimateWithdrawalFeeError>>(
+            caller,
+            "ckbtc_estimate_withdrawal_fee",
+            CkBtcEstimateWithdrawalFeeRequest {
+                amount_satoshis: Some(100_000),
+            },
+        )
+        .expect("Call failed");
+
+    assert_eq!(
+        fee,
+        Ok(CkBtcEstimateWithdrawalFeeResponse {
+            bitcoin_fee_satoshis: 2_000,
+            minter_fee_satoshis: 300,
+        })
+    );
+}
+
+#[test]
+fn test_ckbtc_get_withdrawals_polls_statuses() {
+    let pic_setup = setup_with_stand_ins();
+    let caller = Principal::from_text(CALLER).unwrap();
+    let other_caller = Principal::from_text(USER_1).unwrap();
+    add_own_withdrawal(&pic_setup, caller, 1);
+    add_own_withdrawal(&pic_setup, caller, 2);
+
+    let statuses = |caller| {
+        get_withdrawals(&pic_setup, caller)
+            .expect("Request was not successful")
+            .into_iter()
+            .map(|withdrawal| (withdrawal.block_index, withdrawal.status))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        statuses(caller),
+        vec![
+            (1, CkBtcWithdrawalStatus::Pending),
+            (2, CkBtcWithdrawalStatus::Pending)
+        ]
+    );
+
+    let confirmed = CkBtcWithdrawalStatus::Confirmed { txid: vec![1; 32] };
+    pic_setup.set_ckbtc_stand_in_withdrawal_status(2, confirmed.clone());
+    assert_eq!(
+        statuses(caller),
+        vec![(1, CkBtcWithdrawalStatus::Pending), (2, confirmed.clone())]
+    );
+    // Confirmed withdrawals are not polled anymore.
+    pic_setup.set_ckbtc_stand_in_withdrawal_status(2, CkBtcWithdrawalStatus::Unknown);
+    assert_eq!(
+        statuses(caller),
+        vec![(1, CkBtcWithdrawalStatus::Pending), (2, confirmed)]
+    );
+    // The withdrawals of a user are private.
+    assert_eq!(statuses(other_caller), vec![]);
+}
+
+#[test]
+fn test_ckbtc_add_withdrawal_of_other_user_is_rejected() {
+    let pic_setup = setup_with_stand_ins();
+    let caller = Principal::from_text(CALLER).unwrap();
+    let other_caller = Principal::from_text(USER_1).unwrap();
+    add_own_withdrawal(&pic_setup, caller, 1);
+
+    assert_eq!(
+        add_withdrawal(&pic_setup, other_caller, 1),
+        Err(CkBtcAddWithdrawalError::WithdrawalNotFound)
+    );
+    // Unknown block indices are rejected too.
+    assert_eq!(
+        add_withdrawal(&pic_setup, caller, 2),
+        Err(CkBtcAddWithdrawalError::WithdrawalNotFound)
+    );
+    assert!(get_withdrawals(&pic_setup, other_caller)
+        .expect("Request was not successful")
+        .is_empty());
+    assert_eq!(
+        get_withdrawals(&pic_setup, caller)
+            .expect("Request was not successful")
+            .into_iter()
+            .map(|withdrawal| withdrawal.block_index)
+            .collect::<Vec<_>>(),
+        vec![1]
+    );
+}
+
+#[test]
+fn test_ckbtc_add_withdrawal_with_another_address_or_amount_is_rejected() {
+    let pic_setup = setup_with_stand_ins();
+    let caller = Principal::from_text(CALLER).unwrap();
+    pic_setup.set_ckbtc_stand_in_withdrawal(
+        1,
+        caller,
+        "bcrt1q0ht9tyks4vh7p5p904t340cr9nvahy7uevmqwj",
+        WITHDRAWAL_AMOUNT_SATOSHIS,
+    );
+    pic_setup.set_ckbtc_stand_in_withdrawal(
+        2,
+        caller,
+        WITHDRAWAL_ADDRESS,
+        2 * WITHDRAWAL_AMOUNT_SATOSHIS,
+    );
+
+    assert_eq!(
+        add_withdrawal(&pic_setup, caller, 1),
+        Err(CkBtcAddWithdrawalError::WithdrawalNotFound)
+    );
+    assert_eq!(
+        add_withdrawal(&pic_setup, caller, 2),
+        Err(CkBtcAddWithdrawalError::WithdrawalNotFound)
+    );
+    assert!(get_withdrawals(&pic_setup, caller)
+        .expect("Request was not successful")
+        .is_empty());
+}
@@ -1,9 +1,14 @@
 use crate::utils::assertion::{assert_custom_tokens_eq, assert_tokens_data_eq};
-use crate::utils::mock::CALLER;
+use crate::utils::mock::{
+    CALLER, SEPOLIA_CHAIN_ID, WEENUS_CONTRACT_ADDRESS, WEENUS_DECIMALS, WEENUS_SYMBOL,
+};
 use crate::utils::pocketic::{setup, PicCanisterTrait};
 use candid::Principal;
 use lazy_static::lazy_static;
-use shared::types::custom_token::{CustomToken, CustomTokenId, IcrcToken, Token};
+use shared::types::custom_token::{
+    CustomToken, CustomTokenId, ErcToken, IcrcToken, SolanaNetwork, SplToken, Token,
+};
+use shared::types::token::UserToken;
 use shared::types::TokenVersion;
 
 lazy_static! {
@@ -35,8 +40,38 @@ lazy_static! {
         enabled: true,
         version: None,
     };
+    static ref ERC20_TOKEN: CustomToken = CustomToken {
+        token: Token::Erc20(ErcToken {
+            chain_id: SEPOLIA_CHAIN_ID,
+            contract_address: WEENUS_CONTRACT_ADDRESS.to_string(),
+            symbol: Some(WEENUS_SYMBOL.to_string()),
+            decimals: Some(WEENUS_DECIMALS),
+        }),
+        enabled: true,
+        version: None,
+    };
+    static ref SPL_TOKEN: CustomToken = CustomToken {
+        token: Token::Spl(SplToken {
+            network: SolanaNetwork::Mainnet,
+            mint_address: SPL_MINT_ADDRESS.to_string(),
+            token_program: None,
+        }),
+        enabled: true,
+        version: None,
+    };
+    static ref SPL_TOKEN_WITH_PROGRAM: CustomToken = CustomToken {
+        token: Token::Spl(SplToken {
+            network: SolanaNetwork::Devnet,
+            mint_address: SPL_MINT_ADDRESS.to_string(),
+            token_program: Some("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA".to_string()),
+        }),
+        enabled: true,
+        version: None,
+    };
 }
 
+const SPL_MINT_ADDRESS: &str = "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v";
+
 #[test]
 fn test_add_custom_token_with_index() {
     test_add_custom_token(&USER_TOKEN)
@@ -47,6 +82,21 @@ fn test_add_custom_token_without_index() {
     test_add_custom_token(&USER_TOKEN_NO_INDEX)
 }
 
+#[test]
+fn test_add_custom_token_erc20() {
+    test_add_custom_token(&ERC20_TOKEN)
+}
+
+#[test]
+fn test_add_custom_token_spl() {
+    test_add_custom_token(&SPL_TOKEN)
+}
+
+#[test]
+fn test_add_custom_token_spl_with_token_program() {
+    test_add_custom_token(&SPL_TOKEN_WITH_PROGRAM)
+}
+
 fn test_add_custom_token(user_token: &CustomToken) {
     let pic_setup = setup();
 
@@ -77,6 +127,16 @@ fn test_update_custom_token_without_index() {
     test_update_custom_token(&USER_TOKEN_NO_INDEX);
 }
 
+#[test]
+fn test_update_custom_token_erc20() {
+    test_update_custom_token(&ERC20_TOKEN);
+}
+
+#[test]
+fn test_update_custom_token_spl() {
+    test_update_custom_token(&SPL_TOKEN);
+}
+
 fn test_update_custom_token(user_token: &CustomToken) {
     let pic_setup = setup();
 
@@ -116,6 +176,43 @@ fn test_update_custom_token(user_token: &CustomToken) {
     assert_custom_tokens_eq(updated_tokens.clone(), expected_updated_tokens);
 }
 
+#[test]
+fn test_update_custom_token_erc20_with_another_spelling_of_the_address() {
+    let pic_setup = setup();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+
+    let result = pic_setup.update::<()>(caller, "set_custom_token", ERC20_TOKEN.clone());
+
+    assert!(result.is_ok());
+
+    let results = pic_setup.query::<Vec<CustomToken>>(caller, "list_custom_tokens", ());
+
+    let Token::Erc20(erc20_token) = &ERC20_TOKEN.token else {
+        unreachable!("The token is definitely an ERC20 token")
+    };
+    let update_token: CustomToken = CustomToken {
+        enabled: false,
+        token: Token::Erc20(ErcToken {
+            contract_address: WEENUS_CONTRACT_ADDRESS[2..].to_lowercase(),
+            ..erc20_token.clone()
+        }),
+        version: results.unwrap().get(0).unwrap().version,
+    };
+
+    let update_result = pic_setup.update::<()>(caller, "set_custom_token", update_token.clone());
+
+    assert!(update_result.is_ok());
+
+    let updated_results = pic_setup.query::<Vec<CustomToken>>(caller, "list_custom_tokens", ());
+
+    // The address without prefix in lower case is the same token.
+    let expected_updated_tokens: Vec<CustomToken> =
+        vec![update_token.clone_with_incremented_version()];
+
+    assert_custom_tokens_eq(updated_results.unwrap(), expected_updated_tokens);
+}
+
 #[test]
 fn test_add_many_custom_tokens_with_index() {
     test_add_many_custom_tokens(&USER_TOKEN);
@@ -306,6 +403,169 @@ fn test_cannot_update_custom_token_with_invalid_version(user_token: &CustomToken
         .contains("Version mismatch, token update not allowed"));
 }
 
+#[test]
+fn test_list_spl_custom_tokens_per_network() {
+    let pic_setup = setup();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+
+    let tokens: Vec<CustomToken> = vec![SPL_TOKEN.clone(), SPL_TOKEN_WITH_PROGRAM.clone()];
+
+    let result = pic_setup.update::<()>(caller, "set_many_custom_tokens", tokens);
+
+    assert!(result.is_ok());
+
+    // The same mint on another network is another token
+    let results = pic_setup.query::<Vec<CustomToken>>(caller, "list_custom_tokens", ());
+
+    let expected_tokens: Vec<CustomToken> = vec![
+        SPL_TOKEN.clone_with_incremented_version(),
+        SPL_TOKEN_WITH_PROGRAM.clone_with_incremented_version(),
+    ];
+
+    assert_custom_tokens_eq(results.unwrap(), expected_tokens);
+
+    // The same mint on the same network is the same token, whatever its token program
+    let update_token = CustomToken {
+        token: Token::Spl(SplToken {
+            token_program: Some("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA".to_string()),
+            network: SolanaNetwork::Mainnet,
+            mint_address: SPL_MINT_ADDRESS.to_string(),
+        }),
+        version: Some(1),
+        ..SPL_TOKEN.clone()
+    };
+
+    let update_result = pic_setup.update::<()>(caller, "set_custom_token", update_token.clone());
+
+    assert!(update_result.is_ok());
+
+    let updated_results = pic_setup.query::<Vec<CustomToken>>(caller, "list_custom_tokens", ());
+
+    let expected_updated_tokens: Vec<CustomToken> = vec![
+        update_token.clone_with_incremented_version(),
+        SPL_TOKEN_WITH_PROGRAM.clone_with_incremented_version(),
+    ];
+
+    assert_custom_tokens_eq(updated_results.unwrap(), expected_updated_tokens);
+
+    // SPL tokens are not user tokens
+    let user_tokens = pic_setup.query::<Vec<UserToken>>(caller, "list_user_tokens", ());
+
+    assert_eq!(user_tokens.unwrap().len(), 0);
+}
+
+#[test]
+fn test_cannot_add_spl_custom_token_with_invalid_addresses() {
+    let pic_setup = setup();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+
+    // Not base58, not 32 bytes, and an invalid token program
+    for (mint_address, token_program) in [
+        ("0xEPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v", None),
+        ("EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZw", None),
+        (SPL_MINT_ADDRESS, Some("Token-2022")),
+    ] {
+        let invalid_address = token_program.unwrap_or(mint_address);
+        let token = CustomToken {
+            token: Token::Spl(SplToken {
+                network: SolanaNetwork::Mainnet,
+                mint_address: mint_address.to_string(),
+                token_program: token_program.map(ToString::to_string),
+            }),
+            enabled: true,
+            version: None,
+        };
+
+        let result = pic_setup.update::<()>(caller, "set_custom_token", token);
+
+        assert!(result.is_err());
+        assert!(result
+            .unwrap_err()
+            .contains(&format!("Invalid Solana address {invalid_address}")));
+    }
+
+    let results = pic_setup.query::<Vec<CustomToken>>(caller, "list_custom_tokens", ());
+
+    assert_eq!(results.unwrap().len(), 0);
+}
+
+#[test]
+fn test_erc20_custom_tokens_are_user_tokens() {
+    let pic_setup = setup();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+
+    let result = pic_setup.update::<()>(caller, "set_custom_token", ERC20_TOKEN.clone());
+
+    assert!(result.is_ok());
+
+    let user_tokens = pic_setup.query::<Vec<UserToken>>(caller, "list_user_tokens", ());
+
+    let expected_user_token = UserToken {
+        contract_address: WEENUS_CONTRACT_ADDRESS.to_string(),
+        chain_id: SEPOLIA_CHAIN_ID,
+        symbol: Some(WEENUS_SYMBOL.to_string()),
+        decimals: Some(WEENUS_DECIMALS),
+        version: Some(1),
+        enabled: Some(true),
+    };
+
+    assert_tokens_data_eq(&user_tokens.unwrap(), &[expected_user_token.clone()]);
+
+    // The same token, whatever the case of the contract address
+    let update_token = UserToken {
+        contract_address: WEENUS_CONTRACT_ADDRESS.to_lowercase(),
+        enabled: Some(false),
+        ..expected_user_token
+    };
+
+    let update_result = pic_setup.update::<()>(caller, "set_user_token", update_token.clone());
+
+    assert!(update_result.is_ok());
+
+    let custom_tokens = pic_setup.query::<Vec<CustomToken>>(caller, "list_custom_tokens", ());
+
+    let expected_tokens: Vec<CustomToken> = vec![CustomToken::from(
+        update_token.clone_with_incremented_version(),
+    )];
+
+    assert_custom_tokens_eq(custom_tokens.unwrap(), expected_tokens);
+
+    // ICRC tokens are not user tokens
+    let _ = pic_setup.update::<()>(caller, "set_custom_token", USER_TOKEN.clone());
+
+    let user_tokens = pic_setup.query::<Vec<UserToken>>(caller, "list_user_tokens", ());
+
+    assert_eq!(user_tokens.unwrap().len(), 1);
+}
+
+#[test]
+fn test_cannot_add_erc20_custom_token_with_invalid_contract_address() {
+    let pic_setup = setup();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+
+    let token = CustomToken {
+        token: Token::Erc20(ErcToken {
+            chain_id: SEPOLIA_CHAIN_ID,
+            contract_address: "0x123".to_string(),
+            symbol: None,
+            decimals: None,
+        }),
+        enabled: true,
+        version: None,
+    };
+
+    let result = pic_setup.update::<()>(caller, "set_custom_token", token);
+
+    assert!(result.is_err());
+    assert!(result
+        .unwrap_err()
+        .contains("failed to parse contract address 0x123"));
+}
+
 #[test]
 fn test_anonymous_cannot_add_custom_token() {
     let pic_setup = setup();
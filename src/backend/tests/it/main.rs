@@ -1,4 +1,6 @@
 mod bitcoin;
+mod bitcoin_api_stand_in;
+mod ckbtc;
 mod config;
 mod custom_token;
 mod guard;
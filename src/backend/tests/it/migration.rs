@@ -6,13 +6,27 @@ use crate::{
     user_token::{ANOTHER_TOKEN, MOCK_TOKEN},
     utils::pocketic::{controller, setup, BackendBuilder, PicBackend, PicCanisterTrait},
 };
-use candid::Principal;
+use candid::{encode_one, CandidType, Principal};
+use ic_cdk::api::management_canister::bitcoin::{Outpoint, Utxo};
 use pocket_ic::PocketIcBuilder;
 use shared::types::{
+    bitcoin::{
+        BtcFreezeUtxosError, BtcFreezeUtxosRequest, BtcGetAddressError, BtcGetAddressRequest,
+        BtcGetAddressResponse, BtcNetwork,
+    },
+    ckbtc::{CkBtcAddWithdrawalError, CkBtcAddWithdrawalRequest},
     custom_token::{CustomToken, IcrcToken, Token},
+    token::UserToken,
     ApiEnabled, Guards, MigrationProgress, MigrationReport, Stats,
 };
 
+/// The user tokens chunk of the data migration, with which the ERC20 tokens of users are uploaded
+/// to `user_token`, as they were stored before they became custom tokens.
+#[derive(CandidType)]
+enum MigrationChunk {
+    UserToken(Vec<(Principal, Vec<UserToken>)>),
+}
+
 struct MigrationTestEnv {
     /// The old backend canister ID, from which data is being migrated.
     old_backend: PicBackend,
@@ -29,9 +43,12 @@ impl Default for MigrationTestEnv {
                 .with_fiduciary_subnet()
                 .build(),
         );
+        // The old backend serves the UTXOs spent by the pending transactions of the users.
         let old_backend = PicBackend {
             pic: pic.clone(),
-            canister_id: BackendBuilder::default().deploy_to(&mut pic),
+            canister_id: BackendBuilder::default()
+                .with_wasm(&BackendBuilder::stand_in_wasm_path())
+                .deploy_to(&mut pic),
         };
         let new_controllers = [
             BackendBuilder::default_controllers(),
@@ -60,20 +77,32 @@ impl MigrationTestEnv {
             user_timestamps_count,
             user_token_count,
             custom_token_count,
+            btc_pending_transactions_count,
+            btc_frozen_utxos_count,
+            ckbtc_withdrawals_count,
+            btc_next_address_index_count,
         } = stats;
         assert_eq!(user_profile_count, user_timestamps_count, "Test setup failure: Stats indicate that the database is inconsistent.  Doesn't affect the migration but should be fixed.");
         // Create users
         let expected_users = pic_setup.old_backend.create_users(
             0..u8::try_from(*user_profile_count).expect("Test setup requested too many users"),
         );
-        // Create users with tokens.
+        // Create users with legacy user tokens.
         let user_tokens = vec![MOCK_TOKEN.clone(), ANOTHER_TOKEN.clone()];
-        for user in &expected_users[0..*user_token_count as usize] {
-            pic_setup
-                .old_backend
-                .update::<()>(user.principal, "set_many_user_tokens", &user_tokens)
-                .expect("Test setup error: Failed to set user tokens");
-        }
+        let legacy_user_tokens = MigrationChunk::UserToken(
+            expected_users[0..*user_token_count as usize]
+                .iter()
+                .map(|user| (user.principal, user_tokens.clone()))
+                .collect(),
+        );
+        pic_setup
+            .old_backend
+            .update::<()>(
+                controller(),
+                "bulk_up",
+                encode_one(legacy_user_tokens).unwrap(),
+            )
+            .expect("Test setup error: Failed to set user tokens");
         // Create custom tokens
         let custom_tokens = vec![CustomToken {
             token: Token::Icrc(IcrcToken {
@@ -95,6 +124,89 @@ impl MigrationTestEnv {
                 .update::<()>(user.principal, "set_many_custom_tokens", &custom_tokens)
                 .expect("Test setup error: Failed to set user tokens");
         }
+        // Create pending bitcoin transactions, one per user.
+        for (index, user) in expected_users
+            .iter()
+            .take(*btc_pending_transactions_count as usize)
+            .enumerate()
+        {
+            pic_setup.old_backend.add_btc_pending_transaction(
+                user.principal,
+                vec![u8::try_from(index).expect("Test setup requested too many users"); 32],
+                vec![Utxo {
+                    outpoint: Outpoint {
+                        txid: vec![1; 32],
+                        vout: 0,
+                    },
+                    value: 1000,
+                    height: 100,
+                }],
+            );
+        }
+        // Freeze a bitcoin UTXO, one per user.
+        for user in expected_users.iter().take(*btc_frozen_utxos_count as usize) {
+            let freeze_request = BtcFreezeUtxosRequest {
+                outpoints: vec![Outpoint {
+                    txid: vec![2; 32],
+                    vout: 0,
+                }],
+            };
+            pic_setup
+                .old_backend
+                .update::<Result<(), BtcFreezeUtxosError>>(
+                    user.principal,
+                    "btc_freeze_utxos",
+                    freeze_request,
+                )
+                .expect("Test setup error: Failed to call btc_freeze_utxos")
+                .expect("Test setup error: Failed to freeze UTXO");
+        }
+        // Track a ckBTC withdrawal, one per user.
+        for (index, user) in expected_users
+            .iter()
+            .rev()
+            .take(*ckbtc_withdrawals_count as usize)
+            .enumerate()
+        {
+            let withdrawal_request = CkBtcAddWithdrawalRequest {
+                block_index: index as u64,
+                address: "bcrt1qpg7udjvq7gx2fp480pgt4hnhj3qc4nhrkstc33".to_string(),
+                amount_satoshis: 100_000,
+            };
+            pic_setup.old_backend.set_ckbtc_stand_in_withdrawal(
+                index as u64,
+                user.principal,
+                &withdrawal_request.address,
+                withdrawal_request.amount_satoshis,
+            );
+            pic_setup
+                .old_backend
+                .update::<Result<(), CkBtcAddWithdrawalError>>(
+                    user.principal,
+                    "ckbtc_add_withdrawal",
+                    withdrawal_request,
+                )
+                .expect("Test setup error: Failed to call ckbtc_add_withdrawal")
+                .expect("Test setup error: Failed to add withdrawal");
+        }
+        // Issue a fresh bitcoin address, one per user.
+        for user in expected_users
+            .iter()
+            .take(*btc_next_address_index_count as usize)
+        {
+            pic_setup
+                .old_backend
+                .update::<Result<BtcGetAddressResponse, BtcGetAddressError>>(
+                    user.principal,
+                    "btc_get_fresh_address",
+                    BtcGetAddressRequest {
+                        network: BtcNetwork::Regtest,
+                        address_type: None,
+                    },
+                )
+                .expect("Test setup error: Failed to call btc_get_fresh_address")
+                .expect("Test setup error: Failed to get a fresh address");
+        }
         pic_setup
     }
 
@@ -169,6 +281,10 @@ fn test_migration() {
         user_timestamps_count: 20,
         user_token_count: 10,
         custom_token_count: 5,
+        btc_pending_transactions_count: 7,
+        btc_frozen_utxos_count: 6,
+        ckbtc_withdrawals_count: 4,
+        btc_next_address_index_count: 3,
     };
     let pic_setup = MigrationTestEnv::new(&stats);
     // Test the migration.
@@ -320,6 +436,67 @@ fn test_migration() {
             pic_setup.step_migration();
         }
     }
+    // Should have started the pending bitcoin transactions migration.
+    {
+        pic_setup.assert_migration_progress_is(
+            MigrationProgress::MigratedBtcPendingTransactionsUpTo(None),
+        );
+    }
+    // Keep stepping until the pending bitcoin transactions have been migrated.
+    {
+        while let Some(MigrationReport {
+            progress: shared::types::MigrationProgress::MigratedBtcPendingTransactionsUpTo(_),
+            ..
+        }) = pic_setup.migration_state()
+        {
+            pic_setup.step_migration();
+        }
+    }
+    // Should have started the frozen bitcoin UTXOs migration.
+    {
+        pic_setup.assert_migration_progress_is(MigrationProgress::MigratedBtcFrozenUtxosUpTo(None));
+    }
+    // Keep stepping until the frozen bitcoin UTXOs have been migrated.
+    {
+        while let Some(MigrationReport {
+            progress: shared::types::MigrationProgress::MigratedBtcFrozenUtxosUpTo(_),
+            ..
+        }) = pic_setup.migration_state()
+        {
+            pic_setup.step_migration();
+        }
+    }
+    // Should have started the ckBTC withdrawals migration.
+    {
+        pic_setup
+            .assert_migration_progress_is(MigrationProgress::MigratedCkBtcWithdrawalsUpTo(None));
+    }
+    // Keep stepping until the ckBTC withdrawals have been migrated.
+    {
+        while let Some(MigrationReport {
+            progress: shared::types::MigrationProgress::MigratedCkBtcWithdrawalsUpTo(_),
+            ..
+        }) = pic_setup.migration_state()
+        {
+            pic_setup.step_migration();
+        }
+    }
+    // Should have started the next bitcoin address indices migration.
+    {
+        pic_setup.assert_migration_progress_is(
+            MigrationProgress::MigratedBtcNextAddressIndicesUpTo(None),
+        );
+    }
+    // Keep stepping until the next bitcoin address indices have been migrated.
+    {
+        while let Some(MigrationReport {
+            progress: shared::types::MigrationProgress::MigratedBtcNextAddressIndicesUpTo(_),
+            ..
+        }) = pic_setup.migration_state()
+        {
+            pic_setup.step_migration();
+        }
+    }
     // Should be checking the migration.
     {
         pic_setup.assert_migration_progress_is(MigrationProgress::CheckingDataMigration);
@@ -371,4 +548,53 @@ fn test_migration() {
             "Initially, there should be users in the old backend"
         );
     }
+    // The legacy user tokens are migrated intact and are read as ERC20 custom tokens, before and
+    // after the upgrade that moves them to the custom tokens.
+    {
+        // The first user, see `PicBackend::create_users`, has user tokens but no custom tokens.
+        let user = Principal::self_authenticating(0.to_string());
+        let user_tokens = vec![MOCK_TOKEN.clone(), ANOTHER_TOKEN.clone()];
+        let custom_tokens: Vec<CustomToken> =
+            user_tokens.iter().cloned().map(CustomToken::from).collect();
+        assert!(custom_tokens
+            .iter()
+            .all(|token| matches!(token.token, Token::Erc20(_))));
+        let assert_user_tokens_are_intact = |context: &str| {
+            assert_eq!(
+                pic_setup
+                    .new_backend
+                    .query::<Vec<UserToken>>(user, "list_user_tokens", ()),
+                Ok(user_tokens.clone()),
+                "The user tokens should be intact {context}"
+            );
+            assert_eq!(
+                pic_setup
+                    .new_backend
+                    .query::<Vec<CustomToken>>(user, "list_custom_tokens", ()),
+                Ok(custom_tokens.clone()),
+                "The user tokens should be ERC20 custom tokens {context}"
+            );
+        };
+        assert_user_tokens_are_intact("after the data migration");
+
+        pic_setup
+            .new_backend
+            .upgrade_latest_wasm(None)
+            .expect("Failed to upgrade the new backend");
+        for _ in 0..5 {
+            pic_setup.new_backend.pic.tick();
+        }
+        assert_eq!(
+            pic_setup
+                .new_backend
+                .query::<Stats>(controller(), "stats", ()),
+            Ok(Stats {
+                user_token_count: 0,
+                custom_token_count: 15,
+                ..stats
+            }),
+            "After the upgrade, the user tokens should be moved to the custom tokens"
+        );
+        assert_user_tokens_are_intact("after the upgrade");
+    }
 }
@@ -28,8 +28,13 @@ fn stats_returns_correct_number_of_users() {
     let expected_stats = Stats {
         user_profile_count: expected_users.len() as u64,
         user_timestamps_count: expected_users.len() as u64,
-        user_token_count: NUM_USERS_WITH_TOKENS as u64,
-        custom_token_count: 0,
+        // ERC20 tokens are stored as custom tokens.
+        user_token_count: 0,
+        custom_token_count: NUM_USERS_WITH_TOKENS as u64,
+        btc_pending_transactions_count: 0,
+        btc_frozen_utxos_count: 0,
+        ckbtc_withdrawals_count: 0,
+        btc_next_address_index_count: 0,
     };
 
     let caller = controller();
@@ -1,10 +1,11 @@
 use crate::upgrade::constants::BACKEND_V0_0_19_WASM_PATH;
 use crate::upgrade::types::UserTokenV0_0_19;
-use crate::utils::assertion::assert_tokens_data_eq;
+use crate::utils::assertion::{assert_custom_tokens_eq, assert_tokens_data_eq};
 use crate::utils::mock::{CALLER, WEENUS_CONTRACT_ADDRESS, WEENUS_DECIMALS, WEENUS_SYMBOL};
 use crate::utils::pocketic::{BackendBuilder, PicCanisterTrait};
 use candid::Principal;
 use lazy_static::lazy_static;
+use shared::types::custom_token::CustomToken;
 use shared::types::token::UserToken;
 use shared::types::TokenVersion;
 
@@ -22,7 +23,8 @@ lazy_static! {
         decimals: PRE_UPGRADE_TOKEN.decimals,
         symbol: PRE_UPGRADE_TOKEN.symbol.clone(),
         version: PRE_UPGRADE_TOKEN.version.clone(),
-        enabled: None
+        // Tokens stored without `enabled` were shown, so they are migrated as enabled.
+        enabled: Some(true)
     };
 }
 
@@ -55,6 +57,14 @@ fn test_upgrade_user_token() {
     let results_tokens = results.unwrap();
 
     assert_tokens_data_eq(&results_tokens, &expected_tokens);
+
+    // The token is migrated to the custom tokens
+    let custom_tokens = pic_setup.query::<Vec<CustomToken>>(caller, "list_custom_tokens", ());
+
+    let expected_custom_tokens: Vec<CustomToken> =
+        vec![CustomToken::from(POST_UPGRADE_TOKEN.clone())];
+
+    assert_custom_tokens_eq(custom_tokens.unwrap(), expected_custom_tokens);
 }
 
 #[test]
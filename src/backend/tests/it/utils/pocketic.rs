@@ -3,9 +3,14 @@ pub mod pic_canister;
 pub use pic_canister::PicCanisterTrait;
 
 use crate::utils::mock::CALLER;
-use candid::{encode_one, CandidType, Principal};
-use ic_cdk::api::management_canister::bitcoin::BitcoinNetwork;
-use pocket_ic::{CallError, PocketIc, PocketIcBuilder};
+use candid::{encode_args, encode_one, CandidType, Principal};
+use ic_cdk::api::management_canister::bitcoin::{Outpoint, Utxo};
+use pocket_ic::{CallError, PocketIc, PocketIcBuilder, WasmResult};
+use shared::types::bitcoin::{
+    BtcAddPendingTransactionErrorV2, BtcAddPendingTransactionRequest, BtcGetAddressError,
+    BtcGetAddressRequest, BtcGetAddressResponse, BtcNetwork,
+};
+use shared::types::ckbtc::CkBtcWithdrawalStatus;
 use shared::types::user_profile::{OisyUser, UserProfile};
 use shared::types::{Arg, CredentialType, InitArg, SupportedCredential};
 use std::fs::read;
@@ -19,6 +24,8 @@ use super::mock::{
 };
 
 const BACKEND_WASM: &str = "../../target/wasm32-unknown-unknown/release/backend.wasm";
+const BACKEND_STAND_IN_WASM: &str =
+    "../../target/stand-in/wasm32-unknown-unknown/release/backend.wasm";
 const DEFAULT_BITCOIN_WASM: &str = "../../ic-btc-canister.wasm.gz";
 const BITCOIN_CANISTER_ID: &str = "g4xu7-jiaaa-aaaan-aaaaq-cai";
 
@@ -30,7 +37,7 @@ const BITCOIN_CANISTER_ID: &str = "g4xu7-jiaaa-aaaan-aaaaq-cai";
 #[derive(CandidType)]
 struct BitcoinInitConfig {
     stability_threshold: Option<u64>,
-    network: Option<BitcoinNetwork>,
+    network: Option<BtcNetwork>,
     blocks_source: Option<String>,
     syncing: Option<String>,
     fees: Option<String>,
@@ -98,6 +105,12 @@ impl BackendBuilder {
     pub fn default_wasm_path() -> String {
         env::var("BACKEND_WASM_PATH").unwrap_or_else(|_| BACKEND_WASM.to_string())
     }
+    /// The Wasm file built with the `bitcoin-api-stand-in` and `ckbtc-minter-stand-in` features:
+    /// - If the environment variable `BACKEND_STAND_IN_WASM_PATH` is set, it will use that path.
+    /// - Otherwise, it will use the `BACKEND_STAND_IN_WASM` constant.
+    pub fn stand_in_wasm_path() -> String {
+        env::var("BACKEND_STAND_IN_WASM_PATH").unwrap_or_else(|_| BACKEND_STAND_IN_WASM.to_string())
+    }
     /// The default Wasm file to deploy the bitcoin canister:
     /// - If the environment variable `BITCOIN_CANISTER_WASM_FILE` is set, it will use that path.
     /// - Otherwise, it will use the `DEFAULT_BITCOIN_WASM` constant.
@@ -110,7 +123,7 @@ impl BackendBuilder {
     pub fn default_bitcoin_arg() -> Vec<u8> {
         let init_config = BitcoinInitConfig {
             stability_threshold: None,
-            network: Some(BitcoinNetwork::Regtest),
+            network: Some(BtcNetwork::Regtest),
             blocks_source: None,
             syncing: None,
             fees: None,
@@ -260,6 +273,14 @@ pub fn setup() -> PicBackend {
     BackendBuilder::default().deploy()
 }
 
+/// Deploys the backend built with the stand-in features, whose bitcoin data and ckBTC withdrawal
+/// statuses are set with the `set_*_stand_in_*` methods of `PicBackend`.
+pub fn setup_with_stand_ins() -> PicBackend {
+    BackendBuilder::default()
+        .with_wasm(&BackendBuilder::stand_in_wasm_path())
+        .deploy()
+}
+
 impl PicBackend {
     pub fn upgrade_latest_wasm(&self, encoded_arg: Option<Vec<u8>>) -> Result<(), String> {
         let backend_wasm_path =
@@ -322,6 +343,10 @@ pub(crate) fn init_arg() -> Arg {
         cfs_canister_id: Some(
             Principal::from_text(SIGNER_CANISTER_ID.to_string()).expect("wrong cfs canister id"),
         ),
+        btc_fee_percentiles: None,
+        ckbtc_minter_canister_id: None,
+        ckbtc_ledger_canister_id: None,
+        btc_network_policies: None,
     })
 }
 
@@ -364,4 +389,120 @@ impl PicBackend {
         }
         expected_users
     }
+
+    /// Adds a pending bitcoin transaction of a user with `btc_add_pending_transaction`.
+    ///
+    /// The transaction spends the given UTXOs, which the stand-in bitcoin API then serves at the
+    /// default regtest address of the user, so the backend must be deployed with
+    /// `setup_with_stand_ins()`. Returns the address.
+    pub fn add_btc_pending_transaction(
+        &self,
+        principal: Principal,
+        txid: Vec<u8>,
+        utxos: Vec<Utxo>,
+    ) -> String {
+        let address = self
+            .update::<Result<BtcGetAddressResponse, BtcGetAddressError>>(
+                principal,
+                "btc_get_address",
+                BtcGetAddressRequest {
+                    network: BtcNetwork::Regtest,
+                    address_type: None,
+                },
+            )
+            .expect("Test setup error: Failed to get the bitcoin address")
+            .expect("Test setup error: Failed to get the bitcoin address");
+        self.set_btc_stand_in_utxos(BtcNetwork::Regtest, &address.address, utxos.clone());
+        self.update::<Result<(), BtcAddPendingTransactionErrorV2>>(
+            principal,
+            "btc_add_pending_transaction_v2",
+            BtcAddPendingTransactionRequest {
+                txid,
+                utxos,
+                address: address.address.clone(),
+                network: BtcNetwork::Regtest,
+            },
+        )
+        .expect("Test setup error: Failed to add the pending transaction")
+        .expect("Test setup error: Failed to add the pending transaction");
+        address.address
+    }
+}
+
+// Stand-ins
+impl PicBackend {
+    /// Sets the tip height served by the stand-in bitcoin API.
+    pub fn set_btc_stand_in_tip_height(&self, network: BtcNetwork, tip_height: u32) {
+        self.update_stand_in(
+            "set_btc_stand_in_tip_height",
+            encode_args((network, tip_height)).unwrap(),
+        );
+    }
+
+    /// Sets the UTXOs of an address served by the stand-in bitcoin API.
+    pub fn set_btc_stand_in_utxos(&self, network: BtcNetwork, address: &str, utxos: Vec<Utxo>) {
+        self.update_stand_in(
+            "set_btc_stand_in_utxos",
+            encode_args((network, address, utxos)).unwrap(),
+        );
+    }
+
+    /// Marks a UTXO as spent in the block at the given height of the stand-in bitcoin API.
+    pub fn set_btc_stand_in_spent_height(
+        &self,
+        network: BtcNetwork,
+        outpoint: Outpoint,
+        height: u32,
+    ) {
+        self.update_stand_in(
+            "set_btc_stand_in_spent_height",
+            encode_args((network, outpoint, height)).unwrap(),
+        );
+    }
+
+    /// Sets the fee percentiles, in millisatoshi/byte, served by the stand-in bitcoin API.
+    pub fn set_btc_stand_in_fee_percentiles(&self, network: BtcNetwork, fee_percentiles: Vec<u64>) {
+        self.update_stand_in(
+            "set_btc_stand_in_fee_percentiles",
+            encode_args((network, fee_percentiles)).unwrap(),
+        );
+    }
+
+    /// Sets the status of a withdrawal reported by the stand-in ckBTC minter.
+    pub fn set_ckbtc_stand_in_withdrawal_status(
+        &self,
+        block_index: u64,
+        status: CkBtcWithdrawalStatus,
+    ) {
+        self.update_stand_in(
+            "set_ckbtc_stand_in_withdrawal_status",
+            encode_args((block_index, status)).unwrap(),
+        );
+    }
+
+    /// Sets the owner, destination address and amount of a withdrawal reported by the stand-in
+    /// ckBTC minter.
+    pub fn set_ckbtc_stand_in_withdrawal(
+        &self,
+        block_index: u64,
+        owner: Principal,
+        address: &str,
+        amount_satoshis: u64,
+    ) {
+        self.update_stand_in(
+            "set_ckbtc_stand_in_withdrawal",
+            encode_args((block_index, owner, address, amount_satoshis)).unwrap(),
+        );
+    }
+
+    fn update_stand_in(&self, method: &str, arg: Vec<u8>) {
+        let reply = self
+            .pic
+            .update_call(self.canister_id, controller(), method, arg)
+            .unwrap_or_else(|e| panic!("Test setup error: Failed to call {method}: {e:?}"));
+        assert!(
+            matches!(reply, WasmResult::Reply(_)),
+            "Test setup error: {method} was rejected: {reply:?}"
+        );
+    }
 }
@@ -0,0 +1,165 @@
+// This is an experimental feature to generate Rust binding from Candid.
+// You may want to manually adjust some of the types.
+#![allow(dead_code, unused_imports, clippy::missing_errors_doc)]
+use candid::{self, CandidType, Deserialize, Principal};
+use ic_cdk::api::call::CallResult as Result;
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct Account {
+    pub owner: Principal,
+    pub subaccount: Option<serde_bytes::ByteBuf>,
+}
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct EstimateWithdrawalFeeArg {
+    pub amount: Option<u64>,
+}
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct EstimateWithdrawalFeeRet {
+    pub minter_fee: u64,
+    pub bitcoin_fee: u64,
+}
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct GetBtcAddressArg {
+    pub owner: Option<Principal>,
+    pub subaccount: Option<serde_bytes::ByteBuf>,
+}
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct MinterInfo {
+    pub retrieve_btc_min_amount: u64,
+    pub min_confirmations: u32,
+    pub kyt_fee: u64,
+}
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct RetrieveBtcArgs {
+    pub address: String,
+    pub amount: u64,
+}
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct RetrieveBtcOk {
+    pub block_index: u64,
+}
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub enum RetrieveBtcError {
+    MalformedAddress(String),
+    GenericError {
+        error_message: String,
+        error_code: u64,
+    },
+    TemporarilyUnavailable(String),
+    AlreadyProcessing,
+    AmountTooLow(u64),
+    InsufficientFunds {
+        balance: u64,
+    },
+}
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct RetrieveBtcWithApprovalArgs {
+    pub from_subaccount: Option<serde_bytes::ByteBuf>,
+    pub address: String,
+    pub amount: u64,
+}
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub enum RetrieveBtcWithApprovalError {
+    MalformedAddress(String),
+    GenericError {
+        error_message: String,
+        error_code: u64,
+    },
+    TemporarilyUnavailable(String),
+    InsufficientAllowance {
+        allowance: u64,
+    },
+    AlreadyProcessing,
+    AmountTooLow(u64),
+    InsufficientFunds {
+        balance: u64,
+    },
+}
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct RetrieveBtcStatusV2Arg {
+    pub block_index: u64,
+}
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub enum ReimbursementReason {
+    CallFailed,
+    TaintedDestination {
+        kyt_fee: u64,
+        kyt_provider: Principal,
+    },
+}
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct ReimbursedDeposit {
+    pub account: Account,
+    pub mint_block_index: u64,
+    pub amount: u64,
+    pub reason: ReimbursementReason,
+}
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct ReimbursementRequest {
+    pub account: Account,
+    pub amount: u64,
+    pub reason: ReimbursementReason,
+}
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub enum RetrieveBtcStatusV2 {
+    Signing,
+    Confirmed { txid: serde_bytes::ByteBuf },
+    Sending { txid: serde_bytes::ByteBuf },
+    AmountTooLow,
+    WillReimburse(ReimbursementRequest),
+    Unknown,
+    Submitted { txid: serde_bytes::ByteBuf },
+    Reimbursed(ReimbursedDeposit),
+    Pending,
+}
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct BtcRetrievalStatusV2 {
+    pub block_index: u64,
+    pub status_v2: Option<RetrieveBtcStatusV2>,
+}
+
+pub struct Service(pub Principal);
+impl Service {
+    pub async fn estimate_withdrawal_fee(
+        &self,
+        arg0: &EstimateWithdrawalFeeArg,
+    ) -> Result<(EstimateWithdrawalFeeRet,)> {
+        ic_cdk::call(self.0, "estimate_withdrawal_fee", (arg0,)).await
+    }
+    pub async fn get_btc_address(&self, arg0: &GetBtcAddressArg) -> Result<(String,)> {
+        ic_cdk::call(self.0, "get_btc_address", (arg0,)).await
+    }
+    pub async fn get_deposit_fee(&self) -> Result<(u64,)> {
+        ic_cdk::call(self.0, "get_deposit_fee", ()).await
+    }
+    pub async fn get_minter_info(&self) -> Result<(MinterInfo,)> {
+        ic_cdk::call(self.0, "get_minter_info", ()).await
+    }
+    pub async fn get_withdrawal_account(&self) -> Result<(Account,)> {
+        ic_cdk::call(self.0, "get_withdrawal_account", ()).await
+    }
+    pub async fn retrieve_btc(
+        &self,
+        arg0: &RetrieveBtcArgs,
+    ) -> Result<(std::result::Result<RetrieveBtcOk, RetrieveBtcError>,)> {
+        ic_cdk::call(self.0, "retrieve_btc", (arg0,)).await
+    }
+    pub async fn retrieve_btc_status_v2(
+        &self,
+        arg0: &RetrieveBtcStatusV2Arg,
+    ) -> Result<(RetrieveBtcStatusV2,)> {
+        ic_cdk::call(self.0, "retrieve_btc_status_v2", (arg0,)).await
+    }
+    pub async fn retrieve_btc_status_v2_by_account(
+        &self,
+        arg0: &Option<Account>,
+    ) -> Result<(Vec<BtcRetrievalStatusV2>,)> {
+        ic_cdk::call(self.0, "retrieve_btc_status_v2_by_account", (arg0,)).await
+    }
+    pub async fn retrieve_btc_with_approval(
+        &self,
+        arg0: &RetrieveBtcWithApprovalArgs,
+    ) -> Result<(std::result::Result<RetrieveBtcOk, RetrieveBtcWithApprovalError>,)> {
+        ic_cdk::call(self.0, "retrieve_btc_with_approval", (arg0,)).await
+    }
+}
@@ -1,7 +1,13 @@
 import type {
 	AllowSigningError,
 	BtcAddPendingTransactionError,
-	SelectedUtxosFeeError
+	BtcAddPendingTransactionErrorV2,
+	BtcAddressError,
+	BtcGetPendingTransactionsError,
+	BtcInputError,
+	BtcOutputError,
+	SelectedUtxosFeeError,
+	SelectedUtxosFeeErrorV2
 } from '$declarations/backend/backend.did';
 import { CanisterInternalError } from '$lib/canisters/errors';
 import { mapIcrc2ApproveError, type ApproveError } from '@dfinity/ledger-icp';
@@ -32,6 +38,163 @@ export const mapBtcSelectUserUtxosFeeError = (
 	return new CanisterInternalError('Unknown BtcSelectUserUtxosFeeError');
 };
 
+const mapBtcAddressError = (err: BtcAddressError): string => {
+	if ('NetworkMismatch' in err) {
+		return `The address ${err.NetworkMismatch.address} is not an address of the selected network.`;
+	}
+
+	if ('NotOwned' in err) {
+		return `The address ${err.NotOwned.address} is not an address of the user.`;
+	}
+
+	if ('Malformed' in err) {
+		return `The address ${err.Malformed.address} is not a valid bitcoin address.`;
+	}
+
+	return 'Unknown BtcAddressError';
+};
+
+const mapBtcInputError = (err: BtcInputError): string => {
+	if ('NoInputs' in err) {
+		return 'No utxos were given to spend.';
+	}
+
+	if ('Duplicated' in err) {
+		return 'The same utxo was given more than once.';
+	}
+
+	if ('NotFound' in err) {
+		return 'A given utxo is not a utxo of the user.';
+	}
+
+	if ('Frozen' in err) {
+		return 'A given utxo is frozen - unfreeze it to spend it.';
+	}
+
+	return 'Unknown BtcInputError';
+};
+
+const mapBtcOutputError = (err: BtcOutputError): string => {
+	if ('NoOutputs' in err) {
+		return 'The transaction has no outputs.';
+	}
+
+	if ('SweepWithManyOutputs' in err) {
+		return 'A sweep transaction can only have one output.';
+	}
+
+	if ('AmountOverflow' in err) {
+		return 'The amounts of the outputs are too large.';
+	}
+
+	return 'Unknown BtcOutputError';
+};
+
+// The failures of the chain fusion signer, the bitcoin API and the config, shared by the errors of
+// the bitcoin endpoints.
+const mapBtcServiceError = (
+	err: BtcAddPendingTransactionErrorV2 | BtcGetPendingTransactionsError | SelectedUtxosFeeErrorV2
+): CanisterInternalError | undefined => {
+	if ('Misconfigured' in err) {
+		return new CanisterInternalError(`Bitcoin is misconfigured: ${err.Misconfigured.msg}`);
+	}
+
+	if ('SignerUnavailable' in err) {
+		return new CanisterInternalError(
+			`The signer cannot be contacted, please try again later: ${err.SignerUnavailable.msg}`
+		);
+	}
+
+	if ('BitcoinApiRejected' in err) {
+		return new CanisterInternalError(
+			`The bitcoin API rejected the call, please try again later: ${err.BitcoinApiRejected.msg}`
+		);
+	}
+
+	if ('InternalError' in err) {
+		return new CanisterInternalError(err.InternalError.msg);
+	}
+
+	return undefined;
+};
+
+export const mapBtcPendingTransactionErrorV2 = (
+	err: BtcAddPendingTransactionErrorV2
+): CanisterInternalError => {
+	if ('InvalidAddress' in err) {
+		return new CanisterInternalError(mapBtcAddressError(err.InvalidAddress));
+	}
+
+	if ('UtxoNotFound' in err) {
+		return new CanisterInternalError(
+			'The transaction spends a utxo that is not a current utxo of the user.'
+		);
+	}
+
+	if ('QuotaExceeded' in err) {
+		return new CanisterInternalError(
+			`Too many pending transactions - wait for some to be confirmed: ${err.QuotaExceeded.msg}`
+		);
+	}
+
+	return (
+		mapBtcServiceError(err) ?? new CanisterInternalError('Unknown BtcAddPendingTransactionErrorV2')
+	);
+};
+
+export const mapBtcGetPendingTransactionsError = (
+	err: BtcGetPendingTransactionsError
+): CanisterInternalError => {
+	if ('InvalidAddress' in err) {
+		return new CanisterInternalError(mapBtcAddressError(err.InvalidAddress));
+	}
+
+	return (
+		mapBtcServiceError(err) ?? new CanisterInternalError('Unknown BtcGetPendingTransactionsError')
+	);
+};
+
+export const mapBtcSelectUserUtxosFeeErrorV2 = (
+	err: SelectedUtxosFeeErrorV2
+): CanisterInternalError => {
+	if ('PendingTransactions' in err) {
+		return new CanisterInternalError(
+			'Selecting utxos fee is not possible - pending transactions found.'
+		);
+	}
+
+	if ('InsufficientFunds' in err) {
+		return new CanisterInternalError(
+			'Selecting utxos fee is not possible - the balance does not cover the amount and the fee.'
+		);
+	}
+
+	if ('InvalidAddress' in err) {
+		return new CanisterInternalError(mapBtcAddressError(err.InvalidAddress));
+	}
+
+	if ('InvalidInput' in err) {
+		return new CanisterInternalError(mapBtcInputError(err.InvalidInput));
+	}
+
+	if ('InvalidOutput' in err) {
+		return new CanisterInternalError(mapBtcOutputError(err.InvalidOutput));
+	}
+
+	if ('UnsupportedAddressType' in err) {
+		return new CanisterInternalError('The address type is not supported.');
+	}
+
+	if ('FeeRateOutOfBounds' in err) {
+		const { min_satoshi_per_vbyte, max_satoshi_per_vbyte } = err.FeeRateOutOfBounds;
+		return new CanisterInternalError(
+			`The fee rate must be between ${min_satoshi_per_vbyte} and ${max_satoshi_per_vbyte} satoshis per vbyte.`
+		);
+	}
+
+	return mapBtcServiceError(err) ?? new CanisterInternalError('Unknown SelectedUtxosFeeErrorV2');
+};
+
 export const mapAllowSigningError = (
 	err: AllowSigningError
 ): CanisterInternalError | ApproveError => {
@@ -1,4 +1,9 @@
-use crate::types::custom_token::{CustomToken, CustomTokenId, Token};
+use crate::types::bitcoin::{
+    BtcAddressError, BtcAddressType, BtcCoinSelection, BtcFeePercentiles, BtcFeePolicy,
+};
+use crate::types::custom_token::{
+    CustomToken, CustomTokenId, ErcToken, ErcTokenId, SplTokenId, Token,
+};
 use crate::types::token::UserToken;
 use crate::types::user_profile::{
     AddUserCredentialError, OisyUser, StoredUserProfile, UserCredential, UserProfile,
@@ -8,6 +13,7 @@ use crate::types::{
     Timestamp, TokenVersion, Version,
 };
 use candid::Principal;
+use ethers_core::abi::ethereum_types::H160;
 use ic_canister_sig_creation::{extract_raw_root_pk_from_der, IC_ROOT_PK_DER};
 use std::collections::BTreeMap;
 use std::fmt;
@@ -18,6 +24,70 @@ impl From<&Token> for CustomTokenId {
     fn from(token: &Token) -> Self {
         match token {
             Token::Icrc(token) => CustomTokenId::Icrc(token.ledger_id),
+            Token::Erc20(token) => CustomTokenId::Erc20(ErcTokenId {
+                chain_id: token.chain_id,
+                contract_address: erc20_token_id_address(&token.contract_address),
+            }),
+            // The mint is an account, whose address is unique whatever the token program.
+            Token::Spl(token) => CustomTokenId::Spl(SplTokenId {
+                network: token.network,
+                mint_address: token.mint_address.clone(),
+            }),
+        }
+    }
+}
+
+/// The contract address of an ERC20 token ID: the parsed address in lower case with the `0x`
+/// prefix, so that the spellings of an address are the same token. The addresses that can't be
+/// parsed are never stored, they are only compared in lower case.
+fn erc20_token_id_address(contract_address: &str) -> String {
+    contract_address.parse::<H160>().map_or_else(
+        |_| contract_address.to_lowercase(),
+        |address| format!("{address:#x}"),
+    )
+}
+
+/// An ERC20 token of the former `user_token` list is stored as a custom token.
+///
+/// Tokens stored before `enabled` was introduced have no `enabled` and are shown, so they are
+/// enabled.
+impl From<UserToken> for CustomToken {
+    fn from(token: UserToken) -> Self {
+        let UserToken {
+            contract_address,
+            chain_id,
+            symbol,
+            decimals,
+            version,
+            enabled,
+        } = token;
+        CustomToken {
+            token: Token::Erc20(ErcToken {
+                chain_id,
+                contract_address,
+                symbol,
+                decimals,
+            }),
+            enabled: enabled.unwrap_or(true),
+            version,
+        }
+    }
+}
+
+impl CustomToken {
+    /// The token in the shape of the `user_token` endpoints, if it is an ERC20 token.
+    #[must_use]
+    pub fn to_user_token(&self) -> Option<UserToken> {
+        match &self.token {
+            Token::Erc20(token) => Some(UserToken {
+                contract_address: token.contract_address.clone(),
+                chain_id: token.chain_id,
+                symbol: token.symbol.clone(),
+                decimals: token.decimals,
+                version: self.version,
+                enabled: Some(self.enabled),
+            }),
+            Token::Icrc(_) | Token::Spl(_) => None,
         }
     }
 }
@@ -53,6 +123,10 @@ impl From<InitArg> for Config {
             ic_root_key_der,
             api,
             cfs_canister_id,
+            btc_fee_percentiles,
+            ckbtc_minter_canister_id,
+            ckbtc_ledger_canister_id,
+            btc_network_policies,
         } = arg;
         let ic_root_key_raw = match extract_raw_root_pk_from_der(
             &ic_root_key_der.unwrap_or_else(|| IC_ROOT_PK_DER.to_vec()),
@@ -67,6 +141,10 @@ impl From<InitArg> for Config {
             supported_credentials,
             ic_root_key_raw: Some(ic_root_key_raw),
             api,
+            btc_fee_percentiles,
+            ckbtc_minter_canister_id,
+            ckbtc_ledger_canister_id,
+            btc_network_policies,
         }
     }
 }
@@ -193,6 +271,56 @@ impl From<&Migration> for MigrationReport {
     }
 }
 
+impl Default for BtcAddressType {
+    fn default() -> Self {
+        Self::P2wpkh
+    }
+}
+
+impl fmt::Display for BtcAddressError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BtcAddressError::Malformed { address } => {
+                write!(f, "Invalid bitcoin address {address}")
+            }
+            BtcAddressError::NetworkMismatch { address, network } => {
+                write!(
+                    f,
+                    "Bitcoin address {address} is not an address of {network:?}"
+                )
+            }
+            BtcAddressError::NotOwned { address } => {
+                write!(
+                    f,
+                    "Bitcoin address {address} is not an address of the caller"
+                )
+            }
+        }
+    }
+}
+
+impl Default for BtcCoinSelection {
+    fn default() -> Self {
+        Self::BranchAndBound
+    }
+}
+
+impl Default for BtcFeePolicy {
+    fn default() -> Self {
+        Self::Standard
+    }
+}
+
+impl Default for BtcFeePercentiles {
+    fn default() -> Self {
+        Self {
+            slow: 25,
+            standard: 50,
+            fast: 75,
+        }
+    }
+}
+
 impl Default for ApiEnabled {
     fn default() -> Self {
         Self::Enabled
@@ -208,6 +336,22 @@ impl ApiEnabled {
         matches!(self, Self::Enabled)
     }
 }
+#[test]
+fn test_erc20_token_id_ignores_the_spelling_of_the_address() {
+    let token_id = |contract_address: &str| {
+        CustomTokenId::from(&Token::Erc20(ErcToken {
+            chain_id: 1,
+            contract_address: contract_address.to_string(),
+            symbol: None,
+            decimals: None,
+        }))
+    };
+    let address = "0x7439E9Bb6D8a84dd3A23fe621A30F95403F87fB9";
+    assert!(token_id(address) == token_id(&address.to_lowercase()));
+    assert!(token_id(address) == token_id(&address[2..]));
+    assert!(token_id(address) != token_id("0x7439E9Bb6D8a84dd3A23fe621A30F95403F87fB8"));
+}
+
 #[test]
 fn test_api_enabled() {
     assert_eq!(ApiEnabled::Enabled.readable(), true);
@@ -251,6 +395,18 @@ impl MigrationProgress {
                 MigrationProgress::MigratedUserProfilesUpTo(None)
             }
             MigrationProgress::MigratedUserProfilesUpTo(_) => {
+                MigrationProgress::MigratedBtcPendingTransactionsUpTo(None)
+            }
+            MigrationProgress::MigratedBtcPendingTransactionsUpTo(_) => {
+                MigrationProgress::MigratedBtcFrozenUtxosUpTo(None)
+            }
+            MigrationProgress::MigratedBtcFrozenUtxosUpTo(_) => {
+                MigrationProgress::MigratedCkBtcWithdrawalsUpTo(None)
+            }
+            MigrationProgress::MigratedCkBtcWithdrawalsUpTo(_) => {
+                MigrationProgress::MigratedBtcNextAddressIndicesUpTo(None)
+            }
+            MigrationProgress::MigratedBtcNextAddressIndicesUpTo(_) => {
                 MigrationProgress::CheckingDataMigration
             }
             MigrationProgress::CheckingDataMigration => MigrationProgress::UnlockingTarget,
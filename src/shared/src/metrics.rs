@@ -12,16 +12,32 @@ use serde_bytes::ByteBuf;
 const WASM_PAGE_SIZE: u64 = 65536;
 const GIBIBYTE: u32 = 1 << 30;
 
-/// Returns the metrics in the Prometheus format.
+/// A metric of the canister state, reported along with the health metrics.
+pub enum CanisterMetric {
+    /// A value that only increases, such as a number of requests.
+    Counter {
+        name: &'static str,
+        value: u64,
+        help: &'static str,
+    },
+    /// A value that can go up and down, such as a number of entries.
+    Gauge {
+        name: &'static str,
+        value: u64,
+        help: &'static str,
+    },
+}
+
+/// Returns the health metrics and the given canister metrics in the Prometheus format.
 #[must_use]
-pub fn get_metrics() -> HttpResponse {
+pub fn get_metrics(canister_metrics: &[CanisterMetric]) -> HttpResponse {
     let now = ic_cdk::api::time();
     let mut writer = MetricsEncoder::new(
         vec![],
         i64::try_from(now / 1_000_000)
             .unwrap_or_else(|_| unreachable!("u64::MAX / 1_000_000 is smaller than i64::MAX")),
     );
-    match encode_metrics(&mut writer) {
+    match encode_metrics(&mut writer, canister_metrics) {
         Ok(()) => {
             let body = writer.into_inner();
             HttpResponse {
@@ -45,7 +61,11 @@ pub fn get_metrics() -> HttpResponse {
 }
 
 /// Encodes the metrics in the Prometheus format.
-fn encode_metrics(w: &mut MetricsEncoder<Vec<u8>>) -> std::io::Result<()> {
+#[allow(clippy::cast_precision_loss)]
+fn encode_metrics(
+    w: &mut MetricsEncoder<Vec<u8>>,
+    canister_metrics: &[CanisterMetric],
+) -> std::io::Result<()> {
     w.encode_gauge(
         "ic_eth_wallet_stable_memory_size_gib",
         gibibytes(stable_memory_size_bytes()),
@@ -56,6 +76,16 @@ fn encode_metrics(w: &mut MetricsEncoder<Vec<u8>>) -> std::io::Result<()> {
         gibibytes(wasm_memory_size_bytes()),
         "Amount of wasm memory used by this canister, in GiB",
     )?;
+    for metric in canister_metrics {
+        match metric {
+            CanisterMetric::Counter { name, value, help } => {
+                w.encode_counter(name, *value as f64, help)?;
+            }
+            CanisterMetric::Gauge { name, value, help } => {
+                w.encode_gauge(name, *value as f64, help)?;
+            }
+        }
+    }
     Ok(())
 }
 
@@ -30,6 +30,15 @@ pub struct InitArg {
     pub api: Option<Guards>,
     /// Chain Fussion Signer canister id. Used to derive the bitcoin address in `btc_select_user_utxos_fee`
     pub cfs_canister_id: Option<Principal>,
+    /// Percentiles of the recent bitcoin fee rates used by the fee tiers. Defaults to 25, 50 and 75.
+    pub btc_fee_percentiles: Option<bitcoin::BtcFeePercentiles>,
+    /// ckBTC minter canister id. Used by the `ckbtc_*` endpoints.
+    pub ckbtc_minter_canister_id: Option<Principal>,
+    /// ckBTC ledger canister id. Used by `ckbtc_add_withdrawal` to read the burns of withdrawals.
+    pub ckbtc_ledger_canister_id: Option<Principal>,
+    /// How the UTXOs of each bitcoin network are fetched. The networks without a policy
+    /// keep their defaults, such as confirmations capped to 1 on regtest.
+    pub btc_network_policies: Option<Vec<bitcoin::BtcNetworkPolicy>>,
 }
 
 #[derive(CandidType, Deserialize, Eq, PartialEq, Debug, Copy, Clone)]
@@ -74,6 +83,15 @@ pub struct Config {
     pub api: Option<Guards>,
     /// Chain Fussion Signer canister id. Used to derive the bitcoin address in `btc_select_user_utxos_fee`
     pub cfs_canister_id: Option<Principal>,
+    /// Percentiles of the recent bitcoin fee rates used by the fee tiers. Defaults to 25, 50 and 75.
+    pub btc_fee_percentiles: Option<bitcoin::BtcFeePercentiles>,
+    /// ckBTC minter canister id. Used by the `ckbtc_*` endpoints.
+    pub ckbtc_minter_canister_id: Option<Principal>,
+    /// ckBTC ledger canister id. Used by `ckbtc_add_withdrawal` to read the burns of withdrawals.
+    pub ckbtc_ledger_canister_id: Option<Principal>,
+    /// How the UTXOs of each bitcoin network are fetched. The networks without a policy
+    /// keep their defaults, such as confirmations capped to 1 on regtest.
+    pub btc_network_policies: Option<Vec<bitcoin::BtcNetworkPolicy>>,
 }
 
 pub mod transaction {
@@ -134,11 +152,14 @@ pub mod token {
 
 /// Extendable custom user defined tokens
 pub mod custom_token {
-    use crate::types::Version;
+    use crate::types::{token::ChainId, Version};
     use candid::{CandidType, Deserialize, Principal};
 
     pub type LedgerId = Principal;
     pub type IndexId = Principal;
+    pub type ContractAddress = String;
+    /// A Solana address, base58 encoded.
+    pub type SolanaAddress = String;
 
     #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
     pub struct IcrcToken {
@@ -146,9 +167,36 @@ pub mod custom_token {
         pub index_id: Option<IndexId>,
     }
 
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct ErcToken {
+        pub chain_id: ChainId,
+        pub contract_address: ContractAddress,
+        pub symbol: Option<String>,
+        pub decimals: Option<u8>,
+    }
+
+    /// The Solana clusters.
+    #[derive(CandidType, Deserialize, Clone, Copy, Eq, PartialEq, Debug)]
+    pub enum SolanaNetwork {
+        Mainnet,
+        Devnet,
+        Testnet,
+        Local,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct SplToken {
+        pub network: SolanaNetwork,
+        pub mint_address: SolanaAddress,
+        /// The program that owns the mint, if not the original SPL token program, e.g. Token-2022.
+        pub token_program: Option<SolanaAddress>,
+    }
+
     #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
     pub enum Token {
         Icrc(IcrcToken),
+        Erc20(ErcToken),
+        Spl(SplToken),
     }
 
     #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
@@ -158,34 +206,424 @@ pub mod custom_token {
         pub version: Option<Version>,
     }
 
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct ErcTokenId {
+        pub chain_id: ChainId,
+        /// The contract address, in lower case with the `0x` prefix.
+        pub contract_address: ContractAddress,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct SplTokenId {
+        pub network: SolanaNetwork,
+        pub mint_address: SolanaAddress,
+    }
+
     #[derive(CandidType, Deserialize, Clone, Eq, PartialEq)]
     pub enum CustomTokenId {
         Icrc(LedgerId),
+        Erc20(ErcTokenId),
+        Spl(SplTokenId),
     }
 }
 
 pub mod bitcoin {
     use candid::CandidType;
-    use ic_cdk::api::management_canister::bitcoin::{BitcoinNetwork, Utxo};
+    use ic_cdk::api::management_canister::bitcoin::{Outpoint, Utxo};
     use serde::Deserialize;
 
+    /// The bitcoin networks supported by the backend, those of the bitcoin API of the management
+    /// canister, with the same Candid names.
+    #[derive(CandidType, Deserialize, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+    pub enum BtcNetwork {
+        #[serde(rename = "mainnet")]
+        Mainnet,
+        /// Testnet4 (BIP-94), which the bitcoin API of the IC serves as `testnet`.
+        #[serde(rename = "testnet")]
+        Testnet,
+        #[serde(rename = "regtest")]
+        Regtest,
+    }
+
+    /// How the backend fetches and spends the UTXOs of a bitcoin network.
+    #[derive(CandidType, Deserialize, Clone, Copy, Eq, PartialEq, Debug)]
+    pub struct BtcNetworkPolicy {
+        pub network: BtcNetwork,
+        /// Caps the confirmations required from the fetched UTXOs, for networks where blocks are
+        /// rare such as a local regtest. Defaults to 1 on regtest and to no cap on the other
+        /// networks.
+        pub max_min_confirmations: Option<u32>,
+        /// The maximum number of unconfirmed pending transactions that a transaction spending
+        /// unconfirmed change can descend from. Defaults to 2.
+        pub max_unconfirmed_chain_depth: Option<u32>,
+    }
+
+    /// The type of the bitcoin addresses of a user.
+    #[derive(CandidType, Deserialize, Clone, Copy, Eq, PartialEq, Debug)]
+    pub enum BtcAddressType {
+        /// Native SegWit v0 address.
+        P2wpkh,
+        /// Taproot address with key-path spending only (BIP-86). Not supported yet, as it needs
+        /// threshold Schnorr signatures: the requests for it fail with `UnsupportedAddressType`.
+        P2tr,
+    }
+
+    /// Why a bitcoin address given in a request is rejected.
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub enum BtcAddressError {
+        /// The address is not a bitcoin address.
+        Malformed { address: String },
+        /// The address is a bitcoin address of another network than the requested one.
+        NetworkMismatch {
+            address: String,
+            network: BtcNetwork,
+        },
+        /// The address is not an address of the caller, where one is required.
+        NotOwned { address: String },
+    }
+
+    /// Why the inputs chosen by the user (coin control) are rejected.
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub enum BtcInputError {
+        /// No input was chosen.
+        NoInputs,
+        /// The outpoint is not a UTXO of the addresses.
+        NotFound { outpoint: Outpoint },
+        /// The UTXO is frozen by the user.
+        Frozen { outpoint: Outpoint },
+        /// The outpoint is chosen more than once.
+        Duplicated { outpoint: Outpoint },
+    }
+
+    /// Why the outputs of a transaction are rejected.
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub enum BtcOutputError {
+        /// The transaction has no output.
+        NoOutputs,
+        /// The sum of the amounts of the outputs overflows.
+        AmountOverflow,
+        /// A sweep transaction has more than one output.
+        SweepWithManyOutputs,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct BtcGetAddressRequest {
+        pub network: BtcNetwork,
+        /// Defaults to `BtcAddressType::P2wpkh`.
+        pub address_type: Option<BtcAddressType>,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct BtcGetAddressResponse {
+        pub address: String,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub enum BtcGetAddressError {
+        InternalError {
+            msg: String,
+        },
+        /// The caller has the maximum number of fresh addresses.
+        QuotaExceeded {
+            msg: String,
+        },
+        SignerUnavailable {
+            msg: String,
+        },
+        Misconfigured {
+            msg: String,
+        },
+        /// The backend can't sign for addresses of the requested type.
+        UnsupportedAddressType,
+        /// The backend doesn't issue fresh addresses, which the signer can't sign for yet.
+        FreshAddressesUnsupported,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct BtcGetBalanceRequest {
+        pub network: BtcNetwork,
+        /// Defaults to `BtcAddressType::P2wpkh`.
+        pub address_type: Option<BtcAddressType>,
+        /// The number of confirmations of the UTXOs counted as confirmed. Defaults to 6.
+        pub min_confirmations: Option<u32>,
+    }
+
+    /// The balance of a bitcoin address, in satoshi.
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct BtcGetBalanceResponse {
+        /// The value of the UTXOs with at least `min_confirmations` confirmations.
+        pub confirmed_satoshis: u64,
+        /// The value of the UTXOs with fewer than `min_confirmations` confirmations.
+        pub unconfirmed_satoshis: u64,
+        /// The value of the UTXOs spent by pending transactions or frozen.
+        pub locked_satoshis: u64,
+        /// The value of the confirmed UTXOs that are neither spent by pending transactions nor
+        /// frozen.
+        pub spendable_satoshis: u64,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub enum BtcGetBalanceError {
+        InternalError { msg: String },
+        SignerUnavailable { msg: String },
+        BitcoinApiRejected { code: u32, msg: String },
+        Misconfigured { msg: String },
+        UnsupportedAddressType,
+    }
+
+    /// The UTXOs that a user never wants to spend, e.g. for privacy.
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct BtcFreezeUtxosRequest {
+        pub outpoints: Vec<Outpoint>,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub enum BtcFreezeUtxosError {
+        InternalError {
+            msg: String,
+        },
+        /// The caller would have more frozen UTXOs than allowed.
+        QuotaExceeded {
+            msg: String,
+        },
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct BtcTxOutput {
+        pub destination_address: String,
+        pub sent_satoshis: u64,
+    }
+
     #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
     pub struct SelectedUtxosFeeRequest {
         pub amount_satoshis: u64,
-        pub network: BitcoinNetwork,
+        pub network: BtcNetwork,
         pub min_confirmations: Option<u32>,
+        /// The outputs of a batch transaction. When set, `amount_satoshis` is ignored.
+        pub outputs: Option<Vec<BtcTxOutput>>,
+        /// Defaults to `BtcFeePolicy::Standard`.
+        pub fee_policy: Option<BtcFeePolicy>,
+        /// The type of the address spending its UTXOs. Defaults to `BtcAddressType::P2wpkh`.
+        pub address_type: Option<BtcAddressType>,
+        /// Defaults to `BtcCoinSelection::BranchAndBound`.
+        pub coin_selection: Option<BtcCoinSelection>,
+        /// Sends the maximum amount: selects all the UTXOs that are not spent by pending
+        /// transactions and sends their value, minus the fee, to a single output without change.
+        /// The amount of the request is ignored. Defaults to `false`.
+        pub sweep: Option<bool>,
+        /// The outpoints of the UTXOs to spend, chosen by the user (coin control). All of them
+        /// are spent, and `coin_selection` is ignored. They must be UTXOs of the address that are
+        /// neither frozen nor spent by pending transactions.
+        pub inputs: Option<Vec<Outpoint>>,
+        /// Also spends the unconfirmed change of the pending transactions of the addresses, as
+        /// long as the chain of unconfirmed transactions is not too deep. The addresses can then
+        /// have pending transactions, whose UTXOs are not selected. Defaults to `false`.
+        pub spend_unconfirmed_change: Option<bool>,
     }
 
     #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
     pub struct SelectedUtxosFeeResponse {
         pub utxos: Vec<Utxo>,
         pub fee_satoshis: u64,
+        /// The amount sent by the transaction, excluding the change. When sweeping, this is the
+        /// maximum amount that can be sent.
+        pub amount_satoshis: u64,
+        /// The outputs paid by the transaction, excluding the change.
+        pub outputs: Vec<BtcTxOutput>,
+        /// The value of the change output, if the transaction has one. Change below the dust
+        /// limit of the address is not sent back, it is added to the fee.
+        pub change_satoshis: Option<u64>,
+        /// The fee rate used to compute the fee.
+        pub fee_millisatoshi_per_vbyte: u64,
+        /// The estimated size of the transaction.
+        pub vsize: u64,
+        /// Whether the transaction spends the unconfirmed change of a pending transaction, so that
+        /// it can't be confirmed before it.
+        pub spends_unconfirmed_change: bool,
     }
 
+    /// The error of `btc_select_user_utxos_fee`, as in its first version.
+    ///
+    /// The failures other than pending transactions are internal errors, see
+    /// `SelectedUtxosFeeErrorV2` of `btc_select_user_utxos_fee_v2` for the structured ones.
     #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
     pub enum SelectedUtxosFeeError {
+        PendingTransactions,
         InternalError { msg: String },
+    }
+
+    /// The error of `btc_select_user_utxos_fee_v2`.
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub enum SelectedUtxosFeeErrorV2 {
+        InternalError {
+            msg: String,
+        },
+        PendingTransactions,
+        /// The UTXOs of the addresses don't cover the amount and the fee.
+        InsufficientFunds,
+        InvalidAddress(BtcAddressError),
+        InvalidInput(BtcInputError),
+        InvalidOutput(BtcOutputError),
+        /// The explicit fee rate of `BtcFeePolicy::Custom` is out of the accepted bounds, in
+        /// satoshi/vbyte.
+        FeeRateOutOfBounds {
+            min_satoshi_per_vbyte: u64,
+            max_satoshi_per_vbyte: u64,
+        },
+        /// The chain fusion signer, which derives the addresses of the caller, could not be
+        /// reached. Retrying later might succeed.
+        SignerUnavailable {
+            msg: String,
+        },
+        /// The bitcoin API rejected the call, with the code of the rejection, see
+        /// `ic_cdk::api::call::RejectionCode`. Transient rejections are worth retrying.
+        BitcoinApiRejected {
+            code: u32,
+            msg: String,
+        },
+        /// The canister is not configured for the request, e.g. it has no chain fusion signer or
+        /// it does not serve the network.
+        Misconfigured {
+            msg: String,
+        },
+        /// See `BtcGetAddressError::UnsupportedAddressType`.
+        UnsupportedAddressType,
+    }
+
+    /// How the fee rate of a bitcoin transaction is chosen.
+    ///
+    /// The tiers map to percentiles of the fee rates of recent transactions, see `BtcFeePercentiles`.
+    #[derive(CandidType, Deserialize, Clone, Copy, Eq, PartialEq, Debug)]
+    pub enum BtcFeePolicy {
+        Slow,
+        Standard,
+        Fast,
+        /// An explicit fee rate.
+        Custom {
+            satoshi_per_vbyte: u64,
+        },
+    }
+
+    /// How the UTXOs spent by a bitcoin transaction are selected.
+    #[derive(CandidType, Deserialize, Clone, Copy, Eq, PartialEq, Debug)]
+    pub enum BtcCoinSelection {
+        /// The algorithm of the ckBTC minter: the smallest UTXO covering the remaining target or,
+        /// failing that, the largest UTXO, until the target is reached.
+        Greedy,
+        /// Searches for the selection without change output that wastes the least in fees,
+        /// falling back to `Greedy` if there is none.
+        BranchAndBound,
+    }
+
+    /// The percentiles of the fee rates of recent transactions used by the fee tiers.
+    #[derive(CandidType, Deserialize, Clone, Copy, Eq, PartialEq, Debug)]
+    pub struct BtcFeePercentiles {
+        pub slow: u8,
+        pub standard: u8,
+        pub fast: u8,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct BtcBuildUnsignedTransactionRequest {
+        pub destination_address: String,
+        pub amount_satoshis: u64,
+        pub network: BtcNetwork,
+        pub fee_policy: Option<BtcFeePolicy>,
+        pub min_confirmations: Option<u32>,
+        /// The type of the address spending its UTXOs. Defaults to `BtcAddressType::P2wpkh`.
+        pub address_type: Option<BtcAddressType>,
+        /// Defaults to `BtcCoinSelection::BranchAndBound`.
+        pub coin_selection: Option<BtcCoinSelection>,
+        /// The outpoints of the UTXOs to spend, see `SelectedUtxosFeeRequest::inputs`.
+        pub inputs: Option<Vec<Outpoint>>,
+        /// See `SelectedUtxosFeeRequest::spend_unconfirmed_change`.
+        pub spend_unconfirmed_change: Option<bool>,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct BtcBuildUnsignedTransactionResponse {
+        /// The unsigned transaction, serialized as a PSBT (BIP-174).
+        pub psbt: Vec<u8>,
+        pub txid: Vec<u8>,
+        pub utxos: Vec<Utxo>,
+        pub fee_satoshis: u64,
+        /// See `SelectedUtxosFeeResponse::spends_unconfirmed_change`.
+        pub spends_unconfirmed_change: bool,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub enum BtcBuildUnsignedTransactionError {
+        InternalError {
+            msg: String,
+        },
         PendingTransactions,
+        InsufficientFunds,
+        InvalidAddress(BtcAddressError),
+        InvalidInput(BtcInputError),
+        InvalidOutput(BtcOutputError),
+        /// See `SelectedUtxosFeeErrorV2::FeeRateOutOfBounds`.
+        FeeRateOutOfBounds {
+            min_satoshi_per_vbyte: u64,
+            max_satoshi_per_vbyte: u64,
+        },
+        /// The address has the maximum number of pending transactions.
+        QuotaExceeded {
+            msg: String,
+        },
+        SignerUnavailable {
+            msg: String,
+        },
+        BitcoinApiRejected {
+            code: u32,
+            msg: String,
+        },
+        Misconfigured {
+            msg: String,
+        },
+        UnsupportedAddressType,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct BtcBumpFeeRequest {
+        /// The id of the pending transaction to replace.
+        pub txid: Vec<u8>,
+        pub network: BtcNetwork,
+        /// The replacement pays at least the fee rate of this policy, and more if BIP-125 requires
+        /// it. Defaults to `BtcFeePolicy::Standard`.
+        pub fee_policy: Option<BtcFeePolicy>,
+        pub min_confirmations: Option<u32>,
+        /// The type of the address spending its UTXOs. Defaults to `BtcAddressType::P2wpkh`.
+        pub address_type: Option<BtcAddressType>,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub enum BtcBumpFeeError {
+        InternalError {
+            msg: String,
+        },
+        TransactionNotFound,
+        InsufficientFunds,
+        /// Some UTXOs of the pending transaction are already spent, e.g. by the transaction
+        /// itself once confirmed, so it can't be replaced.
+        UtxosAlreadySpent,
+        /// An output of the pending transaction pays an address that is not valid on the network.
+        InvalidAddress(BtcAddressError),
+        /// See `SelectedUtxosFeeErrorV2::FeeRateOutOfBounds`.
+        FeeRateOutOfBounds {
+            min_satoshi_per_vbyte: u64,
+            max_satoshi_per_vbyte: u64,
+        },
+        SignerUnavailable {
+            msg: String,
+        },
+        BitcoinApiRejected {
+            code: u32,
+            msg: String,
+        },
+        Misconfigured {
+            msg: String,
+        },
+        UnsupportedAddressType,
     }
 
     #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
@@ -193,24 +631,85 @@ pub mod bitcoin {
         pub txid: Vec<u8>,
         pub utxos: Vec<Utxo>,
         pub address: String,
-        pub network: BitcoinNetwork,
+        pub network: BtcNetwork,
     }
 
+    /// The error of `btc_add_pending_transaction` and `btc_get_pending_transactions`, as in their
+    /// first version.
+    ///
+    /// Every failure is an internal error, see `BtcAddPendingTransactionErrorV2` and
+    /// `BtcGetPendingTransactionsError` of the `_v2` endpoints for the structured ones.
     #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
     pub enum BtcAddPendingTransactionError {
         InternalError { msg: String },
     }
 
+    /// The error of `btc_add_pending_transaction_v2`.
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub enum BtcAddPendingTransactionErrorV2 {
+        InternalError {
+            msg: String,
+        },
+        InvalidAddress(BtcAddressError),
+        /// The UTXO is not a current UTXO of the address, so the transaction can't spend it.
+        UtxoNotFound {
+            outpoint: Outpoint,
+        },
+        /// The address has the maximum number of pending transactions, or the caller has pending
+        /// transactions on the maximum number of addresses.
+        QuotaExceeded {
+            msg: String,
+        },
+        SignerUnavailable {
+            msg: String,
+        },
+        BitcoinApiRejected {
+            code: u32,
+            msg: String,
+        },
+        Misconfigured {
+            msg: String,
+        },
+    }
+
     #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
     pub struct BtcGetPendingTransactionsRequest {
         pub address: String,
-        pub network: BitcoinNetwork,
+        pub network: BtcNetwork,
+    }
+
+    /// The lifecycle of a pending transaction.
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub enum BtcPendingTransactionStatus {
+        /// The transaction was built by the backend, but not reported as broadcast yet.
+        Built,
+        /// The user reported that the transaction was broadcast, but the network does not show
+        /// it yet.
+        Broadcast,
+        /// The transaction is in a block, which does not have enough confirmations yet: the
+        /// UTXOs spent by the transaction are only gone from the unconfirmed UTXOs of the address.
+        /// The height of the block is only known if the transaction pays back to the address.
+        InBlock { height: Option<u32> },
+        /// The UTXOs spent by the transaction are gone from the confirmed UTXOs of the address.
+        /// The height of the block is only known if the transaction pays back to the address.
+        Confirmed { height: Option<u32> },
+        /// The transaction was not confirmed within a day. Its UTXOs can be spent again.
+        Dropped,
+        /// The transaction was replaced by the transaction `txid` (BIP-125).
+        Replaced { txid: Vec<u8> },
     }
 
     #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
     pub struct PendingTransaction {
         pub txid: Vec<u8>,
         pub utxos: Vec<Utxo>,
+        /// The destinations and amounts of the transaction, excluding the change.
+        /// Only known for the transactions built by the backend.
+        pub outputs: Option<Vec<BtcTxOutput>>,
+        /// Only known for the transactions built by the backend.
+        pub fee_satoshis: Option<u64>,
+        pub status: BtcPendingTransactionStatus,
+        pub created_at_timestamp_ns: u64,
     }
 
     #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
@@ -218,9 +717,132 @@ pub mod bitcoin {
         pub transactions: Vec<PendingTransaction>,
     }
 
+    /// The error of `btc_get_pending_transactions_v2`.
     #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
     pub enum BtcGetPendingTransactionsError {
         InternalError { msg: String },
+        InvalidAddress(BtcAddressError),
+        SignerUnavailable { msg: String },
+        BitcoinApiRejected { code: u32, msg: String },
+        Misconfigured { msg: String },
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct BtcRemovePendingTransactionRequest {
+        pub txid: Vec<u8>,
+        pub address: String,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub enum BtcRemovePendingTransactionError {
+        InternalError { msg: String },
+        TransactionNotFound,
+        InvalidAddress(BtcAddressError),
+    }
+}
+
+/// Types of the conversions between BTC and ckBTC through the ckBTC minter.
+pub mod ckbtc {
+    use candid::CandidType;
+    use serde::Deserialize;
+
+    /// The bitcoin address where the caller deposits BTC to get ckBTC.
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct CkBtcGetBtcAddressResponse {
+        pub address: String,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub enum CkBtcGetBtcAddressError {
+        InternalError { msg: String },
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct CkBtcEstimateWithdrawalFeeRequest {
+        /// The amount to withdraw. The minter uses its typical withdrawal if not set.
+        pub amount_satoshis: Option<u64>,
+    }
+
+    /// The fees deducted from the amount of a withdrawal.
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct CkBtcEstimateWithdrawalFeeResponse {
+        /// The fee of the bitcoin transaction.
+        pub bitcoin_fee_satoshis: u64,
+        /// The fee charged by the minter.
+        pub minter_fee_satoshis: u64,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub enum CkBtcEstimateWithdrawalFeeError {
+        InternalError { msg: String },
+    }
+
+    /// A withdrawal of BTC that the user requested from the minter with
+    /// `retrieve_btc_with_approval`.
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct CkBtcAddWithdrawalRequest {
+        /// The index of the ckBTC burn block returned by `retrieve_btc_with_approval`.
+        pub block_index: u64,
+        /// The destination of the BTC.
+        pub address: String,
+        pub amount_satoshis: u64,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub enum CkBtcAddWithdrawalError {
+        /// The minter does not know a withdrawal of the caller with this block index, or the
+        /// withdrawal has another destination address or amount.
+        WithdrawalNotFound,
+        InternalError {
+            msg: String,
+        },
+    }
+
+    /// The status of a withdrawal, as reported by the minter.
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub enum CkBtcWithdrawalStatus {
+        /// The minter does not know the withdrawal.
+        Unknown,
+        /// The withdrawal is waiting to be batched into a bitcoin transaction.
+        Pending,
+        Signing,
+        Sending {
+            txid: Vec<u8>,
+        },
+        Submitted {
+            txid: Vec<u8>,
+        },
+        /// The bitcoin transaction is confirmed, the withdrawal is complete.
+        Confirmed {
+            txid: Vec<u8>,
+        },
+        /// The amount does not cover the fees. The ckBTC is not returned.
+        AmountTooLow,
+        /// The withdrawal failed and the ckBTC will be minted back to the user.
+        WillReimburse,
+        /// The withdrawal failed and the ckBTC was minted back to the user.
+        Reimbursed {
+            mint_block_index: u64,
+        },
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct CkBtcWithdrawal {
+        pub block_index: u64,
+        pub address: String,
+        pub amount_satoshis: u64,
+        pub created_at_timestamp_ns: u64,
+        pub status: CkBtcWithdrawalStatus,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct CkBtcGetWithdrawalsResponse {
+        pub withdrawals: Vec<CkBtcWithdrawal>,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub enum CkBtcGetWithdrawalsError {
+        InternalError { msg: String },
     }
 }
 
@@ -317,6 +939,14 @@ pub enum MigrationProgress {
     MigratedUserTimestampsUpTo(Option<Principal>),
     /// Migrated user profiles up to the given timestamp/user pair.
     MigratedUserProfilesUpTo(Option<(Timestamp, Principal)>),
+    /// Migrated pending bitcoin transactions up to the given principal.
+    MigratedBtcPendingTransactionsUpTo(Option<Principal>),
+    /// Migrated frozen bitcoin UTXOs up to the given principal.
+    MigratedBtcFrozenUtxosUpTo(Option<Principal>),
+    /// Migrated ckBTC withdrawals up to the given principal.
+    MigratedCkBtcWithdrawalsUpTo(Option<Principal>),
+    /// Migrated the next bitcoin address indices up to the given principal.
+    MigratedBtcNextAddressIndicesUpTo(Option<Principal>),
     /// Checking that the target canister has all the data.
     CheckingDataMigration,
     /// Unlock user data operations in the target canister.
@@ -376,4 +1006,8 @@ pub struct Stats {
     pub user_timestamps_count: u64,
     pub user_token_count: u64,
     pub custom_token_count: u64,
+    pub btc_pending_transactions_count: u64,
+    pub btc_frozen_utxos_count: u64,
+    pub ckbtc_withdrawals_count: u64,
+    pub btc_next_address_index_count: u64,
 }